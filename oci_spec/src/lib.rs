@@ -1,6 +1,8 @@
 use nix::sys::stat::SFlag;
 use std::collections::HashMap;
+use std::fmt;
 use std::fs::File;
+use std::io::Read;
 use std::path::PathBuf;
 
 use anyhow::{bail, Result};
@@ -30,6 +32,8 @@ pub struct User {
     #[serde(default)]
     pub gid: u32,
     #[serde(default)]
+    pub umask: Option<u32>,
+    #[serde(default)]
     pub additional_gids: Vec<u32>,
     #[serde(default)]
     pub username: String,
@@ -58,6 +62,157 @@ pub struct Process {
     pub capabilities: Option<LinuxCapabilities>,
     #[serde(default)]
     pub rlimits: Vec<LinuxRlimit>,
+    #[serde(default)]
+    pub landlock: Option<Landlock>,
+    #[serde(default)]
+    pub scheduler: Option<Scheduler>,
+    #[serde(default)]
+    pub oom_score_adj: Option<i32>,
+    #[serde(default)]
+    pub io_priority: Option<LinuxIoPriority>,
+    #[serde(default, rename = "execCPUAffinity")]
+    pub exec_cpu_affinity: Option<ExecCpuAffinity>,
+}
+
+/// CPUs which the process runs on, each a list in the format of cpuset, e.g. "0-3,7"
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ExecCpuAffinity {
+    /// while the runtime sets up the process, until it is in the cgroups of the container
+    #[serde(default)]
+    pub initial: Option<String>,
+    /// once the process is in the cgroups of the container
+    #[serde(default, rename = "final")]
+    pub final_cpus: Option<String>,
+}
+
+/// I/O scheduling class and priority of the container process, as ioprio_set(2) takes them
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LinuxIoPriority {
+    pub class: LinuxIoPriorityClass,
+    #[serde(default)]
+    pub priority: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum LinuxIoPriorityClass {
+    IoprioClassRt,
+    IoprioClassBe,
+    IoprioClassIdle,
+}
+
+/// Scheduling policy and attributes of the container process, as sched_setattr(2) takes them
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Scheduler {
+    pub policy: LinuxSchedulerPolicy,
+    #[serde(default)]
+    pub nice: i32,
+    #[serde(default)]
+    pub priority: i32,
+    #[serde(default)]
+    pub flags: Vec<LinuxSchedulerFlag>,
+    // runtime, deadline and period of SCHED_DEADLINE in nanoseconds
+    #[serde(default)]
+    pub runtime: u64,
+    #[serde(default)]
+    pub deadline: u64,
+    #[serde(default)]
+    pub period: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+#[repr(u32)]
+pub enum LinuxSchedulerPolicy {
+    SchedOther = 0,
+    SchedFifo = 1,
+    SchedRr = 2,
+    SchedBatch = 3,
+    SchedIso = 4,
+    SchedIdle = 5,
+    SchedDeadline = 6,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+#[repr(u64)]
+pub enum LinuxSchedulerFlag {
+    SchedFlagResetOnFork = 0x01,
+    SchedFlagReclaim = 0x02,
+    SchedFlagDlOverrun = 0x04,
+    SchedFlagKeepPolicy = 0x08,
+    SchedFlagKeepParams = 0x10,
+    SchedFlagUtilClampMin = 0x20,
+    SchedFlagUtilClampMax = 0x40,
+}
+
+impl LinuxSchedulerFlag {
+    /// Combines flags into the bitmask which sched_setattr takes
+    pub fn to_mask(flags: &[LinuxSchedulerFlag]) -> u64 {
+        flags.iter().fold(0, |mask, flag| mask | *flag as u64)
+    }
+}
+
+/// Landlock ruleset which restricts the filesystem access of the container process
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Landlock {
+    pub ruleset: LandlockRuleset,
+    #[serde(default)]
+    pub rules: LandlockRules,
+    // fail instead of enforcing what the kernel supports, if it does not support everything
+    #[serde(default)]
+    pub disable_best_effort: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LandlockRuleset {
+    #[serde(rename = "handledAccessFS", default)]
+    pub handled_access_fs: Vec<LandlockFsAccess>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct LandlockRules {
+    #[serde(default)]
+    pub path_beneath: Vec<LandlockRulePathBeneath>,
+}
+
+/// Access which is allowed beneath the given paths, out of the handled one
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct LandlockRulePathBeneath {
+    pub allowed_access: Vec<LandlockFsAccess>,
+    pub paths: Vec<PathBuf>,
+}
+
+/// Filesystem access rights of Landlock, with the values of LANDLOCK_ACCESS_FS_*
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+#[repr(u64)]
+pub enum LandlockFsAccess {
+    Execute = 1 << 0,
+    WriteFile = 1 << 1,
+    ReadFile = 1 << 2,
+    ReadDir = 1 << 3,
+    RemoveDir = 1 << 4,
+    RemoveFile = 1 << 5,
+    MakeChar = 1 << 6,
+    MakeDir = 1 << 7,
+    MakeReg = 1 << 8,
+    MakeSock = 1 << 9,
+    MakeFifo = 1 << 10,
+    MakeBlock = 1 << 11,
+    MakeSym = 1 << 12,
+    Refer = 1 << 13,
+    Truncate = 1 << 14,
+}
+
+impl LandlockFsAccess {
+    /// Combines access rights into the bitmask which Landlock takes
+    pub fn to_mask(access: &[LandlockFsAccess]) -> u64 {
+        access.iter().fold(0, |mask, access| mask | *access as u64)
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -69,7 +224,7 @@ pub struct LinuxRlimit {
     #[serde(default)]
     pub soft: u64,
 }
-#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum LinuxRlimitType {
     RlimitCpu,
@@ -131,7 +286,7 @@ impl<'de> Deserialize<'de> for LinuxCapabilityType {
                     "CAP_SYS_NICE" => Capability::CAP_SYS_NICE,
                     "CAP_SYS_RESOURCE" => Capability::CAP_SYS_RESOURCE,
                     "CAP_SYS_TIME" => Capability::CAP_SYS_TIME,
-                    "CAP_SYS_TTYCONFIG" => Capability::CAP_SYS_TTY_CONFIG,
+                    "CAP_SYS_TTY_CONFIG" | "CAP_SYS_TTYCONFIG" => Capability::CAP_SYS_TTY_CONFIG,
                     "CAP_SYSLOG" => Capability::CAP_SYSLOG,
                     "CAP_MKNOD" => Capability::CAP_MKNOD,
                     "CAP_LEASE" => Capability::CAP_LEASE,
@@ -143,6 +298,9 @@ impl<'de> Deserialize<'de> for LinuxCapabilityType {
                     "CAP_MAC_ADMIN" => Capability::CAP_MAC_ADMIN,
                     "CAP_WAKE_ALARM" => Capability::CAP_WAKE_ALARM,
                     "CAP_BLOCK_SUSPEND" => Capability::CAP_BLOCK_SUSPEND,
+                    "CAP_PERFMON" => Capability::CAP_PERFMON,
+                    "CAP_BPF" => Capability::CAP_BPF,
+                    "CAP_CHECKPOINT_RESTORE" => Capability::CAP_CHECKPOINT_RESTORE,
                     unknown_cap => {
                         return Err(serde::de::Error::custom(format!(
                             "{:?} is unexpected type in capabilites",
@@ -157,7 +315,7 @@ impl<'de> Deserialize<'de> for LinuxCapabilityType {
     }
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Debug, Clone, Default)]
 pub struct LinuxCapabilities {
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub bounding: Vec<LinuxCapabilityType>,
@@ -274,22 +432,17 @@ pub struct LinuxIdMapping {
 }
 
 // a is for LinuxDeviceCgroup
-#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[derive(Default, Serialize, Deserialize, Debug, Clone, Copy)]
 #[serde(rename_all = "lowercase")]
 pub enum LinuxDeviceType {
     B,
     C,
     U,
     P,
+    #[default]
     A,
 }
 
-impl Default for LinuxDeviceType {
-    fn default() -> LinuxDeviceType {
-        LinuxDeviceType::A
-    }
-}
-
 impl LinuxDeviceType {
     pub fn to_sflag(&self) -> Result<SFlag> {
         Ok(match self {
@@ -323,8 +476,8 @@ pub struct LinuxDeviceCgroup {
     pub access: String,
 }
 
-impl ToString for LinuxDeviceCgroup {
-    fn to_string(&self) -> String {
+impl fmt::Display for LinuxDeviceCgroup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let major = self
             .major
             .map(|mj| mj.to_string())
@@ -333,7 +486,8 @@ impl ToString for LinuxDeviceCgroup {
             .minor
             .map(|mi| mi.to_string())
             .unwrap_or_else(|| "*".to_string());
-        format!(
+        write!(
+            f,
             "{} {}:{} {}",
             self.typ.as_str(),
             &major,
@@ -359,6 +513,9 @@ pub struct LinuxMemory {
 pub struct LinuxCpu {
     pub shares: Option<u64>,
     pub quota: Option<i64>,
+    /// runtime in microseconds which the container may accumulate beyond its quota
+    #[serde(default)]
+    pub burst: Option<u64>,
     pub period: Option<u64>,
     pub realtime_runtime: Option<i64>,
     pub realtime_period: Option<u64>,
@@ -429,9 +586,9 @@ pub struct LinuxInterfacePriority {
     pub priority: u32,
 }
 
-impl ToString for LinuxInterfacePriority {
-    fn to_string(&self) -> String {
-        format!("{} {}\n", self.name, self.priority)
+impl fmt::Display for LinuxInterfacePriority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} {}", self.name, self.priority)
     }
 }
 
@@ -462,7 +619,7 @@ pub struct LinuxResources {
     pub network: Option<LinuxNetwork>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
 #[serde(rename_all = "snake_case")]
 pub enum LinuxNamespaceType {
     Mount = 0x00020000,
@@ -472,6 +629,7 @@ pub enum LinuxNamespaceType {
     User = 0x10000000,
     Pid = 0x20000000,
     Network = 0x40000000,
+    Time = 0x00000080,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -509,17 +667,106 @@ impl From<&LinuxDevice> for LinuxDeviceCgroup {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 #[repr(u32)]
 pub enum LinuxSeccompAction {
+    #[serde(alias = "SCMP_ACT_KILL_THREAD")]
     ScmpActKill = 0x00000000,
+    ScmpActKillProcess = 0x80000000,
     ScmpActTrap = 0x00030000,
     ScmpActErrno = 0x00050001,
+    ScmpActNotify = 0x7fc00000,
     ScmpActTrace = 0x7ff00001,
+    ScmpActLog = 0x7ffc0000,
     ScmpActAllow = 0x7fff0000,
 }
 
+impl LinuxSeccompAction {
+    /// Returns the value which the filter returns to the kernel for the action. Errno and
+    /// trace carry errno_ret in their lower 16 bits, which is EPERM if it is not given.
+    pub fn to_ret(self, errno_ret: Option<u32>) -> u32 {
+        match self {
+            LinuxSeccompAction::ScmpActErrno | LinuxSeccompAction::ScmpActTrace => {
+                let errno_ret = errno_ret.unwrap_or(nix::errno::Errno::EPERM as u32) & 0xffff;
+                (self as u32 & 0xffff0000) | errno_ret
+            }
+            _ => self as u32,
+        }
+    }
+}
+
+/// Seccomp filter of the container process
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct LinuxSeccomp {
+    pub default_action: LinuxSeccompAction,
+    // errno returned by the default action, if it is SCMP_ACT_ERRNO or SCMP_ACT_TRACE
+    #[serde(default)]
+    pub default_errno_ret: Option<u32>,
+    #[serde(default)]
+    pub architectures: Vec<Arch>,
+    #[serde(default)]
+    pub flags: Vec<LinuxSeccompFlag>,
+    #[serde(default)]
+    pub listener_path: Option<PathBuf>,
+    #[serde(default)]
+    pub listener_metadata: Option<String>,
+    #[serde(default)]
+    pub syscalls: Vec<LinuxSyscall>,
+}
+
+impl LinuxSeccomp {
+    /// Returns the flags to load the filter with. A filter with SCMP_ACT_NOTIFY needs a
+    /// listener, so the flag for it is added even if the spec does not list it.
+    pub fn filter_flags(&self) -> u32 {
+        let mut flags = self.flags.iter().fold(0, |flags, flag| flags | *flag as u32);
+        let notifies = self.default_action == LinuxSeccompAction::ScmpActNotify
+            || self
+                .syscalls
+                .iter()
+                .any(|syscall| syscall.action == LinuxSeccompAction::ScmpActNotify);
+        if notifies {
+            flags |= LinuxSeccompFlag::SeccompFilterFlagNewListener as u32;
+        }
+        flags
+    }
+}
+
+/// Flags of seccomp(SECCOMP_SET_MODE_FILTER), e.g. to skip the mitigation of speculative
+/// store bypass with SPEC_ALLOW, or to apply the filter to every thread with TSYNC
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+#[repr(u32)]
+pub enum LinuxSeccompFlag {
+    SeccompFilterFlagTsync = 1,
+    SeccompFilterFlagLog = 2,
+    SeccompFilterFlagSpecAllow = 4,
+    SeccompFilterFlagNewListener = 8,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct LinuxSyscall {
+    pub names: Vec<String>,
+    pub action: LinuxSeccompAction,
+    // errno returned by the action, if it is SCMP_ACT_ERRNO or SCMP_ACT_TRACE
+    #[serde(default)]
+    pub errno_ret: Option<u32>,
+    #[serde(default)]
+    pub args: Vec<LinuxSeccompArg>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct LinuxSeccompArg {
+    pub index: usize,
+    pub value: u64,
+    #[serde(default)]
+    pub value_two: u64,
+    pub op: LinuxSeccompOperator,
+}
+
 #[allow(clippy::enum_clike_unportable_variant)]
 #[derive(Serialize, Deserialize, Debug, Clone, Copy)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
@@ -543,6 +790,29 @@ pub enum Arch {
     ScmpArchS390x = 0x80000016,
 }
 
+impl Arch {
+    /// Returns the architecture youki is built for, which is what ScmpArchNative refers to
+    pub fn native() -> Arch {
+        if cfg!(target_arch = "x86_64") {
+            Arch::ScmpArchX86_64
+        } else if cfg!(target_arch = "x86") {
+            Arch::ScmpArchX86
+        } else if cfg!(target_arch = "aarch64") {
+            Arch::ScmpArchAarch64
+        } else if cfg!(target_arch = "arm") {
+            Arch::ScmpArchArm
+        } else if cfg!(all(target_arch = "powerpc64", target_endian = "little")) {
+            Arch::ScmpArchPpc64le
+        } else if cfg!(target_arch = "powerpc64") {
+            Arch::ScmpArchPpc64
+        } else if cfg!(target_arch = "s390x") {
+            Arch::ScmpArchS390x
+        } else {
+            Arch::ScmpArchNative
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Copy)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 #[repr(u32)]
@@ -559,9 +829,9 @@ pub enum LinuxSeccompOperator {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Linux {
-    #[serde(default, rename = "LinuxIDMapping")]
+    #[serde(default)]
     pub uid_mappings: Vec<LinuxIdMapping>,
-    #[serde(default, rename = "LinuxIDMapping")]
+    #[serde(default)]
     pub gid_mappings: Vec<LinuxIdMapping>,
     #[serde(default)]
     pub sysctl: HashMap<String, String>,
@@ -580,6 +850,39 @@ pub struct Linux {
     pub readonly_paths: Vec<String>,
     #[serde(default)]
     pub mount_label: String,
+    #[serde(default)]
+    pub seccomp: Option<LinuxSeccomp>,
+    /// Offsets of the clocks of the time namespace, by the name of the clock, i.e.
+    /// monotonic or boottime
+    #[serde(default)]
+    pub time_offsets: HashMap<String, LinuxTimeOffset>,
+    #[serde(default)]
+    pub personality: Option<LinuxPersonality>,
+}
+
+/// Execution domain of the container process, e.g. LINUX32 for a 32-bit userland
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LinuxPersonality {
+    pub domain: LinuxPersonalityDomain,
+    #[serde(default)]
+    pub flags: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum LinuxPersonalityDomain {
+    #[serde(rename = "LINUX")]
+    Linux,
+    #[serde(rename = "LINUX32")]
+    Linux32,
+}
+
+/// Offset of a clock of the time namespace from the one of the host
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct LinuxTimeOffset {
+    #[serde(default)]
+    pub secs: i64,
+    #[serde(default)]
+    pub nanosecs: u32,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -592,22 +895,92 @@ pub struct Spec {
     #[serde(default)]
     pub hostname: String,
     #[serde(default)]
+    pub domainname: String,
+    #[serde(default)]
     pub mounts: Vec<Mount>,
     #[serde(default)]
     pub annotations: HashMap<String, String>,
     pub linux: Option<Linux>,
+    #[serde(default)]
+    pub hooks: Option<Hooks>,
+}
+
+/// Programs run at points of the lifecycle of the container
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Hooks {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub prestart: Vec<Hook>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub create_runtime: Vec<Hook>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub create_container: Vec<Hook>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub start_container: Vec<Hook>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub poststart: Vec<Hook>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub poststop: Vec<Hook>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Hook {
+    pub path: PathBuf,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub env: Vec<String>,
+    // seconds after which the hook is aborted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
 }
 
 impl Spec {
     pub fn load(path: &str) -> Result<Self> {
         let file = File::open(path)?;
-        let mut spec: Spec = serde_json::from_reader(&file)?;
-        // FIME: It is fail if the caller isn't in the correct directory.
-        spec.root.path = std::fs::canonicalize(spec.root.path)?;
+        let spec: Spec = serde_json::from_reader(&file)?;
+        Ok(spec)
+    }
+
+    /// Loads the spec like `load`, but fails with the list of fields which are not
+    /// recognized instead of silently ignoring them, so that typos are caught early.
+    pub fn load_strict(path: &str) -> Result<Self> {
+        let file = File::open(path)?;
+        match Self::from_reader_strict(&file) {
+            Ok(spec) => Ok(spec),
+            Err(e) => bail!("failed to load {}: {}", path, e),
+        }
+    }
+
+    fn from_reader_strict<R: Read>(reader: R) -> Result<Self> {
+        let mut deserializer = serde_json::Deserializer::from_reader(reader);
+        let mut unknown_fields = Vec::new();
+        let spec: Spec = serde_ignored::deserialize(&mut deserializer, |path| {
+            unknown_fields.push(field_path(&path))
+        })?;
+        if !unknown_fields.is_empty() {
+            bail!("unknown fields: {}", unknown_fields.join(", "))
+        }
         Ok(spec)
     }
 }
 
+// formats the path of a field as it is written in config.json, e.g. linux.resources.memory
+fn field_path(path: &serde_ignored::Path) -> String {
+    use serde_ignored::Path;
+    match path {
+        Path::Root => String::new(),
+        Path::Seq { parent, index } => format!("{}[{}]", field_path(parent), index),
+        Path::Map { parent, key } => match field_path(parent).as_str() {
+            "" => key.clone(),
+            parent => format!("{}.{}", parent, key),
+        },
+        Path::Some { parent }
+        | Path::NewtypeStruct { parent }
+        | Path::NewtypeVariant { parent } => field_path(parent),
+    }
+}
+
 #[cfg(feature = "proptests")]
 use quickcheck::{Arbitrary, Gen};
 
@@ -698,4 +1071,122 @@ mod tests {
         };
         assert_eq!(ldc.to_string(), "a 1:9 rwm");
     }
+
+    #[test]
+    fn test_seccomp_actions() {
+        let seccomp: LinuxSeccomp = serde_json::from_str(
+            r#"{
+                "defaultAction": "SCMP_ACT_ERRNO",
+                "defaultErrnoRet": 38,
+                "architectures": ["SCMP_ARCH_X86_64"],
+                "syscalls": [
+                    { "names": ["kexec_load"], "action": "SCMP_ACT_KILL_PROCESS" },
+                    { "names": ["ptrace"], "action": "SCMP_ACT_KILL_THREAD" },
+                    { "names": ["open"], "action": "SCMP_ACT_LOG" },
+                    { "names": ["clone3"], "action": "SCMP_ACT_ERRNO", "errnoRet": 38 },
+                    { "names": ["bpf"], "action": "SCMP_ACT_TRACE",
+                      "args": [{ "index": 0, "value": 1, "op": "SCMP_CMP_EQ" }] }
+                ]
+            }"#,
+        )
+        .expect("parse seccomp");
+
+        assert_eq!(
+            seccomp.default_action.to_ret(seccomp.default_errno_ret),
+            0x00050026
+        );
+        let rets: Vec<u32> = seccomp
+            .syscalls
+            .iter()
+            .map(|syscall| syscall.action.to_ret(syscall.errno_ret))
+            .collect();
+        assert_eq!(
+            rets,
+            vec![0x80000000, 0x00000000, 0x7ffc0000, 0x00050026, 0x7ff00001]
+        );
+        assert_eq!(LinuxSeccompAction::ScmpActErrno.to_ret(None), 0x00050001);
+        assert_eq!(LinuxSeccompAction::ScmpActAllow.to_ret(Some(5)), 0x7fff0000);
+    }
+
+    #[test]
+    fn test_seccomp_flags() {
+        let seccomp: LinuxSeccomp = serde_json::from_str(
+            r#"{
+                "defaultAction": "SCMP_ACT_ALLOW",
+                "flags": ["SECCOMP_FILTER_FLAG_TSYNC", "SECCOMP_FILTER_FLAG_SPEC_ALLOW"],
+                "syscalls": [{ "names": ["mount"], "action": "SCMP_ACT_NOTIFY" }]
+            }"#,
+        )
+        .expect("parse seccomp");
+        assert_eq!(seccomp.filter_flags(), 1 | 4 | 8);
+
+        let seccomp: LinuxSeccomp = serde_json::from_str(
+            r#"{ "defaultAction": "SCMP_ACT_ALLOW", "flags": ["SECCOMP_FILTER_FLAG_LOG"] }"#,
+        )
+        .expect("parse seccomp");
+        assert_eq!(seccomp.filter_flags(), 2);
+
+        assert!(serde_json::from_str::<LinuxSeccomp>(
+            r#"{ "defaultAction": "SCMP_ACT_ALLOW", "flags": ["SECCOMP_FILTER_FLAG_UNKNOWN"] }"#,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_native_arch() {
+        let native = Arch::native() as u32;
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(native, 0xc000003e);
+        #[cfg(target_arch = "aarch64")]
+        assert_eq!(native, 0xc00000b7);
+        assert_ne!(native, Arch::ScmpArchNative as u32);
+    }
+
+    const MINIMAL_SPEC: &str = r#"{
+        "ociVersion": "1.0.2",
+        "process": { "user": { "uid": 0, "gid": 0 }, "args": ["sh"] },
+        "root": { "path": "rootfs" },
+        "linux": { "resources": { "memory": { "limit": 1024 } } }
+    }"#;
+
+    #[test]
+    fn test_from_reader_strict() {
+        let spec = Spec::from_reader_strict(MINIMAL_SPEC.as_bytes()).expect("parse spec");
+        assert_eq!(spec.root.path, PathBuf::from("rootfs"));
+    }
+
+    #[test]
+    fn test_unknown_rlimit() {
+        let spec = MINIMAL_SPEC.replace(
+            r#""args": ["sh"]"#,
+            r#""args": ["sh"], "rlimits": [{ "type": "RLIMIT_FOO", "hard": 1, "soft": 1 }]"#,
+        );
+        let err = serde_json::from_str::<Spec>(&spec)
+            .expect_err("unknown rlimit should be rejected")
+            .to_string();
+        assert!(err.contains("RLIMIT_FOO"), "{}", err);
+    }
+
+    #[test]
+    fn test_from_reader_strict_unknown_fields() {
+        let typo = MINIMAL_SPEC
+            .replace(r#""limit": 1024"#, r#""limits": 1024"#)
+            .replace(r#""args""#, r#""cwdd": "/", "args""#);
+        let err = Spec::from_reader_strict(typo.as_bytes())
+            .expect_err("unknown fields should be rejected")
+            .to_string();
+        assert!(err.contains("linux.resources.memory.limits"), "{}", err);
+        assert!(err.contains("process.cwdd"), "{}", err);
+
+        let spec: Spec = serde_json::from_str(&typo).expect("lenient parse of spec");
+        assert!(spec
+            .linux
+            .unwrap()
+            .resources
+            .unwrap()
+            .memory
+            .unwrap()
+            .limit
+            .is_none());
+    }
 }
@@ -0,0 +1,120 @@
+//! Runs an operation on many containers at once, e.g. to clean up after a test run. The
+//! containers are handled concurrently, and a failure of one does not stop the others.
+
+use std::fs;
+use std::path::Path;
+use std::thread;
+
+use anyhow::{bail, Result};
+
+/// Returns the given ids, or every container in the state root if all_containers is set
+pub fn container_ids(
+    root_path: &Path,
+    ids: &[String],
+    all_containers: bool,
+) -> Result<Vec<String>> {
+    if !all_containers {
+        if ids.is_empty() {
+            bail!("no container id is given, nor --all-containers");
+        }
+        return Ok(ids.to_vec());
+    }
+    if !ids.is_empty() {
+        bail!("container ids cannot be given along with --all-containers");
+    }
+
+    let mut ids = Vec::new();
+    for entry in fs::read_dir(root_path)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            ids.push(entry.file_name().to_string_lossy().into_owned());
+        }
+    }
+    ids.sort();
+    Ok(ids)
+}
+
+/// Applies the operation to each of the containers and fails if it failed for any of them.
+/// If there are several, the id of each one it succeeded for is printed, like docker does.
+pub fn run<F>(verb: &str, ids: &[String], operation: F) -> Result<()>
+where
+    F: Fn(&str) -> Result<()> + Sync,
+{
+    if let [id] = ids {
+        return operation(id);
+    }
+
+    let results: Vec<Result<()>> = thread::scope(|scope| {
+        let handles: Vec<_> = ids
+            .iter()
+            .map(|id| {
+                let operation = &operation;
+                scope.spawn(move || operation(id))
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|_| Err(anyhow::anyhow!("panicked")))
+            })
+            .collect()
+    });
+
+    let mut failed = 0;
+    for (id, result) in ids.iter().zip(results) {
+        match result {
+            Ok(()) => println!("{}", id),
+            Err(e) => {
+                failed += 1;
+                eprintln!("failed to {} {}: {:#}", verb, id, e);
+            }
+        }
+    }
+    if failed > 0 {
+        bail!("failed to {} {} of {} containers", verb, failed, ids.len());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_container_ids() {
+        let root = std::env::temp_dir().join("test_batch_container_ids");
+        let _ = fs::remove_dir_all(&root);
+        for id in &["b", "a"] {
+            fs::create_dir_all(root.join(id)).unwrap();
+        }
+        fs::write(root.join("not-a-container"), "").unwrap();
+
+        assert_eq!(container_ids(&root, &[], true).unwrap(), vec!["a", "b"]);
+        let given = vec!["c".to_owned()];
+        assert_eq!(container_ids(&root, &given, false).unwrap(), given);
+        assert!(container_ids(&root, &given, true).is_err());
+        assert!(container_ids(&root, &[], false).is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_run_continues_after_failure() {
+        let ids: Vec<String> = ["a", "b", "c"].iter().map(|id| id.to_string()).collect();
+        let done = Mutex::new(Vec::new());
+        let result = run("kill", &ids, |id| {
+            done.lock().unwrap().push(id.to_owned());
+            if id == "b" {
+                bail!("{} is stopped", id);
+            }
+            Ok(())
+        });
+
+        assert!(result.is_err());
+        let mut done = done.into_inner().unwrap();
+        done.sort();
+        assert_eq!(done, ids);
+    }
+}
@@ -1,8 +1,24 @@
+use std::fs;
+
 use crate::command::Command;
+use crate::container::Warning;
 use caps::*;
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
+use nix::unistd::Pid;
 use oci_spec::{LinuxCapabilities, LinuxCapabilityType};
+use prctl::PrctlSecurebits;
+use serde::{Deserialize, Serialize};
+
+/// Capabilities which a process actually holds, to verify what has been applied
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct CapabilitySets {
+    pub bounding: Vec<String>,
+    pub effective: Vec<String>,
+    pub inheritable: Vec<String>,
+    pub permitted: Vec<String>,
+    pub ambient: Vec<String>,
+}
 
 fn to_set(caps: &[LinuxCapabilityType]) -> CapsHashSet {
     let mut capabilities = CapsHashSet::new();
@@ -12,35 +28,163 @@ fn to_set(caps: &[LinuxCapabilityType]) -> CapsHashSet {
     capabilities
 }
 
+/// Raises every permitted capability into the effective set, which setuid to a non-root
+/// user clears even if the permitted one is kept
 pub fn reset_effective(command: &impl Command) -> Result<()> {
     log::debug!("reset all caps");
-    command.set_capability(CapSet::Effective, &caps::all())?;
+    command.set_capability(CapSet::Effective, &caps::read(None, CapSet::Permitted)?)?;
     Ok(())
 }
 
+/// Sets the capability sets of the process to those of the spec. A set which is empty
+/// leaves the process without any capability of it, and so does a spec without
+/// capabilities. Capabilities which the kernel does not know are left out.
 pub fn drop_privileges(cs: &LinuxCapabilities, command: &impl Command) -> Result<()> {
-    let all = caps::all();
+    let supported = caps::runtime::thread_all_supported();
+    let set = |caps: &[LinuxCapabilityType]| -> CapsHashSet {
+        to_set(caps).intersection(&supported).copied().collect()
+    };
     log::debug!("dropping bounding capabilities to {:?}", cs.bounding);
-    for c in all.difference(&to_set(&cs.bounding)) {
-        match c {
-            Capability::CAP_PERFMON | Capability::CAP_CHECKPOINT_RESTORE | Capability::CAP_BPF => {
-                log::warn!("{:?} doesn't support.", c);
-                continue;
-            }
-            _ => caps::drop(None, CapSet::Bounding, *c)?,
+    command.set_capability(CapSet::Bounding, &set(&cs.bounding))?;
+    // raising inheritable capabilities needs CAP_SETPCAP, which the effective set may drop
+    command.set_capability(CapSet::Inheritable, &set(&cs.inheritable))?;
+    command.set_capability(CapSet::Effective, &set(&cs.effective))?;
+    command.set_capability(CapSet::Permitted, &set(&cs.permitted))?;
+
+    if let Err(e) = command.set_capability(CapSet::Ambient, &set(&cs.ambient)) {
+        // kernels before 4.3 have no ambient capabilities
+        if caps::runtime::ambient_set_supported().is_ok() {
+            bail!("failed to set ambient capabilities: {}", e);
         }
+        log::warn!(
+            "ambient capabilities are not supported by the kernel: {}",
+            e
+        );
+    }
+    Ok(())
+}
+
+/// Capabilities of the spec which the kernel does not know, and which are left out
+pub fn unsupported(cs: &LinuxCapabilities) -> Vec<Warning> {
+    let supported = caps::runtime::thread_all_supported();
+    let sets = [
+        ("bounding", &cs.bounding),
+        ("effective", &cs.effective),
+        ("inheritable", &cs.inheritable),
+        ("permitted", &cs.permitted),
+        ("ambient", &cs.ambient),
+    ];
+    sets.iter()
+        .filter_map(|(name, caps)| {
+            let mut unknown: Vec<String> = to_set(caps)
+                .difference(&supported)
+                .map(|cap| cap.to_string())
+                .collect();
+            if unknown.is_empty() {
+                return None;
+            }
+            unknown.sort();
+            Some(Warning::new(
+                &format!("process.capabilities.{}", name),
+                &format!("{:?} are not known by the kernel", unknown),
+            ))
+        })
+        .collect()
+}
+
+/// Effective and permitted capabilities which a process of a non-root user loses when it
+/// is executed, as only the ambient ones are kept then, unless the executable has file
+/// capabilities
+pub fn lost_on_exec(cs: &LinuxCapabilities, uid: u32) -> Option<Warning> {
+    if uid == 0 {
+        return None;
+    }
+    let kept = to_set(&cs.ambient);
+    let mut lost: Vec<String> = to_set(&cs.effective)
+        .union(&to_set(&cs.permitted))
+        .filter(|cap| !kept.contains(cap))
+        .map(|cap| cap.to_string())
+        .collect();
+    if lost.is_empty() {
+        return None;
     }
+    lost.sort();
+    Some(Warning::new(
+        "process.capabilities",
+        &format!(
+            "{:?} are dropped when uid {} executes the process, add them to ambient to keep them",
+            lost, uid
+        ),
+    ))
+}
 
-    command.set_capability(CapSet::Effective, &to_set(&cs.effective))?;
-    command.set_capability(CapSet::Permitted, &to_set(&cs.permitted))?;
-    command.set_capability(CapSet::Inheritable, &to_set(&cs.inheritable))?;
+/// Annotation with the securebits of the container process, e.g. "keep-caps,noroot-locked".
+/// The OCI spec has no field for them, but non-root containers need some of them to keep
+/// the ambient capabilities they are given.
+pub const SECUREBITS_ANNOTATION: &str = "org.youki.securebits";
 
-    if let Err(e) = command.set_capability(CapSet::Ambient, &to_set(&cs.ambient)) {
-        log::error!("failed to set ambient capabilities: {}", e);
+/// Parses the comma separated names of securebits, as in capabilities(7) without SECBIT_
+pub fn parse_securebits(value: &str) -> Result<Vec<PrctlSecurebits>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            Ok(match name {
+                "noroot" => PrctlSecurebits::SECBIT_NOROOT,
+                "noroot-locked" => PrctlSecurebits::SECBIT_NOROOT_LOCKED,
+                "no-setuid-fixup" => PrctlSecurebits::SECBIT_NO_SETUID_FIXUP,
+                "no-setuid-fixup-locked" => PrctlSecurebits::SECBIT_NO_SETUID_FIXUP_LOCKED,
+                "keep-caps" => PrctlSecurebits::SECBIT_KEEP_CAPS,
+                "keep-caps-locked" => PrctlSecurebits::SECBIT_KEEP_CAPS_LOCKED,
+                _ => bail!("{} is not a securebit", name),
+            })
+        })
+        .collect()
+}
+
+/// Sets the securebits, which needs CAP_SETPCAP in the effective set. A locked bit cannot
+/// be changed afterwards, so this is done once the user of the process has been set.
+pub fn set_securebits(bits: Vec<PrctlSecurebits>) -> Result<()> {
+    log::debug!("set securebits to {:?}", bits);
+    if let Err(errno) = prctl::set_securebits(bits) {
+        bail!("failed to set securebits: errno {}", errno);
     }
     Ok(())
 }
 
+/// Reads back the capability sets of a process from /proc/<pid>/status
+pub fn read_sets(pid: Pid) -> Result<CapabilitySets> {
+    let status = fs::read_to_string(format!("/proc/{}/status", pid))?;
+    parse_status(&status)
+}
+
+fn parse_status(status: &str) -> Result<CapabilitySets> {
+    let set = |field: &str| -> Result<Vec<String>> {
+        let mask = status
+            .lines()
+            .find_map(|line| line.strip_prefix(field))
+            .ok_or_else(|| anyhow!("{} is missing in the status", field))?;
+        let mask = u64::from_str_radix(mask.trim(), 16)?;
+        let mut names: Vec<String> = caps::all()
+            .into_iter()
+            .filter(|cap| mask & (1 << cap.index()) != 0)
+            .map(|cap| cap.to_string())
+            .collect();
+        names.sort();
+        Ok(names)
+    };
+
+    Ok(CapabilitySets {
+        bounding: set("CapBnd:")?,
+        effective: set("CapEff:")?,
+        inheritable: set("CapInh:")?,
+        permitted: set("CapPrm:")?,
+        // kernels before 4.3 have no ambient capabilities
+        ambient: set("CapAmb:").unwrap_or_default(),
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -55,6 +199,88 @@ mod tests {
             .into_iter()
             .map(|(_capset, caps)| caps)
             .collect();
-        assert_eq!(set_capability_args, vec![caps::all()]);
+        let permitted = caps::read(None, CapSet::Permitted).unwrap();
+        assert_eq!(set_capability_args, vec![permitted]);
+    }
+
+    #[test]
+    fn test_lost_on_exec() {
+        let cap = |cap| LinuxCapabilityType { cap };
+        let spec = LinuxCapabilities {
+            effective: vec![cap(Capability::CAP_KILL), cap(Capability::CAP_NET_RAW)],
+            permitted: vec![cap(Capability::CAP_KILL), cap(Capability::CAP_NET_RAW)],
+            inheritable: vec![cap(Capability::CAP_KILL)],
+            ambient: vec![cap(Capability::CAP_KILL)],
+            ..Default::default()
+        };
+        assert_eq!(lost_on_exec(&spec, 0), None);
+        let warning = lost_on_exec(&spec, 1000).expect("CAP_NET_RAW is lost");
+        assert!(warning.reason.contains("CAP_NET_RAW"), "{}", warning);
+        assert!(!warning.reason.contains("CAP_KILL"), "{}", warning);
+    }
+
+    #[test]
+    fn test_drop_privileges() {
+        let test_command = TestHelperCommand::default();
+        let cap = |cap| LinuxCapabilityType { cap };
+        let spec = LinuxCapabilities {
+            bounding: vec![cap(Capability::CAP_CHOWN), cap(Capability::CAP_KILL)],
+            effective: vec![cap(Capability::CAP_KILL)],
+            permitted: vec![cap(Capability::CAP_CHOWN), cap(Capability::CAP_KILL)],
+            ..Default::default()
+        };
+        drop_privileges(&spec, &test_command).unwrap();
+
+        let set = |caps: &[Capability]| caps.iter().copied().collect::<CapsHashSet>();
+        let both = set(&[Capability::CAP_CHOWN, Capability::CAP_KILL]);
+        // CapSet has no PartialEq
+        let args: Vec<_> = test_command
+            .get_set_capability_args()
+            .into_iter()
+            .map(|(capset, caps)| (format!("{:?}", capset), caps))
+            .collect();
+        assert_eq!(
+            args,
+            vec![
+                ("Bounding".to_owned(), both.clone()),
+                ("Inheritable".to_owned(), set(&[])),
+                ("Effective".to_owned(), set(&[Capability::CAP_KILL])),
+                ("Permitted".to_owned(), both),
+                ("Ambient".to_owned(), set(&[])),
+            ]
+        );
+
+        // without capabilities in the spec, every set is emptied
+        let test_command = TestHelperCommand::default();
+        drop_privileges(&LinuxCapabilities::default(), &test_command).unwrap();
+        assert!(test_command
+            .get_set_capability_args()
+            .iter()
+            .all(|(_, caps)| caps.is_empty()));
+    }
+
+    #[test]
+    fn test_parse_securebits() {
+        let bits = parse_securebits("keep-caps, keep-caps-locked,no-setuid-fixup").unwrap();
+        let mask = bits.iter().fold(0, |mask, bit| mask | *bit as u32);
+        assert_eq!(mask, 0x10 | 0x20 | 0x04);
+        assert!(parse_securebits("").unwrap().is_empty());
+        assert!(parse_securebits("keep-caps,keep_caps").is_err());
+    }
+
+    #[test]
+    fn test_parse_status() {
+        let status = "Name:\tsh\nCapInh:\t0000000000000000\nCapPrm:\t00000000000000c1\nCapEff:\t0000000000000001\nCapBnd:\t00000000000000c1\n";
+        let sets = parse_status(status).unwrap();
+        assert_eq!(sets.effective, vec!["CAP_CHOWN"]);
+        assert_eq!(
+            sets.permitted,
+            vec!["CAP_CHOWN", "CAP_SETGID", "CAP_SETUID"]
+        );
+        assert_eq!(sets.bounding, sets.permitted);
+        assert!(sets.inheritable.is_empty());
+        assert!(sets.ambient.is_empty());
+
+        assert!(parse_status("Name:\tsh\n").is_err());
     }
 }
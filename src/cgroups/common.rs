@@ -11,15 +11,40 @@ use nix::unistd::Pid;
 use oci_spec::LinuxResources;
 use procfs::process::Process;
 
+use crate::cgroups::stats::Stats;
+use crate::cgroups::systemd;
 use crate::cgroups::v1;
 use crate::cgroups::v2;
+use crate::container::Warning;
 
 pub const CGROUP_PROCS: &str = "cgroup.procs";
 pub const DEFAULT_CGROUP_ROOT: &str = "/sys/fs/cgroup";
 
 pub trait CgroupManager {
     fn apply(&self, linux_resources: &LinuxResources, pid: Pid) -> Result<()>;
+    /// Adds the process to the existing cgroups without touching their limits, which
+    /// belong to whoever created them
+    fn add_task(&self, pid: Pid) -> Result<()>;
+    /// Changes the limits of the existing cgroups, e.g. of a running container, without
+    /// adding any process to them
+    fn update(&self, linux_resources: &LinuxResources) -> Result<()>;
     fn remove(&self) -> Result<()>;
+    /// Freezes the processes of the container, e.g. for pause, or lets them run again
+    fn freeze(&self, state: FreezerState) -> Result<()>;
+    /// Returns the pids of the processes in the cgroups of the container
+    fn get_all_pids(&self) -> Result<Vec<Pid>>;
+    /// Returns the resource usage of the container which is accounted in its cgroups
+    fn stats(&self) -> Result<Stats>;
+    /// Returns how many processes of the container the OOM killer has killed
+    fn oom_kill_count(&self) -> Result<u64>;
+    /// Returns warnings for the requested resources which cannot be applied on this host
+    fn unsupported(&self, linux_resources: &LinuxResources) -> Vec<Warning>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FreezerState {
+    Frozen,
+    Thawed,
 }
 
 #[derive(Debug)]
@@ -63,6 +88,34 @@ pub fn write_cgroup_file<P: AsRef<Path>, T: ToString>(path: P, data: T) -> Resul
     Ok(())
 }
 
+/// Reads the processes of a cgroup, a cgroup which does not exist has none
+pub fn read_cgroup_procs(cgroup_path: &Path) -> Result<Vec<Pid>> {
+    let procs_path = cgroup_path.join(CGROUP_PROCS);
+    if !procs_path.exists() {
+        return Ok(Vec::new());
+    }
+
+    fs::read_to_string(procs_path)?
+        .lines()
+        .map(|line| Ok(Pid::from_raw(line.trim().parse()?)))
+        .collect()
+}
+
+/// Reads the oom_kill counter of memory.oom_control of v1 or memory.events of v2.
+/// Kernels before 4.13 do not count, which reads as none.
+pub fn read_oom_kill_count(path: &Path) -> Result<u64> {
+    if !path.exists() {
+        return Ok(0);
+    }
+
+    for line in fs::read_to_string(path)?.lines() {
+        if let Some(count) = line.strip_prefix("oom_kill ") {
+            return Ok(count.trim().parse()?);
+        }
+    }
+    Ok(0)
+}
+
 pub fn get_cgroupv1_mount_path(subsystem: &str) -> Result<PathBuf> {
     Process::myself()?
         .mountinfo()?
@@ -73,6 +126,32 @@ pub fn get_cgroupv1_mount_path(subsystem: &str) -> Result<PathBuf> {
 }
 
 pub fn create_cgroup_manager<P: Into<PathBuf>>(cgroup_path: P) -> Result<Box<dyn CgroupManager>> {
+    let cgroup_path = cgroup_path.into();
+    let cgroup2_root = cgroup2_root()?;
+    // a path of the form slice:prefix:name asks for a scope unit of systemd
+    if let Some(unit) = systemd::Unit::parse(&cgroup_path) {
+        log::info!("systemd cgroup manager will be used");
+        return Ok(Box::new(systemd::manager::Manager::new(
+            unit,
+            cgroup2_root,
+        )?));
+    }
+
+    match cgroup2_root {
+        Some(root) => {
+            log::info!("cgroup manager V2 will be used");
+            Ok(Box::new(v2::manager::Manager::new(root, cgroup_path)?))
+        }
+        None => {
+            log::info!("cgroup manager V1 will be used");
+            Ok(Box::new(v1::manager::Manager::new(cgroup_path)?))
+        }
+    }
+}
+
+/// Returns the mount point of the cgroup v2 hierarchy if it is the one to use, or None if
+/// the v1 hierarchies are
+pub fn cgroup2_root() -> Result<Option<PathBuf>> {
     let cgroup_mount = Process::myself()?
         .mountinfo()?
         .into_iter()
@@ -84,33 +163,60 @@ pub fn create_cgroup_manager<P: Into<PathBuf>>(cgroup_path: P) -> Result<Box<dyn
         .find(|m| m.fs_type == "cgroup2");
 
     match (cgroup_mount, cgroup2_mount) {
-        (Some(_), None) => {
-            log::info!("cgroup manager V1 will be used");
-            Ok(Box::new(v1::manager::Manager::new(cgroup_path.into())?))
-        }
-        (None, Some(cgroup2)) => {
-            log::info!("cgroup manager V2 will be used");
-            Ok(Box::new(v2::manager::Manager::new(
-                cgroup2.mount_point,
-                cgroup_path.into(),
-            )?))
-        }
+        (Some(_), None) => Ok(None),
+        (None, Some(cgroup2)) => Ok(Some(cgroup2.mount_point)),
         (Some(_), Some(cgroup2)) => {
             let cgroup_override = env::var("YOUKI_PREFER_CGROUPV2");
             match cgroup_override {
-                Ok(v) if v == "true" => {
-                    log::info!("cgroup manager V2 will be used");
-                    Ok(Box::new(v2::manager::Manager::new(
-                        cgroup2.mount_point,
-                        cgroup_path.into(),
-                    )?))
-                }
-                _ => {
-                    log::info!("cgroup manager V1 will be used");
-                    Ok(Box::new(v1::manager::Manager::new(cgroup_path.into())?))
-                }
+                Ok(v) if v == "true" => Ok(Some(cgroup2.mount_point)),
+                _ => Ok(None),
             }
         }
         _ => bail!("could not find cgroup filesystem"),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cgroups::test::{create_temp_dir, set_fixture};
+
+    #[test]
+    fn test_read_cgroup_procs() {
+        let tmp =
+            create_temp_dir("test_read_cgroup_procs").expect("create temp directory for test");
+        assert!(read_cgroup_procs(&tmp.join("gone")).unwrap().is_empty());
+
+        set_fixture(&tmp, CGROUP_PROCS, "").expect("set fixture for empty cgroup");
+        assert!(read_cgroup_procs(&tmp).unwrap().is_empty());
+
+        set_fixture(&tmp, CGROUP_PROCS, "1234\n5678\n").expect("set fixture for cgroup procs");
+        assert_eq!(
+            read_cgroup_procs(&tmp).unwrap(),
+            vec![Pid::from_raw(1234), Pid::from_raw(5678)]
+        );
+    }
+
+    #[test]
+    fn test_read_oom_kill_count() {
+        let tmp =
+            create_temp_dir("test_read_oom_kill_count").expect("create temp directory for test");
+        assert_eq!(read_oom_kill_count(&tmp.join("memory.events")).unwrap(), 0);
+
+        let path = set_fixture(
+            &tmp,
+            "memory.oom_control",
+            "oom_kill_disable 0\nunder_oom 0\n",
+        )
+        .expect("set fixture for oom control");
+        assert_eq!(read_oom_kill_count(&path).unwrap(), 0);
+
+        let path = set_fixture(
+            &tmp,
+            "memory.events",
+            "low 0\nhigh 0\nmax 3\noom 2\noom_kill 2\n",
+        )
+        .expect("set fixture for memory events");
+        assert_eq!(read_oom_kill_count(&path).unwrap(), 2);
+    }
+}
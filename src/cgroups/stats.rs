@@ -0,0 +1,426 @@
+//! Resource usage statistics of a container, as reported by `youki events --stats`.
+//! The field names follow the ones of runc, which monitoring tools like cadvisor expect.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use nix::unistd::Pid;
+use serde::Serialize;
+
+#[derive(Serialize, Debug, Default)]
+pub struct Stats {
+    pub cpu: CpuStats,
+    pub memory: MemoryStats,
+    pub pids: PidsStats,
+    pub blkio: BlkioStats,
+    /// Usage of huge pages by page size, e.g. 2MB
+    pub hugetlb: BTreeMap<String, HugetlbStats>,
+    pub network_interfaces: Vec<NetworkInterface>,
+}
+
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct CpuStats {
+    pub usage: CpuUsage,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub psi: Option<Psi>,
+}
+
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct CpuUsage {
+    /// Cpu time consumed by the container in nanoseconds
+    pub total: u64,
+}
+
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct MemoryStats {
+    pub usage: MemoryData,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub psi: Option<Psi>,
+}
+
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct MemoryData {
+    /// Memory used by the container in bytes
+    pub usage: u64,
+    /// Limit of the memory of the container in bytes, u64::MAX if there is none
+    pub limit: u64,
+}
+
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct PidsStats {
+    /// Number of tasks in the container
+    pub current: u64,
+}
+
+/// Pressure stall information of a resource, which is only provided by cgroup v2
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct Psi {
+    /// Time in which some tasks stalled on the resource
+    pub some: PsiData,
+    /// Time in which all tasks stalled on the resource at once
+    pub full: PsiData,
+}
+
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct PsiData {
+    /// Percentages of the time stalled over the last 10, 60 and 300 seconds
+    pub avg10: f64,
+    pub avg60: f64,
+    pub avg300: f64,
+    /// Total time stalled in microseconds
+    pub total: u64,
+}
+
+/// Block io statistics, per device and operation
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct BlkioStats {
+    /// Number of bytes transferred to and from the devices
+    #[serde(rename = "ioServiceBytesRecursive")]
+    pub service_bytes: Vec<BlkioStatEntry>,
+    /// Number of io operations on the devices
+    #[serde(rename = "ioServicedRecursive")]
+    pub serviced: Vec<BlkioStatEntry>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub psi: Option<Psi>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct BlkioStatEntry {
+    pub major: u64,
+    pub minor: u64,
+    // Operation, e.g. Read or Write
+    pub op: String,
+    pub value: u64,
+}
+
+/// Usage of huge pages of one size
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct HugetlbStats {
+    /// Huge pages in use in bytes
+    pub usage: u64,
+    /// Highest usage in bytes, which only cgroup v1 records
+    pub max: u64,
+    /// Number of allocations which failed because of the limit
+    pub failcnt: u64,
+}
+
+/// Returns the sizes of huge pages which the hugetlb controller has files for in the
+/// cgroup, e.g. 2MB for hugetlb.2MB.max
+pub fn hugetlb_page_sizes(path: &Path) -> Result<BTreeSet<String>> {
+    let mut sizes = BTreeSet::new();
+    if !path.exists() {
+        return Ok(sizes);
+    }
+    for entry in fs::read_dir(path)? {
+        let name = entry?.file_name();
+        let mut parts = name.to_str().unwrap_or_default().split('.');
+        if let (Some("hugetlb"), Some(size)) = (parts.next(), parts.next()) {
+            sizes.insert(size.to_owned());
+        }
+    }
+    Ok(sizes)
+}
+
+/// Reads a cgroup file which has a single value, where "max" means no limit.
+/// A file which does not exist reads as 0, as not every controller may be enabled.
+pub fn read_value(path: &Path) -> Result<u64> {
+    if !path.exists() {
+        return Ok(0);
+    }
+    let content = fs::read_to_string(path)?;
+    match content.trim() {
+        "max" => Ok(u64::MAX),
+        value => value
+            .parse()
+            .with_context(|| format!("invalid value in {:?}", path)),
+    }
+}
+
+/// Reads a flat keyed cgroup file, e.g. cpu.stat, for the value of the given key
+pub fn read_keyed_value(path: &Path, key: &str) -> Result<u64> {
+    if !path.exists() {
+        return Ok(0);
+    }
+    for line in fs::read_to_string(path)?.lines() {
+        if let Some((k, value)) = line.split_once(' ') {
+            if k == key {
+                return Ok(value.trim().parse()?);
+            }
+        }
+    }
+    Ok(0)
+}
+
+/// Reads a pressure file of cgroup v2, e.g. cpu.pressure, which does not exist unless
+/// the kernel has PSI enabled
+pub fn read_psi(path: &Path) -> Result<Option<Psi>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    parse_psi(&fs::read_to_string(path)?).map(Some)
+}
+
+/// Parses lines like "some avg10=0.00 avg60=0.00 avg300=0.00 total=0"
+fn parse_psi(content: &str) -> Result<Psi> {
+    let mut psi = Psi::default();
+    for line in content.lines() {
+        let mut fields = line.split_whitespace();
+        let data = match fields.next() {
+            Some("some") => &mut psi.some,
+            Some("full") => &mut psi.full,
+            _ => continue,
+        };
+        for field in fields {
+            let (key, value) = field
+                .split_once('=')
+                .ok_or_else(|| anyhow!("invalid field in pressure: {}", field))?;
+            match key {
+                "avg10" => data.avg10 = value.parse()?,
+                "avg60" => data.avg60 = value.parse()?,
+                "avg300" => data.avg300 = value.parse()?,
+                "total" => data.total = value.parse()?,
+                _ => {}
+            }
+        }
+    }
+    Ok(psi)
+}
+
+/// Traffic counters of a network interface in the network namespace of the container
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct NetworkInterface {
+    pub name: String,
+    pub rx_bytes: u64,
+    pub rx_packets: u64,
+    pub rx_errors: u64,
+    pub rx_dropped: u64,
+    pub tx_bytes: u64,
+    pub tx_packets: u64,
+    pub tx_errors: u64,
+    pub tx_dropped: u64,
+}
+
+/// Reads the counters of the interfaces in the network namespace of the given process.
+/// /proc/<pid>/net/dev shows the namespace of the process, so it does not need to be entered.
+pub fn network_interfaces(pid: Pid) -> Result<Vec<NetworkInterface>> {
+    let dev_path = Path::new("/proc").join(pid.to_string()).join("net/dev");
+    let content =
+        fs::read_to_string(&dev_path).with_context(|| format!("failed to read {:?}", dev_path))?;
+    parse_net_dev(&content)
+}
+
+/// Parses the format of /proc/net/dev. The loopback interface is left out, like runc does.
+fn parse_net_dev(content: &str) -> Result<Vec<NetworkInterface>> {
+    let mut interfaces = Vec::new();
+    // the first two lines are headers
+    for line in content.lines().skip(2) {
+        let (name, counters) = line
+            .split_once(':')
+            .ok_or_else(|| anyhow!("invalid line in net/dev: {}", line))?;
+        let name = name.trim();
+        if name == "lo" {
+            continue;
+        }
+
+        let counters = counters
+            .split_whitespace()
+            .map(|counter| counter.parse::<u64>())
+            .collect::<Result<Vec<u64>, _>>()?;
+        if counters.len() < 12 {
+            return Err(anyhow!("invalid line in net/dev: {}", line));
+        }
+        // receive: bytes packets errs drop fifo frame compressed multicast,
+        // transmit: bytes packets errs drop ...
+        interfaces.push(NetworkInterface {
+            name: name.to_owned(),
+            rx_bytes: counters[0],
+            rx_packets: counters[1],
+            rx_errors: counters[2],
+            rx_dropped: counters[3],
+            tx_bytes: counters[8],
+            tx_packets: counters[9],
+            tx_errors: counters[10],
+            tx_dropped: counters[11],
+        });
+    }
+
+    Ok(interfaces)
+}
+
+/// Parses the blkio files of cgroup v1, with lines like "8:0 Read 4096".
+/// A file which does not exist has no entries, as not every kernel provides all of them.
+pub fn read_blkio_entries(path: &Path) -> Result<Vec<BlkioStatEntry>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut entries = Vec::new();
+    for line in fs::read_to_string(path)?.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        // the last line is the sum over all devices, e.g. "Total 4096"
+        if fields.len() != 3 {
+            continue;
+        }
+        let (major, minor) = parse_device(fields[0])?;
+        entries.push(BlkioStatEntry {
+            major,
+            minor,
+            op: fields[1].to_owned(),
+            value: fields[2].parse()?,
+        });
+    }
+
+    Ok(entries)
+}
+
+/// Parses io.stat of cgroup v2, with lines like "8:0 rbytes=4096 wbytes=0 rios=1 wios=0 ..."
+pub fn parse_io_stat(content: &str) -> Result<BlkioStats> {
+    let mut stats = BlkioStats::default();
+    for line in content.lines() {
+        let mut fields = line.split_whitespace();
+        let (major, minor) = match fields.next() {
+            Some(device) => parse_device(device)?,
+            None => continue,
+        };
+
+        for field in fields {
+            let (key, value) = field
+                .split_once('=')
+                .ok_or_else(|| anyhow!("invalid field in io.stat: {}", field))?;
+            let (entries, op) = match key {
+                "rbytes" => (&mut stats.service_bytes, "Read"),
+                "wbytes" => (&mut stats.service_bytes, "Write"),
+                "rios" => (&mut stats.serviced, "Read"),
+                "wios" => (&mut stats.serviced, "Write"),
+                _ => continue,
+            };
+            entries.push(BlkioStatEntry {
+                major,
+                minor,
+                op: op.to_owned(),
+                value: value.parse()?,
+            });
+        }
+    }
+
+    Ok(stats)
+}
+
+fn parse_device(device: &str) -> Result<(u64, u64)> {
+    let (major, minor) = device
+        .split_once(':')
+        .ok_or_else(|| anyhow!("invalid device {}", device))?;
+    Ok((major.parse()?, minor.parse()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cgroups::test::{create_temp_dir, set_fixture};
+
+    #[test]
+    fn test_parse_net_dev() {
+        let content = "Inter-|   Receive                                                |  Transmit
+ face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets errs drop fifo colls carrier compressed
+    lo:    1000      10    0    0    0     0          0         0     1000      10    0    0    0     0       0          0
+  eth0:    2048      16    1    2    0     0          0         0     4096      32    3    4    0     0       0          0
+";
+        let interfaces = parse_net_dev(content).unwrap();
+        assert_eq!(
+            interfaces,
+            vec![NetworkInterface {
+                name: "eth0".to_owned(),
+                rx_bytes: 2048,
+                rx_packets: 16,
+                rx_errors: 1,
+                rx_dropped: 2,
+                tx_bytes: 4096,
+                tx_packets: 32,
+                tx_errors: 3,
+                tx_dropped: 4,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_read_blkio_entries() {
+        let tmp =
+            create_temp_dir("test_read_blkio_entries").expect("create temp directory for test");
+        let path = set_fixture(
+            &tmp,
+            "blkio.throttle.io_service_bytes",
+            "8:0 Read 4096\n8:0 Write 512\nTotal 4608\n",
+        )
+        .expect("set fixture for blkio stats");
+
+        let entries = read_blkio_entries(&path).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(
+            entries[1],
+            BlkioStatEntry {
+                major: 8,
+                minor: 0,
+                op: "Write".to_owned(),
+                value: 512,
+            }
+        );
+        assert!(read_blkio_entries(&tmp.join("blkio.io_serviced"))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_parse_psi() {
+        let content = "some avg10=1.50 avg60=0.25 avg300=0.00 total=12345\nfull avg10=0.00 avg60=0.00 avg300=0.00 total=678\n";
+        let psi = parse_psi(content).unwrap();
+        assert_eq!(psi.some.avg10, 1.5);
+        assert_eq!(psi.some.total, 12345);
+        assert_eq!(psi.full.total, 678);
+    }
+
+    #[test]
+    fn test_read_values() {
+        let tmp = create_temp_dir("test_read_values").expect("create temp directory for test");
+        let max = set_fixture(&tmp, "memory.max", "max\n").expect("set fixture");
+        let current = set_fixture(&tmp, "memory.current", "4096\n").expect("set fixture");
+        let cpu_stat =
+            set_fixture(&tmp, "cpu.stat", "usage_usec 100\nuser_usec 60\n").expect("set fixture");
+
+        assert_eq!(read_value(&max).unwrap(), u64::MAX);
+        assert_eq!(read_value(&current).unwrap(), 4096);
+        assert_eq!(read_value(&tmp.join("pids.current")).unwrap(), 0);
+        assert_eq!(read_keyed_value(&cpu_stat, "user_usec").unwrap(), 60);
+        assert_eq!(read_keyed_value(&cpu_stat, "system_usec").unwrap(), 0);
+    }
+
+    #[test]
+    fn test_hugetlb_page_sizes() {
+        let tmp =
+            create_temp_dir("test_hugetlb_page_sizes").expect("create temp directory for test");
+        for file in &[
+            "hugetlb.2MB.max",
+            "hugetlb.2MB.rsvd.max",
+            "hugetlb.1GB.current",
+        ] {
+            set_fixture(&tmp, file, "0\n").expect("set fixture");
+        }
+        set_fixture(&tmp, "memory.max", "max\n").expect("set fixture");
+
+        let sizes: Vec<String> = hugetlb_page_sizes(&tmp).unwrap().into_iter().collect();
+        assert_eq!(sizes, vec!["1GB".to_owned(), "2MB".to_owned()]);
+        assert!(hugetlb_page_sizes(&tmp.join("gone")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parse_io_stat() {
+        let content = "8:16 rbytes=1024 wbytes=2048 rios=3 wios=4 dbytes=0 dios=0\n";
+        let stats = parse_io_stat(content).unwrap();
+        assert_eq!(stats.service_bytes.len(), 2);
+        assert_eq!(stats.service_bytes[0].op, "Read");
+        assert_eq!(stats.service_bytes[0].value, 1024);
+        assert_eq!(stats.serviced[1].minor, 16);
+        assert_eq!(stats.serviced[1].value, 4);
+    }
+}
@@ -0,0 +1,458 @@
+//! Just enough of the D-Bus protocol to call methods of systemd: authentication as the
+//! user of the process, marshalling of the arguments and waiting for the reply. Signals
+//! and the contents of replies other than errors are not needed, so they are skipped.
+
+use std::env;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use nix::unistd;
+
+const SYSTEM_BUS: &str = "/run/dbus/system_bus_socket";
+/// Time to wait for a reply, after which systemd is taken to be hung
+const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+
+const METHOD_CALL: u8 = 1;
+const METHOD_RETURN: u8 = 2;
+const ERROR: u8 = 3;
+
+const FIELD_PATH: u8 = 1;
+const FIELD_INTERFACE: u8 = 2;
+const FIELD_MEMBER: u8 = 3;
+const FIELD_ERROR_NAME: u8 = 4;
+const FIELD_REPLY_SERIAL: u8 = 5;
+const FIELD_DESTINATION: u8 = 6;
+const FIELD_SIGNATURE: u8 = 8;
+
+/// Value which can be passed to a method, of the types which systemd takes
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Byte(u8),
+    Bool(bool),
+    U32(u32),
+    U64(u64),
+    Str(String),
+    ObjectPath(String),
+    Signature(String),
+    /// array with the signature of its elements, which is needed when it is empty
+    Array(String, Vec<Value>),
+    Struct(Vec<Value>),
+    Variant(Box<Value>),
+}
+
+impl Value {
+    pub fn signature(&self) -> String {
+        match self {
+            Value::Byte(_) => "y".to_owned(),
+            Value::Bool(_) => "b".to_owned(),
+            Value::U32(_) => "u".to_owned(),
+            Value::U64(_) => "t".to_owned(),
+            Value::Str(_) => "s".to_owned(),
+            Value::ObjectPath(_) => "o".to_owned(),
+            Value::Signature(_) => "g".to_owned(),
+            Value::Array(element, _) => format!("a{}", element),
+            Value::Struct(fields) => {
+                let fields: String = fields.iter().map(Value::signature).collect();
+                format!("({})", fields)
+            }
+            Value::Variant(_) => "v".to_owned(),
+        }
+    }
+
+    fn alignment(&self) -> usize {
+        match self {
+            Value::Byte(_) | Value::Signature(_) | Value::Variant(_) => 1,
+            Value::U64(_) | Value::Struct(_) => 8,
+            _ => 4,
+        }
+    }
+
+    fn marshal(&self, buf: &mut Vec<u8>) {
+        pad(buf, self.alignment());
+        match self {
+            Value::Byte(value) => buf.push(*value),
+            Value::Bool(value) => buf.extend_from_slice(&(*value as u32).to_le_bytes()),
+            Value::U32(value) => buf.extend_from_slice(&value.to_le_bytes()),
+            Value::U64(value) => buf.extend_from_slice(&value.to_le_bytes()),
+            Value::Str(value) | Value::ObjectPath(value) => {
+                buf.extend_from_slice(&(value.len() as u32).to_le_bytes());
+                buf.extend_from_slice(value.as_bytes());
+                buf.push(0);
+            }
+            Value::Signature(value) => {
+                buf.push(value.len() as u8);
+                buf.extend_from_slice(value.as_bytes());
+                buf.push(0);
+            }
+            Value::Array(element, values) => {
+                let length_at = buf.len();
+                buf.extend_from_slice(&[0; 4]);
+                // the padding up to the first element does not count into the length
+                pad(buf, element_alignment(element));
+                let start = buf.len();
+                for value in values {
+                    value.marshal(buf);
+                }
+                let length = (buf.len() - start) as u32;
+                buf[length_at..length_at + 4].copy_from_slice(&length.to_le_bytes());
+            }
+            Value::Struct(fields) => {
+                for field in fields {
+                    field.marshal(buf);
+                }
+            }
+            Value::Variant(value) => {
+                Value::Signature(value.signature()).marshal(buf);
+                value.marshal(buf);
+            }
+        }
+    }
+}
+
+/// Alignment of the type which a signature starts with
+fn element_alignment(signature: &str) -> usize {
+    match signature.as_bytes().first() {
+        Some(b'y') | Some(b'g') | Some(b'v') => 1,
+        Some(b't') | Some(b'x') | Some(b'd') | Some(b'(') | Some(b'{') => 8,
+        _ => 4,
+    }
+}
+
+fn pad(buf: &mut Vec<u8>, alignment: usize) {
+    while !buf.len().is_multiple_of(alignment) {
+        buf.push(0);
+    }
+}
+
+/// Connection to a message bus
+pub struct Connection {
+    stream: UnixStream,
+    serial: u32,
+}
+
+impl Connection {
+    /// Connects to the bus of the system as root, or to the bus of the session of the user,
+    /// whose systemd instance manages the cgroups of an unprivileged user
+    pub fn connect() -> Result<Self> {
+        let path = if unistd::geteuid().is_root() {
+            PathBuf::from(SYSTEM_BUS)
+        } else {
+            session_bus()?
+        };
+        let stream = UnixStream::connect(&path)
+            .with_context(|| format!("failed to connect to D-Bus at {:?}", path))?;
+        stream.set_read_timeout(Some(REPLY_TIMEOUT))?;
+
+        let mut connection = Self { stream, serial: 0 };
+        connection.authenticate()?;
+        connection.call(
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus",
+            "Hello",
+            &[],
+        )?;
+        Ok(connection)
+    }
+
+    /// Authenticates as the user of the process, whose credentials the bus gets from the socket
+    fn authenticate(&mut self) -> Result<()> {
+        let uid = unistd::geteuid().to_string();
+        let hex: String = uid.bytes().map(|b| format!("{:02x}", b)).collect();
+        self.stream
+            .write_all(format!("\0AUTH EXTERNAL {}\r\n", hex).as_bytes())?;
+        let reply = self.read_line()?;
+        if !reply.starts_with("OK ") {
+            bail!("D-Bus rejected the authentication: {}", reply.trim());
+        }
+        self.stream.write_all(b"BEGIN\r\n")?;
+        Ok(())
+    }
+
+    fn read_line(&mut self) -> Result<String> {
+        let mut line = Vec::new();
+        let mut byte = [0u8; 1];
+        while !line.ends_with(b"\r\n") {
+            self.stream.read_exact(&mut byte)?;
+            line.push(byte[0]);
+        }
+        Ok(String::from_utf8_lossy(&line).into_owned())
+    }
+
+    /// Calls a method and waits for it to return, turning an error reply into an error
+    /// which starts with the name of the D-Bus error
+    pub fn call(
+        &mut self,
+        destination: &str,
+        path: &str,
+        interface: &str,
+        member: &str,
+        args: &[Value],
+    ) -> Result<()> {
+        self.serial += 1;
+        let serial = self.serial;
+        let message = method_call(serial, destination, path, interface, member, args);
+        self.stream.write_all(&message)?;
+
+        loop {
+            let reply = self
+                .read_message()
+                .with_context(|| format!("failed to receive the reply to {}", member))?;
+            if reply.reply_serial != Some(serial) {
+                continue;
+            }
+            match reply.typ {
+                METHOD_RETURN => return Ok(()),
+                ERROR => bail!(
+                    "{}: {}",
+                    reply.error_name.unwrap_or_default(),
+                    reply.error_message.unwrap_or_default()
+                ),
+                typ => bail!("unexpected D-Bus message of type {}", typ),
+            }
+        }
+    }
+
+    fn read_message(&mut self) -> Result<Message> {
+        // the fixed part of the header and the length of the array of header fields
+        let mut fixed = [0u8; 16];
+        self.stream.read_exact(&mut fixed)?;
+        if fixed[0] != b'l' {
+            bail!("only little endian D-Bus messages are supported");
+        }
+        let body_length = u32::from_le_bytes([fixed[4], fixed[5], fixed[6], fixed[7]]) as usize;
+        let fields_length =
+            u32::from_le_bytes([fixed[12], fixed[13], fixed[14], fixed[15]]) as usize;
+        // the body starts at a multiple of 8
+        let header_rest = fields_length + (8 - (16 + fields_length) % 8) % 8;
+        let mut rest = vec![0u8; header_rest + body_length];
+        self.stream.read_exact(&mut rest)?;
+
+        let mut message = parse_header_fields(fixed[1], &rest[..fields_length])?;
+        if message.typ == ERROR && message.signature.as_deref().unwrap_or("").starts_with('s') {
+            let mut reader = Reader::new(&rest[header_rest..], 0);
+            message.error_message = Some(reader.string()?);
+        }
+        Ok(message)
+    }
+}
+
+/// Address of the bus of the session, which is only supported as a path
+fn session_bus() -> Result<PathBuf> {
+    if let Ok(address) = env::var("DBUS_SESSION_BUS_ADDRESS") {
+        return match address.strip_prefix("unix:path=") {
+            Some(path) => Ok(PathBuf::from(path.split(',').next().unwrap_or(path))),
+            None => bail!(
+                "D-Bus address {} is not supported, only unix:path= is",
+                address
+            ),
+        };
+    }
+    match env::var_os("XDG_RUNTIME_DIR") {
+        Some(runtime_dir) => Ok(PathBuf::from(runtime_dir).join("bus")),
+        None => bail!("neither DBUS_SESSION_BUS_ADDRESS nor XDG_RUNTIME_DIR is set"),
+    }
+}
+
+fn method_call(
+    serial: u32,
+    destination: &str,
+    path: &str,
+    interface: &str,
+    member: &str,
+    args: &[Value],
+) -> Vec<u8> {
+    let mut body = Vec::new();
+    for arg in args {
+        arg.marshal(&mut body);
+    }
+    let signature: String = args.iter().map(Value::signature).collect();
+
+    let mut fields = vec![
+        (FIELD_PATH, Value::ObjectPath(path.to_owned())),
+        (FIELD_INTERFACE, Value::Str(interface.to_owned())),
+        (FIELD_MEMBER, Value::Str(member.to_owned())),
+        (FIELD_DESTINATION, Value::Str(destination.to_owned())),
+    ];
+    if !signature.is_empty() {
+        fields.push((FIELD_SIGNATURE, Value::Signature(signature)));
+    }
+    let fields = fields
+        .into_iter()
+        .map(|(code, value)| {
+            Value::Struct(vec![Value::Byte(code), Value::Variant(Box::new(value))])
+        })
+        .collect();
+
+    let mut message = vec![b'l', METHOD_CALL, 0, 1];
+    message.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    message.extend_from_slice(&serial.to_le_bytes());
+    Value::Array("(yv)".to_owned(), fields).marshal(&mut message);
+    pad(&mut message, 8);
+    message.extend_from_slice(&body);
+    message
+}
+
+#[derive(Debug, Default)]
+struct Message {
+    typ: u8,
+    reply_serial: Option<u32>,
+    error_name: Option<String>,
+    error_message: Option<String>,
+    signature: Option<String>,
+}
+
+fn parse_header_fields(typ: u8, fields: &[u8]) -> Result<Message> {
+    let mut message = Message {
+        typ,
+        ..Default::default()
+    };
+    // the offsets are counted from the start of the message, whose fields start at 16
+    let mut reader = Reader::new(fields, 16);
+    while !reader.is_empty() {
+        reader.align(8);
+        if reader.is_empty() {
+            break;
+        }
+        let code = reader.byte()?;
+        let signature = reader.signature()?;
+        match signature.as_str() {
+            "s" | "o" => {
+                let value = reader.string()?;
+                if code == FIELD_ERROR_NAME {
+                    message.error_name = Some(value);
+                }
+            }
+            "g" => {
+                let value = reader.signature()?;
+                if code == FIELD_SIGNATURE {
+                    message.signature = Some(value);
+                }
+            }
+            "u" => {
+                let value = reader.u32()?;
+                if code == FIELD_REPLY_SERIAL {
+                    message.reply_serial = Some(value);
+                }
+            }
+            _ => bail!(
+                "unexpected type {} of D-Bus header field {}",
+                signature,
+                code
+            ),
+        }
+    }
+    Ok(message)
+}
+
+/// Reads values from a message, aligned relative to the start of the message
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], offset: usize) -> Self {
+        Self {
+            data,
+            pos: 0,
+            offset,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn align(&mut self, alignment: usize) {
+        while !(self.offset + self.pos).is_multiple_of(alignment) {
+            self.pos += 1;
+        }
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.pos + n > self.data.len() {
+            bail!("D-Bus message is cut short");
+        }
+        let bytes = &self.data[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        self.align(4);
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let length = self.u32()? as usize;
+        let bytes = self.take(length + 1)?;
+        Ok(String::from_utf8_lossy(&bytes[..length]).into_owned())
+    }
+
+    fn signature(&mut self) -> Result<String> {
+        let length = self.byte()? as usize;
+        let bytes = self.take(length + 1)?;
+        Ok(String::from_utf8_lossy(&bytes[..length]).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_marshal() {
+        let property = Value::Struct(vec![
+            Value::Str("PIDs".to_owned()),
+            Value::Variant(Box::new(Value::Array("u".to_owned(), vec![Value::U32(7)]))),
+        ]);
+        assert_eq!(property.signature(), "(sv)");
+
+        // one byte before the array, so that its alignment shows
+        let mut buf = vec![0];
+        Value::Array("(sv)".to_owned(), vec![property]).marshal(&mut buf);
+        let expected: Vec<u8> = [
+            &[0, 0, 0, 0][..],
+            &24u32.to_le_bytes(),
+            &4u32.to_le_bytes(),
+            b"PIDs\0",
+            &[2, b'a', b'u', 0],
+            &[0, 0, 0],
+            &4u32.to_le_bytes(),
+            &7u32.to_le_bytes(),
+        ]
+        .concat();
+        assert_eq!(buf, expected);
+    }
+
+    #[test]
+    fn test_empty_array() {
+        let mut buf = Vec::new();
+        Value::Array("(sa(sv))".to_owned(), vec![]).marshal(&mut buf);
+        assert_eq!(buf, vec![0, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_parse_header_fields() {
+        // an error reply to serial 3, as the bus sends it
+        let call = method_call(3, "d", "/p", "i", "m", &[Value::Str("x".to_owned())]);
+        let fields_length = u32::from_le_bytes([call[12], call[13], call[14], call[15]]) as usize;
+        let message = parse_header_fields(METHOD_CALL, &call[16..16 + fields_length]).unwrap();
+        assert_eq!(message.signature.as_deref(), Some("s"));
+        assert_eq!(message.reply_serial, None);
+        // the body starts at a multiple of 8
+        let body_at = 16 + fields_length + (8 - (16 + fields_length) % 8) % 8;
+        assert_eq!(&call[body_at..body_at + 4], &1u32.to_le_bytes());
+        assert_eq!(call.len(), body_at + 6);
+    }
+}
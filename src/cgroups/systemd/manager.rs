@@ -0,0 +1,302 @@
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use nix::unistd::{self, Pid};
+use oci_spec::LinuxResources;
+
+use super::dbus::{Connection, Value};
+use super::Unit;
+use crate::cgroups::common::{CgroupManager, FreezerState};
+use crate::cgroups::stats::Stats;
+use crate::cgroups::v1;
+use crate::cgroups::v2;
+use crate::cgroups::v2::cpu::Cpu;
+use crate::container::Warning;
+
+const DESTINATION: &str = "org.freedesktop.systemd1";
+const PATH: &str = "/org/freedesktop/systemd1";
+const INTERFACE: &str = "org.freedesktop.systemd1.Manager";
+const NO_SUCH_UNIT: &str = "org.freedesktop.systemd1.NoSuchUnit";
+/// Time for systemd to move the process into the scope, which it does in a job of its own
+const SCOPE_TIMEOUT: Duration = Duration::from_secs(5);
+const DEFAULT_CPU_PERIOD: u64 = 100_000;
+
+/// Manager of the cgroup which systemd creates, which writes the limits that systemd has
+/// no properties for
+enum Fs {
+    V1(v1::manager::Manager),
+    V2(v2::manager::Manager),
+}
+
+pub struct Manager {
+    unit: Unit,
+    fs: Fs,
+}
+
+impl Manager {
+    /// Manages the scope in the cgroup v2 hierarchy at the given root, or in the v1
+    /// hierarchies if there is none
+    pub fn new(unit: Unit, cgroup2_root: Option<PathBuf>) -> Result<Self> {
+        let cgroup_path = unit.cgroup_path(unistd::geteuid());
+        let fs = match cgroup2_root {
+            Some(root) => Fs::V2(v2::manager::Manager::new(root, cgroup_path)?),
+            None => Fs::V1(v1::manager::Manager::new(cgroup_path)?),
+        };
+        Ok(Self { unit, fs })
+    }
+
+    fn fs(&self) -> &dyn CgroupManager {
+        match &self.fs {
+            Fs::V1(manager) => manager,
+            Fs::V2(manager) => manager,
+        }
+    }
+
+    /// Properties of the scope, with the limits which systemd manages itself
+    fn properties(&self, linux_resources: &LinuxResources, pid: Pid) -> Vec<Value> {
+        let cgroup2 = matches!(self.fs, Fs::V2(_));
+        let mut properties = vec![
+            property(
+                "Description",
+                Value::Str(format!("youki container {}", self.unit.name)),
+            ),
+            property("Slice", Value::Str(self.unit.slice.clone())),
+            property(
+                "PIDs",
+                Value::Array("u".to_owned(), vec![Value::U32(pid.as_raw() as u32)]),
+            ),
+            property("DefaultDependencies", Value::Bool(false)),
+            // the container may create cgroups below its own, e.g. for a nested runtime
+            property("Delegate", Value::Bool(true)),
+            property("CPUAccounting", Value::Bool(true)),
+            property("MemoryAccounting", Value::Bool(true)),
+            property("TasksAccounting", Value::Bool(true)),
+        ];
+        properties.push(if cgroup2 {
+            property("IOAccounting", Value::Bool(true))
+        } else {
+            property("BlockIOAccounting", Value::Bool(true))
+        });
+        properties.extend(self.resource_properties(linux_resources));
+        properties
+    }
+
+    /// Properties for the limits which systemd manages itself
+    fn resource_properties(&self, linux_resources: &LinuxResources) -> Vec<Value> {
+        let cgroup2 = matches!(self.fs, Fs::V2(_));
+        let mut properties = Vec::new();
+        if let Some(limit) = linux_resources.memory.as_ref().and_then(|m| m.limit) {
+            if limit > 0 {
+                let name = if cgroup2 { "MemoryMax" } else { "MemoryLimit" };
+                properties.push(property(name, Value::U64(limit as u64)));
+            }
+        }
+        if let Some(cpu) = &linux_resources.cpu {
+            if let Some(shares) = cpu.shares.filter(|shares| *shares > 0) {
+                properties.push(if cgroup2 {
+                    property(
+                        "CPUWeight",
+                        Value::U64(Cpu::convert_shares_to_cgroup2(shares)),
+                    )
+                } else {
+                    property("CPUShares", Value::U64(shares))
+                });
+            }
+            if let Some(quota) = cpu.quota.filter(|quota| *quota > 0) {
+                let period = cpu.period.filter(|period| *period > 0);
+                if let Some(period) = period {
+                    properties.push(property("CPUQuotaPeriodUSec", Value::U64(period)));
+                }
+                properties.push(property(
+                    "CPUQuotaPerSecUSec",
+                    Value::U64(quota_per_sec(
+                        quota as u64,
+                        period.unwrap_or(DEFAULT_CPU_PERIOD),
+                    )),
+                ));
+            }
+        }
+        if let Some(limit) = linux_resources.pids.as_ref().map(|pids| pids.limit) {
+            if limit > 0 {
+                properties.push(property("TasksMax", Value::U64(limit as u64)));
+            }
+        }
+        properties
+    }
+
+    fn call(&self, method: &str, args: &[Value]) -> Result<()> {
+        Connection::connect()?.call(DESTINATION, PATH, INTERFACE, method, args)
+    }
+
+    /// Waits until systemd has moved the process into the cgroup of the scope
+    fn wait_for_scope(&self, pid: Pid) -> Result<()> {
+        let cgroup = format!("/proc/{}/cgroup", pid);
+        let suffix = format!("/{}", self.unit.name);
+        let start = Instant::now();
+        loop {
+            let content = fs::read_to_string(&cgroup)?;
+            if content.lines().any(|line| line.ends_with(&suffix)) {
+                return Ok(());
+            }
+            if start.elapsed() > SCOPE_TIMEOUT {
+                bail!("systemd did not move {} into {}", pid, self.unit.name);
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+impl CgroupManager for Manager {
+    fn apply(&self, linux_resources: &LinuxResources, pid: Pid) -> Result<()> {
+        log::debug!(
+            "start transient unit {} in {}",
+            self.unit.name,
+            self.unit.slice
+        );
+        let properties = self.properties(linux_resources, pid);
+        self.call(
+            "StartTransientUnit",
+            &[
+                Value::Str(self.unit.name.clone()),
+                Value::Str("replace".to_owned()),
+                Value::Array("(sv)".to_owned(), properties),
+                Value::Array("(sa(sv))".to_owned(), vec![]),
+            ],
+        )?;
+        self.wait_for_scope(pid)?;
+
+        // the rest of the limits are written into the cgroup of the scope
+        match &self.fs {
+            Fs::V1(manager) => manager.apply(linux_resources, pid),
+            Fs::V2(manager) => manager.apply_controllers(linux_resources),
+        }
+    }
+
+    fn update(&self, linux_resources: &LinuxResources) -> Result<()> {
+        let properties = self.resource_properties(linux_resources);
+        if !properties.is_empty() {
+            log::debug!("set properties of unit {}", self.unit.name);
+            // changed at runtime only, as the transient unit is gone after a reboot anyway
+            self.call(
+                "SetUnitProperties",
+                &[
+                    Value::Str(self.unit.name.clone()),
+                    Value::Bool(true),
+                    Value::Array("(sv)".to_owned(), properties),
+                ],
+            )?;
+        }
+        self.fs().update(linux_resources)
+    }
+
+    fn add_task(&self, pid: Pid) -> Result<()> {
+        self.call(
+            "AttachProcessesToUnit",
+            &[
+                Value::Str(self.unit.name.clone()),
+                Value::Str(String::new()),
+                Value::Array("u".to_owned(), vec![Value::U32(pid.as_raw() as u32)]),
+            ],
+        )
+    }
+
+    fn remove(&self) -> Result<()> {
+        log::debug!("stop transient unit {}", self.unit.name);
+        let stopped = self.call(
+            "StopUnit",
+            &[
+                Value::Str(self.unit.name.clone()),
+                Value::Str("replace".to_owned()),
+            ],
+        );
+        match stopped {
+            Ok(()) => {}
+            // the unit is gone along with its last process
+            Err(e) if e.to_string().starts_with(NO_SUCH_UNIT) => {}
+            Err(e) => return Err(e),
+        }
+        // systemd removes the cgroups it knows about, but not those of the v1 hierarchies
+        // which it does not manage, e.g. hugetlb
+        if let Err(e) = self.fs().remove() {
+            log::debug!("cgroups of {} are already removed: {}", self.unit.name, e);
+        }
+        Ok(())
+    }
+
+    fn freeze(&self, state: FreezerState) -> Result<()> {
+        self.fs().freeze(state)
+    }
+
+    fn get_all_pids(&self) -> Result<Vec<Pid>> {
+        self.fs().get_all_pids()
+    }
+
+    fn stats(&self) -> Result<Stats> {
+        self.fs().stats()
+    }
+
+    fn oom_kill_count(&self) -> Result<u64> {
+        self.fs().oom_kill_count()
+    }
+
+    fn unsupported(&self, linux_resources: &LinuxResources) -> Vec<Warning> {
+        self.fs().unsupported(linux_resources)
+    }
+}
+
+fn property(name: &str, value: Value) -> Value {
+    Value::Struct(vec![
+        Value::Str(name.to_owned()),
+        Value::Variant(Box::new(value)),
+    ])
+}
+
+/// Converts a quota per period into the quota per second of systemd, which it only
+/// accepts in steps of 10ms
+fn quota_per_sec(quota: u64, period: u64) -> u64 {
+    let per_sec = quota * 1_000_000 / period;
+    per_sec.div_ceil(10_000) * 10_000
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quota_per_sec() {
+        assert_eq!(quota_per_sec(50_000, 100_000), 500_000);
+        assert_eq!(quota_per_sec(12_345, 100_000), 130_000);
+        assert_eq!(quota_per_sec(1_000, 1_000_000), 10_000);
+    }
+
+    #[test]
+    fn test_properties() {
+        let manager = Manager {
+            unit: Unit::parse(std::path::Path::new("system.slice:youki:abc")).unwrap(),
+            fs: Fs::V2(
+                v2::manager::Manager::new(PathBuf::from("/sys/fs/cgroup"), PathBuf::from("/"))
+                    .unwrap(),
+            ),
+        };
+        let resources: LinuxResources = serde_json::from_value(serde_json::json!({
+            "cpu": { "quota": 50000 },
+            "pids": { "limit": 100 }
+        }))
+        .unwrap();
+        let properties = manager.properties(&resources, Pid::from_raw(7));
+        assert!(properties.contains(&property("Slice", Value::Str("system.slice".to_owned()))));
+        assert!(properties.contains(&property("CPUQuotaPerSecUSec", Value::U64(500_000))));
+        assert!(properties.contains(&property("TasksMax", Value::U64(100))));
+        assert!(properties.contains(&property("IOAccounting", Value::Bool(true))));
+        assert!(!properties
+            .iter()
+            .any(|p| matches!(p, Value::Struct(f) if f[0] == Value::Str("MemoryMax".to_owned()))));
+
+        let updated = manager.resource_properties(&resources);
+        assert_eq!(updated.len(), 2);
+        assert!(updated.contains(&property("TasksMax", Value::U64(100))));
+    }
+}
@@ -0,0 +1,142 @@
+//! Leaves the creation of the cgroups of a container to systemd, which Kubernetes expects
+//! with its systemd cgroup driver. The container gets a transient scope unit whose limits
+//! systemd knows about, so that it does not reset them when it reloads its units.
+//!
+//! Such a container is asked for with a cgroups path of the form slice:prefix:name, which
+//! becomes the unit prefix-name.scope in the given slice.
+
+mod dbus;
+pub mod manager;
+
+use std::path::{Path, PathBuf};
+
+use nix::unistd::{self, Uid};
+
+/// Scope unit of a container, given by a cgroups path of the form slice:prefix:name
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unit {
+    pub slice: String,
+    pub name: String,
+}
+
+impl Unit {
+    /// Parses a cgroups path, which is a cgroupfs path unless it is of the form of systemd
+    pub fn parse(cgroups_path: &Path) -> Option<Self> {
+        let path = cgroups_path.to_str()?;
+        if path.contains('/') {
+            return None;
+        }
+        let parts: Vec<&str> = path.split(':').collect();
+        let (slice, prefix, name) = match parts.as_slice() {
+            [slice, prefix, name] if !name.is_empty() => (*slice, *prefix, *name),
+            _ => return None,
+        };
+        let slice = match slice {
+            "" => default_slice().to_owned(),
+            slice if slice.ends_with(".slice") => slice.to_owned(),
+            _ => return None,
+        };
+        let name = match prefix {
+            "" => format!("{}.scope", name),
+            prefix => format!("{}-{}.scope", prefix, name),
+        };
+        Some(Self { slice, name })
+    }
+
+    /// Path of the cgroup of the unit relative to the cgroup root. The units of an
+    /// unprivileged user are in the cgroup of the systemd instance of the user.
+    pub fn cgroup_path(&self, uid: Uid) -> PathBuf {
+        let mut path = if uid.is_root() {
+            PathBuf::from("/")
+        } else {
+            PathBuf::from(format!(
+                "/user.slice/user-{uid}.slice/user@{uid}.service",
+                uid = uid
+            ))
+        };
+        path.push(expand_slice(&self.slice));
+        path.push(&self.name);
+        path
+    }
+}
+
+/// Whether a cgroups path asks for the systemd cgroup manager
+pub fn is_systemd_path(cgroups_path: &Path) -> bool {
+    Unit::parse(cgroups_path).is_some()
+}
+
+/// Cgroups path of a container whose spec has none
+pub fn default_cgroups_path(container_id: &str) -> PathBuf {
+    PathBuf::from(format!("{}:youki:{}", default_slice(), container_id))
+}
+
+fn default_slice() -> &'static str {
+    if unistd::geteuid().is_root() {
+        "system.slice"
+    } else {
+        "user.slice"
+    }
+}
+
+/// Turns the name of a slice into the path of its cgroup below the root of its manager,
+/// where a dash stands for a parent, e.g. a-b.slice is in a.slice
+fn expand_slice(slice: &str) -> PathBuf {
+    let mut path = PathBuf::new();
+    let name = slice.trim_end_matches(".slice");
+    if name == "-" {
+        return path;
+    }
+    let mut parent = String::new();
+    for component in name.split('-') {
+        if !parent.is_empty() {
+            parent.push('-');
+        }
+        parent.push_str(component);
+        path.push(format!("{}.slice", parent));
+    }
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_unit() {
+        assert_eq!(
+            Unit::parse(Path::new("kubepods-besteffort.slice:cri-containerd:abc")),
+            Some(Unit {
+                slice: "kubepods-besteffort.slice".to_owned(),
+                name: "cri-containerd-abc.scope".to_owned(),
+            })
+        );
+        assert_eq!(
+            Unit::parse(Path::new("system.slice::abc")).unwrap().name,
+            "abc.scope"
+        );
+        assert!(Unit::parse(Path::new(":youki:abc")).is_some());
+        assert!(Unit::parse(Path::new("/youki/abc")).is_none());
+        assert!(Unit::parse(Path::new("youki/abc")).is_none());
+        assert!(Unit::parse(Path::new("system:youki:abc")).is_none());
+        assert!(Unit::parse(Path::new("system.slice:youki:")).is_none());
+        assert!(Unit::parse(Path::new("system.slice:youki")).is_none());
+    }
+
+    #[test]
+    fn test_cgroup_path() {
+        let unit = Unit::parse(Path::new("kubepods-burstable.slice:cri:abc")).unwrap();
+        assert_eq!(
+            unit.cgroup_path(Uid::from_raw(0)),
+            PathBuf::from("/kubepods.slice/kubepods-burstable.slice/cri-abc.scope")
+        );
+        assert_eq!(
+            unit.cgroup_path(Uid::from_raw(1000)),
+            PathBuf::from("/user.slice/user-1000.slice/user@1000.service/kubepods.slice/kubepods-burstable.slice/cri-abc.scope")
+        );
+        let root = Unit::parse(Path::new("-.slice:youki:abc")).unwrap();
+        assert_eq!(
+            root.cgroup_path(Uid::from_raw(0)),
+            PathBuf::from("/youki-abc.scope")
+        );
+    }
+}
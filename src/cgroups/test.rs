@@ -91,6 +91,7 @@ impl LinuxCpuBuilder {
             resource: LinuxCpu {
                 shares: None,
                 quota: None,
+                burst: None,
                 period: None,
                 realtime_runtime: None,
                 realtime_period: None,
@@ -110,6 +111,11 @@ impl LinuxCpuBuilder {
         self
     }
 
+    pub fn with_burst(mut self, burst: u64) -> Self {
+        self.resource.burst = Some(burst);
+        self
+    }
+
     pub fn with_period(mut self, period: u64) -> Self {
         self.resource.period = Some(period);
         self
@@ -5,6 +5,7 @@ use std::{
 
 use crate::cgroups::{
     common::{self, CGROUP_PROCS},
+    stats::{self, BlkioStats},
     v1::Controller,
 };
 use oci_spec::{LinuxBlockIo, LinuxResources};
@@ -13,6 +14,10 @@ const CGROUP_BLKIO_THROTTLE_READ_BPS: &str = "blkio.throttle.read_bps_device";
 const CGROUP_BLKIO_THROTTLE_WRITE_BPS: &str = "blkio.throttle.write_bps_device";
 const CGROUP_BLKIO_THROTTLE_READ_IOPS: &str = "blkio.throttle.read_iops_device";
 const CGROUP_BLKIO_THROTTLE_WRITE_IOPS: &str = "blkio.throttle.write_iops_device";
+const CGROUP_BLKIO_SERVICE_BYTES: &str = "blkio.io_service_bytes_recursive";
+const CGROUP_BLKIO_SERVICED: &str = "blkio.io_serviced_recursive";
+const CGROUP_BLKIO_THROTTLE_SERVICE_BYTES: &str = "blkio.throttle.io_service_bytes";
+const CGROUP_BLKIO_THROTTLE_SERVICED: &str = "blkio.throttle.io_serviced";
 
 pub struct Blkio {}
 
@@ -20,7 +25,7 @@ impl Controller for Blkio {
     fn apply(
         linux_resources: &LinuxResources,
         cgroup_root: &Path,
-        pid: nix::unistd::Pid,
+        pid: Option<nix::unistd::Pid>,
     ) -> anyhow::Result<()> {
         log::debug!("Apply blkio cgroup config");
         fs::create_dir_all(cgroup_root)?;
@@ -29,37 +34,61 @@ impl Controller for Blkio {
             Self::apply(cgroup_root, blkio)?;
         }
 
-        common::write_cgroup_file(cgroup_root.join(CGROUP_PROCS), pid)?;
+        if let Some(pid) = pid {
+            common::write_cgroup_file(cgroup_root.join(CGROUP_PROCS), pid)?;
+        }
         Ok(())
     }
 }
 
 impl Blkio {
+    /// Reads the io of the cgroup per device. The counters of the CFQ scheduler are only
+    /// filled in with it, otherwise the ones of the throttling policy are used, like runc does.
+    pub fn stats(cgroup_root: &Path) -> anyhow::Result<BlkioStats> {
+        let service_bytes =
+            stats::read_blkio_entries(&cgroup_root.join(CGROUP_BLKIO_SERVICE_BYTES))?;
+        if !service_bytes.is_empty() {
+            return Ok(BlkioStats {
+                service_bytes,
+                serviced: stats::read_blkio_entries(&cgroup_root.join(CGROUP_BLKIO_SERVICED))?,
+                psi: None,
+            });
+        }
+
+        Ok(BlkioStats {
+            service_bytes: stats::read_blkio_entries(
+                &cgroup_root.join(CGROUP_BLKIO_THROTTLE_SERVICE_BYTES),
+            )?,
+            serviced: stats::read_blkio_entries(&cgroup_root.join(CGROUP_BLKIO_THROTTLE_SERVICED))?,
+            psi: None,
+        })
+    }
+
     fn apply(root_path: &Path, blkio: &LinuxBlockIo) -> anyhow::Result<()> {
         for trbd in &blkio.blkio_throttle_read_bps_device {
             common::write_cgroup_file_str(
-                &root_path.join(CGROUP_BLKIO_THROTTLE_READ_BPS),
+                root_path.join(CGROUP_BLKIO_THROTTLE_READ_BPS),
                 &format!("{}:{} {}", trbd.major, trbd.minor, trbd.rate),
             )?;
         }
 
         for twbd in &blkio.blkio_throttle_write_bps_device {
             common::write_cgroup_file_str(
-                &root_path.join(CGROUP_BLKIO_THROTTLE_WRITE_BPS),
+                root_path.join(CGROUP_BLKIO_THROTTLE_WRITE_BPS),
                 &format!("{}:{} {}", twbd.major, twbd.minor, twbd.rate),
             )?;
         }
 
         for trid in &blkio.blkio_throttle_read_iops_device {
             common::write_cgroup_file_str(
-                &root_path.join(CGROUP_BLKIO_THROTTLE_READ_IOPS),
+                root_path.join(CGROUP_BLKIO_THROTTLE_READ_IOPS),
                 &format!("{}:{} {}", trid.major, trid.minor, trid.rate),
             )?;
         }
 
         for twid in &blkio.blkio_throttle_write_iops_device {
             common::write_cgroup_file_str(
-                &root_path.join(CGROUP_BLKIO_THROTTLE_WRITE_IOPS),
+                root_path.join(CGROUP_BLKIO_THROTTLE_WRITE_IOPS),
                 &format!("{}:{} {}", twid.major, twid.minor, twid.rate),
             )?;
         }
@@ -6,5 +6,7 @@ use nix::unistd::Pid;
 use oci_spec::LinuxResources;
 
 pub trait Controller {
-    fn apply(linux_resources: &LinuxResources, cgroup_root: &Path, pid: Pid) -> Result<()>;
+    /// Writes the limits into the cgroup, which is created if needed, and adds the process
+    /// to it if one is given, which an update of the limits does not
+    fn apply(linux_resources: &LinuxResources, cgroup_root: &Path, pid: Option<Pid>) -> Result<()>;
 }
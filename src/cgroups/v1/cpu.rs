@@ -17,14 +17,16 @@ const CGROUP_CPU_RT_PERIOD: &str = "cpu.rt_period_us";
 pub struct Cpu {}
 
 impl Controller for Cpu {
-    fn apply(linux_resources: &LinuxResources, cgroup_root: &Path, pid: Pid) -> Result<()> {
+    fn apply(linux_resources: &LinuxResources, cgroup_root: &Path, pid: Option<Pid>) -> Result<()> {
         log::debug!("Apply Cpu cgroup config");
         fs::create_dir_all(cgroup_root)?;
         if let Some(cpu) = &linux_resources.cpu {
             Self::apply(cgroup_root, cpu)?;
         }
 
-        common::write_cgroup_file(cgroup_root.join(CGROUP_PROCS), pid)?;
+        if let Some(pid) = pid {
+            common::write_cgroup_file(cgroup_root.join(CGROUP_PROCS), pid)?;
+        }
         Ok(())
     }
 }
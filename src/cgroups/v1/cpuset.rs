@@ -14,7 +14,7 @@ const CGROUP_CPUSET_MEMS: &str = "cpuset.mems";
 pub struct CpuSet {}
 
 impl Controller for CpuSet {
-    fn apply(linux_resources: &LinuxResources, cgroup_path: &Path, pid: Pid) -> Result<()> {
+    fn apply(linux_resources: &LinuxResources, cgroup_path: &Path, pid: Option<Pid>) -> Result<()> {
         log::debug!("Apply CpuSet cgroup config");
         fs::create_dir_all(cgroup_path)?;
 
@@ -25,7 +25,9 @@ impl Controller for CpuSet {
             Self::apply(cgroup_path, cpuset)?;
         }
 
-        common::write_cgroup_file(cgroup_path.join(CGROUP_PROCS), pid)?;
+        if let Some(pid) = pid {
+            common::write_cgroup_file(cgroup_path.join(CGROUP_PROCS), pid)?;
+        }
         Ok(())
     }
 }
@@ -34,11 +36,11 @@ impl CpuSet {
     fn apply(cgroup_path: &Path, cpuset: &LinuxCpu) -> Result<()> {
         if let Some(cpus) = &cpuset.cpus {
             common::write_cgroup_file_str(cgroup_path.join(CGROUP_CPUSET_CPUS), cpus)?;
-        } 
+        }
 
         if let Some(mems) = &cpuset.mems {
             common::write_cgroup_file_str(cgroup_path.join(CGROUP_CPUSET_MEMS), mems)?;
-        } 
+        }
 
         Ok(())
     }
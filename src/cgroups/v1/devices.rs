@@ -4,15 +4,15 @@ use anyhow::Result;
 use nix::unistd::Pid;
 
 use crate::cgroups::common::{self, CGROUP_PROCS};
-use crate::{cgroups::v1::Controller, rootfs::default_devices};
+use crate::{cgroups::v1::Controller, devices::default_devices};
 use oci_spec::{LinuxDeviceCgroup, LinuxDeviceType, LinuxResources};
 
 pub struct Devices {}
 
 impl Controller for Devices {
-    fn apply(linux_resources: &LinuxResources, cgroup_root: &Path, pid: Pid) -> Result<()> {
+    fn apply(linux_resources: &LinuxResources, cgroup_root: &Path, pid: Option<Pid>) -> Result<()> {
         log::debug!("Apply Devices cgroup config");
-        create_dir_all(&cgroup_root)?;
+        create_dir_all(cgroup_root)?;
 
         for d in &linux_resources.devices {
             Self::apply_device(d, cgroup_root)?;
@@ -24,10 +24,12 @@ impl Controller for Devices {
         ]
         .concat()
         {
-            Self::apply_device(&d, &cgroup_root)?;
+            Self::apply_device(&d, cgroup_root)?;
         }
 
-        common::write_cgroup_file(cgroup_root.join(CGROUP_PROCS), pid)?;
+        if let Some(pid) = pid {
+            common::write_cgroup_file(cgroup_root.join(CGROUP_PROCS), pid)?;
+        }
         Ok(())
     }
 }
@@ -115,8 +117,8 @@ mod tests {
             set_fixture(&tmp, "devices.allow", "").expect("create allowed devices list");
             set_fixture(&tmp, "devices.deny", "").expect("create denied devices list");
 
-            Devices::apply_device(&d, &tmp).expect("Apply default device");
-            println!("Device: {}", d.to_string());
+            Devices::apply_device(d, &tmp).expect("Apply default device");
+            println!("Device: {}", d);
             if d.allow {
                 let allowed_content =
                     read_to_string(tmp.join("devices.allow")).expect("read to string");
@@ -167,8 +169,8 @@ mod tests {
             set_fixture(&tmp, "devices.allow", "").expect("create allowed devices list");
             set_fixture(&tmp, "devices.deny", "").expect("create denied devices list");
 
-            Devices::apply_device(&d, &tmp).expect("Apply default device");
-            println!("Device: {}", d.to_string());
+            Devices::apply_device(d, &tmp).expect("Apply default device");
+            println!("Device: {}", d);
             if d.allow {
                 let allowed_content =
                     read_to_string(tmp.join("devices.allow")).expect("read to string");
@@ -201,10 +203,10 @@ mod tests {
         fn property_test_apply_multiple_devices(devices: Vec<LinuxDeviceCgroup>) -> bool {
             let tmp = create_temp_dir("property_test_apply_multiple_devices").expect("create temp directory for test");
             devices.iter()
-                .map(|device| {
+                .all(|device| {
                     set_fixture(&tmp, "devices.allow", "").expect("create allowed devices list");
                     set_fixture(&tmp, "devices.deny", "").expect("create denied devices list");
-                    Devices::apply_device(&device, &tmp).expect("Apply default device");
+                    Devices::apply_device(device, &tmp).expect("Apply default device");
                     if device.allow {
                         let allowed_content =
                             read_to_string(tmp.join("devices.allow")).expect("read to string");
@@ -215,7 +217,6 @@ mod tests {
                         denied_content == device.to_string()
                     }
                 })
-                .all(|is_ok| is_ok)
         }
     }
 }
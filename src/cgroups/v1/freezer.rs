@@ -0,0 +1,73 @@
+use std::{fs, path::Path, thread, time::Duration};
+
+use anyhow::{bail, Result};
+use nix::unistd::Pid;
+
+use crate::cgroups::common::{self, FreezerState, CGROUP_PROCS};
+use crate::cgroups::v1::Controller;
+use oci_spec::LinuxResources;
+
+const CGROUP_FREEZER_STATE: &str = "freezer.state";
+const FREEZER_STATE_FROZEN: &str = "FROZEN";
+const FREEZER_STATE_THAWED: &str = "THAWED";
+/// The kernel freezes the tasks one by one, in the meantime the state is FREEZING
+const FREEZE_ATTEMPTS: u32 = 1000;
+
+pub struct Freezer {}
+
+impl Controller for Freezer {
+    fn apply(
+        _linux_resources: &LinuxResources,
+        cgroup_root: &Path,
+        pid: Option<Pid>,
+    ) -> Result<()> {
+        log::debug!("Apply Freezer cgroup config");
+        fs::create_dir_all(cgroup_root)?;
+
+        if let Some(pid) = pid {
+            common::write_cgroup_file(cgroup_root.join(CGROUP_PROCS), pid)?;
+        }
+        Ok(())
+    }
+}
+
+impl Freezer {
+    /// Freezes or thaws the tasks of the cgroup and waits until the kernel is done
+    pub fn apply_state(cgroup_root: &Path, state: FreezerState) -> Result<()> {
+        let target = match state {
+            FreezerState::Frozen => FREEZER_STATE_FROZEN,
+            FreezerState::Thawed => FREEZER_STATE_THAWED,
+        };
+        let state_path = cgroup_root.join(CGROUP_FREEZER_STATE);
+        for _ in 0..FREEZE_ATTEMPTS {
+            common::write_cgroup_file_str(&state_path, target)?;
+            if fs::read_to_string(&state_path)?.trim() == target {
+                return Ok(());
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        // a task which does not freeze must not leave the others frozen
+        if let FreezerState::Frozen = state {
+            common::write_cgroup_file_str(&state_path, FREEZER_STATE_THAWED)?;
+        }
+        bail!("cgroup {:?} did not become {}", cgroup_root, target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cgroups::test::setup;
+
+    #[test]
+    fn test_apply_state() {
+        let (tmp, state) = setup("test_apply_freezer_state", CGROUP_FREEZER_STATE);
+
+        Freezer::apply_state(&tmp, FreezerState::Frozen).expect("freeze cgroup");
+        assert_eq!(fs::read_to_string(&state).unwrap(), FREEZER_STATE_FROZEN);
+
+        Freezer::apply_state(&tmp, FreezerState::Thawed).expect("thaw cgroup");
+        assert_eq!(fs::read_to_string(&state).unwrap(), FREEZER_STATE_THAWED);
+    }
+}
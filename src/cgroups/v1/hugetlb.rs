@@ -15,7 +15,7 @@ impl Controller for Hugetlb {
     fn apply(
         linux_resources: &LinuxResources,
         cgroup_root: &std::path::Path,
-        pid: nix::unistd::Pid,
+        pid: Option<nix::unistd::Pid>,
     ) -> anyhow::Result<()> {
         log::debug!("Apply Hugetlb cgroup config");
         fs::create_dir_all(cgroup_root)?;
@@ -24,7 +24,9 @@ impl Controller for Hugetlb {
             Self::apply(cgroup_root, hugetlb)?
         }
 
-        common::write_cgroup_file(cgroup_root.join(CGROUP_PROCS), pid)?;
+        if let Some(pid) = pid {
+            common::write_cgroup_file(cgroup_root.join(CGROUP_PROCS), pid)?;
+        }
         Ok(())
     }
 }
@@ -44,8 +46,8 @@ impl Hugetlb {
         }
 
         common::write_cgroup_file(
-            &root_path.join(format!("hugetlb.{}.limit_in_bytes", hugetlb.page_size)),
-            &hugetlb.limit,
+            root_path.join(format!("hugetlb.{}.limit_in_bytes", hugetlb.page_size)),
+            hugetlb.limit,
         )?;
         Ok(())
     }
@@ -2,22 +2,27 @@ use std::fs;
 use std::path::Path;
 use std::{collections::HashMap, path::PathBuf};
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 use nix::unistd::Pid;
 
 use procfs::process::Process;
 
 use super::{
-    blkio::Blkio, cpu::Cpu, cpuset::CpuSet, devices::Devices, hugetlb::Hugetlb, memory::Memory,
-    network_classifier::NetworkClassifier, network_priority::NetworkPriority, pids::Pids,
-    Controller, ControllerType,
+    blkio::Blkio, cpu::Cpu, cpuset::CpuSet, devices::Devices, freezer::Freezer, hugetlb::Hugetlb,
+    memory::Memory, network_classifier::NetworkClassifier, network_priority::NetworkPriority,
+    pids::Pids, Controller, ControllerType,
 };
 
-use crate::cgroups::common::CGROUP_PROCS;
+use crate::cgroups::common::{self, FreezerState, CGROUP_PROCS};
+use crate::cgroups::stats::{self, Stats};
+use crate::container::Warning;
 use crate::utils;
 use crate::{cgroups::common::CgroupManager, utils::PathBufExt};
 use oci_spec::LinuxResources;
 
+const CGROUP_CPUACCT_USAGE: &str = "cpuacct.usage";
+const CGROUP_PIDS_CURRENT: &str = "pids.current";
+
 const CONTROLLERS: &[ControllerType] = &[
     ControllerType::Cpu,
     ControllerType::CpuSet,
@@ -28,6 +33,7 @@ const CONTROLLERS: &[ControllerType] = &[
     ControllerType::Blkio,
     ControllerType::NetworkPriority,
     ControllerType::NetworkClassifier,
+    ControllerType::Freezer,
 ];
 
 pub struct Manager {
@@ -38,10 +44,14 @@ impl Manager {
     pub fn new(cgroup_path: PathBuf) -> Result<Self> {
         let mut subsystems = HashMap::<String, PathBuf>::new();
         for subsystem in CONTROLLERS.iter().map(|c| c.to_string()) {
-            subsystems.insert(
-                subsystem.to_owned(),
-                Self::get_subsystem_path(&cgroup_path, &subsystem)?,
-            );
+            // a hierarchy may not be mounted on the host, e.g. hugetlb, in which case
+            // the subsystem is skipped and reported by unsupported if it is requested
+            match Self::get_subsystem_path(&cgroup_path, &subsystem) {
+                Ok(path) => {
+                    subsystems.insert(subsystem.to_owned(), path);
+                }
+                Err(e) => log::warn!("{} cgroup is not available: {}", subsystem, e),
+            }
         }
 
         Ok(Manager { subsystems })
@@ -74,49 +84,214 @@ impl Manager {
                 }
                 m.mount_point.ends_with(subsystem)
             })
-            .unwrap();
+            .ok_or_else(|| anyhow!("could not find mountpoint for {}", subsystem))?;
 
         let cgroup = Process::myself()?
             .cgroups()?
             .into_iter()
             .find(|c| c.controllers.contains(&subsystem.to_owned()))
-            .unwrap();
+            .ok_or_else(|| anyhow!("could not find cgroup of {}", subsystem))?;
 
         let p = if cgroup_path.to_string_lossy().into_owned().is_empty() {
             mount
                 .mount_point
                 .join_absolute_path(Path::new(&cgroup.pathname))?
         } else if cgroup_path.is_absolute() {
-            mount.mount_point.join_absolute_path(&cgroup_path)?
+            mount.mount_point.join_absolute_path(cgroup_path)?
         } else {
             mount.mount_point.join(cgroup_path)
         };
 
         Ok(p)
     }
+
+    /// Returns the field of the spec which needs the subsystem, if it is requested
+    fn requested_field(subsystem: &str, linux_resources: &LinuxResources) -> Option<&'static str> {
+        let requested = match subsystem {
+            "cpu" => linux_resources.cpu.as_ref().is_some_and(|cpu| {
+                cpu.shares.is_some()
+                    || cpu.quota.is_some()
+                    || cpu.period.is_some()
+                    || cpu.realtime_runtime.is_some()
+                    || cpu.realtime_period.is_some()
+            }),
+            "cpuset" => linux_resources
+                .cpu
+                .as_ref()
+                .is_some_and(|cpu| cpu.cpus.is_some() || cpu.mems.is_some()),
+            "devices" => !linux_resources.devices.is_empty(),
+            "hugetlb" => !linux_resources.hugepage_limits.is_empty(),
+            "memory" => linux_resources.memory.is_some(),
+            "pids" => linux_resources.pids.is_some(),
+            "blkio" => linux_resources.block_io.is_some(),
+            "net_prio" => linux_resources
+                .network
+                .as_ref()
+                .is_some_and(|network| !network.priorities.is_empty()),
+            "net_cls" => linux_resources
+                .network
+                .as_ref()
+                .is_some_and(|network| network.class_id.is_some()),
+            _ => false,
+        };
+
+        if !requested {
+            return None;
+        }
+
+        Some(match subsystem {
+            "cpu" | "cpuset" => "linux.resources.cpu",
+            "devices" => "linux.resources.devices",
+            "hugetlb" => "linux.resources.hugepageLimits",
+            "memory" => "linux.resources.memory",
+            "pids" => "linux.resources.pids",
+            "blkio" => "linux.resources.blockIO",
+            "net_prio" => "linux.resources.network.priorities",
+            _ => "linux.resources.network.classID",
+        })
+    }
 }
 
-impl CgroupManager for Manager {
-    fn apply(&self, linux_resources: &LinuxResources, pid: Pid) -> Result<()> {
+impl Manager {
+    /// Applies the resources to the cgroup of every subsystem, and adds the process if one
+    /// is given
+    fn apply_subsystems(&self, linux_resources: &LinuxResources, pid: Option<Pid>) -> Result<()> {
         for subsys in &self.subsystems {
             match subsys.0.as_str() {
-                "cpu" => Cpu::apply(linux_resources, &subsys.1, pid)?,
-                "cpuset" => CpuSet::apply(linux_resources, &subsys.1, pid)?,
-                "devices" => Devices::apply(linux_resources, &subsys.1, pid)?,
-                "hugetlb" => Hugetlb::apply(linux_resources, &subsys.1, pid)?,
-                "memory" => Memory::apply(linux_resources, &subsys.1, pid)?,
-                "pids" => Pids::apply(linux_resources, &subsys.1, pid)?,
-                "blkio" => Blkio::apply(linux_resources, &subsys.1, pid)?,
-                "net_prio" => NetworkPriority::apply(linux_resources, &subsys.1, pid)?,
-                "net_cls" => NetworkClassifier::apply(linux_resources, &subsys.1, pid)?,
+                "cpu" => Cpu::apply(linux_resources, subsys.1, pid)?,
+                "cpuset" => CpuSet::apply(linux_resources, subsys.1, pid)?,
+                "devices" => Devices::apply(linux_resources, subsys.1, pid)?,
+                "hugetlb" => Hugetlb::apply(linux_resources, subsys.1, pid)?,
+                "memory" => Memory::apply(linux_resources, subsys.1, pid)?,
+                "pids" => Pids::apply(linux_resources, subsys.1, pid)?,
+                "blkio" => Blkio::apply(linux_resources, subsys.1, pid)?,
+                "net_prio" => NetworkPriority::apply(linux_resources, subsys.1, pid)?,
+                "net_cls" => NetworkClassifier::apply(linux_resources, subsys.1, pid)?,
+                "freezer" => Freezer::apply(linux_resources, subsys.1, pid)?,
                 _ => unreachable!("every subsystem should have an associated controller"),
             }
         }
 
         Ok(())
     }
+}
+
+impl CgroupManager for Manager {
+    fn apply(&self, linux_resources: &LinuxResources, pid: Pid) -> Result<()> {
+        self.apply_subsystems(linux_resources, Some(pid))
+    }
+
+    fn update(&self, linux_resources: &LinuxResources) -> Result<()> {
+        for cgroup_path in self.subsystems.values() {
+            if !cgroup_path.exists() {
+                bail!("cgroup {:?} does not exist", cgroup_path);
+            }
+        }
+        self.apply_subsystems(linux_resources, None)
+    }
+
+    fn add_task(&self, pid: Pid) -> Result<()> {
+        for cgroup_path in self.subsystems.values() {
+            if !cgroup_path.exists() {
+                bail!("cgroup {:?} does not exist", cgroup_path);
+            }
+            common::write_cgroup_file(cgroup_path.join(CGROUP_PROCS), pid)?;
+        }
+        Ok(())
+    }
+
+    fn unsupported(&self, linux_resources: &LinuxResources) -> Vec<Warning> {
+        let mut warnings: Vec<Warning> = CONTROLLERS
+            .iter()
+            .map(|c| c.to_string())
+            .filter(|subsystem| !self.subsystems.contains_key(subsystem))
+            .filter_map(|subsystem| {
+                Self::requested_field(&subsystem, linux_resources).map(|field| {
+                    Warning::new(
+                        field,
+                        &format!("{} cgroup hierarchy is not mounted", subsystem),
+                    )
+                })
+            })
+            .collect();
+
+        if let (Some(memory), Some(memory_path)) =
+            (&linux_resources.memory, self.subsystems.get("memory"))
+        {
+            if memory.swap.is_some() && !Memory::swap_accounting_enabled(memory_path) {
+                warnings.push(Warning::new(
+                    "linux.resources.memory.swap",
+                    "swap accounting is not enabled on this host",
+                ));
+            }
+        }
+
+        warnings
+    }
+
+    fn get_all_pids(&self) -> Result<Vec<Pid>> {
+        let mut pids = Vec::new();
+        for cgroup_path in self.subsystems.values() {
+            for pid in common::read_cgroup_procs(cgroup_path)? {
+                if !pids.contains(&pid) {
+                    pids.push(pid);
+                }
+            }
+        }
+        Ok(pids)
+    }
+
+    fn stats(&self) -> Result<Stats> {
+        let mut stats = Stats::default();
+        // cpuacct is mounted along with cpu on most hosts
+        if let Some(cpu_path) = self.subsystems.get("cpu") {
+            stats.cpu.usage.total = stats::read_value(&cpu_path.join(CGROUP_CPUACCT_USAGE))?;
+        }
+        if let Some(memory_path) = self.subsystems.get("memory") {
+            stats.memory.usage = Memory::stats(memory_path)?;
+        }
+        if let Some(pids_path) = self.subsystems.get("pids") {
+            stats.pids.current = stats::read_value(&pids_path.join(CGROUP_PIDS_CURRENT))?;
+        }
+        if let Some(blkio_path) = self.subsystems.get("blkio") {
+            stats.blkio = Blkio::stats(blkio_path)?;
+        }
+        if let Some(hugetlb_path) = self.subsystems.get("hugetlb") {
+            for size in stats::hugetlb_page_sizes(hugetlb_path)? {
+                let file = |name: &str| hugetlb_path.join(format!("hugetlb.{}.{}", size, name));
+                let entry = stats::HugetlbStats {
+                    usage: stats::read_value(&file("usage_in_bytes"))?,
+                    max: stats::read_value(&file("max_usage_in_bytes"))?,
+                    failcnt: stats::read_value(&file("failcnt"))?,
+                };
+                stats.hugetlb.insert(size, entry);
+            }
+        }
+        Ok(stats)
+    }
+
+    fn oom_kill_count(&self) -> Result<u64> {
+        match self.subsystems.get("memory") {
+            Some(memory_path) => Memory::oom_kill_count(memory_path),
+            None => Ok(0),
+        }
+    }
+
+    fn freeze(&self, state: FreezerState) -> Result<()> {
+        match self.subsystems.get("freezer") {
+            Some(cgroup_path) if cgroup_path.exists() => Freezer::apply_state(cgroup_path, state),
+            Some(cgroup_path) => bail!("cgroup {:?} does not exist", cgroup_path),
+            None => bail!("freezer cgroup is not available"),
+        }
+    }
 
     fn remove(&self) -> Result<()> {
+        // frozen processes would not die before they are thawed
+        if let Some(freezer) = self.subsystems.get("freezer") {
+            if freezer.exists() {
+                let _ = Freezer::apply_state(freezer, FreezerState::Thawed);
+            }
+        }
         for cgroup_path in &self.subsystems {
             if cgroup_path.1.exists() {
                 log::debug!("remove cgroup {:?}", cgroup_path.1);
@@ -135,3 +310,30 @@ impl CgroupManager for Manager {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cgroups::test::LinuxCpuBuilder;
+    use oci_spec::LinuxPids;
+
+    #[test]
+    fn test_requested_field() {
+        let mut resources = LinuxResources::default();
+        for subsystem in CONTROLLERS.iter().map(|c| c.to_string()) {
+            assert_eq!(Manager::requested_field(&subsystem, &resources), None);
+        }
+
+        resources.pids = Some(LinuxPids { limit: 10 });
+        resources.cpu = Some(LinuxCpuBuilder::new().with_cpus("0".to_owned()).build());
+        assert_eq!(
+            Manager::requested_field("pids", &resources),
+            Some("linux.resources.pids")
+        );
+        assert_eq!(
+            Manager::requested_field("cpuset", &resources),
+            Some("linux.resources.cpu")
+        );
+        assert_eq!(Manager::requested_field("cpu", &resources), None);
+    }
+}
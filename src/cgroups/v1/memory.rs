@@ -8,6 +8,7 @@ use anyhow::{Result, *};
 use nix::{errno::Errno, unistd::Pid};
 
 use crate::cgroups::common::{self, CGROUP_PROCS};
+use crate::cgroups::stats::MemoryData;
 use crate::cgroups::v1::Controller;
 use oci_spec::{LinuxMemory, LinuxResources};
 
@@ -25,14 +26,14 @@ const CGROUP_KERNEL_TCP_MEMORY_LIMIT: &str = "memory.kmem.tcp.limit_in_bytes";
 pub struct Memory {}
 
 impl Controller for Memory {
-    fn apply(linux_resources: &LinuxResources, cgroup_root: &Path, pid: Pid) -> Result<()> {
+    fn apply(linux_resources: &LinuxResources, cgroup_root: &Path, pid: Option<Pid>) -> Result<()> {
         log::debug!("Apply Memory cgroup config");
-        create_dir_all(&cgroup_root)?;
+        create_dir_all(cgroup_root)?;
 
         if let Some(memory) = &linux_resources.memory {
             let reservation = memory.reservation.unwrap_or(0);
 
-            Self::apply(&memory, cgroup_root)?;
+            Self::apply(memory, cgroup_root)?;
 
             if reservation != 0 {
                 common::write_cgroup_file(
@@ -76,12 +77,36 @@ impl Controller for Memory {
             }
         }
 
-        common::write_cgroup_file(cgroup_root.join(CGROUP_PROCS), pid)?;
+        if let Some(pid) = pid {
+            common::write_cgroup_file(cgroup_root.join(CGROUP_PROCS), pid)?;
+        }
         Ok(())
     }
 }
 
 impl Memory {
+    /// Returns how many processes of the cgroup the OOM killer has killed
+    pub fn oom_kill_count(cgroup_root: &Path) -> Result<u64> {
+        common::read_oom_kill_count(&cgroup_root.join(CGROUP_MEMORY_OOM_CONTROL))
+    }
+
+    /// Returns the memory usage and limit of the cgroup
+    pub fn stats(cgroup_root: &Path) -> Result<MemoryData> {
+        Ok(MemoryData {
+            usage: Self::get_memory_usage(cgroup_root)?,
+            limit: Self::get_memory_limit(cgroup_root)?.max(0) as u64,
+        })
+    }
+
+    /// Swap can only be limited if the kernel accounts for it, in which case every
+    /// cgroup in the memory hierarchy has the memsw files
+    pub fn swap_accounting_enabled(cgroup_root: &Path) -> bool {
+        cgroup_root
+            .ancestors()
+            .find(|p| p.exists())
+            .is_some_and(|p| p.join(CGROUP_MEMORY_SWAP_LIMIT).exists())
+    }
+
     fn get_memory_usage(cgroup_root: &Path) -> Result<u64> {
         let path = cgroup_root.join(CGROUP_MEMORY_USAGE);
         let mut contents = String::new();
@@ -184,6 +209,11 @@ impl Memory {
             return Ok(());
         }
 
+        if !Self::swap_accounting_enabled(cgroup_root) {
+            log::warn!("swap accounting is not enabled, skip setting swap limit");
+            return Ok(());
+        }
+
         common::write_cgroup_file(cgroup_root.join(CGROUP_MEMORY_SWAP_LIMIT), swap)?;
         Ok(())
     }
@@ -367,7 +397,7 @@ mod tests {
             };
 
             let pid = Pid::from_raw(pid_int);
-            let result = <Memory as Controller>::apply(&linux_resources, &tmp, pid);
+            let result = <Memory as Controller>::apply(&linux_resources, &tmp, Some(pid));
 
             if result.is_err() {
                 if let Some(swappiness) = memory_limits.swappiness {
@@ -4,6 +4,7 @@ mod controller_type;
 mod cpu;
 mod cpuset;
 mod devices;
+mod freezer;
 mod hugetlb;
 pub mod manager;
 mod memory;
@@ -11,15 +11,17 @@ use oci_spec::{LinuxNetwork, LinuxResources};
 pub struct NetworkClassifier {}
 
 impl Controller for NetworkClassifier {
-    fn apply(linux_resources: &LinuxResources, cgroup_root: &Path, pid: Pid) -> Result<()> {
+    fn apply(linux_resources: &LinuxResources, cgroup_root: &Path, pid: Option<Pid>) -> Result<()> {
         log::debug!("Apply NetworkClassifier cgroup config");
-        create_dir_all(&cgroup_root)?;
+        create_dir_all(cgroup_root)?;
 
         if let Some(network) = linux_resources.network.as_ref() {
             Self::apply(cgroup_root, network)?;
         }
 
-        common::write_cgroup_file(cgroup_root.join(CGROUP_PROCS), pid)?;
+        if let Some(pid) = pid {
+            common::write_cgroup_file(cgroup_root.join(CGROUP_PROCS), pid)?;
+        }
         Ok(())
     }
 }
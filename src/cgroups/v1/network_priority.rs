@@ -11,15 +11,17 @@ use oci_spec::{LinuxNetwork, LinuxResources};
 pub struct NetworkPriority {}
 
 impl Controller for NetworkPriority {
-    fn apply(linux_resources: &LinuxResources, cgroup_root: &Path, pid: Pid) -> Result<()> {
+    fn apply(linux_resources: &LinuxResources, cgroup_root: &Path, pid: Option<Pid>) -> Result<()> {
         log::debug!("Apply NetworkPriority cgroup config");
-        create_dir_all(&cgroup_root)?;
+        create_dir_all(cgroup_root)?;
 
         if let Some(network) = linux_resources.network.as_ref() {
             Self::apply(cgroup_root, network)?;
         }
 
-        common::write_cgroup_file(cgroup_root.join(CGROUP_PROCS), pid)?;
+        if let Some(pid) = pid {
+            common::write_cgroup_file(cgroup_root.join(CGROUP_PROCS), pid)?;
+        }
         Ok(())
     }
 }
@@ -27,7 +29,7 @@ impl Controller for NetworkPriority {
 impl NetworkPriority {
     fn apply(root_path: &Path, network: &LinuxNetwork) -> Result<()> {
         let priorities: String = network.priorities.iter().map(|p| p.to_string()).collect();
-        common::write_cgroup_file_str(root_path.join("net_prio.ifpriomap"), &priorities.trim())?;
+        common::write_cgroup_file_str(root_path.join("net_prio.ifpriomap"), priorities.trim())?;
 
         Ok(())
     }
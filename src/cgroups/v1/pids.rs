@@ -17,7 +17,7 @@ impl Controller for Pids {
     fn apply(
         linux_resources: &LinuxResources,
         cgroup_root: &std::path::Path,
-        pid: nix::unistd::Pid,
+        pid: Option<nix::unistd::Pid>,
     ) -> anyhow::Result<()> {
         log::debug!("Apply pids cgroup config");
         fs::create_dir_all(cgroup_root)?;
@@ -26,7 +26,9 @@ impl Controller for Pids {
             Self::apply(cgroup_root, pids)?;
         }
 
-        common::write_cgroup_file(cgroup_root.join(CGROUP_PROCS), pid)?;
+        if let Some(pid) = pid {
+            common::write_cgroup_file(cgroup_root.join(CGROUP_PROCS), pid)?;
+        }
         Ok(())
     }
 }
@@ -39,7 +41,7 @@ impl Pids {
             "max".to_string()
         };
 
-        common::write_cgroup_file_str(&root_path.join("pids.max"), &limit)?;
+        common::write_cgroup_file_str(root_path.join("pids.max"), &limit)?;
         Ok(())
     }
 }
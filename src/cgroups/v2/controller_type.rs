@@ -1,3 +1,5 @@
+use std::fmt::Display;
+
 pub enum ControllerType {
     Cpu,
     CpuSet,
@@ -7,15 +9,17 @@ pub enum ControllerType {
     Pids,
 }
 
-impl ToString for ControllerType {
-    fn to_string(&self) -> String {
-        match self {
-            Self::Cpu => "cpu".into(),
-            Self::CpuSet => "cpuset".into(),
-            Self::Io => "io".into(),
-            Self::Memory => "memory".into(),
-            Self::HugeTlb => "hugetlb".into(),
-            Self::Pids => "pids".into(),
-        }
+impl Display for ControllerType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let print = match self {
+            Self::Cpu => "cpu",
+            Self::CpuSet => "cpuset",
+            Self::Io => "io",
+            Self::Memory => "memory",
+            Self::HugeTlb => "hugetlb",
+            Self::Pids => "pids",
+        };
+
+        write!(f, "{}", print)
     }
 }
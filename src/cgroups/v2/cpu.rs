@@ -8,8 +8,11 @@ use super::controller::Controller;
 
 const CGROUP_CPU_WEIGHT: &str = "cpu.weight";
 const CGROUP_CPU_MAX: &str = "cpu.max";
+const CGROUP_CPU_MAX_BURST: &str = "cpu.max.burst";
 const DEFAULT_PERIOD: &str = "100000";
 const UNRESTRICTED_QUOTA: &str = "max";
+const MIN_SHARES: u64 = 2;
+const MAX_SHARES: u64 = 262144;
 
 pub struct Cpu {}
 
@@ -29,45 +32,57 @@ impl Cpu {
             return Err(anyhow!("realtime is not supported on cgroup v2 yet"));
         }
 
-        if let Some(mut shares) = cpu.shares {
-            shares = Self::convert_shares_to_cgroup2(shares);
-            if shares != 0 {
+        if let Some(shares) = cpu.shares {
+            let weight = Self::convert_shares_to_cgroup2(shares);
+            if weight != 0 {
                 // will result in Erno 34 (numerical result out of range) otherwise
-                common::write_cgroup_file(path.join(CGROUP_CPU_WEIGHT), shares)?;
+                common::write_cgroup_file(path.join(CGROUP_CPU_WEIGHT), weight)?;
             }
         }
 
-        // if quota is unrestricted set to 'max'
-        let mut quota_string = UNRESTRICTED_QUOTA.to_owned();
-        if let Some(quota) = cpu.quota {
-            if quota > 0 {
-                quota_string = quota.to_string();
+        // without either, the limit which is set already is kept, e.g. on update
+        if cpu.quota.is_some() || cpu.period.is_some() {
+            // if quota is unrestricted set to 'max'
+            let mut quota_string = UNRESTRICTED_QUOTA.to_owned();
+            if let Some(quota) = cpu.quota {
+                if quota > 0 {
+                    quota_string = quota.to_string();
+                }
             }
-        }
 
-        let mut period_string: String = DEFAULT_PERIOD.to_owned();
-        if let Some(period) = cpu.period {
-            if period > 0 {
-                period_string = period.to_string();
+            let mut period_string: String = DEFAULT_PERIOD.to_owned();
+            if let Some(period) = cpu.period {
+                if period > 0 {
+                    period_string = period.to_string();
+                }
             }
+
+            // format is 'quota period'
+            // the kernel default is 'max 100000'
+            // 250000 250000 -> 1 CPU worth of runtime every 250ms
+            // 10000 50000 -> 20% of one CPU every 50ms
+            let max = quota_string + " " + &period_string;
+            common::write_cgroup_file_str(path.join(CGROUP_CPU_MAX), &max)?;
         }
 
-        // format is 'quota period'
-        // the kernel default is 'max 100000'
-        // 250000 250000 -> 1 CPU worth of runtime every 250ms
-        // 10000 50000 -> 20% of one CPU every 50ms
-        let max = quota_string + " " + &period_string;
-        common::write_cgroup_file_str(path.join(CGROUP_CPU_MAX), &max)?;
+        // after the quota, as the burst may not exceed it
+        if let Some(burst) = cpu.burst {
+            common::write_cgroup_file(path.join(CGROUP_CPU_MAX_BURST), burst)?;
+        }
 
         Ok(())
     }
 
-    fn convert_shares_to_cgroup2(shares: u64) -> u64 {
+    /// Maps cpu shares of cgroup v1 from [2, 262144] linearly onto cpu.weight of cgroup v2
+    /// from [1, 10000], like runc does. Shares out of the range are clamped to it, as the
+    /// kernel does for cgroup v1. 0 means that no weight is set.
+    pub fn convert_shares_to_cgroup2(shares: u64) -> u64 {
         if shares == 0 {
             return 0;
         }
 
-        1 + ((shares - 2) * 9999) / 262142
+        let shares = shares.clamp(MIN_SHARES, MAX_SHARES);
+        1 + ((shares - MIN_SHARES) * 9999) / (MAX_SHARES - MIN_SHARES)
     }
 
     fn is_realtime_requested(cpu: &LinuxCpu) -> bool {
@@ -194,6 +209,60 @@ mod tests {
         assert_eq!(content, format!("{} {}", QUOTA, PERIOD));
     }
 
+    #[test]
+    fn test_convert_shares_to_cgroup2() {
+        assert_eq!(Cpu::convert_shares_to_cgroup2(0), 0);
+        assert_eq!(Cpu::convert_shares_to_cgroup2(1), 1);
+        assert_eq!(Cpu::convert_shares_to_cgroup2(2), 1);
+        assert_eq!(Cpu::convert_shares_to_cgroup2(1024), 39);
+        assert_eq!(Cpu::convert_shares_to_cgroup2(262144), 10000);
+        assert_eq!(Cpu::convert_shares_to_cgroup2(1 << 20), 10000);
+    }
+
+    #[test]
+    fn test_keep_max_without_quota_and_period() {
+        // arrange
+        let (tmp, max) = setup("test_keep_max_without_quota_and_period", CGROUP_CPU_MAX);
+        let _ = set_fixture(&tmp, CGROUP_CPU_MAX, "50000 100000")
+            .unwrap_or_else(|_| panic!("set test fixture for {}", CGROUP_CPU_MAX));
+        let _ = set_fixture(&tmp, CGROUP_CPU_WEIGHT, "")
+            .unwrap_or_else(|_| panic!("set test fixture for {}", CGROUP_CPU_WEIGHT));
+        let cpu = LinuxCpuBuilder::new().with_shares(1024).build();
+
+        // act
+        Cpu::apply(&tmp, &cpu).expect("apply cpu");
+
+        // assert
+        let content = fs::read_to_string(max)
+            .unwrap_or_else(|_| panic!("read {} file content", CGROUP_CPU_MAX));
+        assert_eq!(content, "50000 100000");
+    }
+
+    #[test]
+    fn test_set_burst() {
+        // arrange
+        const QUOTA: i64 = 50000;
+        const BURST: u64 = 20000;
+        let (tmp, burst) = setup("test_set_burst", CGROUP_CPU_MAX_BURST);
+        let _ = set_fixture(&tmp, CGROUP_CPU_MAX, "")
+            .unwrap_or_else(|_| panic!("set test fixture for {}", CGROUP_CPU_MAX));
+        let cpu = LinuxCpuBuilder::new()
+            .with_quota(QUOTA)
+            .with_burst(BURST)
+            .build();
+
+        // act
+        Cpu::apply(&tmp, &cpu).expect("apply cpu");
+
+        // assert
+        let content = fs::read_to_string(burst)
+            .unwrap_or_else(|_| panic!("read {} file content", CGROUP_CPU_MAX_BURST));
+        assert_eq!(content, BURST.to_string());
+        let content = fs::read_to_string(tmp.join(CGROUP_CPU_MAX))
+            .unwrap_or_else(|_| panic!("read {} file content", CGROUP_CPU_MAX));
+        assert_eq!(content, format!("{} {}", QUOTA, DEFAULT_PERIOD));
+    }
+
     #[test]
     fn test_realtime_runtime_not_supported() {
         // arrange
@@ -1,12 +1,67 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
+use std::path::Path;
+
+use regex::Regex;
 
 use super::controller::Controller;
-use oci_spec::LinuxResources;
+use crate::cgroups::common;
+use oci_spec::{LinuxHugepageLimit, LinuxResources};
 
 pub struct HugeTlb {}
 
 impl Controller for HugeTlb {
-    fn apply(_: &LinuxResources, _: &std::path::Path) -> Result<()> {
+    fn apply(linux_resources: &LinuxResources, cgroup_path: &Path) -> Result<()> {
+        for hugetlb in &linux_resources.hugepage_limits {
+            Self::apply(cgroup_path, hugetlb)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl HugeTlb {
+    fn apply(path: &Path, hugetlb: &LinuxHugepageLimit) -> Result<()> {
+        let re = Regex::new(r"^(?P<pagesize>[0-9]+)[KMG]B$")?;
+        let page_size: u64 = match re.captures(&hugetlb.page_size) {
+            Some(caps) => caps["pagesize"].parse()?,
+            None => bail!("page size must be in the format [0-9]+[KMG]B"),
+        };
+        if !page_size.is_power_of_two() {
+            bail!("page size must be in the format of 2^(integer)");
+        }
+
+        common::write_cgroup_file(
+            path.join(format!("hugetlb.{}.max", hugetlb.page_size)),
+            hugetlb.limit,
+        )?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cgroups::test::{create_temp_dir, set_fixture};
+    use std::fs;
+
+    #[test]
+    fn test_set_hugetlb() {
+        let page_file_name = "hugetlb.2MB.max";
+        let tmp = create_temp_dir("test_set_hugetlb_v2").expect("create temp directory for test");
+        set_fixture(&tmp, page_file_name, "0").expect("set fixture for 2 MB page size");
+
+        let hugetlb = LinuxHugepageLimit {
+            page_size: "2MB".to_owned(),
+            limit: 16384,
+        };
+        HugeTlb::apply(&tmp, &hugetlb).expect("apply hugetlb");
+        let content = fs::read_to_string(tmp.join(page_file_name)).expect("read hugetlb file");
+        assert_eq!(content, "16384");
+
+        let invalid = LinuxHugepageLimit {
+            page_size: "3MB".to_owned(),
+            limit: 16384,
+        };
+        assert!(HugeTlb::apply(&tmp, &invalid).is_err());
+    }
+}
@@ -1,12 +1,133 @@
 use anyhow::Result;
+use std::path::Path;
 
 use super::controller::Controller;
-use oci_spec::LinuxResources;
+use crate::cgroups::common;
+use oci_spec::{LinuxBlockIo, LinuxResources, LinuxThrottleDevice};
+
+const CGROUP_IO_WEIGHT: &str = "io.weight";
+const CGROUP_IO_MAX: &str = "io.max";
 
 pub struct Io {}
 
 impl Controller for Io {
-    fn apply(_: &LinuxResources, _: &std::path::Path) -> Result<()> {
+    fn apply(linux_resources: &LinuxResources, cgroup_path: &Path) -> Result<()> {
+        if let Some(block_io) = &linux_resources.block_io {
+            Self::apply(cgroup_path, block_io)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Io {
+    fn apply(path: &Path, block_io: &LinuxBlockIo) -> Result<()> {
+        if let Some(weight) = block_io.blkio_weight {
+            if weight > 0 {
+                common::write_cgroup_file_str(
+                    path.join(CGROUP_IO_WEIGHT),
+                    &format!("default {}", Self::convert_weight_to_cgroup2(weight)),
+                )?;
+            }
+        }
+        for device in &block_io.blkio_weight_device {
+            if let Some(weight) = device.weight {
+                common::write_cgroup_file_str(
+                    path.join(CGROUP_IO_WEIGHT),
+                    &format!(
+                        "{}:{} {}",
+                        device.major,
+                        device.minor,
+                        Self::convert_weight_to_cgroup2(weight)
+                    ),
+                )?;
+            }
+        }
+
+        // every limit of io.max is written on its own, which leaves the others of the device
+        let throttles: [(&str, &Vec<LinuxThrottleDevice>); 4] = [
+            ("rbps", &block_io.blkio_throttle_read_bps_device),
+            ("wbps", &block_io.blkio_throttle_write_bps_device),
+            ("riops", &block_io.blkio_throttle_read_iops_device),
+            ("wiops", &block_io.blkio_throttle_write_iops_device),
+        ];
+        for (key, devices) in throttles.iter() {
+            for device in devices.iter() {
+                common::write_cgroup_file_str(
+                    path.join(CGROUP_IO_MAX),
+                    &format!("{}:{} {}={}", device.major, device.minor, key, device.rate),
+                )?;
+            }
+        }
+
         Ok(())
     }
+
+    /// Converts a weight of blkio, 10 to 1000, to one of io, 1 to 10000
+    fn convert_weight_to_cgroup2(weight: u16) -> u64 {
+        let weight = u64::from(weight.clamp(10, 1000));
+        1 + (weight - 10) * 9999 / 990
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cgroups::test::{set_fixture, setup};
+    use oci_spec::LinuxWeightDevice;
+    use std::fs;
+
+    fn block_io() -> LinuxBlockIo {
+        LinuxBlockIo {
+            blkio_weight: None,
+            blkio_leaf_weight: None,
+            blkio_weight_device: vec![],
+            blkio_throttle_read_bps_device: vec![],
+            blkio_throttle_write_bps_device: vec![],
+            blkio_throttle_read_iops_device: vec![],
+            blkio_throttle_write_iops_device: vec![],
+        }
+    }
+
+    #[test]
+    fn test_set_io_weight() {
+        let (tmp, weight) = setup("test_set_io_weight", CGROUP_IO_WEIGHT);
+        let mut block_io = block_io();
+        block_io.blkio_weight = Some(500);
+        Io::apply(&tmp, &block_io).expect("apply io");
+        assert_eq!(
+            fs::read_to_string(&weight).expect("read io.weight"),
+            "default 4950"
+        );
+
+        set_fixture(&tmp, CGROUP_IO_WEIGHT, "").expect("set test fixture for io.weight");
+        block_io.blkio_weight = None;
+        block_io.blkio_weight_device = vec![LinuxWeightDevice {
+            major: 8,
+            minor: 0,
+            weight: Some(1000),
+            leaf_weight: None,
+        }];
+        Io::apply(&tmp, &block_io).expect("apply io");
+        assert_eq!(
+            fs::read_to_string(&weight).expect("read io.weight"),
+            "8:0 10000"
+        );
+    }
+
+    #[test]
+    fn test_set_io_max() {
+        let (tmp, max) = setup("test_set_io_max", CGROUP_IO_MAX);
+        let mut block_io = block_io();
+        block_io.blkio_throttle_write_iops_device = vec![LinuxThrottleDevice {
+            major: 8,
+            minor: 16,
+            rate: 120,
+        }];
+        Io::apply(&tmp, &block_io).expect("apply io");
+        assert_eq!(
+            fs::read_to_string(&max).expect("read io.max"),
+            "8:16 wiops=120"
+        );
+    }
 }
@@ -2,9 +2,11 @@ use std::{
     fs::{self},
     os::unix::fs::PermissionsExt,
     path::{Path, PathBuf},
+    thread,
+    time::Duration,
 };
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Result};
 
 use nix::unistd::Pid;
 use oci_spec::LinuxResources;
@@ -13,14 +15,29 @@ use super::{cpu::Cpu, cpuset::CpuSet, hugetlb::HugeTlb, io::Io, memory::Memory,
 use crate::{
     cgroups::v2::controller::Controller,
     cgroups::{
-        common::{self, CgroupManager, CGROUP_PROCS},
+        common::{self, CgroupManager, FreezerState, CGROUP_PROCS},
+        stats::{self, Stats},
         v2::controller_type::ControllerType,
     },
+    container::Warning,
     utils::PathBufExt,
 };
 
 const CGROUP_CONTROLLERS: &str = "cgroup.controllers";
 const CGROUP_SUBTREE_CONTROL: &str = "cgroup.subtree_control";
+const CGROUP_IO_STAT: &str = "io.stat";
+const CGROUP_MEMORY_EVENTS: &str = "memory.events";
+const CGROUP_IO_PRESSURE: &str = "io.pressure";
+const CGROUP_CPU_STAT: &str = "cpu.stat";
+const CGROUP_CPU_PRESSURE: &str = "cpu.pressure";
+const CGROUP_MEMORY_CURRENT: &str = "memory.current";
+const CGROUP_MEMORY_MAX: &str = "memory.max";
+const CGROUP_MEMORY_PRESSURE: &str = "memory.pressure";
+const CGROUP_PIDS_CURRENT: &str = "pids.current";
+const CGROUP_FREEZE: &str = "cgroup.freeze";
+const CGROUP_EVENTS: &str = "cgroup.events";
+/// The kernel freezes the tasks one by one, cgroup.events tells when it is done
+const FREEZE_ATTEMPTS: u32 = 1000;
 
 const CONTROLLER_TYPES: &[ControllerType] = &[
     ControllerType::Cpu,
@@ -49,7 +66,7 @@ impl Manager {
         let controllers: Vec<String> = self
             .get_available_controllers(&self.root_path)?
             .into_iter()
-            .map(|c| format!("{}{}", "+", c.to_string()))
+            .map(|c| format!("{}{}", "+", c))
             .collect();
 
         Self::write_controllers(&self.root_path, &controllers)?;
@@ -70,7 +87,7 @@ impl Manager {
             }
         }
 
-        common::write_cgroup_file(&full_path.join(CGROUP_PROCS), pid)?;
+        common::write_cgroup_file(full_path.join(CGROUP_PROCS), pid)?;
         Ok(full_path)
     }
 
@@ -102,6 +119,24 @@ impl Manager {
         Ok(controllers)
     }
 
+    /// Writes the limits into a cgroup which exists already, e.g. one which systemd created
+    pub fn apply_controllers(&self, linux_resources: &LinuxResources) -> Result<()> {
+        let full_cgroup_path = self.root_path.join_absolute_path(&self.cgroup_path)?;
+
+        for controller in CONTROLLER_TYPES {
+            match controller {
+                ControllerType::Cpu => Cpu::apply(linux_resources, &full_cgroup_path)?,
+                ControllerType::CpuSet => CpuSet::apply(linux_resources, &full_cgroup_path)?,
+                ControllerType::HugeTlb => HugeTlb::apply(linux_resources, &full_cgroup_path)?,
+                ControllerType::Io => Io::apply(linux_resources, &full_cgroup_path)?,
+                ControllerType::Memory => Memory::apply(linux_resources, &full_cgroup_path)?,
+                ControllerType::Pids => Pids::apply(linux_resources, &full_cgroup_path)?,
+            }
+        }
+
+        Ok(())
+    }
+
     fn write_controllers(path: &Path, controllers: &Vec<String>) -> Result<()> {
         for controller in controllers {
             common::write_cgroup_file_str(path.join(CGROUP_SUBTREE_CONTROL), controller)?;
@@ -113,20 +148,181 @@ impl Manager {
 
 impl CgroupManager for Manager {
     fn apply(&self, linux_resources: &LinuxResources, pid: Pid) -> Result<()> {
-        let full_cgroup_path = self.create_unified_cgroup(&self.cgroup_path, pid)?;
+        self.create_unified_cgroup(&self.cgroup_path, pid)?;
+        self.apply_controllers(linux_resources)
+    }
 
-        for controller in CONTROLLER_TYPES {
-            match controller {
-                ControllerType::Cpu => Cpu::apply(linux_resources, &full_cgroup_path)?,
-                ControllerType::CpuSet => CpuSet::apply(linux_resources, &full_cgroup_path)?,
-                ControllerType::HugeTlb => HugeTlb::apply(linux_resources, &&full_cgroup_path)?,
-                ControllerType::Io => Io::apply(linux_resources, &&full_cgroup_path)?,
-                ControllerType::Memory => Memory::apply(linux_resources, &full_cgroup_path)?,
-                ControllerType::Pids => Pids::apply(linux_resources, &&full_cgroup_path)?,
+    fn update(&self, linux_resources: &LinuxResources) -> Result<()> {
+        let full_path = self.root_path.join_absolute_path(&self.cgroup_path)?;
+        if !full_path.exists() {
+            bail!("cgroup {:?} does not exist", full_path);
+        }
+        self.apply_controllers(linux_resources)
+    }
+
+    fn add_task(&self, pid: Pid) -> Result<()> {
+        let full_path = self.root_path.join_absolute_path(&self.cgroup_path)?;
+        if !full_path.exists() {
+            bail!("cgroup {:?} does not exist", full_path);
+        }
+        common::write_cgroup_file(full_path.join(CGROUP_PROCS), pid)
+    }
+
+    fn unsupported(&self, linux_resources: &LinuxResources) -> Vec<Warning> {
+        let mut warnings = Vec::new();
+        // these have no counterpart in cgroup v2
+        if let Some(memory) = &linux_resources.memory {
+            if memory.swappiness.is_some() {
+                warnings.push(Warning::new(
+                    "linux.resources.memory.swappiness",
+                    "swappiness cannot be set per cgroup on cgroup v2",
+                ));
+            }
+            if memory.kernel.is_some() || memory.kernel_tcp.is_some() {
+                warnings.push(Warning::new(
+                    "linux.resources.memory.kernel",
+                    "kernel memory is accounted along with all memory on cgroup v2",
+                ));
+            }
+        }
+        if linux_resources.disable_oom_killer {
+            warnings.push(Warning::new(
+                "linux.resources.disableOOMKiller",
+                "the OOM killer cannot be disabled on cgroup v2",
+            ));
+        }
+        if let Some(block_io) = &linux_resources.block_io {
+            let leaf_weight = block_io.blkio_leaf_weight.is_some()
+                || block_io
+                    .blkio_weight_device
+                    .iter()
+                    .any(|device| device.leaf_weight.is_some());
+            if leaf_weight {
+                warnings.push(Warning::new(
+                    "linux.resources.blockIO.leafWeight",
+                    "leaf weights do not exist on cgroup v2",
+                ));
             }
         }
 
-        Ok(())
+        // controllers have to be delegated to be usable, which may not be the case in rootless mode
+        let available: Vec<String> = self
+            .get_available_controllers(&self.root_path)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|c| c.to_string())
+            .collect();
+        let mut require = |requested: bool, controller: ControllerType, field: &str| {
+            if requested && !available.contains(&controller.to_string()) {
+                warnings.push(Warning::new(
+                    field,
+                    &format!("{} controller is not available", controller),
+                ));
+            }
+        };
+        if let Some(cpu) = &linux_resources.cpu {
+            require(
+                cpu.shares.is_some()
+                    || cpu.quota.is_some()
+                    || cpu.period.is_some()
+                    || cpu.burst.is_some(),
+                ControllerType::Cpu,
+                "linux.resources.cpu",
+            );
+            require(
+                cpu.cpus.is_some() || cpu.mems.is_some(),
+                ControllerType::CpuSet,
+                "linux.resources.cpu",
+            );
+        }
+        require(
+            linux_resources.memory.is_some(),
+            ControllerType::Memory,
+            "linux.resources.memory",
+        );
+        require(
+            linux_resources.pids.is_some(),
+            ControllerType::Pids,
+            "linux.resources.pids",
+        );
+        require(
+            linux_resources.block_io.is_some(),
+            ControllerType::Io,
+            "linux.resources.blockIO",
+        );
+        require(
+            !linux_resources.hugepage_limits.is_empty(),
+            ControllerType::HugeTlb,
+            "linux.resources.hugepageLimits",
+        );
+
+        warnings
+    }
+
+    fn get_all_pids(&self) -> Result<Vec<Pid>> {
+        let full_path = self.root_path.join_absolute_path(&self.cgroup_path)?;
+        common::read_cgroup_procs(&full_path)
+    }
+
+    fn stats(&self) -> Result<Stats> {
+        let full_path = self.root_path.join_absolute_path(&self.cgroup_path)?;
+        let mut stats = Stats::default();
+        // io.stat only exists when the io controller is enabled for the cgroup
+        let io_stat_path = full_path.join(CGROUP_IO_STAT);
+        if io_stat_path.exists() {
+            stats.blkio = stats::parse_io_stat(&fs::read_to_string(io_stat_path)?)?;
+        }
+        stats.blkio.psi = stats::read_psi(&full_path.join(CGROUP_IO_PRESSURE))?;
+
+        let usage_usec = stats::read_keyed_value(&full_path.join(CGROUP_CPU_STAT), "usage_usec")?;
+        stats.cpu.usage.total = usage_usec * 1000;
+        stats.cpu.psi = stats::read_psi(&full_path.join(CGROUP_CPU_PRESSURE))?;
+
+        stats.memory.usage.usage = stats::read_value(&full_path.join(CGROUP_MEMORY_CURRENT))?;
+        stats.memory.usage.limit = stats::read_value(&full_path.join(CGROUP_MEMORY_MAX))?;
+        stats.memory.psi = stats::read_psi(&full_path.join(CGROUP_MEMORY_PRESSURE))?;
+
+        stats.pids.current = stats::read_value(&full_path.join(CGROUP_PIDS_CURRENT))?;
+
+        for size in stats::hugetlb_page_sizes(&full_path)? {
+            let file = |name: &str| full_path.join(format!("hugetlb.{}.{}", size, name));
+            let entry = stats::HugetlbStats {
+                usage: stats::read_value(&file("current"))?,
+                max: 0,
+                failcnt: stats::read_keyed_value(&file("events"), "max")?,
+            };
+            stats.hugetlb.insert(size, entry);
+        }
+        Ok(stats)
+    }
+
+    fn oom_kill_count(&self) -> Result<u64> {
+        let full_path = self.root_path.join_absolute_path(&self.cgroup_path)?;
+        common::read_oom_kill_count(&full_path.join(CGROUP_MEMORY_EVENTS))
+    }
+
+    fn freeze(&self, state: FreezerState) -> Result<()> {
+        let full_path = self.root_path.join_absolute_path(&self.cgroup_path)?;
+        let (value, frozen) = match state {
+            FreezerState::Frozen => ("1", "frozen 1"),
+            FreezerState::Thawed => ("0", "frozen 0"),
+        };
+        common::write_cgroup_file_str(full_path.join(CGROUP_FREEZE), value)?;
+
+        let events = full_path.join(CGROUP_EVENTS);
+        for _ in 0..FREEZE_ATTEMPTS {
+            if fs::read_to_string(&events)?
+                .lines()
+                .any(|line| line == frozen)
+            {
+                return Ok(());
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        if state == FreezerState::Frozen {
+            common::write_cgroup_file_str(full_path.join(CGROUP_FREEZE), "0")?;
+        }
+        bail!("cgroup {:?} did not become {}", full_path, frozen)
     }
 
     fn remove(&self) -> Result<()> {
@@ -1,9 +1,15 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
 use std::path::Path;
 
 use oci_spec::{LinuxMemory, LinuxResources};
 
 use super::controller::Controller;
+use crate::cgroups::common;
+
+const CGROUP_MEMORY_MAX: &str = "memory.max";
+const CGROUP_MEMORY_LOW: &str = "memory.low";
+const CGROUP_MEMORY_SWAP: &str = "memory.swap.max";
+const UNLIMITED: &str = "max";
 
 pub struct Memory {}
 
@@ -18,7 +24,98 @@ impl Controller for Memory {
 }
 
 impl Memory {
-    fn apply(_: &Path, _: &LinuxMemory) -> Result<()> {
+    fn apply(path: &Path, memory: &LinuxMemory) -> Result<()> {
+        // the swap of the spec is the limit of memory and swap together, as in cgroup v1,
+        // while memory.swap.max limits the swap alone
+        let swap = match (memory.limit, memory.swap) {
+            (_, None) => None,
+            (_, Some(-1)) => Some(UNLIMITED.to_owned()),
+            (Some(limit), Some(swap)) if limit > 0 => {
+                if swap < limit {
+                    bail!(
+                        "memory swap {} must not be lower than the memory limit {}",
+                        swap,
+                        limit
+                    )
+                }
+                Some((swap - limit).to_string())
+            }
+            (_, Some(_)) => bail!("memory swap can only be limited along with memory"),
+        };
+
+        if let Some(limit) = memory.limit {
+            match limit {
+                -1 => common::write_cgroup_file_str(path.join(CGROUP_MEMORY_MAX), UNLIMITED)?,
+                limit if limit > 0 => {
+                    common::write_cgroup_file(path.join(CGROUP_MEMORY_MAX), limit)?
+                }
+                _ => {}
+            }
+        }
+        if let Some(swap) = swap {
+            common::write_cgroup_file_str(path.join(CGROUP_MEMORY_SWAP), &swap)?;
+        }
+        // a reservation is memory which is protected from reclaim as long as possible
+        if let Some(reservation) = memory.reservation {
+            if reservation > 0 {
+                common::write_cgroup_file(path.join(CGROUP_MEMORY_LOW), reservation)?;
+            }
+        }
+
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cgroups::test::{create_temp_dir, set_fixture};
+    use std::fs;
+
+    fn memory(limit: Option<i64>, swap: Option<i64>, reservation: Option<i64>) -> LinuxMemory {
+        LinuxMemory {
+            limit,
+            reservation,
+            swap,
+            kernel: None,
+            kernel_tcp: None,
+            swappiness: None,
+        }
+    }
+
+    fn setup(testname: &str) -> crate::cgroups::test::TempDir {
+        let tmp = create_temp_dir(testname).expect("create temp directory for test");
+        for file in &[CGROUP_MEMORY_MAX, CGROUP_MEMORY_LOW, CGROUP_MEMORY_SWAP] {
+            set_fixture(&tmp, file, "").unwrap_or_else(|_| panic!("set test fixture for {}", file));
+        }
+        tmp
+    }
+
+    #[test]
+    fn test_set_memory() {
+        let tmp = setup("test_set_memory_v2");
+        Memory::apply(&tmp, &memory(Some(1024), Some(3072), Some(512))).expect("apply memory");
+
+        let read = |file| fs::read_to_string(tmp.join(file)).expect("read cgroup file");
+        assert_eq!(read(CGROUP_MEMORY_MAX), "1024");
+        assert_eq!(read(CGROUP_MEMORY_SWAP), "2048");
+        assert_eq!(read(CGROUP_MEMORY_LOW), "512");
+    }
+
+    #[test]
+    fn test_set_unlimited_memory() {
+        let tmp = setup("test_set_unlimited_memory_v2");
+        Memory::apply(&tmp, &memory(Some(-1), Some(-1), None)).expect("apply memory");
+
+        let read = |file| fs::read_to_string(tmp.join(file)).expect("read cgroup file");
+        assert_eq!(read(CGROUP_MEMORY_MAX), UNLIMITED);
+        assert_eq!(read(CGROUP_MEMORY_SWAP), UNLIMITED);
+    }
+
+    #[test]
+    fn test_invalid_swap() {
+        let tmp = setup("test_invalid_swap_v2");
+        assert!(Memory::apply(&tmp, &memory(Some(2048), Some(1024), None)).is_err());
+        assert!(Memory::apply(&tmp, &memory(None, Some(1024), None)).is_err());
+    }
+}
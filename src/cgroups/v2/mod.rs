@@ -1,6 +1,6 @@
 mod controller;
 mod controller_type;
-mod cpu;
+pub mod cpu;
 mod cpuset;
 mod hugetlb;
 mod io;
@@ -1,12 +1,51 @@
 use anyhow::Result;
+use std::path::Path;
 
 use super::controller::Controller;
-use oci_spec::LinuxResources;
+use crate::cgroups::common;
+use oci_spec::{LinuxPids, LinuxResources};
+
+const CGROUP_PIDS_MAX: &str = "pids.max";
 
 pub struct Pids {}
 
 impl Controller for Pids {
-    fn apply(_: &LinuxResources, _: &std::path::Path) -> Result<()> {
+    fn apply(linux_resources: &LinuxResources, cgroup_path: &Path) -> Result<()> {
+        if let Some(pids) = &linux_resources.pids {
+            Self::apply(cgroup_path, pids)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Pids {
+    fn apply(path: &Path, pids: &LinuxPids) -> Result<()> {
+        let limit = if pids.limit > 0 {
+            pids.limit.to_string()
+        } else {
+            "max".to_string()
+        };
+
+        common::write_cgroup_file_str(path.join(CGROUP_PIDS_MAX), &limit)?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cgroups::test::{set_fixture, setup};
+    use std::fs;
+
+    #[test]
+    fn test_set_pids() {
+        let (tmp, max) = setup("test_set_pids_v2", CGROUP_PIDS_MAX);
+        Pids::apply(&tmp, &LinuxPids { limit: 1000 }).expect("apply pids");
+        assert_eq!(fs::read_to_string(&max).expect("read pids.max"), "1000");
+
+        set_fixture(&tmp, CGROUP_PIDS_MAX, "").expect("set test fixture for pids.max");
+        Pids::apply(&tmp, &LinuxPids { limit: 0 }).expect("apply pids");
+        assert_eq!(fs::read_to_string(&max).expect("read pids.max"), "max");
+    }
+}
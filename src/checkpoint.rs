@@ -0,0 +1,92 @@
+//! Checkpoints a running container into image files with CRIU, from which restore brings it
+//! back, on this host or on another one
+
+use std::path::PathBuf;
+
+use anyhow::{bail, Result};
+use clap::Clap;
+
+use crate::config::Timeouts;
+use crate::container::{Container, ContainerStatus};
+use crate::criu;
+
+#[derive(Clap, Debug)]
+pub struct Checkpoint {
+    /// directory to write the images of the container to
+    #[clap(long, default_value = "checkpoint")]
+    image_path: PathBuf,
+    /// directory for the log of CRIU, the image path by default
+    #[clap(long)]
+    work_path: Option<PathBuf>,
+    /// keep the container running after it is checkpointed, instead of deleting it
+    #[clap(long)]
+    leave_running: bool,
+    /// checkpoint established TCP connections
+    #[clap(long)]
+    tcp_established: bool,
+    /// checkpoint unix sockets connected to the outside of the container
+    #[clap(long)]
+    ext_unix_sk: bool,
+    /// checkpoint a container attached to a terminal
+    #[clap(long)]
+    shell_job: bool,
+    /// checkpoint file locks
+    #[clap(long)]
+    file_locks: bool,
+    /// only dump the memory and keep the container running, so that a later checkpoint
+    /// given these images with --parent-path has less to copy
+    #[clap(long)]
+    pre_dump: bool,
+    /// images of a pre-dump, relative to the image path
+    #[clap(long)]
+    parent_path: Option<PathBuf>,
+    /// how CRIU handles the cgroups of the container: soft, full, strict or ignore
+    #[clap(long)]
+    manage_cgroups_mode: Option<String>,
+    pub container_id: String,
+}
+
+impl Checkpoint {
+    pub fn exec(&self, root_path: PathBuf, timeouts: &Timeouts) -> Result<()> {
+        let container_root = root_path.join(&self.container_id);
+        if !container_root.exists() {
+            bail!("{} doesn't exist.", self.container_id)
+        }
+        let container = Container::load(container_root)?.refresh_status()?;
+        let pid = match container.pid() {
+            Some(pid) if container.status() == ContainerStatus::Running => pid,
+            _ => bail!(
+                "cannot checkpoint {} because it was {:?}",
+                container.id(),
+                container.status()
+            ),
+        };
+        if container.state.shared_cgroups_path.is_some() {
+            bail!(
+                "cannot checkpoint {} because its cgroups belong to another container",
+                container.id()
+            );
+        }
+
+        let spec = oci_spec::Spec::load(&container.root.join("config.json").to_string_lossy())?;
+        let options = criu::Options {
+            image_path: self.image_path.clone(),
+            work_path: self.work_path.clone(),
+            leave_running: self.leave_running,
+            tcp_established: self.tcp_established,
+            ext_unix_sk: self.ext_unix_sk,
+            shell_job: self.shell_job,
+            file_locks: self.file_locks,
+            pre_dump: self.pre_dump,
+            parent_path: self.parent_path.clone(),
+            manage_cgroups_mode: self.manage_cgroups_mode.clone(),
+        };
+        log::debug!("checkpoint {} into {:?}", container.id(), self.image_path);
+        criu::dump(pid, &spec, &options, timeouts.criu())
+    }
+
+    /// Whether the processes of the container are gone after the checkpoint
+    pub fn stops_container(&self) -> bool {
+        !self.leave_running && !self.pre_dump
+    }
+}
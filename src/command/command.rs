@@ -19,8 +19,10 @@ pub trait Command {
     fn pivot_rootfs(&self, path: &Path) -> Result<()>;
     fn set_ns(&self, rawfd: i32, nstype: CloneFlags) -> Result<()>;
     fn set_id(&self, uid: Uid, gid: Gid) -> Result<()>;
+    fn set_groups(&self, gids: &[Gid]) -> Result<()>;
     fn unshare(&self, flags: CloneFlags) -> Result<()>;
     fn set_capability(&self, cset: CapSet, value: &CapsHashSet) -> Result<(), CapsError>;
     fn set_hostname(&self, hostname: &str) -> Result<()>;
+    fn set_domainname(&self, domainname: &str) -> Result<()>;
     fn set_rlimit(&self, rlimit: &LinuxRlimit) -> Result<()>;
 }
@@ -1,7 +1,7 @@
 //! Implements Command trait for Linux systems
-use std::{any::Any, path::Path};
+use std::{any::Any, fs, path::Path};
 
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
 use caps::{errors::CapsError, CapSet, CapsHashSet};
 use nix::{
     errno::Errno,
@@ -18,7 +18,7 @@ use nix::{
 };
 use nix::{sched::unshare, sys::stat::Mode};
 
-use oci_spec::LinuxRlimit;
+use oci_spec::{LinuxRlimit, LinuxRlimitType};
 
 use super::Command;
 use crate::capabilities;
@@ -86,6 +86,20 @@ impl Command for LinuxCommand {
         Ok(())
     }
 
+    /// Sets the supplementary groups of the process, replacing those it inherited. In a user
+    /// namespace which denies setgroups, the process keeps them, and can get no others.
+    fn set_groups(&self, gids: &[Gid]) -> Result<()> {
+        let setgroups = fs::read_to_string("/proc/self/setgroups").unwrap_or_default();
+        if setgroups.trim() == "deny" {
+            if !gids.is_empty() {
+                bail!("cannot set additional gids, setgroups is denied in the user namespace");
+            }
+            return Ok(());
+        }
+        unistd::setgroups(gids).context("failed to set supplementary groups")?;
+        Ok(())
+    }
+
     /// Disassociate parts of execution context
     // see https://man7.org/linux/man-pages/man2/unshare.2.html for more information
     fn unshare(&self, flags: CloneFlags) -> Result<()> {
@@ -93,16 +107,31 @@ impl Command for LinuxCommand {
         Ok(())
     }
 
-    /// Set capabilities for container process
+    /// Set capabilities for container process. Capabilities can only be dropped from the
+    /// bounding set, so all others which the kernel knows are dropped from it.
     fn set_capability(&self, cset: CapSet, value: &CapsHashSet) -> Result<(), CapsError> {
-        caps::set(None, cset, value)
+        match cset {
+            CapSet::Bounding => {
+                for cap in caps::runtime::thread_all_supported().difference(value) {
+                    caps::drop(None, CapSet::Bounding, *cap)?;
+                }
+                Ok(())
+            }
+            _ => caps::set(None, cset, value),
+        }
     }
 
     /// Sets hostname for process
     fn set_hostname(&self, hostname: &str) -> Result<()> {
-        if let Err(e) = sethostname(hostname) {
-            bail!("Failed to set {} as hostname. {:?}", hostname, e)
-        }
+        sethostname(hostname).with_context(|| format!("Failed to set {} as hostname", hostname))?;
+        Ok(())
+    }
+
+    /// Sets NIS domain name for process
+    fn set_domainname(&self, domainname: &str) -> Result<()> {
+        let res = unsafe { libc::setdomainname(domainname.as_ptr() as *const _, domainname.len()) };
+        Errno::result(res)
+            .with_context(|| format!("Failed to set {} as domainname", domainname))?;
         Ok(())
     }
 
@@ -112,10 +141,36 @@ impl Command for LinuxCommand {
             rlim_cur: rlimit.soft,
             rlim_max: rlimit.hard,
         };
-        let res = unsafe { libc::setrlimit(rlimit.typ as u32, rlim) };
-        if let Err(e) = Errno::result(res).map(drop) {
-            bail!("Failed to set {:?}. {:?}", rlimit.typ, e)
-        }
+        let res = unsafe { libc::setrlimit(to_resource(rlimit.typ), rlim) };
+        Errno::result(res).with_context(|| format!("Failed to set {:?}", rlimit.typ))?;
         Ok(())
     }
 }
+
+#[cfg(target_env = "gnu")]
+type RlimitResource = libc::__rlimit_resource_t;
+#[cfg(not(target_env = "gnu"))]
+type RlimitResource = libc::c_int;
+
+/// Converts the type of rlimit in the spec to the resource number of the target architecture,
+/// as the numbering is not the same everywhere, e.g. RLIMIT_NOFILE is 7 on x86_64 and aarch64 but 5 on mips
+fn to_resource(typ: LinuxRlimitType) -> RlimitResource {
+    match typ {
+        LinuxRlimitType::RlimitCpu => libc::RLIMIT_CPU,
+        LinuxRlimitType::RlimitFsize => libc::RLIMIT_FSIZE,
+        LinuxRlimitType::RlimitData => libc::RLIMIT_DATA,
+        LinuxRlimitType::RlimitStack => libc::RLIMIT_STACK,
+        LinuxRlimitType::RlimitCore => libc::RLIMIT_CORE,
+        LinuxRlimitType::RlimitRss => libc::RLIMIT_RSS,
+        LinuxRlimitType::RlimitNproc => libc::RLIMIT_NPROC,
+        LinuxRlimitType::RlimitNofile => libc::RLIMIT_NOFILE,
+        LinuxRlimitType::RlimitMemlock => libc::RLIMIT_MEMLOCK,
+        LinuxRlimitType::RlimitAs => libc::RLIMIT_AS,
+        LinuxRlimitType::RlimitLocks => libc::RLIMIT_LOCKS,
+        LinuxRlimitType::RlimitSigpending => libc::RLIMIT_SIGPENDING,
+        LinuxRlimitType::RlimitMsgqueue => libc::RLIMIT_MSGQUEUE,
+        LinuxRlimitType::RlimitNice => libc::RLIMIT_NICE,
+        LinuxRlimitType::RlimitRtprio => libc::RLIMIT_RTPRIO,
+        LinuxRlimitType::RlimitRttime => libc::RLIMIT_RTTIME,
+    }
+}
@@ -39,7 +39,11 @@ impl Command for TestHelperCommand {
     }
 
     fn set_id(&self, _uid: nix::unistd::Uid, _gid: nix::unistd::Gid) -> anyhow::Result<()> {
-        unimplemented!()
+        Ok(())
+    }
+
+    fn set_groups(&self, _gids: &[nix::unistd::Gid]) -> anyhow::Result<()> {
+        Ok(())
     }
 
     fn unshare(&self, flags: CloneFlags) -> anyhow::Result<()> {
@@ -57,6 +61,10 @@ impl Command for TestHelperCommand {
         todo!()
     }
 
+    fn set_domainname(&self, _domainname: &str) -> anyhow::Result<()> {
+        Ok(())
+    }
+
     fn set_rlimit(&self, _rlimit: &LinuxRlimit) -> anyhow::Result<()> {
         todo!()
     }
@@ -0,0 +1,335 @@
+//! Defaults of the runtime, read from /etc/youki/config.toml and from config.toml in the
+//! youki directory of the configuration of the user, which takes precedence. Options given
+//! on the command line take precedence over both.
+//!
+//! Only the part of TOML which such a file needs is understood: comments, tables, and keys
+//! with a string, boolean or integer value.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+pub const SYSTEM_CONFIG: &str = "/etc/youki/config.toml";
+
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RuntimeConfig {
+    /// root directory to store container state
+    pub root: Option<PathBuf>,
+    pub log: Option<PathBuf>,
+    /// level of logging, unless YOUKI_LOG_LEVEL is set
+    pub log_level: Option<String>,
+    pub log_format: Option<String>,
+    /// driver of cgroups, cgroupfs or systemd
+    pub cgroup_manager: Option<String>,
+    /// seccomp profile of containers whose spec has none
+    pub seccomp_profile: Option<PathBuf>,
+    #[serde(default)]
+    pub features: Features,
+    #[serde(default)]
+    pub timeouts: Timeouts,
+}
+
+/// Behaviors of the runtime which can be turned off
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Features {
+    /// add the hooks in /etc/youki/hooks.d to every container
+    pub drop_in_hooks: bool,
+    /// give every container its own SELinux labels when SELinux is enforcing
+    pub selinux_labels: bool,
+}
+
+impl Default for Features {
+    fn default() -> Self {
+        Self {
+            drop_in_hooks: true,
+            selinux_labels: true,
+        }
+    }
+}
+
+/// Seconds to wait for external programs and for the processes of the runtime, so that
+/// one which is stuck cannot block the runtime forever
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Timeouts {
+    /// for the processes forked at create to report back to each other
+    pub child_sync: u64,
+    /// for start to hand the start notification to the init process
+    pub start: u64,
+    /// for a hook which sets no timeout of its own
+    pub hooks: u64,
+    /// for CRIU to checkpoint or restore a container
+    pub criu: u64,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            child_sync: 5,
+            start: 5,
+            hooks: 30,
+            criu: 300,
+        }
+    }
+}
+
+impl Timeouts {
+    pub fn child_sync(&self) -> Duration {
+        Duration::from_secs(self.child_sync)
+    }
+
+    pub fn start(&self) -> Duration {
+        Duration::from_secs(self.start)
+    }
+
+    pub fn hooks(&self) -> Duration {
+        Duration::from_secs(self.hooks)
+    }
+
+    pub fn criu(&self) -> Duration {
+        Duration::from_secs(self.criu)
+    }
+
+    /// Overrides a timeout with one given as name=seconds, e.g. on the command line
+    pub fn set(&mut self, assignment: &str) -> Result<()> {
+        let (name, seconds) = assignment
+            .split_once('=')
+            .ok_or_else(|| anyhow!("timeout {} is not of the form name=seconds", assignment))?;
+        let seconds = seconds
+            .parse()
+            .with_context(|| format!("invalid seconds of timeout {}", name))?;
+        match name {
+            "child_sync" => self.child_sync = seconds,
+            "start" => self.start = seconds,
+            "hooks" => self.hooks = seconds,
+            "criu" => self.criu = seconds,
+            _ => bail!(
+                "unknown timeout {}, expected child_sync, start, hooks or criu",
+                name
+            ),
+        }
+        self.validate()
+    }
+
+    fn validate(&self) -> Result<()> {
+        if [self.child_sync, self.start, self.hooks, self.criu].contains(&0) {
+            bail!("timeouts must be at least one second")
+        }
+        Ok(())
+    }
+}
+
+impl RuntimeConfig {
+    /// Loads the configuration of the system and of the user, where present
+    pub fn load() -> Result<Self> {
+        let mut paths = vec![PathBuf::from(SYSTEM_CONFIG)];
+        paths.extend(user_config());
+        Self::load_from(&paths)
+    }
+
+    fn load_from(paths: &[PathBuf]) -> Result<Self> {
+        let mut merged = Map::new();
+        for path in paths {
+            let content = match fs::read_to_string(path) {
+                Ok(content) => content,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e).with_context(|| format!("failed to read {:?}", path)),
+            };
+            let table =
+                parse_toml(&content).with_context(|| format!("failed to parse {:?}", path))?;
+            merge(&mut merged, table);
+        }
+
+        let config: RuntimeConfig = serde_json::from_value(Value::Object(merged))
+            .context("invalid runtime configuration")?;
+        if let Some(manager) = &config.cgroup_manager {
+            if manager != "cgroupfs" && manager != "systemd" {
+                bail!(
+                    "cgroup_manager {} is not supported, only cgroupfs and systemd are",
+                    manager
+                )
+            }
+        }
+        config.timeouts.validate()?;
+        Ok(config)
+    }
+}
+
+fn user_config() -> Option<PathBuf> {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(config_home.join("youki/config.toml"))
+}
+
+/// Merges the keys of a later file into the earlier ones, table by table
+fn merge(base: &mut Map<String, Value>, other: Map<String, Value>) {
+    for (key, value) in other {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Object(base)), Value::Object(other)) => merge(base, other),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+fn parse_toml(content: &str) -> Result<Map<String, Value>> {
+    let mut root = Map::new();
+    let mut table: Option<String> = None;
+    for (number, line) in content.lines().enumerate() {
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        let error = |message: &str| anyhow!("line {}: {}", number + 1, message);
+
+        if let Some(name) = line.strip_prefix('[') {
+            let name = name
+                .strip_suffix(']')
+                .ok_or_else(|| error("table is not closed"))?
+                .trim();
+            let entry = root
+                .entry(name.to_owned())
+                .or_insert_with(|| Value::Object(Map::new()));
+            if !entry.is_object() {
+                return Err(error("table has the name of a key"));
+            }
+            table = Some(name.to_owned());
+            continue;
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| error("expected a key = value pair"))?;
+        let value = parse_value(value.trim()).ok_or_else(|| error("unsupported value"))?;
+        let entries = match &table {
+            Some(name) => root[name.as_str()].as_object_mut().unwrap(),
+            None => &mut root,
+        };
+        entries.insert(key.trim().to_owned(), value);
+    }
+    Ok(root)
+}
+
+/// Cuts off a comment, unless the # is inside of a string
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (Some(q), _) if q == c => quote = None,
+            (None, '#') => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn parse_value(value: &str) -> Option<Value> {
+    if let Some(literal) = value.strip_prefix('\'') {
+        return Some(Value::String(literal.strip_suffix('\'')?.to_owned()));
+    }
+    if let Some(basic) = value.strip_prefix('"') {
+        let basic = basic.strip_suffix('"')?;
+        return Some(Value::String(
+            basic.replace("\\\"", "\"").replace("\\\\", "\\"),
+        ));
+    }
+    match value {
+        "true" => Some(Value::Bool(true)),
+        "false" => Some(Value::Bool(false)),
+        _ => value.parse::<i64>().ok().map(Value::from),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_timeout() {
+        let mut timeouts = Timeouts::default();
+        timeouts.set("criu=600").unwrap();
+        assert_eq!(timeouts.criu(), Duration::from_secs(600));
+        assert!(timeouts.set("criu").is_err());
+        assert!(timeouts.set("criu=ten").is_err());
+        assert!(timeouts.set("hooks=0").is_err());
+        assert!(timeouts.set("exec=10").is_err());
+    }
+
+    #[test]
+    fn test_parse_toml() {
+        let table = parse_toml(
+            r#"
+# defaults of the fleet
+root = "/run/youki-fleet"   # tmpfs
+log_level = 'debug'
+[features]
+selinux_labels = false
+"#,
+        )
+        .unwrap();
+        assert_eq!(table["root"], "/run/youki-fleet");
+        assert_eq!(table["log_level"], "debug");
+        assert_eq!(table["features"]["selinux_labels"], false);
+
+        assert!(parse_toml("root = [1, 2]").is_err());
+        assert!(parse_toml("[features").is_err());
+        assert!(parse_toml("features = 1\n[features]").is_err());
+    }
+
+    #[test]
+    fn test_load_from() {
+        let dir = std::env::temp_dir().join("test_runtime_config_load_from");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let system = dir.join("system.toml");
+        let user = dir.join("user.toml");
+        fs::write(
+            &system,
+            "root = \"/run/youki\"\nlog_level = \"info\"\n[features]\ndrop_in_hooks = false\n",
+        )
+        .unwrap();
+        fs::write(
+            &user,
+            "log_level = \"debug\"\n[features]\nselinux_labels = false\n",
+        )
+        .unwrap();
+
+        let config =
+            RuntimeConfig::load_from(&[system, user.clone(), dir.join("missing")]).unwrap();
+        assert_eq!(config.root, Some(PathBuf::from("/run/youki")));
+        assert_eq!(config.log_level.as_deref(), Some("debug"));
+        assert_eq!(
+            config.features,
+            Features {
+                drop_in_hooks: false,
+                selinux_labels: false,
+            }
+        );
+
+        assert_eq!(config.timeouts, Timeouts::default());
+
+        fs::write(&user, "[timeouts]\nhooks = 60\n").unwrap();
+        let config = RuntimeConfig::load_from(std::slice::from_ref(&user)).unwrap();
+        assert_eq!(config.timeouts.hooks(), Duration::from_secs(60));
+        assert_eq!(config.timeouts.child_sync, 5);
+        fs::write(&user, "[timeouts]\nstart = 0\n").unwrap();
+        assert!(RuntimeConfig::load_from(std::slice::from_ref(&user)).is_err());
+        fs::write(&user, "roots = \"/run/typo\"\n").unwrap();
+        assert!(RuntimeConfig::load_from(std::slice::from_ref(&user)).is_err());
+        fs::write(&user, "cgroup_manager = \"systemd\"\n").unwrap();
+        let config = RuntimeConfig::load_from(std::slice::from_ref(&user)).unwrap();
+        assert_eq!(config.cgroup_manager.as_deref(), Some("systemd"));
+        fs::write(&user, "cgroup_manager = \"cgmanager\"\n").unwrap();
+        assert!(RuntimeConfig::load_from(&[user]).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,11 +1,18 @@
+use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use nix::unistd::Pid;
+use oci_spec::LinuxResources;
 use procfs::process::Process;
+use serde_json::Value;
 
-use crate::container::{ContainerStatus, State};
+use crate::capabilities::CapabilitySets;
+use crate::cgroups::common::CgroupManager;
+use crate::container::{ContainerStatus, ExitStatus, State, Warning};
+use crate::selinux;
+use crate::utils;
 
 /// Structure representing the container data
 #[derive(Debug)]
@@ -50,7 +57,9 @@ impl Container {
                     match proc.stat.state().unwrap() {
                         ProcState::Zombie | ProcState::Dead => ContainerStatus::Stopped,
                         _ => match self.status() {
-                            ContainerStatus::Creating | ContainerStatus::Created => self.status(),
+                            ContainerStatus::Creating
+                            | ContainerStatus::Created
+                            | ContainerStatus::Paused => self.status(),
                             _ => ContainerStatus::Running,
                         },
                     }
@@ -63,6 +72,62 @@ impl Container {
         self.update_status(new_status)
     }
 
+    /// Records a stale container as stopped. It is stale when its processes are gone while
+    /// its state is left behind, e.g. after a crash of the host: nothing is left in its
+    /// cgroups. A live process with the pid of its init process has reused that pid.
+    pub fn refresh_stale(&self, cmanager: &dyn CgroupManager) -> Result<Self> {
+        // processes of others may be in shared cgroups, so they tell nothing
+        if self.state.shared_cgroups_path.is_some() {
+            return self.update_status(self.status());
+        }
+        if cmanager.get_all_pids()?.is_empty() {
+            log::debug!("{} is stale, its cgroups are empty", self.id());
+            return self.update_status(ContainerStatus::Stopped);
+        }
+        self.update_status(self.status())
+    }
+
+    /// Returns the path of the cgroups of the container. It is taken from the copy of
+    /// config.json in the container root, as the bundle may be gone by now, unless the
+    /// container joined the cgroups of another one.
+    pub fn cgroups_path(&self) -> Result<PathBuf> {
+        if let Some(shared) = &self.state.shared_cgroups_path {
+            return Ok(shared.clone());
+        }
+        let config_path = self.root.join("config.json");
+        let spec = oci_spec::Spec::load(&config_path.to_string_lossy())?;
+        let cgroups_path = spec.linux.and_then(|linux| linux.cgroups_path);
+        Ok(utils::get_cgroup_path(&cgroups_path, self.id()))
+    }
+
+    /// Merges resources which were changed after create into the copy of config.json in the
+    /// container root, and into the one of the bundle if asked to, so that a restart or
+    /// restore of the container keeps them instead of reverting to the original ones.
+    /// Resources which are not set, i.e. null, empty or false, keep their saved values.
+    pub fn persist_resources(&self, resources: &LinuxResources, bundle: bool) -> Result<()> {
+        merge_resources(&self.root.join("config.json"), resources)?;
+        if bundle {
+            merge_resources(
+                &Path::new(&self.state.bundle).join("config.json"),
+                resources,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Returns the resources saved in the copy of config.json in the container root, with
+    /// the changes merged in the way persist_resources merges them
+    pub fn merged_resources(&self, changes: &LinuxResources) -> Result<LinuxResources> {
+        let config: Value =
+            serde_json::from_reader(fs::File::open(self.root.join("config.json"))?)?;
+        let mut resources = match &config["linux"]["resources"] {
+            Value::Null => Value::Object(Default::default()),
+            saved => saved.clone(),
+        };
+        merge_json(&mut resources, serde_json::to_value(changes)?);
+        Ok(serde_json::from_value(resources)?)
+    }
+
     pub fn save(&self) -> Result<()> {
         log::debug!("Sava container status: {:?} in {:?}", self, self.root);
         self.state.save(&self.root)
@@ -80,29 +145,161 @@ impl Container {
         self.state.status.can_delete()
     }
 
+    pub fn can_pause(&self) -> bool {
+        self.state.status.can_pause()
+    }
+
+    pub fn can_resume(&self) -> bool {
+        self.state.status.can_resume()
+    }
+
     pub fn pid(&self) -> Option<Pid> {
         self.state.pid.map(Pid::from_raw)
     }
 
     pub fn set_pid(&self, pid: i32) -> Self {
-        Self::new(
-            self.state.id.as_str(),
-            self.state.status,
-            Some(pid),
-            self.state.bundle.as_str(),
-            &self.root,
-        )
-        .expect("unexpected error")
+        let mut new_state = self.state.clone();
+        new_state.pid = Some(pid);
+
+        Self {
+            state: new_state,
+            root: self.root.clone(),
+        }
     }
 
     pub fn update_status(&self, status: ContainerStatus) -> Result<Self> {
-        Self::new(
-            self.state.id.as_str(),
-            status,
-            self.state.pid,
-            self.state.bundle.as_str(),
-            &self.root,
-        )
+        let mut new_state = self.state.clone();
+        new_state.status = status;
+
+        Ok(Self {
+            state: new_state,
+            root: self.root.clone(),
+        })
+    }
+
+    pub fn set_annotations(&self, annotations: HashMap<String, String>) -> Self {
+        let mut new_state = self.state.clone();
+        new_state.annotations = annotations;
+
+        Self {
+            state: new_state,
+            root: self.root.clone(),
+        }
+    }
+
+    pub fn set_config_digest(&self, config_digest: String) -> Self {
+        let mut new_state = self.state.clone();
+        new_state.config_digest = Some(config_digest);
+
+        Self {
+            state: new_state,
+            root: self.root.clone(),
+        }
+    }
+
+    pub fn set_exit_status(&self, exit_status: ExitStatus) -> Self {
+        let mut new_state = self.state.clone();
+        new_state.exit_status = Some(exit_status);
+
+        Self {
+            state: new_state,
+            root: self.root.clone(),
+        }
+    }
+
+    pub fn set_selinux_labels(&self, labels: selinux::Labels) -> Self {
+        let mut new_state = self.state.clone();
+        new_state.selinux_labels = Some(labels);
+
+        Self {
+            state: new_state,
+            root: self.root.clone(),
+        }
+    }
+
+    pub fn set_shared_cgroups_path(&self, cgroups_path: PathBuf) -> Self {
+        let mut new_state = self.state.clone();
+        new_state.shared_cgroups_path = Some(cgroups_path);
+
+        Self {
+            state: new_state,
+            root: self.root.clone(),
+        }
+    }
+
+    pub fn set_notify_socket(&self, notify_socket: String) -> Self {
+        let mut new_state = self.state.clone();
+        new_state.notify_socket = Some(notify_socket);
+
+        Self {
+            state: new_state,
+            root: self.root.clone(),
+        }
+    }
+
+    pub fn set_capabilities(&self, capabilities: CapabilitySets) -> Self {
+        let mut new_state = self.state.clone();
+        new_state.capabilities = Some(capabilities);
+
+        Self {
+            state: new_state,
+            root: self.root.clone(),
+        }
+    }
+
+    pub fn add_warnings(&self, warnings: Vec<Warning>) -> Self {
+        let mut new_state = self.state.clone();
+        new_state.warnings.extend(warnings);
+
+        Self {
+            state: new_state,
+            root: self.root.clone(),
+        }
+    }
+
+    /// Loads the container from the first of the state roots which has it
+    pub fn find(roots: &[PathBuf], container_id: &str) -> Result<Self> {
+        for root in roots {
+            let container_root = root.join(container_id);
+            if container_root.exists() {
+                log::debug!("found {} in {:?}", container_id, root);
+                return Self::load(container_root);
+            }
+        }
+        bail!("{} doesn't exist.", container_id)
+    }
+
+    /// Loads every container in the state roots, with its status refreshed
+    pub fn load_all(roots: &[PathBuf]) -> Result<Vec<Self>> {
+        let mut containers = Vec::new();
+        for root in roots {
+            // other roots may belong to users whose containers cannot be seen
+            let dir = match fs::read_dir(root) {
+                Ok(dir) => dir,
+                Err(e) => {
+                    log::debug!("failed to read the state root {:?}: {}", root, e);
+                    continue;
+                }
+            };
+
+            for entry in dir {
+                let container_root = entry?.path();
+                if !container_root.is_dir() {
+                    continue;
+                }
+
+                // a container which is being created or deleted may not have a state right now
+                match Self::load(container_root.clone()) {
+                    Ok(container) => containers.push(container.refresh_status()?),
+                    Err(e) => log::debug!(
+                        "failed to load the container from {:?}: {}",
+                        container_root,
+                        e
+                    ),
+                }
+            }
+        }
+        Ok(containers)
     }
 
     pub fn load(container_root: PathBuf) -> Result<Self> {
@@ -113,3 +310,122 @@ impl Container {
         })
     }
 }
+
+fn merge_resources(config_path: &Path, resources: &LinuxResources) -> Result<()> {
+    let mut config: Value = serde_json::from_reader(fs::File::open(config_path)?)?;
+    let linux = match config.as_object_mut() {
+        Some(config) => config
+            .entry("linux")
+            .or_insert_with(|| Value::Object(Default::default())),
+        None => bail!("{:?} is not a json object", config_path),
+    };
+    let saved = match linux.as_object_mut() {
+        Some(linux) => linux
+            .entry("resources")
+            .or_insert_with(|| Value::Object(Default::default())),
+        None => bail!("linux of {:?} is not a json object", config_path),
+    };
+    merge_json(saved, serde_json::to_value(resources)?);
+
+    fs::write(config_path, serde_json::to_vec_pretty(&config)?)?;
+    Ok(())
+}
+
+fn merge_json(saved: &mut Value, update: Value) {
+    match update {
+        Value::Null | Value::Bool(false) => {}
+        Value::Array(update) if update.is_empty() => {}
+        Value::Object(update) => {
+            if !saved.is_object() {
+                *saved = Value::Object(Default::default());
+            }
+            if let Value::Object(saved) = saved {
+                for (key, value) in update {
+                    // an unset resource must not leave a null behind, which the spec does
+                    // not accept for lists
+                    let entry = saved.entry(key.clone()).or_insert(Value::Null);
+                    merge_json(entry, value);
+                    if entry.is_null() {
+                        saved.remove(&key);
+                    }
+                }
+            }
+        }
+        update => *saved = update,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_annotations_are_kept() {
+        let annotations: HashMap<String, String> =
+            vec![("io.kubernetes.cri.sandbox-id".to_owned(), "abc".to_owned())]
+                .into_iter()
+                .collect();
+        let container = Container::new(
+            "test",
+            ContainerStatus::Creating,
+            None,
+            "/bundle",
+            &std::env::temp_dir(),
+        )
+        .unwrap()
+        .set_annotations(annotations.clone())
+        .update_status(ContainerStatus::Created)
+        .unwrap()
+        .set_pid(1);
+
+        assert_eq!(container.state.annotations, annotations);
+    }
+
+    #[test]
+    fn test_shared_cgroups_path() {
+        // the container root has no config.json, which is not read for shared cgroups
+        let root = std::env::temp_dir().join("test_shared_cgroups_path");
+        fs::create_dir_all(&root).unwrap();
+        let container = Container::new("test", ContainerStatus::Creating, None, "/bundle", &root)
+            .unwrap()
+            .set_shared_cgroups_path(PathBuf::from("/pod/sandbox"));
+        assert_eq!(
+            container.cgroups_path().unwrap(),
+            PathBuf::from("/pod/sandbox")
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_persist_resources() {
+        let root = std::env::temp_dir().join("test_persist_resources");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        fs::write(
+            root.join("config.json"),
+            r#"{"ociVersion": "1.0.2", "linux": {"resources": {"pids": {"limit": 10}, "memory": {"limit": 1024, "swap": 2048}}}}"#,
+        )
+        .unwrap();
+        let container =
+            Container::new("test", ContainerStatus::Running, Some(1), "/bundle", &root).unwrap();
+
+        let resources: LinuxResources =
+            serde_json::from_str(r#"{"memory": {"limit": 4096}}"#).unwrap();
+        let merged = container.merged_resources(&resources).unwrap();
+        let memory = merged.memory.unwrap();
+        assert_eq!((memory.limit, memory.swap), (Some(4096), Some(2048)));
+        assert_eq!(merged.pids.unwrap().limit, 10);
+
+        container.persist_resources(&resources, false).unwrap();
+
+        let config: Value =
+            serde_json::from_slice(&fs::read(root.join("config.json")).unwrap()).unwrap();
+        let saved = &config["linux"]["resources"];
+        assert_eq!(saved["memory"]["limit"], 4096);
+        assert_eq!(saved["memory"]["swap"], 2048);
+        assert_eq!(saved["pids"]["limit"], 10);
+        assert!(saved.get("devices").is_none());
+        assert_eq!(config["ociVersion"], "1.0.2");
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -4,4 +4,4 @@
 mod container;
 mod state;
 pub use container::Container;
-pub use state::{ContainerStatus, State};
+pub use state::{ContainerStatus, ExitStatus, OciState, State, StateEntry, Warning};
@@ -1,15 +1,29 @@
 //! Information about status and state of the container
 use std::collections::HashMap;
+use std::fmt::Display;
 use std::fs;
-use std::{fs::File, path::Path};
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+};
 
 use anyhow::Result;
+use chrono::{SecondsFormat, Utc};
+use nix::sys::wait::WaitStatus;
 use serde::{Deserialize, Serialize};
 
+use crate::capabilities::CapabilitySets;
+use crate::container::Container;
+use crate::output::Output;
+use crate::reaper;
+use crate::selinux;
+
 const STATE_FILE_PATH: &str = "state.json";
+/// Version of the runtime spec whose state format the state follows
+const OCI_VERSION: &str = "1.0.2";
 
 /// Indicates status of the container
-#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub enum ContainerStatus {
     // The container is being created
@@ -18,6 +32,8 @@ pub enum ContainerStatus {
     Created,
     // The container process has executed the user-specified program but has not exited
     Running,
+    // The processes of the container are frozen
+    Paused,
     // The container process has exited
     Stopped,
 }
@@ -31,13 +47,91 @@ impl ContainerStatus {
         use ContainerStatus::*;
         match self {
             Creating | Stopped => false,
-            Created | Running => true,
+            Created | Running | Paused => true,
         }
     }
 
     pub fn can_delete(&self) -> bool {
         matches!(self, ContainerStatus::Stopped)
     }
+
+    pub fn can_pause(&self) -> bool {
+        matches!(self, ContainerStatus::Running)
+    }
+
+    pub fn can_resume(&self) -> bool {
+        matches!(self, ContainerStatus::Paused)
+    }
+}
+
+impl Display for ContainerStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let print = match *self {
+            ContainerStatus::Creating => "creating",
+            ContainerStatus::Created => "created",
+            ContainerStatus::Running => "running",
+            ContainerStatus::Paused => "paused",
+            ContainerStatus::Stopped => "stopped",
+        };
+
+        write!(f, "{}", print)
+    }
+}
+
+/// Describes how the init process of the container exited
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ExitStatus {
+    // Exit code, or 128 + the number of the signal if it was terminated by one
+    pub code: i32,
+    // Signal which terminated the process, e.g. SIGKILL
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signal: Option<String>,
+    // Whether the process dumped core when it was terminated
+    pub core_dumped: bool,
+    // Whether the OOM killer killed a process of the container
+    pub oom_killed: bool,
+}
+
+impl ExitStatus {
+    pub fn new(status: WaitStatus, oom_killed: bool) -> Self {
+        let (signal, core_dumped) = match status {
+            WaitStatus::Signaled(_, sig, core_dumped) => {
+                (Some(sig.as_str().to_owned()), core_dumped)
+            }
+            _ => (None, false),
+        };
+        Self {
+            code: reaper::exit_code(status),
+            signal,
+            core_dumped,
+            oom_killed,
+        }
+    }
+}
+
+/// Describes a feature requested by the spec which had to be skipped
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Warning {
+    // Field of the spec which could not be honored, e.g. linux.resources.hugepageLimits
+    pub field: String,
+    // Reason why it was skipped
+    pub reason: String,
+}
+
+impl Warning {
+    pub fn new(field: &str, reason: &str) -> Self {
+        Self {
+            field: field.to_owned(),
+            reason: reason.to_owned(),
+        }
+    }
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} is ignored: {}", self.field, self.reason)
+    }
 }
 
 /// Stores the state information of the container
@@ -57,6 +151,32 @@ pub struct State {
     pub bundle: String,
     // Annotations are key values associated with the container.
     pub annotations: HashMap<String, String>,
+    // Warnings about requested features which could not be applied.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<Warning>,
+    // Digest of config.json of the bundle at the time the container was created.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config_digest: Option<String>,
+    // How the init process exited, once it has.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_status: Option<ExitStatus>,
+    // SELinux labels generated for the container, released along with the state.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub selinux_labels: Option<selinux::Labels>,
+    // Capabilities which the init process holds once the container is created.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capabilities: Option<CapabilitySets>,
+    // Cgroups of another container or of a sandbox which the container joined instead of
+    // creating its own. They are neither limited nor removed on behalf of the container.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shared_cgroups_path: Option<PathBuf>,
+    // Socket of the service manager, from NOTIFY_SOCKET of create, which the container
+    // notifies through a proxy socket of its own.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notify_socket: Option<String>,
+    // Time the container was created, in RFC 3339 and UTC.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created: Option<String>,
 }
 
 impl State {
@@ -67,26 +187,32 @@ impl State {
         bundle: &str,
     ) -> Self {
         Self {
-            oci_version: "v1.0.2".to_string(),
+            oci_version: OCI_VERSION.to_string(),
             id: container_id.to_string(),
             status,
             pid,
             bundle: bundle.to_string(),
             annotations: HashMap::default(),
+            warnings: Vec::new(),
+            config_digest: None,
+            exit_status: None,
+            selinux_labels: None,
+            capabilities: None,
+            shared_cgroups_path: None,
+            notify_socket: None,
+            created: Some(Utc::now().to_rfc3339_opts(SecondsFormat::Nanos, true)),
         }
     }
 
+    /// Saves the state by replacing the file, so that concurrent readers never see
+    /// it partially written
     pub fn save(&self, container_root: &Path) -> Result<()> {
         let state_file_path = container_root.join(STATE_FILE_PATH);
-        let file = fs::OpenOptions::new()
-            .read(true)
-            .write(true)
-            .append(false)
-            .create(true)
-            .truncate(true)
-            .open(state_file_path)
-            .expect("Unable to open");
+        let tmp_file_path = container_root.join(format!(".{}.tmp", STATE_FILE_PATH));
+        let file = File::create(&tmp_file_path)?;
         serde_json::to_writer(&file, self)?;
+        file.sync_all()?;
+        fs::rename(tmp_file_path, state_file_path)?;
         Ok(())
     }
 
@@ -97,3 +223,190 @@ impl State {
         Ok(state)
     }
 }
+
+impl Output for State {
+    fn headers() -> Vec<&'static str> {
+        vec!["ID", "PID", "STATUS", "BUNDLE", "CREATED"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.id.clone(),
+            self.pid.map_or("0".to_owned(), |pid| pid.to_string()),
+            self.status.to_string(),
+            self.bundle.clone(),
+            self.created.clone().unwrap_or_default(),
+        ]
+    }
+}
+
+/// State of a container in the format of the runtime spec, which engines read from the
+/// output of state. It leaves out what youki keeps for itself.
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct OciState {
+    pub oci_version: String,
+    pub id: String,
+    pub status: ContainerStatus,
+    // Only set while the init process is alive, as its pid may be reused once it is gone
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pid: Option<i32>,
+    pub bundle: String,
+    pub annotations: HashMap<String, String>,
+}
+
+impl From<&State> for OciState {
+    fn from(state: &State) -> Self {
+        Self {
+            // states saved by older versions have a version of their own
+            oci_version: OCI_VERSION.to_owned(),
+            id: state.id.clone(),
+            status: state.status,
+            pid: state.pid.filter(|_| state.status.can_kill()),
+            bundle: state.bundle.clone(),
+            annotations: state.annotations.clone(),
+        }
+    }
+}
+
+/// State of a container along with the state root it was found in, which read-only
+/// commands show as they look into several roots
+#[derive(Serialize, Debug)]
+pub struct StateEntry {
+    #[serde(flatten)]
+    pub state: State,
+    pub root: PathBuf,
+}
+
+impl From<Container> for StateEntry {
+    fn from(container: Container) -> Self {
+        let root = container
+            .root
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        Self {
+            state: container.state,
+            root,
+        }
+    }
+}
+
+impl Output for StateEntry {
+    fn headers() -> Vec<&'static str> {
+        let mut headers = State::headers();
+        headers.push("ROOT");
+        headers
+    }
+
+    fn row(&self) -> Vec<String> {
+        let mut row = self.state.row();
+        row.push(self.root.display().to_string());
+        row
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_warnings() {
+        let mut state = State::new("test", ContainerStatus::Created, Some(1), "/bundle");
+        let json = serde_json::to_string(&state).unwrap();
+        assert!(!json.contains("warnings"));
+
+        state.warnings.push(Warning::new(
+            "linux.resources.pids",
+            "pids cgroup is not available",
+        ));
+        let json = serde_json::to_string(&state).unwrap();
+        let loaded: State = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.warnings, state.warnings);
+        assert_eq!(
+            loaded.warnings[0].to_string(),
+            "linux.resources.pids is ignored: pids cgroup is not available"
+        );
+    }
+
+    #[test]
+    fn test_state_save_replaces_file() {
+        let root = std::env::temp_dir().join("test_state_save_replaces_file");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+
+        State::new("test", ContainerStatus::Creating, None, "/bundle")
+            .save(&root)
+            .unwrap();
+        State::new("test", ContainerStatus::Created, Some(1), "/bundle")
+            .save(&root)
+            .unwrap();
+
+        let loaded = State::load(&root).unwrap();
+        assert_eq!(loaded.status, ContainerStatus::Created);
+        assert_eq!(loaded.pid, Some(1));
+        assert_eq!(fs::read_dir(&root).unwrap().count(), 1);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_exit_status() {
+        use nix::sys::signal::Signal;
+        use nix::unistd::Pid;
+
+        let pid = Pid::from_raw(1);
+        let exited = ExitStatus::new(WaitStatus::Exited(pid, 3), false);
+        assert_eq!(exited.code, 3);
+        assert_eq!(exited.signal, None);
+        assert!(!exited.core_dumped);
+
+        let killed = ExitStatus::new(WaitStatus::Signaled(pid, Signal::SIGKILL, false), true);
+        assert_eq!(killed.code, 137);
+        assert_eq!(killed.signal, Some("SIGKILL".to_owned()));
+        assert!(killed.oom_killed);
+
+        let dumped = ExitStatus::new(WaitStatus::Signaled(pid, Signal::SIGSEGV, true), false);
+        assert!(dumped.core_dumped);
+        let json = serde_json::to_string(&dumped).unwrap();
+        assert!(json.contains(r#""coreDumped":true"#));
+    }
+
+    #[test]
+    fn test_oci_state() {
+        let mut state = State::new("test", ContainerStatus::Running, Some(42), "/bundle");
+        state
+            .warnings
+            .push(Warning::new("linux.resources.pids", "not available"));
+        state.oci_version = "v1.0.2".to_owned();
+        let json = serde_json::to_value(OciState::from(&state)).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "ociVersion": "1.0.2",
+                "id": "test",
+                "status": "running",
+                "pid": 42,
+                "bundle": "/bundle",
+                "annotations": {}
+            })
+        );
+
+        state.status = ContainerStatus::Stopped;
+        assert_eq!(OciState::from(&state).pid, None);
+    }
+
+    #[test]
+    fn test_state_row() {
+        let mut state = State::new("test", ContainerStatus::Created, None, "/bundle");
+        state.created = Some("2021-06-01T12:00:00.5Z".to_owned());
+        assert_eq!(
+            state.row(),
+            vec!["test", "0", "created", "/bundle", "2021-06-01T12:00:00.5Z"]
+        );
+
+        state.pid = Some(1);
+        state.status = ContainerStatus::Running;
+        state.created = None;
+        assert_eq!(state.row(), vec!["test", "1", "running", "/bundle", ""]);
+    }
+}
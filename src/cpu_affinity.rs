@@ -0,0 +1,117 @@
+//! Pins the container process to the CPUs of process.execCPUAffinity. The process of the
+//! runtime runs on the initial CPUs until it is in the cgroups of the container, so that it
+//! does not disturb the pinned workloads on the host, and the process of the container is
+//! executed on the final ones.
+
+use anyhow::{bail, Context, Result};
+use nix::sched::{self, CpuSet};
+use nix::unistd::Pid;
+use oci_spec::ExecCpuAffinity;
+
+/// Checks that the lists of CPUs can be parsed, so that a mistake is reported up front
+pub fn validate(affinity: &ExecCpuAffinity) -> Result<()> {
+    for cpus in affinity.initial.iter().chain(affinity.final_cpus.iter()) {
+        parse_cpu_list(cpus)?;
+    }
+    Ok(())
+}
+
+/// Runs the current process on the initial CPUs, before it is placed in the cgroups
+pub fn apply_initial(affinity: &ExecCpuAffinity) -> Result<()> {
+    match &affinity.initial {
+        Some(cpus) => set_affinity(&parse_cpu_list(cpus)?),
+        None => Ok(()),
+    }
+}
+
+/// Runs the current process on the final CPUs, once it is in the cgroups. Without final
+/// CPUs, a process which was pinned to the initial ones may run on any CPU again, within
+/// the cpuset of its cgroups.
+pub fn apply_final(affinity: &ExecCpuAffinity) -> Result<()> {
+    match (&affinity.final_cpus, &affinity.initial) {
+        (Some(cpus), _) => set_affinity(&parse_cpu_list(cpus)?),
+        (None, Some(_)) => {
+            let mut all = CpuSet::new();
+            for cpu in 0..CpuSet::count() {
+                all.set(cpu)?;
+            }
+            set_affinity(&all)
+        }
+        (None, None) => Ok(()),
+    }
+}
+
+fn set_affinity(cpus: &CpuSet) -> Result<()> {
+    sched::sched_setaffinity(Pid::from_raw(0), cpus).context("failed to set the CPU affinity")
+}
+
+/// Parses a list of CPUs in the format of cpuset, e.g. "0-3,7"
+fn parse_cpu_list(list: &str) -> Result<CpuSet> {
+    let mut cpus = CpuSet::new();
+    let mut empty = true;
+    for range in list.split(',').map(str::trim) {
+        let (first, last) = match range.split_once('-') {
+            Some((first, last)) => (first, last),
+            None => (range, range),
+        };
+        let parse = |cpu: &str| {
+            cpu.trim()
+                .parse::<usize>()
+                .with_context(|| format!("invalid CPU {:?} in the CPU list {:?}", cpu, list))
+        };
+        let (first, last) = (parse(first)?, parse(last)?);
+        if first > last {
+            bail!("invalid range {} in the CPU list {:?}", range, list);
+        }
+        if last >= CpuSet::count() {
+            bail!(
+                "CPU {} in the CPU list {:?} is above the maximum of {}",
+                last,
+                list,
+                CpuSet::count() - 1
+            );
+        }
+        for cpu in first..=last {
+            cpus.set(cpu)?;
+            empty = false;
+        }
+    }
+    if empty {
+        bail!("the CPU list {:?} is empty", list);
+    }
+    Ok(cpus)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cpu_list() {
+        let cpus = parse_cpu_list("0-2, 7").expect("parse CPU list");
+        for cpu in 0..10 {
+            let expected = cpu <= 2 || cpu == 7;
+            assert_eq!(cpus.is_set(cpu).unwrap(), expected, "CPU {}", cpu);
+        }
+        assert!(parse_cpu_list("3").unwrap().is_set(3).unwrap());
+
+        for invalid in &["", "a", "3-1", "1,", "0-100000"] {
+            assert!(parse_cpu_list(invalid).is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let affinity: ExecCpuAffinity =
+            serde_json::from_str(r#"{ "initial": "0", "final": "1-2" }"#)
+                .expect("parse execCPUAffinity");
+        assert_eq!(affinity.final_cpus.as_deref(), Some("1-2"));
+        assert!(validate(&affinity).is_ok());
+
+        let affinity = ExecCpuAffinity {
+            initial: Some("0-".to_owned()),
+            final_cpus: None,
+        };
+        assert!(validate(&affinity).is_err());
+    }
+}
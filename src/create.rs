@@ -1,39 +1,71 @@
 //! Handles the creation of a new container
+use std::env;
 use std::fs;
+use std::io;
 use std::path::{Path, PathBuf};
 use std::process;
 
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
 use clap::Clap;
 use nix::sched;
 use nix::unistd;
 use nix::unistd::{Gid, Uid};
+use oci_spec::LinuxNamespaceType;
 
+use crate::capabilities;
 use crate::cgroups;
-use crate::container::{Container, ContainerStatus};
+use crate::command::Command;
+use crate::config::RuntimeConfig;
+use crate::container::{Container, ContainerStatus, Warning};
+use crate::cpu_affinity;
+use crate::exec_fifo;
+use crate::hooks;
+use crate::init::Init;
+use crate::ioprio;
+use crate::logger;
 use crate::namespaces::Namespaces;
-use crate::notify_socket::NotifyListener;
+use crate::notify_socket;
+use crate::personality;
+use crate::process::message::Phase;
 use crate::process::{fork, Process};
+use crate::rlimits;
 use crate::rootfs;
-use crate::stdio::FileDescriptor;
+use crate::rootless;
+use crate::scheduler;
+use crate::selinux;
+use crate::sysctl;
 use crate::tty;
 use crate::utils;
-use crate::{capabilities, command::Command};
 
 /// This is the main structure which stores various commandline options given by
 /// high-level container runtime
-#[derive(Clap, Debug)]
+#[derive(Clap, Debug, Clone)]
 pub struct Create {
     /// File to write pid of the container created
     // note that in the end, container is just another process
     #[clap(short, long)]
-    pid_file: Option<String>,
+    pid_file: Option<PathBuf>,
     /// path to the bundle directory, containing config.json and root filesystem
     #[clap(short, long, default_value = ".")]
-    bundle: PathBuf,
+    pub bundle: PathBuf,
     /// Unix socket (file) path , which will receive file descriptor of the writing end of the pseudoterminal
     #[clap(short, long)]
-    console_socket: Option<String>,
+    pub console_socket: Option<String>,
+    /// fail if config.json contains fields which are not recognized, instead of ignoring them
+    #[clap(long)]
+    strict: bool,
+    /// run a minimal init as PID 1 of the container, which forwards signals to the
+    /// process of the container and reaps zombies
+    #[clap(long)]
+    init: bool,
+    /// join the cgroups of another container, given by its id, or the cgroups at an absolute
+    /// path instead of creating its own, e.g. for a sidecar. Their limits are left as they
+    /// are, and they are not removed along with this container.
+    #[clap(long)]
+    join_cgroups: Option<String>,
+    /// pass N additional file descriptors, from 3 on, to the process of the container
+    #[clap(long, default_value = "0")]
+    preserve_fds: u32,
     /// name of the container instance to be started
     pub container_id: String,
 }
@@ -45,26 +77,134 @@ pub struct Create {
 // associated with it like any other process.
 impl Create {
     /// Starts a new container process
-    pub fn exec(&self, root_path: PathBuf, command: impl Command) -> Result<()> {
-        // create a directory for the container to store state etc.
-        // if already present, return error
+    pub fn exec(
+        &self,
+        root_path: PathBuf,
+        config: &RuntimeConfig,
+        command: impl Command,
+    ) -> Result<()> {
+        let process = self.create(root_path, config, command)?;
+        // the run_container forks the process, so not after return if in
+        // parent process, exit ;  as the work of creating the container is done
+        if let Process::Parent(_) = process {
+            process::exit(0);
+        }
+        // if in the child process after fork, then just return
+        Ok(())
+    }
+
+    /// Creates the container, and returns the parent process in the process which called
+    /// it once the container is created
+    pub fn create(
+        &self,
+        root_path: PathBuf,
+        config: &RuntimeConfig,
+        command: impl Command,
+    ) -> Result<Process> {
         let bundle_canonicalized = fs::canonicalize(&self.bundle)
             .unwrap_or_else(|_| panic!("failed to canonicalied {:?}", &self.bundle));
-        let container_dir = root_path.join(&self.container_id);
+        // the state directory and the pid file are relative to where create is run, not to
+        // the directories it changes to
+        let container_dir = env::current_dir()?
+            .join(&root_path)
+            .join(&self.container_id);
         log::debug!("container directory will be {:?}", container_dir);
-
-        if !container_dir.exists() {
-            fs::create_dir(&container_dir).unwrap();
-        } else {
-            bail!("{} already exists", self.container_id)
-        }
+        let pid_file = match &self.pid_file {
+            Some(path) => Some(env::current_dir()?.join(path)),
+            None => None,
+        };
 
         // change directory to the bundle directory, and load configuration,
         // copy that to the container's directory
         unistd::chdir(&self.bundle)?;
 
-        let spec = oci_spec::Spec::load("config.json")?;
+        let mut spec = if self.strict {
+            oci_spec::Spec::load_strict("config.json")?
+        } else {
+            oci_spec::Spec::load("config.json")?
+        };
+        // the terminal is handed over through the console socket, which is of no use without
+        if spec.process.terminal != self.console_socket.is_some() {
+            if spec.process.terminal {
+                bail!("process.terminal needs --console-socket to hand the terminal over");
+            }
+            bail!("--console-socket needs process.terminal, no terminal is allocated without it");
+        }
+        validate(&spec)?;
+
+        // resolve path of root file system of the container to absolute path,
+        // relative paths are evaluated against the bundle directory
+        let rootfs = rootfs::resolve_root_path(&bundle_canonicalized, &spec.root.path)?;
+        rootfs::check_root(&rootfs, &spec)?;
+
+        let systemd_cgroup = config.cgroup_manager.as_deref() == Some("systemd");
+        let default_cgroups_path = match spec.linux.as_mut() {
+            Some(linux) => resolve_cgroups_path(linux, &self.container_id, systemd_cgroup)?,
+            None => false,
+        };
+        let mut shared_cgroups_path = match &self.join_cgroups {
+            Some(target) => Some(shared_cgroups_path(&root_path, target)?),
+            None => None,
+        };
+        // an unprivileged user learns about everything missing before anything is set up
+        if rootless::is_rootless() {
+            let linux = spec.linux.as_ref();
+            let cgroups_path = shared_cgroups_path.clone().unwrap_or_else(|| {
+                utils::get_cgroup_path(
+                    &linux.and_then(|linux| linux.cgroups_path.clone()),
+                    &self.container_id,
+                )
+            });
+            // without limits, the container needs no cgroups of its own and stays in the
+            // one of the user, as if it joined the cgroups of another container
+            let limited = linux.is_some_and(|linux| linux.resources.is_some());
+            let stays =
+                shared_cgroups_path.is_none() && !limited && !rootless::is_delegated(&cgroups_path);
+            if stays {
+                let own = rootless::cgroup_of(unistd::getpid())?;
+                log::debug!(
+                    "cgroups are not delegated, the container stays in {:?}",
+                    own
+                );
+                shared_cgroups_path = Some(own);
+                rootless::preflight(&spec, None)?;
+            } else {
+                rootless::preflight(&spec, Some(&cgroups_path))?;
+            }
+        }
+
+        // creating the directory reserves the id, only one of concurrent creates gets it.
+        // Everything above only checks, so a create which fails there doesn't take the id.
+        match fs::create_dir(&container_dir) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                bail!("container {} already exists", self.container_id)
+            }
+            Err(e) => return Err(e.into()),
+        }
+        // a create which fails from here on gives the id back, until the processes of the
+        // container take over cleaning up
+        let reservation = Reservation::new(&container_dir);
         fs::copy("config.json", container_dir.join("config.json"))?;
+        // additions of administrators are kept along with the spec of the container
+        let mut added = default_cgroups_path;
+        if config.features.drop_in_hooks {
+            added |= hooks::merge_drop_ins(&mut spec, Path::new(hooks::DROP_IN_DIR))? > 0;
+        }
+        if let Some(profile) = &config.seccomp_profile {
+            added |= add_default_seccomp(&mut spec, profile)?;
+        }
+        // the service manager which runs the runtime hears from the container instead
+        let notify_socket = env::var(notify_socket::NOTIFY_SOCKET).ok();
+        if notify_socket.is_some() {
+            notify_socket::add_proxy(&container_dir, &mut spec)?;
+            added = true;
+        }
+        if added {
+            save_additions(&container_dir.join("config.json"), &spec)?;
+        }
+        // start checks against it whether the config has been edited in between
+        let config_digest = utils::config_digest("config.json")?;
         log::debug!("spec: {:?}", spec);
 
         // convert path to absolute path, as relative path will be evaluated
@@ -80,50 +220,204 @@ impl Create {
             None,
             bundle_canonicalized.to_str().unwrap(),
             &container_dir,
-        )?;
+        )?
+        .set_annotations(spec.annotations.clone())
+        .set_config_digest(config_digest);
+        let container = match shared_cgroups_path {
+            Some(cgroups_path) => container.set_shared_cgroups_path(cgroups_path),
+            None => container,
+        };
+        let container = match notify_socket {
+            Some(notify_socket) => container.set_notify_socket(notify_socket),
+            None => container,
+        };
+        // isolate the container from others by default, unless the engine labels it
+        let linux = spec.linux.as_ref();
+        let labeled = !spec.process.selinux_label.is_empty()
+            || linux.is_some_and(|linux| !linux.mount_label.is_empty());
+        let container = if config.features.selinux_labels && !labeled && selinux::is_enforcing() {
+            let labels = selinux::generate_labels(&root_path, &self.container_id)?;
+            log::debug!("generated SELinux labels {:?}", labels);
+            container.set_selinux_labels(labels)
+        } else {
+            container
+        };
         container.save()?;
+        // the init process waits on it to be started
+        exec_fifo::create(&container_dir)?;
 
-        let mut notify_socket: NotifyListener = NotifyListener::new(&container_dir)?;
-        // convert path of root file system of the container to absolute path
-        let rootfs = fs::canonicalize(&spec.root.path)?;
-        // if socket file path is given in commandline options,
-        // get file descriptors of console and console socket
-        let (csocketfd, _consolefd) = {
-            if let Some(console_socket) = &self.console_socket {
-                let (csocketfd, consolefd) =
-                    tty::load_console_sockets(&container_dir, console_socket)?;
-                (Some(csocketfd), Some(consolefd))
-            } else {
-                (None, None)
-            }
-        };
+        // init connects to the socket to hand the terminal over once it has allocated it
+        if let Some(console_socket) = &self.console_socket {
+            tty::link_console_socket(&container_dir, console_socket)?;
+        }
 
-        let process = run_container(
-            self.pid_file.as_ref(),
-            &mut notify_socket,
+        run_container(
+            self,
+            pid_file,
             rootfs,
             spec,
-            csocketfd,
             container,
+            reservation,
+            config,
             command,
-        )?;
-        // the run_container forks the process, so not after return if in
-        // parent process, exit ;  as the work of creating the container is done
-        if let Process::Parent(_) = process {
-            process::exit(0);
+        )
+    }
+}
+
+/// Checks what init would only find out about once the namespaces are set up
+fn validate(spec: &oci_spec::Spec) -> Result<()> {
+    if let Some(securebits) = spec.annotations.get(capabilities::SECUREBITS_ANNOTATION) {
+        capabilities::parse_securebits(securebits)?;
+    }
+    if let Some(sched_core) = spec.annotations.get(scheduler::SCHED_CORE_ANNOTATION) {
+        scheduler::parse_sched_core(sched_core)?;
+    }
+    rlimits::validate(&spec.process.rlimits)?;
+    if let Some(affinity) = &spec.process.exec_cpu_affinity {
+        cpu_affinity::validate(affinity)?;
+    }
+    if let Some(io_priority) = &spec.process.io_priority {
+        ioprio::validate(io_priority)?;
+    }
+    if let Some(scheduler) = &spec.process.scheduler {
+        scheduler::validate(scheduler)?;
+    }
+    if let Some(linux) = &spec.linux {
+        sysctl::validate(linux)?;
+    }
+    // without a UTS namespace of its own, the names would be those of the host
+    let uts = spec.linux.as_ref().is_some_and(|linux| {
+        linux
+            .namespaces
+            .iter()
+            .any(|ns| ns.typ == LinuxNamespaceType::Uts)
+    });
+    if !uts && (!spec.hostname.is_empty() || !spec.domainname.is_empty()) {
+        bail!("hostname and domainname need a UTS namespace, setting them would change the host");
+    }
+    Ok(())
+}
+
+/// Holds the id of a container reserved by its state directory, and removes the directory
+/// when dropped unless it is released
+struct Reservation {
+    dir: Option<PathBuf>,
+}
+
+impl Reservation {
+    fn new(dir: &Path) -> Self {
+        Self {
+            dir: Some(dir.to_path_buf()),
         }
-        // if in the child process after fork, then just return
-        Ok(())
     }
+
+    /// Keeps the state directory, whoever takes it over is in charge of removing it
+    fn release(mut self) {
+        self.dir = None;
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if let Some(dir) = &self.dir {
+            if let Err(e) = fs::remove_dir_all(dir) {
+                log::warn!("failed to remove {:?}: {}", dir, e);
+            }
+        }
+    }
+}
+
+/// Resolves the cgroups to join, given by the id of a container which is alive, or as an
+/// absolute path relative to the cgroup root like linux.cgroupsPath
+fn shared_cgroups_path(root_path: &Path, target: &str) -> Result<PathBuf> {
+    if target.starts_with('/') {
+        return Ok(PathBuf::from(target));
+    }
+    let owner_root = root_path.join(target);
+    if !owner_root.exists() {
+        bail!("{} to join the cgroups of doesn't exist.", target)
+    }
+    let owner = Container::load(owner_root)?.refresh_status()?;
+    if !owner.can_kill() {
+        bail!(
+            "the cgroups of {} could not be joined because it was {:?}",
+            owner.id(),
+            owner.status()
+        )
+    }
+    owner.cgroups_path()
+}
+
+/// Checks that linux.cgroupsPath has the form which the cgroup manager expects, and gives a
+/// container without one a scope of its own if systemd manages the cgroups
+fn resolve_cgroups_path(
+    linux: &mut oci_spec::Linux,
+    container_id: &str,
+    systemd_cgroup: bool,
+) -> Result<bool> {
+    match &linux.cgroups_path {
+        None if systemd_cgroup => {
+            linux.cgroups_path = Some(cgroups::systemd::default_cgroups_path(container_id));
+            Ok(true)
+        }
+        None => Ok(false),
+        Some(path) if systemd_cgroup && !cgroups::systemd::is_systemd_path(path) => bail!(
+            "linux.cgroupsPath {:?} must be of the form slice:prefix:name for the systemd cgroup manager",
+            path
+        ),
+        Some(path) if !systemd_cgroup && cgroups::systemd::is_systemd_path(path) => bail!(
+            "linux.cgroupsPath {:?} is of the form of systemd, use the systemd cgroup manager for it",
+            path
+        ),
+        Some(_) => Ok(false),
+    }
+}
+
+/// Gives a container without a seccomp profile the default one of the runtime
+fn add_default_seccomp(spec: &mut oci_spec::Spec, profile: &Path) -> Result<bool> {
+    let linux = match spec.linux.as_mut() {
+        Some(linux) if linux.seccomp.is_none() => linux,
+        _ => return Ok(false),
+    };
+    let seccomp = fs::read(profile)
+        .map_err(anyhow::Error::from)
+        .and_then(|profile| Ok(serde_json::from_slice(&profile)?))
+        .with_context(|| format!("failed to load the default seccomp profile {:?}", profile))?;
+    linux.seccomp = Some(seccomp);
+    Ok(true)
+}
+
+/// Writes what the runtime added to the spec into the copy of config.json of the
+/// container, keeping everything else
+fn save_additions(config_path: &Path, spec: &oci_spec::Spec) -> Result<()> {
+    let mut config: serde_json::Value = serde_json::from_slice(&fs::read(config_path)?)?;
+    if let Some(hooks) = &spec.hooks {
+        config["hooks"] = serde_json::to_value(hooks)?;
+    }
+    if let Some(linux) = &spec.linux {
+        if let Some(seccomp) = &linux.seccomp {
+            config["linux"]["seccomp"] = serde_json::to_value(seccomp)?;
+        }
+        if let Some(cgroups_path) = &linux.cgroups_path {
+            config["linux"]["cgroupsPath"] = serde_json::to_value(cgroups_path)?;
+        }
+    }
+    config["mounts"] = serde_json::to_value(&spec.mounts)?;
+    config["process"]["env"] = serde_json::to_value(&spec.process.env)?;
+    fs::write(config_path, serde_json::to_vec(&config)?)?;
+    Ok(())
 }
+
 /// Fork the process and actually start the container process
-fn run_container<P: AsRef<Path>>(
-    pid_file: Option<P>,
-    notify_socket: &mut NotifyListener,
+#[allow(clippy::too_many_arguments)]
+fn run_container(
+    opts: &Create,
+    pid_file: Option<PathBuf>,
     rootfs: PathBuf,
     spec: oci_spec::Spec,
-    csocketfd: Option<FileDescriptor>,
     container: Container,
+    reservation: Reservation,
+    config: &RuntimeConfig,
     command: impl Command,
 ) -> Result<Process> {
     // disable core dump for the process, check https://man7.org/linux/man-pages/man2/prctl.2.html for more information
@@ -134,56 +428,86 @@ fn run_container<P: AsRef<Path>>(
     let linux = spec.linux.as_ref().unwrap();
     let namespaces: Namespaces = linux.namespaces.clone().into();
 
-    let cgroups_path = utils::get_cgroup_path(&linux.cgroups_path, container.id());
+    let shared_cgroups_path = container.state.shared_cgroups_path.clone();
+    let cgroups_path = shared_cgroups_path
+        .clone()
+        .unwrap_or_else(|| utils::get_cgroup_path(&linux.cgroups_path, container.id()));
     let cmanager = cgroups::common::create_cgroup_manager(&cgroups_path)?;
 
+    // record what is requested but cannot be honored on this host, so that it is
+    // visible in the state of the container instead of being silently dropped
+    let mut warnings = rootfs::unsupported(&spec);
+    if let Some(caps) = &spec.process.capabilities {
+        warnings.extend(capabilities::unsupported(caps));
+        warnings.extend(capabilities::lost_on_exec(caps, spec.process.user.uid));
+    }
+    if let Some(personality) = &linux.personality {
+        warnings.extend(personality::unsupported(personality));
+    }
+    match (&linux.resources, shared_cgroups_path) {
+        (Some(_), Some(shared)) => warnings.push(Warning::new(
+            "linux.resources",
+            &format!(
+                "the container joins the cgroups {:?}, whose limits apply",
+                shared
+            ),
+        )),
+        (Some(resources), None) => warnings.extend(cmanager.unsupported(resources)),
+        (None, _) => {}
+    }
+    for warning in &warnings {
+        eprintln!("WARNING: {}", warning);
+    }
+    let container = container.add_warnings(warnings);
+    // the forked processes remove the state directory along with the cgroups if the
+    // create fails, the child process must not remove it under the parent process
+    reservation.release();
+
     // first fork, which creates process, which will later create actual container process
     match fork::fork_first(
         pid_file,
         namespaces
             .clone_flags
             .contains(sched::CloneFlags::CLONE_NEWUSER),
-        linux,
+        &spec,
         &container,
         cmanager,
+        &config.timeouts,
     )? {
         // In the parent process, which called run_container
         Process::Parent(parent) => Ok(Process::Parent(parent)),
         // in child process
-        Process::Child(child) => {
-            // set limits and namespaces to the process
-            for rlimit in spec.process.rlimits.iter() {
-                command.set_rlimit(rlimit)?
-            }
-            command.set_id(Uid::from_raw(0), Gid::from_raw(0))?;
-
-            let without = sched::CloneFlags::CLONE_NEWUSER;
-            namespaces.apply_unshare(without)?;
-
-            // set up tty if specified
-            if let Some(csocketfd) = csocketfd {
-                tty::ready(csocketfd)?;
+        Process::Child(mut child) => {
+            if let Err(err) = prepare_child(&spec, &command, &namespaces) {
+                child.report_error(&err);
+                return Err(err);
             }
 
-            // set namespaces
-            namespaces.apply_setns()?;
-
             // fork second time, which will later create container
-            match fork::fork_init(child)? {
+            match fork::fork_init(child, &container, &cgroups_path)? {
                 Process::Child(_child) => unreachable!(),
                 // This is actually the child process after fork
-                Process::Init(mut init) => {
-                    // setup args and env vars as in the spec
-                    let spec_args: &Vec<String> = &spec.process.args.clone();
-                    let envs: &Vec<String> = &spec.process.env.clone();
-                    // prepare process
-                    init_process(spec, command, rootfs, namespaces)?;
-                    init.ready()?;
-                    notify_socket.wait_for_container_start()?;
-                    // actually run the command / program to be run in container
-                    utils::do_exec(&spec_args[0], spec_args, envs)?;
-                    // the command / program is done executing
-                    container.update_status(ContainerStatus::Stopped)?.save()?;
+                Process::Init(init) => {
+                    // execute youki again as the init subcommand, which prepares the
+                    // container inside of the namespaces and runs its command
+                    let root = container.root.parent().unwrap();
+                    let mut args = vec![
+                        "--root".to_owned(),
+                        root.display().to_string(),
+                        "--timeout".to_owned(),
+                        format!("child_sync={}", config.timeouts.child_sync),
+                        "--timeout".to_owned(),
+                        format!("hooks={}", config.timeouts.hooks),
+                    ];
+                    if let Some(Some(log_file)) = logger::LOG_FILE_PATH.get() {
+                        args.push("--log".to_owned());
+                        args.push(log_file.display().to_string());
+                    }
+                    let tty = opts.console_socket.is_some();
+                    let init_opts =
+                        Init::new(container.id(), rootfs, opts.init, tty, opts.preserve_fds);
+                    args.extend(init_opts.args());
+                    init.exec_init(&args)?;
 
                     Ok(Process::Init(init))
                 }
@@ -194,35 +518,54 @@ fn run_container<P: AsRef<Path>>(
     }
 }
 
-/// setup hostname, rootfs for the container process
-fn init_process(
-    spec: oci_spec::Spec,
-    command: impl Command,
-    rootfs: PathBuf,
-    namespaces: Namespaces,
+/// set limits and namespaces to the child process, which forks the container process
+fn prepare_child(
+    spec: &oci_spec::Spec,
+    command: &impl Command,
+    namespaces: &Namespaces,
 ) -> Result<()> {
-    let proc = spec.process.clone();
+    // the limits of the container come from its spec only, not from the runtime
+    utils::restore_nofile_limit().context(Phase::new("restore RLIMIT_NOFILE"))?;
+    for rlimit in spec.process.rlimits.iter() {
+        command
+            .set_rlimit(rlimit)
+            .with_context(|| Phase::new(&format!("setrlimit {:?}", rlimit.typ)))?
+    }
+    command
+        .set_id(Uid::from_raw(0), Gid::from_raw(0))
+        .context(Phase::new("set root user"))?;
 
-    command.set_hostname(&spec.hostname.as_str())?;
-    if spec.process.no_new_privileges {
-        let _ = prctl::set_no_new_privileges(true);
+    // join the namespaces given by path first, so that the new ones are owned by a user
+    // namespace which is joined, e.g. those of a pod sandbox
+    namespaces.apply_setns()?;
+    // the cgroup namespace is unshared by the init process, once it is in its cgroups
+    let without = sched::CloneFlags::CLONE_NEWUSER | sched::CloneFlags::CLONE_NEWCGROUP;
+    namespaces
+        .apply_unshare(without)
+        .context(Phase::new("unshare namespaces"))?;
+    if let Some(linux) = &spec.linux {
+        namespaces.apply_time_offsets(&linux.time_offsets)?;
     }
+    Ok(())
+}
 
-    rootfs::prepare_rootfs(
-        &spec,
-        &rootfs,
-        namespaces
-            .clone_flags
-            .contains(sched::CloneFlags::CLONE_NEWUSER),
-    )?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reservation() {
+        let tmp = std::env::temp_dir().join("test_reservation");
+        let _ = fs::remove_dir_all(&tmp);
+        fs::create_dir_all(tmp.join("dropped")).unwrap();
+        fs::write(tmp.join("dropped").join("config.json"), "{}").unwrap();
+        fs::create_dir_all(tmp.join("released")).unwrap();
 
-    // change the root of filesystem of the process to the rootfs
-    command.pivot_rootfs(&rootfs)?;
+        drop(Reservation::new(&tmp.join("dropped")));
+        assert!(!tmp.join("dropped").exists());
 
-    command.set_id(Uid::from_raw(proc.user.uid), Gid::from_raw(proc.user.gid))?;
-    capabilities::reset_effective(&command)?;
-    if let Some(caps) = &proc.capabilities {
-        capabilities::drop_privileges(&caps, &command)?;
+        Reservation::new(&tmp.join("released")).release();
+        assert!(tmp.join("released").exists());
+        fs::remove_dir_all(&tmp).unwrap();
     }
-    Ok(())
 }
@@ -0,0 +1,408 @@
+//! Checkpoints the processes of a container into image files with CRIU, e.g. to migrate it
+//! to another host or to suspend it, and restores them. The criu binary is run for it,
+//! which has to be installed on the host.
+
+use std::fs;
+use std::io;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context, Result};
+use nix::fcntl::{self, OFlag};
+use nix::mount::{self, MsFlags};
+use nix::sched::{self, CloneFlags};
+use nix::sys::stat::Mode;
+use nix::unistd::{self, Pid};
+use oci_spec::{LinuxNamespaceType, Spec};
+
+const CRIU: &str = "criu";
+/// Targets of the stdio of the init process, which a restore connects to the new stdio
+pub const DESCRIPTORS: &str = "descriptors.json";
+/// Key under which a network namespace joined by the container is external to the images
+pub const EXTERNAL_NETNS: &str = "extRootNetNS";
+const CGROUPS_MODES: &[&str] = &["soft", "full", "strict", "ignore"];
+/// Mount point of the rootfs in the container root, which CRIU restores the container into
+const CRIU_ROOT: &str = "criu-root";
+const RESTORE_PID_FILE: &str = "restore.pid";
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Options of CRIU for a checkpoint or a restore, which ignores those of dump
+#[derive(Debug, Default)]
+pub struct Options {
+    /// directory of the images
+    pub image_path: PathBuf,
+    /// directory of the log of CRIU, the image directory if none is given
+    pub work_path: Option<PathBuf>,
+    /// options of dump
+    pub leave_running: bool,
+    pub tcp_established: bool,
+    pub ext_unix_sk: bool,
+    pub shell_job: bool,
+    pub file_locks: bool,
+    /// only dump the memory, for a later dump to copy less of it
+    pub pre_dump: bool,
+    /// images of a pre-dump, relative to the image directory
+    pub parent_path: Option<PathBuf>,
+    /// how CRIU handles the cgroups of the container: soft, full, strict or ignore
+    pub manage_cgroups_mode: Option<String>,
+}
+
+impl Options {
+    /// Arguments which CRIU takes for both dump and restore
+    fn common_args(&self, log_file: &str) -> Result<Vec<String>> {
+        let mut args = vec![
+            "--images-dir".to_owned(),
+            self.image_path.display().to_string(),
+            "--log-file".to_owned(),
+            log_file.to_owned(),
+            "-v4".to_owned(),
+        ];
+        if let Some(work_path) = &self.work_path {
+            args.push("--work-dir".to_owned());
+            args.push(work_path.display().to_string());
+        }
+        for (enabled, flag) in &[
+            (self.tcp_established, "--tcp-established"),
+            (self.ext_unix_sk, "--ext-unix-sk"),
+            (self.shell_job, "--shell-job"),
+            (self.file_locks, "--file-locks"),
+        ] {
+            if *enabled {
+                args.push((*flag).to_owned());
+            }
+        }
+        match self.manage_cgroups_mode.as_deref() {
+            Some(mode) if CGROUPS_MODES.contains(&mode) => {
+                args.push(format!("--manage-cgroups={}", mode))
+            }
+            Some(mode) => bail!(
+                "invalid cgroups mode {}, expected one of {}",
+                mode,
+                CGROUPS_MODES.join(", ")
+            ),
+            None => args.push("--manage-cgroups".to_owned()),
+        }
+        Ok(args)
+    }
+
+    /// Log of CRIU, which tells why it failed
+    fn log_path(&self, log_file: &str) -> PathBuf {
+        self.work_path
+            .as_ref()
+            .unwrap_or(&self.image_path)
+            .join(log_file)
+    }
+}
+
+/// Dumps the process tree of the init process of a container into the image directory
+pub fn dump(pid: Pid, spec: &Spec, options: &Options, timeout: Duration) -> Result<()> {
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&options.image_path)
+        .with_context(|| format!("failed to create {:?}", options.image_path))?;
+
+    let (command, log_file) = if options.pre_dump {
+        ("pre-dump", "pre-dump.log")
+    } else {
+        ("dump", "dump.log")
+    };
+    let mut args = vec![command.to_owned(), "--tree".to_owned(), pid.to_string()];
+    args.extend(options.common_args(log_file)?);
+    if options.pre_dump {
+        args.push("--track-mem".to_owned());
+    } else if options.leave_running {
+        args.push("--leave-running".to_owned());
+    }
+    if let Some(parent_path) = &options.parent_path {
+        args.push("--prev-images-dir".to_owned());
+        args.push(parent_path.display().to_string());
+        args.push("--track-mem".to_owned());
+    }
+    for external in external_args(spec)? {
+        args.push("--external".to_owned());
+        args.push(external);
+    }
+
+    if !options.pre_dump {
+        write_descriptors(pid, &options.image_path)?;
+    }
+    run(&args, timeout, &options.log_path(log_file))
+}
+
+/// Restores the process tree from the image directory as a child of the current process,
+/// with the rootfs as its root and in the cgroups at the given path, and returns the pid
+/// of the init process. The mount namespace of the current process is unshared, so this
+/// is meant for a process of its own.
+pub fn restore(
+    spec: &Spec,
+    rootfs: &Path,
+    cgroups_path: &Path,
+    container_root: &Path,
+    options: &Options,
+    timeout: Duration,
+) -> Result<Pid> {
+    // CRIU restores the mounts of the container below the root, which has to be a mount
+    // point, without the bind mount leaking to the host
+    sched::unshare(CloneFlags::CLONE_NEWNS)?;
+    mount::mount(
+        None::<&str>,
+        "/",
+        None::<&str>,
+        MsFlags::MS_REC | MsFlags::MS_PRIVATE,
+        None::<&str>,
+    )?;
+    let criu_root = container_root.join(CRIU_ROOT);
+    fs::create_dir_all(&criu_root)?;
+    mount::mount(
+        Some(rootfs),
+        &criu_root,
+        None::<&str>,
+        MsFlags::MS_BIND | MsFlags::MS_REC,
+        None::<&str>,
+    )
+    .with_context(|| format!("failed to bind {:?} for CRIU", rootfs))?;
+
+    let log_file = "restore.log";
+    let pid_file = container_root.join(RESTORE_PID_FILE);
+    let mut args = vec![
+        "restore".to_owned(),
+        "--root".to_owned(),
+        criu_root.display().to_string(),
+        // the init process becomes a child of this process instead of CRIU
+        "--restore-detached".to_owned(),
+        "--restore-sibling".to_owned(),
+        "--pidfile".to_owned(),
+        pid_file.display().to_string(),
+        "--cgroup-root".to_owned(),
+        cgroups_path.display().to_string(),
+    ];
+    args.extend(options.common_args(log_file)?);
+    let descriptors: Vec<String> =
+        serde_json::from_slice(&fs::read(options.image_path.join(DESCRIPTORS))?)?;
+    args.extend(restore_external_args(spec, &descriptors));
+    // a network namespace to join is handed over open, CRIU needs its fd to be inherited
+    let netns = match network_namespace(spec) {
+        Some(path) => {
+            let fd = fcntl::open(path.as_str(), OFlag::O_RDONLY, Mode::empty())
+                .with_context(|| format!("failed to open network namespace {:?}", path))?;
+            args.push("--inherit-fd".to_owned());
+            args.push(format!("fd[{}]:{}", fd, EXTERNAL_NETNS));
+            Some(fd)
+        }
+        None => None,
+    };
+
+    let restored = run(&args, timeout, &options.log_path(log_file));
+    if let Some(fd) = netns {
+        let _ = unistd::close(fd);
+    }
+    restored?;
+    let pid = fs::read_to_string(&pid_file)?;
+    let _ = fs::remove_file(&pid_file);
+    Ok(Pid::from_raw(pid.trim().parse()?))
+}
+
+/// Arguments which connect the restored processes to what is outside of the container:
+/// bind mounts to their sources, and stdio which went to pipes to the stdio of restore
+fn restore_external_args(spec: &Spec, descriptors: &[String]) -> Vec<String> {
+    let mut args = Vec::new();
+    for mount in spec.mounts.iter().filter(|mount| is_bind(mount)) {
+        args.push("--external".to_owned());
+        args.push(format!(
+            "mnt[{}]:{}",
+            mount.destination.display(),
+            mount.source.display()
+        ));
+    }
+    for (fd, descriptor) in descriptors.iter().enumerate() {
+        if descriptor.starts_with("pipe:") {
+            args.push("--inherit-fd".to_owned());
+            args.push(format!("fd[{}]:{}", fd, descriptor));
+        }
+    }
+    args
+}
+
+fn is_bind(mount: &oci_spec::Mount) -> bool {
+    mount.typ == "bind"
+        || mount
+            .options
+            .iter()
+            .any(|option| option == "bind" || option == "rbind")
+}
+
+fn network_namespace(spec: &Spec) -> Option<String> {
+    spec.linux.as_ref().and_then(|linux| {
+        linux
+            .namespaces
+            .iter()
+            .find(|ns| ns.typ == LinuxNamespaceType::Network)
+            .and_then(|ns| ns.path.clone())
+    })
+}
+
+/// Resources of the container which are not its own and so are not in the images: bind
+/// mounts from the host and a network namespace which the container joined
+fn external_args(spec: &Spec) -> Result<Vec<String>> {
+    let mut externals: Vec<String> = spec
+        .mounts
+        .iter()
+        .filter(|mount| is_bind(mount))
+        .map(|mount| {
+            let destination = mount.destination.display();
+            format!("mnt[{}]:{}", destination, destination)
+        })
+        .collect();
+
+    if let Some(path) = network_namespace(spec) {
+        let inode = fs::metadata(&path)
+            .with_context(|| format!("failed to read network namespace {:?}", path))?
+            .ino();
+        externals.push(format!("net[{}]:{}", inode, EXTERNAL_NETNS));
+    }
+    Ok(externals)
+}
+
+/// Records where the stdio of the init process goes, e.g. a pipe of the engine
+fn write_descriptors(pid: Pid, image_path: &Path) -> Result<()> {
+    let descriptors = (0..3)
+        .map(|fd| {
+            let path = format!("/proc/{}/fd/{}", pid, fd);
+            Ok(fs::read_link(&path)?.display().to_string())
+        })
+        .collect::<Result<Vec<String>>>()?;
+    fs::write(
+        image_path.join(DESCRIPTORS),
+        serde_json::to_vec(&descriptors)?,
+    )?;
+    Ok(())
+}
+
+/// Runs CRIU and waits for it, killing it if it takes longer than the timeout
+fn run(args: &[String], timeout: Duration, log_path: &Path) -> Result<()> {
+    log::debug!("run {} {:?}", CRIU, args);
+    let mut child = match process::Command::new(CRIU).args(args).spawn() {
+        Ok(child) => child,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            bail!(
+                "{} is not installed, it is needed to checkpoint and restore",
+                CRIU
+            )
+        }
+        Err(e) => return Err(anyhow!("failed to run {}: {}", CRIU, e)),
+    };
+
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            if status.success() {
+                return Ok(());
+            }
+            bail!(
+                "{} {} failed with {}, see {:?}",
+                CRIU,
+                args[0],
+                status,
+                log_path
+            );
+        }
+        if start.elapsed() > timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            bail!(
+                "{} {} did not finish within {:?}, see {:?}",
+                CRIU,
+                args[0],
+                timeout,
+                log_path
+            );
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_common_args() {
+        let mut options = Options {
+            image_path: PathBuf::from("/images"),
+            tcp_established: true,
+            ..Default::default()
+        };
+        let args = options.common_args("dump.log").unwrap();
+        assert_eq!(args[..2], ["--images-dir", "/images"]);
+        assert!(args.contains(&"--tcp-established".to_owned()));
+        assert!(!args.contains(&"--shell-job".to_owned()));
+        assert!(args.contains(&"--manage-cgroups".to_owned()));
+        assert_eq!(
+            options.log_path("dump.log"),
+            PathBuf::from("/images/dump.log")
+        );
+
+        options.manage_cgroups_mode = Some("full".to_owned());
+        options.work_path = Some(PathBuf::from("/work"));
+        let args = options.common_args("dump.log").unwrap();
+        assert!(args.contains(&"--manage-cgroups=full".to_owned()));
+        assert!(args.contains(&"--work-dir".to_owned()));
+        assert_eq!(
+            options.log_path("dump.log"),
+            PathBuf::from("/work/dump.log")
+        );
+
+        options.manage_cgroups_mode = Some("none".to_owned());
+        assert!(options.common_args("dump.log").is_err());
+    }
+
+    #[test]
+    fn test_external_args() {
+        let spec: Spec = serde_json::from_value(serde_json::json!({
+            "ociVersion": "1.0.2",
+            "root": { "path": "rootfs" },
+            "process": { "user": { "uid": 0, "gid": 0 }, "args": ["sh"], "cwd": "/" },
+            "mounts": [
+                { "destination": "/proc", "type": "proc", "source": "proc" },
+                { "destination": "/data", "type": "none", "source": "/srv", "options": ["rbind"] },
+                { "destination": "/etc/hosts", "type": "bind", "source": "/hosts" }
+            ],
+            "linux": { "namespaces": [{ "type": "network", "path": "/proc/self/ns/net" }] }
+        }))
+        .unwrap();
+        let externals = external_args(&spec).unwrap();
+        assert_eq!(externals[0], "mnt[/data]:/data");
+        assert_eq!(externals[1], "mnt[/etc/hosts]:/etc/hosts");
+        assert!(externals[2].starts_with("net["));
+        assert!(externals[2].ends_with(&format!("]:{}", EXTERNAL_NETNS)));
+
+        let descriptors = vec![
+            "/dev/null".to_owned(),
+            "pipe:[1234]".to_owned(),
+            "pipe:[1235]".to_owned(),
+        ];
+        let args = restore_external_args(&spec, &descriptors);
+        assert_eq!(
+            args[..4],
+            [
+                "--external",
+                "mnt[/data]:/srv",
+                "--external",
+                "mnt[/etc/hosts]:/hosts"
+            ]
+        );
+        assert_eq!(
+            args[4..],
+            [
+                "--inherit-fd",
+                "fd[1]:pipe:[1234]",
+                "--inherit-fd",
+                "fd[2]:pipe:[1235]"
+            ]
+        );
+    }
+}
@@ -0,0 +1,175 @@
+//! Creates the device nodes of the container in its /dev: those of linux.devices along with
+//! the ones every container gets. /dev/console is bound to the terminal of the container
+//! later on, once it is allocated.
+
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use nix::errno::Errno;
+use nix::fcntl::{open, OFlag};
+use nix::mount::{mount as nix_mount, MsFlags};
+use nix::sys::stat::{mknod, umask, Mode};
+use nix::unistd::{chown, close, Gid, Uid};
+use oci_spec::{LinuxDevice, LinuxDeviceType};
+
+use crate::utils::PathBufExt;
+
+/// The devices which the runtime provides to every container
+pub fn default_devices() -> Vec<LinuxDevice> {
+    [
+        ("/dev/null", 1, 3),
+        ("/dev/zero", 1, 5),
+        ("/dev/full", 1, 7),
+        ("/dev/tty", 5, 0),
+        ("/dev/urandom", 1, 9),
+        ("/dev/random", 1, 8),
+    ]
+    .iter()
+    .map(|&(path, major, minor)| LinuxDevice {
+        path: PathBuf::from(path),
+        typ: LinuxDeviceType::C,
+        major,
+        minor,
+        file_mode: Some(0o666),
+        uid: None,
+        gid: None,
+    })
+    .collect()
+}
+
+/// The devices to create, the default ones unless the config gives the same path
+fn devices_to_create(devices: &[LinuxDevice]) -> Vec<LinuxDevice> {
+    default_devices()
+        .into_iter()
+        .filter(|default| devices.iter().all(|dev| dev.path != default.path))
+        .chain(devices.iter().cloned())
+        .collect()
+}
+
+/// Creates the devices relative to the current directory, which is the rootfs. In a user
+/// namespace, where device nodes cannot be created, those of the host are bound instead.
+pub fn create_devices(devices: &[LinuxDevice], bind: bool) -> Result<()> {
+    let devices = devices_to_create(devices);
+    for dev in &devices {
+        if !is_device_path(&dev.path) {
+            bail!("{:?} is not a valid device path", dev.path);
+        }
+    }
+    let old_mode = umask(Mode::empty());
+    let created = devices.iter().try_for_each(|dev| {
+        let path = dev.path.as_in_container()?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if bind {
+            bind_dev(dev, &path)
+        } else {
+            mknod_dev(dev, &path)
+        }
+        .with_context(|| format!("failed to create device {:?}", dev.path))
+    });
+    umask(old_mode);
+    created
+}
+
+/// Whether the path is a file below /dev, which .. could leave for anywhere in the rootfs or
+/// beyond it
+fn is_device_path(path: &Path) -> bool {
+    match path.strip_prefix("/dev") {
+        Ok(rest) => {
+            rest.components().next().is_some()
+                && rest
+                    .components()
+                    .all(|component| matches!(component, Component::Normal(_)))
+        }
+        Err(_) => false,
+    }
+}
+
+fn bind_dev(dev: &LinuxDevice, path: &Path) -> Result<()> {
+    let fd = open(
+        path,
+        OFlag::O_RDWR | OFlag::O_CREAT,
+        Mode::from_bits_truncate(0o644),
+    )?;
+    close(fd)?;
+    nix_mount(
+        Some(&dev.path),
+        path,
+        None::<&str>,
+        MsFlags::MS_BIND,
+        None::<&str>,
+    )?;
+    Ok(())
+}
+
+fn mknod_dev(dev: &LinuxDevice, path: &Path) -> Result<()> {
+    fn makedev(major: u64, minor: u64) -> u64 {
+        (minor & 0xff) | ((major & 0xfff) << 8) | ((minor & !0xff) << 12) | ((major & !0xfff) << 32)
+    }
+
+    match mknod(
+        path,
+        dev.typ.to_sflag()?,
+        Mode::from_bits_truncate(dev.file_mode.unwrap_or(0)),
+        makedev(dev.major, dev.minor),
+    ) {
+        // e.g. the image has it in a /dev which is not a mount of its own
+        Err(nix::Error::Sys(Errno::EEXIST)) => {
+            log::debug!("device {:?} exists already", dev.path);
+            return Ok(());
+        }
+        // not permitted without CAP_MKNOD, the one of the host is bound instead
+        Err(nix::Error::Sys(Errno::EPERM)) => return bind_dev(dev, path),
+        Err(e) => return Err(e.into()),
+        Ok(()) => {}
+    }
+    chown(path, dev.uid.map(Uid::from_raw), dev.gid.map(Gid::from_raw))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_devices_to_create() {
+        let fuse = LinuxDevice {
+            path: PathBuf::from("/dev/fuse"),
+            typ: LinuxDeviceType::C,
+            major: 10,
+            minor: 229,
+            file_mode: Some(0o666),
+            uid: None,
+            gid: None,
+        };
+        let null = LinuxDevice {
+            path: PathBuf::from("/dev/null"),
+            file_mode: Some(0o600),
+            uid: Some(1000),
+            ..fuse.clone()
+        };
+        let devices = devices_to_create(&[fuse, null]);
+        assert_eq!(devices.len(), default_devices().len() + 1);
+        let nulls: Vec<_> = devices
+            .iter()
+            .filter(|dev| dev.path == Path::new("/dev/null"))
+            .collect();
+        assert_eq!(nulls.len(), 1);
+        assert_eq!(nulls[0].uid, Some(1000));
+        assert!(devices.iter().any(|dev| dev.path == Path::new("/dev/fuse")));
+    }
+    #[test]
+    fn test_is_device_path() {
+        assert!(is_device_path(Path::new("/dev/fuse")));
+        assert!(is_device_path(Path::new("/dev/net/tun")));
+        assert!(!is_device_path(Path::new("/dev")));
+        assert!(!is_device_path(Path::new("/dev/")));
+        assert!(!is_device_path(Path::new("/devices/null")));
+        assert!(!is_device_path(Path::new("dev/null")));
+        assert!(!is_device_path(Path::new("/etc/passwd")));
+        assert!(!is_device_path(Path::new("/dev/../../../host/x")));
+        assert!(!is_device_path(Path::new("/dev/net/../../etc/passwd")));
+    }
+}
@@ -0,0 +1,98 @@
+//! Reports resource usage statistics of a container, once or as a stream of JSON lines for
+//! monitoring agents
+
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use clap::Clap;
+use nix::unistd::Pid;
+use serde::Serialize;
+
+use crate::cgroups;
+use crate::cgroups::common::CgroupManager;
+use crate::cgroups::stats::{self, Stats};
+use crate::container::Container;
+
+#[derive(Clap, Debug)]
+pub struct Events {
+    /// display the statistics of the container once and exit
+    #[clap(long)]
+    pub stats: bool,
+    /// seconds between the statistics while streaming them
+    #[clap(long, default_value = "5")]
+    pub interval: u64,
+    pub container_id: String,
+}
+
+/// Event in the format of runc, which tools consuming the events expect
+#[derive(Serialize, Debug)]
+struct Event {
+    #[serde(rename = "type")]
+    typ: String,
+    id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<Stats>,
+}
+
+impl Events {
+    pub fn exec(&self, roots: &[PathBuf]) -> Result<()> {
+        if self.interval == 0 {
+            bail!("interval must be greater than 0");
+        }
+
+        let container = Container::find(roots, &self.container_id)?.refresh_status()?;
+        let pid = match container.pid() {
+            Some(pid) if container.can_kill() => pid,
+            _ => bail!(
+                "{} has no statistics because it was {:?}",
+                container.id(),
+                container.status()
+            ),
+        };
+
+        let cmanager = cgroups::common::create_cgroup_manager(container.cgroups_path()?)?;
+        if self.stats {
+            return self.emit("stats", Some(collect(cmanager.as_ref(), pid)?));
+        }
+
+        // streams until the container stops, with an oom event whenever the OOM killer has
+        // killed one of its processes since the last statistics
+        let mut oom_kills = cmanager.oom_kill_count()?;
+        loop {
+            let data = match collect(cmanager.as_ref(), pid) {
+                Ok(data) => data,
+                Err(e) if container.refresh_status()?.can_kill() => return Err(e),
+                Err(_) => return Ok(()),
+            };
+            let count = cmanager.oom_kill_count()?;
+            if count > oom_kills {
+                self.emit("oom", None)?;
+                oom_kills = count;
+            }
+            self.emit("stats", Some(data))?;
+
+            thread::sleep(Duration::from_secs(self.interval));
+            if !container.refresh_status()?.can_kill() {
+                return Ok(());
+            }
+        }
+    }
+
+    fn emit(&self, typ: &str, data: Option<Stats>) -> Result<()> {
+        let event = Event {
+            typ: typ.to_owned(),
+            id: self.container_id.clone(),
+            data,
+        };
+        println!("{}", serde_json::to_string(&event)?);
+        Ok(())
+    }
+}
+
+fn collect(cmanager: &dyn CgroupManager, pid: Pid) -> Result<Stats> {
+    let mut data = cmanager.stats()?;
+    data.network_interfaces = stats::network_interfaces(pid)?;
+    Ok(data)
+}
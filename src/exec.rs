@@ -0,0 +1,410 @@
+//! Runs another process in a running container, e.g. for docker exec. The process joins the
+//! namespaces and cgroups of the init process of the container, and gets the user,
+//! capabilities, labels and seccomp filter of the container unless they are overridden.
+
+use std::fs;
+use std::os::unix::io::RawFd;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use anyhow::{bail, Context, Result};
+use clap::Clap;
+use nix::errno::Errno;
+use nix::sys::stat::{self, Mode};
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::{self, ForkResult, Gid, Pid, Uid};
+use oci_spec::{Linux, LinuxNamespace, LinuxNamespaceType, LinuxSeccomp};
+
+use crate::capabilities;
+use crate::cgroups;
+use crate::command::Command;
+use crate::container::{Container, ContainerStatus};
+use crate::cpu_affinity;
+use crate::ioprio;
+use crate::namespaces::Namespaces;
+use crate::personality;
+use crate::process::channel::{channel, Channel};
+use crate::process::message::{Message, Phase};
+use crate::rlimits;
+use crate::scheduler;
+use crate::seccomp;
+use crate::selinux;
+use crate::utils;
+
+#[derive(Clap, Debug)]
+pub struct Exec {
+    /// path to a json file with the process to run, in the format of process of
+    /// config.json, instead of giving the command
+    #[clap(short, long)]
+    process: Option<PathBuf>,
+    /// working directory of the process, instead of the one of the container
+    #[clap(long)]
+    cwd: Option<String>,
+    /// set an environment variable as NAME=value, in addition to those of the container
+    #[clap(short, long, number_of_values = 1)]
+    env: Vec<String>,
+    /// run the process as uid[:gid] instead of the user of the container
+    #[clap(short, long)]
+    user: Option<String>,
+    /// do not wait for the process to exit
+    #[clap(short, long)]
+    detach: bool,
+    /// file to write the pid of the process to
+    #[clap(long)]
+    pid_file: Option<PathBuf>,
+    /// pass N additional file descriptors, from 3 on, to the process
+    #[clap(long, default_value = "0")]
+    preserve_fds: u32,
+    pub container_id: String,
+    /// command to run and its arguments
+    command: Vec<String>,
+}
+
+impl Exec {
+    pub fn exec(&self, root_path: PathBuf, command: impl Command) -> Result<()> {
+        let container_root = root_path.join(&self.container_id);
+        if !container_root.exists() {
+            bail!("{} doesn't exist.", self.container_id)
+        }
+        let container = Container::load(container_root)?.refresh_status()?;
+        let pid = match container.pid() {
+            Some(pid) if container.status() == ContainerStatus::Running => pid,
+            _ => bail!(
+                "cannot exec in {} because it was {:?}",
+                container.id(),
+                container.status()
+            ),
+        };
+
+        let spec = oci_spec::Spec::load(&container.root.join("config.json").to_string_lossy())?;
+        let mut process = self.process(&spec)?;
+        if process.selinux_label.is_empty() {
+            if let Some(labels) = &container.state.selinux_labels {
+                process.selinux_label = labels.process_label.clone();
+            }
+        }
+        let namespaces = container_namespaces(
+            pid,
+            spec.linux
+                .as_ref()
+                .map_or(&[][..], |linux| &linux.namespaces),
+        );
+
+        let (mut channel, mut child_channel) = channel()?;
+        match unsafe { unistd::fork()? } {
+            ForkResult::Child => {
+                drop(channel);
+                let pid = match join(
+                    &container,
+                    namespaces,
+                    &process,
+                    spec.linux.as_ref(),
+                    &command,
+                    self.preserve_fds,
+                    &mut child_channel,
+                ) {
+                    Ok(pid) => pid,
+                    Err(err) => {
+                        report_error(&mut child_channel, &err);
+                        process::exit(1);
+                    }
+                };
+                let sent = child_channel.send(&Message::InitPid(pid.as_raw()));
+                // from here on only the process holds the channel, until it executes
+                drop(child_channel);
+                if sent.is_err() || self.detach {
+                    process::exit(0);
+                }
+                let status = match waitpid(pid, None) {
+                    Ok(WaitStatus::Exited(_, status)) => status,
+                    Ok(WaitStatus::Signaled(_, signal, _)) => 128 + signal as i32,
+                    _ => 1,
+                };
+                process::exit(status);
+            }
+            ForkResult::Parent { child } => {
+                drop(child_channel);
+                let process_pid = wait_for_exec(&mut channel, child)?;
+                if let Some(pid_file) = &self.pid_file {
+                    utils::write_pid_file(pid_file, process_pid)?;
+                }
+                match waitpid(child, None)? {
+                    WaitStatus::Exited(_, 0) => Ok(()),
+                    // the intermediate process exits with the status of the process
+                    WaitStatus::Exited(_, status) => process::exit(status),
+                    status => bail!("exec process ended unexpectedly: {:?}", status),
+                }
+            }
+        }
+    }
+
+    /// The process to run, from the file given or the command line on top of the one of
+    /// the container
+    fn process(&self, spec: &oci_spec::Spec) -> Result<oci_spec::Process> {
+        let mut process = match &self.process {
+            Some(path) => {
+                let file = fs::File::open(path)
+                    .with_context(|| format!("failed to open process {:?}", path))?;
+                serde_json::from_reader(file)
+                    .with_context(|| format!("failed to parse process {:?}", path))?
+            }
+            None if self.command.is_empty() => bail!("no command to run is given"),
+            None => {
+                let mut process = spec.process.clone();
+                process.args = self.command.clone();
+                process.terminal = false;
+                process
+            }
+        };
+        if process.terminal {
+            bail!("exec does not support a terminal yet");
+        }
+        rlimits::validate(&process.rlimits)?;
+        if let Some(io_priority) = &process.io_priority {
+            ioprio::validate(io_priority)?;
+        }
+        if let Some(scheduler) = &process.scheduler {
+            scheduler::validate(scheduler)?;
+        }
+        if let Some(affinity) = &process.exec_cpu_affinity {
+            cpu_affinity::validate(affinity)?;
+        }
+        if let Some(cwd) = &self.cwd {
+            process.cwd = cwd.clone();
+        }
+        process.env.extend(self.env.iter().cloned());
+        if let Some(user) = &self.user {
+            let (uid, gid) = parse_user(user)?;
+            process.user.uid = uid;
+            process.user.gid = gid;
+        }
+        Ok(process)
+    }
+}
+
+/// Namespaces of the init process of the container, with the user namespace first, as the
+/// others are owned by it
+fn container_namespaces(pid: Pid, namespaces: &[LinuxNamespace]) -> Vec<LinuxNamespace> {
+    let mut namespaces: Vec<LinuxNamespace> = namespaces
+        .iter()
+        .map(|ns| LinuxNamespace {
+            typ: ns.typ,
+            path: Some(format!("/proc/{}/ns/{}", pid, proc_ns_name(ns.typ))),
+        })
+        .collect();
+    namespaces.sort_by_key(|ns| ns.typ != LinuxNamespaceType::User);
+    namespaces
+}
+
+fn proc_ns_name(typ: LinuxNamespaceType) -> &'static str {
+    match typ {
+        LinuxNamespaceType::Mount => "mnt",
+        LinuxNamespaceType::Cgroup => "cgroup",
+        LinuxNamespaceType::Uts => "uts",
+        LinuxNamespaceType::Ipc => "ipc",
+        LinuxNamespaceType::User => "user",
+        LinuxNamespaceType::Pid => "pid",
+        LinuxNamespaceType::Network => "net",
+        LinuxNamespaceType::Time => "time",
+    }
+}
+
+/// Parses uid[:gid], where the gid defaults to the uid
+fn parse_user(user: &str) -> Result<(u32, u32)> {
+    let mut ids = user.splitn(2, ':');
+    let uid = ids.next().unwrap_or_default();
+    let uid: u32 = uid
+        .parse()
+        .with_context(|| format!("invalid uid in {}", user))?;
+    let gid = match ids.next() {
+        Some(gid) => gid
+            .parse()
+            .with_context(|| format!("invalid gid in {}", user))?,
+        None => uid,
+    };
+    Ok((uid, gid))
+}
+
+/// Joins the cgroups and namespaces of the container and forks the process, as only the
+/// children of this one are in the pid namespace. Returns the pid of the process.
+fn join(
+    container: &Container,
+    namespaces: Vec<LinuxNamespace>,
+    process: &oci_spec::Process,
+    linux: Option<&Linux>,
+    command: &impl Command,
+    preserve_fds: u32,
+    channel: &mut Channel,
+) -> Result<Pid> {
+    if let Some(affinity) = &process.exec_cpu_affinity {
+        cpu_affinity::apply_initial(affinity).context(Phase::new("set CPU affinity"))?;
+    }
+    let cmanager = cgroups::common::create_cgroup_manager(container.cgroups_path()?)?;
+    cmanager
+        .add_task(unistd::getpid())
+        .context(Phase::new("join cgroups"))?;
+    // before the user namespace of the container is joined, the process inherits it
+    if let Some(adj) = process.oom_score_adj {
+        utils::set_oom_score_adj(adj).context(Phase::new("set oom_score_adj"))?;
+    }
+    let namespaces: Namespaces = namespaces.into();
+    namespaces.apply_setns()?;
+
+    match unsafe { unistd::fork()? } {
+        ForkResult::Child => {
+            let err = match prepare_process(process, linux, command, preserve_fds) {
+                Ok(()) => {
+                    let args = &process.args;
+                    utils::do_exec(&args[0], args, &process.env)
+                        .with_context(|| Phase::with_path("exec", &args[0]))
+                        .unwrap_err()
+                }
+                Err(err) => err,
+            };
+            report_error(channel, &err);
+            process::exit(127);
+        }
+        ForkResult::Parent { child } => Ok(child),
+    }
+}
+
+/// Sets up the process like init does for the command of the container
+fn prepare_process(
+    process: &oci_spec::Process,
+    linux: Option<&Linux>,
+    command: &impl Command,
+    preserve_fds: u32,
+) -> Result<()> {
+    // the limits come from the process only, not from the runtime
+    utils::restore_nofile_limit().context(Phase::new("restore RLIMIT_NOFILE"))?;
+    for rlimit in process.rlimits.iter() {
+        command
+            .set_rlimit(rlimit)
+            .with_context(|| Phase::new(&format!("setrlimit {:?}", rlimit.typ)))?
+    }
+    if !process.cwd.is_empty() {
+        unistd::chdir(Path::new(&process.cwd))
+            .with_context(|| Phase::with_path("chdir", &process.cwd))?;
+    }
+    // the process gets the filter of the container as well, which needs either this or
+    // CAP_SYS_ADMIN, so it is loaded before the capabilities are dropped if this is not set
+    if process.no_new_privileges {
+        prctl::set_no_new_privileges(true)
+            .map_err(Errno::from_i32)
+            .context(Phase::new("set no_new_privileges"))?;
+    }
+    if !process.selinux_label.is_empty() {
+        selinux::set_exec_label(&process.selinux_label).context(Phase::new("set SELinux label"))?;
+    }
+    let gids: Vec<Gid> = process
+        .user
+        .additional_gids
+        .iter()
+        .map(|gid| Gid::from_raw(*gid))
+        .collect();
+    command
+        .set_groups(&gids)
+        .context(Phase::new("set additional gids"))?;
+    command
+        .set_id(
+            Uid::from_raw(process.user.uid),
+            Gid::from_raw(process.user.gid),
+        )
+        .context(Phase::new("set user"))?;
+    if let Some(umask) = process.user.umask {
+        stat::umask(Mode::from_bits_truncate(umask));
+    }
+    if let Some(personality) = linux.and_then(|linux| linux.personality.as_ref()) {
+        personality::apply(personality).context(Phase::new("set personality"))?;
+    }
+    if let Some(affinity) = &process.exec_cpu_affinity {
+        cpu_affinity::apply_final(affinity).context(Phase::new("set CPU affinity"))?;
+    }
+    // while still privileged, as a real-time policy or a lower nice needs CAP_SYS_NICE
+    if let Some(scheduler) = &process.scheduler {
+        scheduler::apply(scheduler).context(Phase::new("set scheduler"))?;
+    }
+    if let Some(io_priority) = &process.io_priority {
+        ioprio::apply(io_priority).context(Phase::new("set I/O priority"))?;
+    }
+    capabilities::reset_effective(command).context(Phase::new("set capabilities"))?;
+    let seccomp = linux.and_then(|linux| linux.seccomp.as_ref());
+    if let Some(seccomp) = seccomp.filter(|_| !process.no_new_privileges) {
+        load_seccomp(seccomp)?;
+    }
+    // a process without capabilities in the spec gets none, not those of the runtime
+    let caps = process.capabilities.clone().unwrap_or_default();
+    capabilities::drop_privileges(&caps, command).context(Phase::new("set capabilities"))?;
+    if let Some(seccomp) = seccomp.filter(|_| process.no_new_privileges) {
+        load_seccomp(seccomp)?;
+    }
+    utils::close_on_exec_from(3 + preserve_fds as RawFd)
+        .context(Phase::new("close file descriptors"))?;
+    Ok(())
+}
+
+/// Loads the filter of the container. Only the listener of the init process is handed over
+/// to the agent, syscalls of the process which would notify it fail instead.
+fn load_seccomp(seccomp: &LinuxSeccomp) -> Result<()> {
+    if let Some(listener) = seccomp::load(seccomp).context(Phase::new("set up seccomp"))? {
+        log::warn!("the seccomp listener of an exec process is not handed over");
+        let _ = unistd::close(listener);
+    }
+    Ok(())
+}
+
+fn report_error(channel: &mut Channel, err: &anyhow::Error) {
+    if let Err(e) = channel.send(&Message::Error(err.into())) {
+        log::warn!("failed to report error to exec: {}", e);
+    }
+}
+
+/// Waits for the process to be forked and executed, and returns its pid. The process holds
+/// the channel until it executes, which closes it as it is close-on-exec.
+fn wait_for_exec(channel: &mut Channel, child: Pid) -> Result<Pid> {
+    let pid = match channel.recv() {
+        Ok(Message::InitPid(pid)) => Pid::from_raw(pid),
+        Ok(Message::Error(err)) => bail!("{}", err),
+        Ok(msg) => bail!("receive unexpected message {:?} in exec", msg),
+        Err(_) => {
+            let _ = waitpid(child, None);
+            bail!("exec process exited before it forked the process")
+        }
+    };
+    match channel.recv() {
+        Ok(Message::Error(err)) => bail!("{}", err),
+        Ok(msg) => bail!("receive unexpected message {:?} in exec", msg),
+        Err(_) => Ok(pid),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_user() {
+        assert_eq!(parse_user("1000").unwrap(), (1000, 1000));
+        assert_eq!(parse_user("1000:100").unwrap(), (1000, 100));
+        assert!(parse_user("nobody").is_err());
+        assert!(parse_user("1000:").is_err());
+    }
+
+    #[test]
+    fn test_container_namespaces() {
+        let namespaces = vec![
+            LinuxNamespace {
+                typ: LinuxNamespaceType::Mount,
+                path: None,
+            },
+            LinuxNamespace {
+                typ: LinuxNamespaceType::User,
+                path: Some("/run/netns/other".to_owned()),
+            },
+        ];
+        let joined = container_namespaces(Pid::from_raw(42), &namespaces);
+        assert_eq!(joined[0].typ, LinuxNamespaceType::User);
+        assert_eq!(joined[0].path.as_deref(), Some("/proc/42/ns/user"));
+        assert_eq!(joined[1].path.as_deref(), Some("/proc/42/ns/mnt"));
+    }
+}
@@ -0,0 +1,129 @@
+//! Holds the init process of a created container until it is started. The init process
+//! blocks opening a fifo in the container directory for writing, and start lets it go by
+//! reading from the fifo, which is removed once the container is started.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::prelude::*;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use nix::errno::Errno;
+use nix::fcntl::{self, OFlag};
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::stat::Mode;
+use nix::unistd;
+
+pub const EXEC_FIFO: &str = "exec.fifo";
+
+/// Creates the fifo in the container directory
+pub fn create(container_dir: &Path) -> Result<()> {
+    let path = container_dir.join(EXEC_FIFO);
+    unistd::mkfifo(&path, Mode::from_bits_truncate(0o622))
+        .with_context(|| format!("failed to create {:?}", path))?;
+    // the process of the container may run as any user by the time it writes
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o622))?;
+    Ok(())
+}
+
+/// The end of the fifo which the init process holds
+pub struct ExecFifo {
+    fd: RawFd,
+}
+
+impl ExecFifo {
+    /// Opens the fifo while it is reachable, i.e. before the root is changed
+    pub fn open(container_dir: &Path) -> Result<Self> {
+        let path = container_dir.join(EXEC_FIFO);
+        let fd = fcntl::open(&path, OFlag::O_PATH | OFlag::O_CLOEXEC, Mode::empty())
+            .with_context(|| format!("failed to open {:?}", path))?;
+        Ok(Self { fd })
+    }
+
+    /// Blocks until start reads from the fifo. It is reopened for writing through /proc of
+    /// the container, as the container directory is out of reach by now.
+    pub fn wait_for_start(self) -> Result<()> {
+        let path = format!("/proc/self/fd/{}", self.fd);
+        let mut fifo = OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("failed to open {}", path))?;
+        fifo.write_all(b"0")?;
+        Ok(())
+    }
+}
+
+impl Drop for ExecFifo {
+    fn drop(&mut self) {
+        let _ = unistd::close(self.fd);
+    }
+}
+
+/// Lets the init process of the container go, which must take it within the timeout
+pub fn start(container_dir: &Path, timeout: Duration) -> Result<()> {
+    let path = container_dir.join(EXEC_FIFO);
+    if !path.exists() {
+        bail!("the container has been started already");
+    }
+    // opening for reading does not wait for the init process, polling does
+    let fd = fcntl::open(
+        &path,
+        OFlag::O_RDONLY | OFlag::O_NONBLOCK | OFlag::O_CLOEXEC,
+        Mode::empty(),
+    )
+    .with_context(|| format!("failed to open {:?}", path))?;
+    let mut fifo = unsafe { File::from_raw_fd(fd) };
+    let timeout_ms = timeout.as_millis().min(i32::MAX as u128) as i32;
+    let mut fds = [PollFd::new(fd, PollFlags::POLLIN)];
+    loop {
+        match poll(&mut fds, timeout_ms) {
+            Ok(0) => bail!("the init process of the container did not take the start in time"),
+            Ok(_) => break,
+            Err(nix::Error::Sys(Errno::EINTR)) => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    let mut buf = [0; 1];
+    if fifo.read(&mut buf)? == 0 {
+        bail!("the init process of the container exited before it was started");
+    }
+    fs::remove_file(&path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_start() {
+        let tmp = std::env::temp_dir().join("test_exec_fifo_start");
+        let _ = fs::remove_dir_all(&tmp);
+        fs::create_dir_all(&tmp).unwrap();
+        create(&tmp).unwrap();
+
+        let fifo = ExecFifo::open(&tmp).unwrap();
+        let init = thread::spawn(move || fifo.wait_for_start());
+        start(&tmp, Duration::from_secs(5)).unwrap();
+        init.join().unwrap().unwrap();
+        assert!(!tmp.join(EXEC_FIFO).exists());
+        assert!(start(&tmp, Duration::from_secs(5)).is_err());
+        fs::remove_dir_all(&tmp).unwrap();
+    }
+
+    #[test]
+    fn test_start_timeout() {
+        let tmp = std::env::temp_dir().join("test_exec_fifo_start_timeout");
+        let _ = fs::remove_dir_all(&tmp);
+        fs::create_dir_all(&tmp).unwrap();
+        create(&tmp).unwrap();
+
+        assert!(start(&tmp, Duration::from_millis(10)).is_err());
+        // the container can still be started
+        assert!(tmp.join(EXEC_FIFO).exists());
+        fs::remove_dir_all(&tmp).unwrap();
+    }
+}
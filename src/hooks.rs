@@ -0,0 +1,362 @@
+//! Hooks of the container, which are run at points of its lifecycle with its state on stdin.
+//!
+//! Administrators may define hooks for every container as drop-in files in the format of
+//! oci-hooks(5), e.g. to inject GPU devices or to audit containers without editing bundles.
+//! They are added after the hooks of the bundle when the container is created.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context, Result};
+use oci_spec::{Hook, Hooks, Spec};
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::container::OciState;
+
+pub const DROP_IN_DIR: &str = "/etc/youki/hooks.d";
+
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Hooks which the runtime runs once the mounts of the container are prepared, before
+/// its root is changed. Prestart hooks are deprecated in favor of createRuntime ones, and
+/// run first.
+pub fn runtime_hooks(hooks: &Option<Hooks>) -> Vec<&Hook> {
+    match hooks {
+        Some(hooks) => hooks.prestart.iter().chain(&hooks.create_runtime).collect(),
+        None => Vec::new(),
+    }
+}
+
+/// Time the hooks may take at most, each one its own timeout or the default one
+pub fn timeout_of<'a>(hooks: impl IntoIterator<Item = &'a Hook>, default: Duration) -> Duration {
+    hooks
+        .into_iter()
+        .map(|hook| hook.timeout.map_or(default, Duration::from_secs))
+        .sum()
+}
+
+/// Runs the hooks one after another with the state of the container on stdin. Fails as
+/// soon as one fails or does not finish within its timeout, or the default one.
+pub fn run<'a>(
+    hooks: impl IntoIterator<Item = &'a Hook>,
+    state: &OciState,
+    default_timeout: Duration,
+) -> Result<()> {
+    let state = serde_json::to_vec(state)?;
+    for hook in hooks {
+        let timeout = hook.timeout.map_or(default_timeout, Duration::from_secs);
+        run_hook(hook, &state, timeout).with_context(|| format!("hook {:?}", hook.path))?;
+    }
+    Ok(())
+}
+
+fn run_hook(hook: &Hook, state: &[u8], timeout: Duration) -> Result<()> {
+    log::debug!("run hook {:?}", hook);
+    let mut command = Command::new(&hook.path);
+    // args are those of execv, the first one is the name of the program
+    if let Some((arg0, args)) = hook.args.split_first() {
+        command.arg0(arg0).args(args);
+    }
+    command.env_clear().envs(hook.env.iter().filter_map(|env| {
+        let mut pair = env.splitn(2, '=');
+        Some((pair.next()?, pair.next()?))
+    }));
+    let mut child = command
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| anyhow!("failed to run: {}", e))?;
+
+    // a hook which does not care about the state may exit without reading it
+    let mut stdin = child.stdin.take().unwrap();
+    match stdin.write_all(state) {
+        Err(e) if e.kind() != io::ErrorKind::BrokenPipe => {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(e.into());
+        }
+        _ => drop(stdin),
+    }
+
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            if status.success() {
+                return Ok(());
+            }
+            bail!("failed with {}", status);
+        }
+        if start.elapsed() > timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            bail!("did not finish within {:?}", timeout);
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Hook defined in a drop-in file, along with when and at which stages it is run
+#[derive(Deserialize, Debug)]
+struct DropIn {
+    version: String,
+    hook: Hook,
+    when: When,
+    stages: Vec<Stage>,
+}
+
+/// Conditions of a drop-in hook, of which any one is enough for it to be run
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+struct When {
+    #[serde(default)]
+    always: bool,
+    /// regular expressions of the keys and values of annotations of the container
+    #[serde(default)]
+    annotations: HashMap<String, String>,
+    /// regular expressions of the command of the container
+    #[serde(default)]
+    commands: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+enum Stage {
+    Prestart,
+    CreateRuntime,
+    CreateContainer,
+    StartContainer,
+    Poststart,
+    Poststop,
+}
+
+impl When {
+    fn matches(&self, spec: &Spec) -> Result<bool> {
+        if self.always {
+            return Ok(true);
+        }
+        for (key, value) in &self.annotations {
+            let key = Regex::new(key)?;
+            let value = Regex::new(value)?;
+            if spec
+                .annotations
+                .iter()
+                .any(|(k, v)| key.is_match(k) && value.is_match(v))
+            {
+                return Ok(true);
+            }
+        }
+        if let Some(command) = spec.process.args.first() {
+            for pattern in &self.commands {
+                if Regex::new(pattern)?.is_match(command) {
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
+    }
+}
+
+/// Adds the hooks of the drop-in files in the directory which match the container to the
+/// spec, in the order of the file names, and returns how many were added
+pub fn merge_drop_ins(spec: &mut Spec, dir: &Path) -> Result<usize> {
+    let mut added = 0;
+    let mut hooks = spec.hooks.take().unwrap_or_default();
+    for drop_in in load_drop_ins(dir)? {
+        if !drop_in.when.matches(spec)? {
+            continue;
+        }
+        for stage in &drop_in.stages {
+            stage_hooks(&mut hooks, *stage).push(drop_in.hook.clone());
+            added += 1;
+        }
+    }
+    if hooks != Hooks::default() {
+        spec.hooks = Some(hooks);
+    }
+    Ok(added)
+}
+
+fn load_drop_ins(dir: &Path) -> Result<Vec<DropIn>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut paths = entries
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    paths.retain(|path| path.extension().is_some_and(|ext| ext == "json"));
+    paths.sort();
+
+    paths
+        .iter()
+        .map(|path| {
+            let drop_in: DropIn = serde_json::from_slice(&fs::read(path)?)
+                .with_context(|| format!("failed to load the hook {:?}", path))?;
+            if drop_in.version != "1.0.0" {
+                bail!(
+                    "hook {:?} has version {}, but only 1.0.0 is supported",
+                    path,
+                    drop_in.version
+                )
+            }
+            Ok(drop_in)
+        })
+        .collect()
+}
+
+fn stage_hooks(hooks: &mut Hooks, stage: Stage) -> &mut Vec<Hook> {
+    match stage {
+        Stage::Prestart => &mut hooks.prestart,
+        Stage::CreateRuntime => &mut hooks.create_runtime,
+        Stage::CreateContainer => &mut hooks.create_container,
+        Stage::StartContainer => &mut hooks.start_container,
+        Stage::Poststart => &mut hooks.poststart,
+        Stage::Poststop => &mut hooks.poststop,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn setup_dir(name: &str, drop_ins: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("create hooks directory");
+        for (file, content) in drop_ins {
+            fs::write(dir.join(file), content).expect("write drop-in hook");
+        }
+        dir
+    }
+
+    fn spec() -> Spec {
+        serde_json::from_str(
+            r#"{
+                "ociVersion": "1.0.2",
+                "root": { "path": "rootfs" },
+                "process": { "user": { "uid": 0, "gid": 0 }, "args": ["/usr/bin/train"], "cwd": "/" },
+                "annotations": { "com.example.gpu": "true" },
+                "hooks": { "poststop": [{ "path": "/bundle/cleanup" }] }
+            }"#,
+        )
+        .expect("parse spec")
+    }
+
+    #[test]
+    fn test_merge_drop_ins() {
+        let dir = setup_dir(
+            "test_merge_drop_ins",
+            &[
+                (
+                    "10-gpu.json",
+                    r#"{ "version": "1.0.0", "hook": { "path": "/usr/bin/gpu-hook", "args": ["gpu-hook", "prestart"] },
+                         "when": { "annotations": { "^com\\.example\\.gpu$": "^true$" } },
+                         "stages": ["prestart", "createRuntime"] }"#,
+                ),
+                (
+                    "20-audit.json",
+                    r#"{ "version": "1.0.0", "hook": { "path": "/usr/bin/audit" },
+                         "when": { "always": true }, "stages": ["poststop"] }"#,
+                ),
+                (
+                    "30-other.json",
+                    r#"{ "version": "1.0.0", "hook": { "path": "/usr/bin/other" },
+                         "when": { "commands": ["^/bin/sh$"] }, "stages": ["poststart"] }"#,
+                ),
+                ("README", "not a hook"),
+            ],
+        );
+
+        let mut spec = spec();
+        assert_eq!(merge_drop_ins(&mut spec, &dir).unwrap(), 3);
+        let hooks = spec.hooks.unwrap();
+        assert_eq!(hooks.prestart[0].path, PathBuf::from("/usr/bin/gpu-hook"));
+        assert_eq!(hooks.create_runtime.len(), 1);
+        let poststop: Vec<_> = hooks.poststop.iter().map(|hook| &hook.path).collect();
+        assert_eq!(poststop, vec!["/bundle/cleanup", "/usr/bin/audit"]);
+        assert!(hooks.poststart.is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn state() -> OciState {
+        OciState {
+            oci_version: "1.0.2".to_owned(),
+            id: "test".to_owned(),
+            status: crate::container::ContainerStatus::Creating,
+            pid: Some(42),
+            bundle: "/bundle".to_owned(),
+            annotations: HashMap::new(),
+        }
+    }
+
+    fn hook(script: &str, timeout: Option<u64>) -> Hook {
+        Hook {
+            path: PathBuf::from("/bin/sh"),
+            args: vec!["sh".to_owned(), "-c".to_owned(), script.to_owned()],
+            env: vec!["HOOK_ENV=set".to_owned()],
+            timeout,
+        }
+    }
+
+    #[test]
+    fn test_run() {
+        let dir = setup_dir("test_run_hooks", &[]);
+        let out = dir.join("out");
+        let script = format!(
+            "cat > {0}; echo \"$0 $HOOK_ENV ${{HOME:-unset}}\" >> {0}",
+            out.display()
+        );
+        run(&[hook(&script, None)], &state(), Duration::from_secs(5)).unwrap();
+        let written = fs::read_to_string(&out).unwrap();
+        assert!(written
+            .starts_with(r#"{"ociVersion":"1.0.2","id":"test","status":"creating","pid":42"#));
+        assert!(written.ends_with("}sh set unset\n"));
+
+        // hooks after a failing one are not run
+        let failing = [hook("exit 3", None), hook(&script, None)];
+        let err = run(&failing, &state(), Duration::from_secs(5)).unwrap_err();
+        assert!(format!("{:#}", err).contains("exit status: 3"));
+        assert_eq!(fs::read_to_string(&out).unwrap(), written);
+
+        let slow = [hook("sleep 10", Some(1))];
+        let start = Instant::now();
+        assert!(run(&slow, &state(), Duration::from_secs(30)).is_err());
+        assert!(start.elapsed() < Duration::from_secs(5));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_timeout_of() {
+        let hooks = [hook("true", Some(3)), hook("true", None)];
+        assert_eq!(
+            timeout_of(&hooks, Duration::from_secs(30)),
+            Duration::from_secs(33)
+        );
+    }
+
+    #[test]
+    fn test_invalid_drop_in() {
+        let dir = setup_dir(
+            "test_invalid_drop_in",
+            &[(
+                "hook.json",
+                r#"{ "version": "2.0.0", "hook": { "path": "/usr/bin/hook" },
+                     "when": { "always": true }, "stages": ["prestart"] }"#,
+            )],
+        );
+        assert!(merge_drop_ins(&mut spec(), &dir).is_err());
+        assert_eq!(
+            merge_drop_ins(&mut spec(), &dir.join("missing")).unwrap(),
+            0
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
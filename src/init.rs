@@ -0,0 +1,381 @@
+//! Prepares the container from inside of its namespaces. The init process forked by
+//! create re-executes youki as this subcommand, so that the container is set up by a
+//! freshly started process instead of by code running after fork.
+
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use clap::Clap;
+use nix::errno::Errno;
+use nix::sched::CloneFlags;
+use nix::sys::stat::{self, Mode};
+use nix::unistd::{self, Gid, Uid};
+use oci_spec::LinuxNamespaceType;
+
+use crate::capabilities;
+use crate::command::Command;
+use crate::config::Timeouts;
+use crate::container::{Container, ContainerStatus, OciState};
+use crate::cpu_affinity;
+use crate::exec_fifo::ExecFifo;
+use crate::hooks;
+use crate::ioprio;
+use crate::landlock;
+use crate::namespaces::Namespaces;
+use crate::personality;
+use crate::process::message::Phase;
+use crate::process::{fork, InitProcess};
+use crate::reaper;
+use crate::rootfs;
+use crate::scheduler;
+use crate::seccomp;
+use crate::selinux;
+use crate::sysctl;
+use crate::tty;
+use crate::utils;
+
+/// Options of the init process of a container. It is run by create only,
+/// which hands over the channel to the parent process as well.
+#[derive(Clap, Debug, PartialEq)]
+pub struct Init {
+    /// path of the root filesystem of the container
+    #[clap(long)]
+    rootfs: PathBuf,
+    /// run the command of the container under the minimal init
+    #[clap(long)]
+    minimal_init: bool,
+    /// allocate a pseudoterminal for the process, and hand it over through the console socket
+    #[clap(long)]
+    tty: bool,
+    /// number of file descriptors from 3 on which the process of the container inherits
+    #[clap(long, default_value = "0")]
+    preserve_fds: u32,
+    /// name of the container instance to be prepared
+    pub container_id: String,
+}
+
+impl Init {
+    pub fn new(
+        container_id: &str,
+        rootfs: PathBuf,
+        minimal_init: bool,
+        tty: bool,
+        preserve_fds: u32,
+    ) -> Self {
+        Self {
+            rootfs,
+            minimal_init,
+            tty,
+            preserve_fds,
+            container_id: container_id.to_owned(),
+        }
+    }
+
+    /// Arguments of the init subcommand which reproduce these options
+    pub fn args(&self) -> Vec<String> {
+        let mut args = vec![
+            "init".to_owned(),
+            "--rootfs".to_owned(),
+            self.rootfs.to_string_lossy().into_owned(),
+        ];
+        if self.minimal_init {
+            args.push("--minimal-init".to_owned());
+        }
+        if self.tty {
+            args.push("--tty".to_owned());
+        }
+        if self.preserve_fds > 0 {
+            args.push("--preserve-fds".to_owned());
+            args.push(self.preserve_fds.to_string());
+        }
+        args.push(self.container_id.clone());
+        args
+    }
+
+    /// Prepares the container, waits for it to be started and runs its command
+    pub fn exec(
+        &self,
+        root_path: PathBuf,
+        timeouts: &Timeouts,
+        command: impl Command,
+    ) -> Result<()> {
+        let mut init = InitProcess::from_env(timeouts.child_sync())?;
+        // exec made the process dumpable again
+        if let Err(err) = prctl::set_dumpable(false)
+            .map_err(Errno::from_i32)
+            .context(Phase::new("set dumpable"))
+        {
+            init.report_error(&err);
+            return Err(err);
+        }
+
+        let container_dir = root_path.join(&self.container_id);
+        let (container, spec, exec_fifo, pid) =
+            match self.prepare(&container_dir, &mut init, timeouts, command) {
+                Ok(prepared) => prepared,
+                Err(err) => {
+                    init.report_error(&err);
+                    return Err(err);
+                }
+            };
+        init.ready()?;
+        exec_fifo.wait_for_start()?;
+        if let Some(hooks) = &spec.hooks {
+            let state = hook_state(&container, ContainerStatus::Created, pid);
+            hooks::run(&hooks.start_container, &state, timeouts.hooks())
+                .context("failed to run the startContainer hooks")?;
+        }
+        // the command of the container is not bound to the life of the child process
+        fork::set_death_signal(None)?;
+        // the process gets stdio and the preserved file descriptors only, not whatever else
+        // the caller of create left open
+        utils::close_on_exec_from(3 + self.preserve_fds as RawFd)?;
+
+        // actually run the command / program to be run in container,
+        // under the minimal init if it is requested
+        let spec_args = &spec.process.args;
+        let envs = &spec.process.env;
+        if self.minimal_init {
+            reaper::run(&spec_args[0], spec_args, envs)?;
+        } else {
+            utils::do_exec(&spec_args[0], spec_args, envs)?;
+        }
+        // the command / program is done executing
+        container.update_status(ContainerStatus::Stopped)?.save()?;
+        Ok(())
+    }
+
+    fn prepare(
+        &self,
+        container_dir: &Path,
+        init: &mut InitProcess,
+        timeouts: &Timeouts,
+        command: impl Command,
+    ) -> Result<(Container, oci_spec::Spec, ExecFifo, i32)> {
+        unistd::chdir(container_dir)?;
+        let container = Container::load(container_dir.to_path_buf())?;
+        let mut spec = oci_spec::Spec::load("config.json")?;
+        if let Some(labels) = &container.state.selinux_labels {
+            spec.process.selinux_label = labels.process_label.clone();
+            if let Some(linux) = spec.linux.as_mut() {
+                linux.mount_label = labels.mount_label.clone();
+            }
+        }
+        // start reads from the fifo once the container is created
+        let exec_fifo = ExecFifo::open(container_dir)?;
+
+        let pid = host_pid()?;
+        // the socket is reached through the container directory, which is gone for init
+        // once the root is changed
+        let console_socket = if self.tty {
+            Some(tty::connect_console_socket().context(Phase::new("connect console socket"))?)
+        } else {
+            None
+        };
+
+        // prepare process, once it is placed in its cgroups
+        init.wait_to_proceed()?;
+        let listener = init_process(&spec, &command, &self.rootfs, console_socket, || {
+            let runtime_hooks = hooks::runtime_hooks(&spec.hooks);
+            if !runtime_hooks.is_empty() {
+                init.wait_for_runtime_hooks(hooks::timeout_of(runtime_hooks, timeouts.hooks()))?;
+            }
+            // the paths of createContainer hooks are those of the host, which are only
+            // reachable until the root is changed
+            if let Some(hooks) = &spec.hooks {
+                let state = hook_state(&container, ContainerStatus::Creating, pid);
+                hooks::run(&hooks.create_container, &state, timeouts.hooks())
+                    .context(Phase::new("run createContainer hooks"))?;
+            }
+            Ok(())
+        })?;
+        if let Some(fd) = listener {
+            let sent = init.send_seccomp_listener(fd);
+            let _ = unistd::close(fd);
+            sent.context(Phase::new("hand over the seccomp listener"))?;
+        }
+        Ok((container, spec, exec_fifo, pid))
+    }
+}
+
+/// Pid of this process as seen by the runtime. /proc is the one of the host until the
+/// root is changed, and /proc/self links to the pid in the pid namespace of that.
+fn host_pid() -> Result<i32> {
+    let pid = std::fs::read_link("/proc/self")?;
+    Ok(pid.to_string_lossy().parse()?)
+}
+
+/// State of the container which its hooks get, while it is not yet recorded with its pid
+fn hook_state(container: &Container, status: ContainerStatus, pid: i32) -> OciState {
+    let mut state = OciState::from(&container.state);
+    state.status = status;
+    state.pid = Some(pid);
+    state
+}
+
+/// setup hostname, rootfs for the container process. The hooks are run once the mounts are
+/// prepared, before the root is changed. Returns the listener of the seccomp filter if it
+/// notifies about syscalls.
+fn init_process(
+    spec: &oci_spec::Spec,
+    command: &impl Command,
+    rootfs: &Path,
+    console_socket: Option<RawFd>,
+    run_hooks: impl FnOnce() -> Result<()>,
+) -> Result<Option<RawFd>> {
+    let proc = &spec.process;
+    let namespaces: Namespaces = spec.linux.as_ref().unwrap().namespaces.clone().into();
+
+    // a cgroup namespace is rooted at the cgroup of the process which creates it, so
+    // the container sees its own cgroup as the root
+    if namespaces.clone_flags.contains(CloneFlags::CLONE_NEWCGROUP) {
+        command
+            .unshare(CloneFlags::CLONE_NEWCGROUP)
+            .context(Phase::new("unshare cgroup namespace"))?;
+    }
+    // in the UTS namespace of the container, which create made sure of
+    if !spec.hostname.is_empty() {
+        command
+            .set_hostname(spec.hostname.as_str())
+            .context(Phase::new("sethostname"))?;
+    }
+    if !spec.domainname.is_empty() {
+        command
+            .set_domainname(spec.domainname.as_str())
+            .context(Phase::new("setdomainname"))?;
+    }
+
+    // devices cannot be created in a user namespace, be it a new or a joined one
+    rootfs::prepare_rootfs(spec, rootfs, namespaces.contains(LinuxNamespaceType::User))?;
+    run_hooks()?;
+
+    // only now, as the hooks would be executed with these as well
+    if proc.no_new_privileges {
+        prctl::set_no_new_privileges(true)
+            .map_err(Errno::from_i32)
+            .context(Phase::new("set no_new_privileges"))?;
+    }
+    // the label is taken on by the command of the container when it is executed
+    if !proc.selinux_label.is_empty() {
+        selinux::set_exec_label(&proc.selinux_label).context(Phase::new("set SELinux label"))?;
+    }
+
+    // change the root of filesystem of the process to the rootfs
+    command
+        .pivot_rootfs(rootfs)
+        .with_context(|| Phase::with_path("pivot_root", rootfs))?;
+    if let Some(linux) = &spec.linux {
+        // through /proc of the container, before /proc/sys may be made read-only
+        sysctl::apply(&linux.sysctl).context(Phase::new("set sysctl"))?;
+        // a masked path under a read-only one is still masked
+        rootfs::readonly_paths(&linux.readonly_paths)?;
+        rootfs::mask_paths(&linux.masked_paths, &linux.mount_label)?;
+    }
+
+    // while still privileged, let the user of the container own its terminal
+    if let Some(console_socket) = console_socket {
+        tty::setup_console(console_socket, &proc.console_size)
+            .context(Phase::new("set up console"))?;
+        tty::set_ownership(
+            io::stdin().as_raw_fd(),
+            Uid::from_raw(proc.user.uid),
+            Path::new("/etc/group"),
+        )
+        .context(Phase::new("set up terminal ownership"))?;
+    }
+    // the console may have been created in the root, if /dev is not a mount of its own
+    if spec.root.readonly {
+        rootfs::readonly_root()?;
+    }
+
+    if let Some(personality) = spec
+        .linux
+        .as_ref()
+        .and_then(|linux| linux.personality.as_ref())
+    {
+        personality::apply(personality).context(Phase::new("set personality"))?;
+    }
+
+    // while still privileged, as a real-time policy or a lower nice needs CAP_SYS_NICE
+    if let Some(scheduler) = &proc.scheduler {
+        scheduler::apply(scheduler).context(Phase::new("set scheduler"))?;
+    }
+    // init is in the cgroups of the container, so the initial CPUs are done with
+    if let Some(affinity) = &proc.exec_cpu_affinity {
+        cpu_affinity::apply_final(affinity).context(Phase::new("set CPU affinity"))?;
+    }
+    // the real-time class needs CAP_SYS_ADMIN
+    if let Some(io_priority) = &proc.io_priority {
+        ioprio::apply(io_priority).context(Phase::new("set I/O priority"))?;
+    }
+    if let Some(sched_core) = spec.annotations.get(scheduler::SCHED_CORE_ANNOTATION) {
+        if scheduler::parse_sched_core(sched_core)? {
+            scheduler::create_core_cookie().context(Phase::new("set up core scheduling"))?;
+        }
+    }
+
+    // without no_new_privileges, loading the filter needs CAP_SYS_ADMIN which is dropped
+    // below, so the filter applies to the rest of the setup as well, like in runc
+    let seccomp = spec.linux.as_ref().and_then(|linux| linux.seccomp.as_ref());
+    let mut listener = None;
+    if let Some(seccomp) = seccomp.filter(|_| !proc.no_new_privileges) {
+        listener = seccomp::load(seccomp).context(Phase::new("set up seccomp"))?;
+    }
+
+    // the process does not keep the supplementary groups of the runtime
+    let gids: Vec<Gid> = proc
+        .user
+        .additional_gids
+        .iter()
+        .map(|gid| Gid::from_raw(*gid))
+        .collect();
+    command
+        .set_groups(&gids)
+        .context(Phase::new("set additional gids"))?;
+    command
+        .set_id(Uid::from_raw(proc.user.uid), Gid::from_raw(proc.user.gid))
+        .context(Phase::new("set user"))?;
+    // without one in the spec, the process keeps the umask of the runtime
+    if let Some(umask) = proc.user.umask {
+        stat::umask(Mode::from_bits_truncate(umask));
+    }
+    capabilities::reset_effective(command).context(Phase::new("set capabilities"))?;
+    // set while every capability is still effective, as this needs CAP_SETPCAP
+    if let Some(securebits) = spec.annotations.get(capabilities::SECUREBITS_ANNOTATION) {
+        let bits = capabilities::parse_securebits(securebits)?;
+        capabilities::set_securebits(bits).context(Phase::new("set securebits"))?;
+    }
+    // a process without capabilities in the spec gets none, not those of the runtime
+    let caps = proc.capabilities.clone().unwrap_or_default();
+    capabilities::drop_privileges(&caps, command).context(Phase::new("set capabilities"))?;
+    // last, as the paths of the rules are in the rootfs and init has nothing left to set up
+    if let Some(ruleset) = &proc.landlock {
+        landlock::restrict(ruleset).context(Phase::new("set up Landlock"))?;
+    }
+    if let Some(seccomp) = seccomp.filter(|_| proc.no_new_privileges) {
+        listener = seccomp::load(seccomp).context(Phase::new("set up seccomp"))?;
+    }
+    Ok(listener)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_args_reproduce_options() {
+        for (minimal_init, tty, preserve_fds) in &[(true, false, 0), (false, true, 2)] {
+            let init = Init::new(
+                "test",
+                PathBuf::from("/bundle/rootfs"),
+                *minimal_init,
+                *tty,
+                *preserve_fds,
+            );
+            let parsed = Init::try_parse_from(init.args()).expect("parse init arguments");
+            assert_eq!(parsed, init);
+        }
+    }
+}
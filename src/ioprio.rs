@@ -0,0 +1,74 @@
+//! Sets the I/O priority of process.ioPriority for the container process with ioprio_set(2),
+//! e.g. to demote a batch workload to the idle class. The priority is kept across exec.
+
+use anyhow::{bail, Context, Result};
+use nix::errno::Errno;
+use oci_spec::{LinuxIoPriority, LinuxIoPriorityClass};
+
+/// IOPRIO_WHO_PROCESS of linux/ioprio.h, which applies to a single process
+const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+/// The class is stored above the 13 bits of the priority
+const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+
+/// Checks the priority as far as the kernel does, so that a mistake is reported at create
+/// instead of by the init process
+pub fn validate(io_priority: &LinuxIoPriority) -> Result<()> {
+    if !(0..=7).contains(&io_priority.priority) {
+        bail!(
+            "ioPriority priority {} of {:?} is not between 0 and 7",
+            io_priority.priority,
+            io_priority.class
+        )
+    }
+    Ok(())
+}
+
+fn ioprio_value(io_priority: &LinuxIoPriority) -> libc::c_int {
+    let class = match io_priority.class {
+        LinuxIoPriorityClass::IoprioClassRt => 1,
+        LinuxIoPriorityClass::IoprioClassBe => 2,
+        LinuxIoPriorityClass::IoprioClassIdle => 3,
+    };
+    class << IOPRIO_CLASS_SHIFT | io_priority.priority
+}
+
+/// Sets the I/O priority of the current process. The real-time class needs CAP_SYS_ADMIN.
+pub fn apply(io_priority: &LinuxIoPriority) -> Result<()> {
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_ioprio_set,
+            IOPRIO_WHO_PROCESS,
+            0,
+            ioprio_value(io_priority),
+        )
+    };
+    Errno::result(res)
+        .map(drop)
+        .with_context(|| format!("failed to set I/O priority {:?}", io_priority))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ioprio_value() {
+        let io_priority: LinuxIoPriority =
+            serde_json::from_str(r#"{ "class": "IOPRIO_CLASS_BE", "priority": 4 }"#)
+                .expect("parse ioPriority");
+        assert!(validate(&io_priority).is_ok());
+        assert_eq!(ioprio_value(&io_priority), 2 << 13 | 4);
+
+        let io_priority = LinuxIoPriority {
+            class: LinuxIoPriorityClass::IoprioClassIdle,
+            priority: 0,
+        };
+        assert_eq!(ioprio_value(&io_priority), 3 << 13);
+
+        let io_priority = LinuxIoPriority {
+            class: LinuxIoPriorityClass::IoprioClassRt,
+            priority: 8,
+        };
+        assert!(validate(&io_priority).is_err());
+    }
+}
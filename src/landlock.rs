@@ -0,0 +1,177 @@
+//! Restricts the filesystem access of the container process with Landlock, which needs no
+//! privilege and is available since Linux 5.13. Unless best effort is disabled in the spec,
+//! access rights which the kernel does not know are left out instead of failing.
+
+use std::mem;
+use std::os::unix::io::RawFd;
+use std::path::Path;
+use std::ptr;
+
+use anyhow::{bail, Context, Result};
+use nix::errno::Errno;
+use nix::fcntl::{open, OFlag};
+use nix::sys::stat::Mode;
+use nix::unistd::close;
+use oci_spec::{Landlock, LandlockFsAccess};
+
+// the numbers of the Landlock syscalls are the same on every architecture
+const SYS_LANDLOCK_CREATE_RULESET: libc::c_long = 444;
+const SYS_LANDLOCK_ADD_RULE: libc::c_long = 445;
+const SYS_LANDLOCK_RESTRICT_SELF: libc::c_long = 446;
+
+const LANDLOCK_CREATE_RULESET_VERSION: libc::c_uint = 1;
+const LANDLOCK_RULE_PATH_BENEATH: libc::c_uint = 1;
+
+#[repr(C)]
+struct RulesetAttr {
+    handled_access_fs: u64,
+}
+
+#[repr(C, packed)]
+struct PathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: i32,
+}
+
+/// Restricts the process to the rules of the spec, which apply to the programs it executes
+/// as well. The paths are resolved in the current root, so this is done after pivot_root.
+pub fn restrict(landlock: &Landlock) -> Result<()> {
+    let handled = handled_access(landlock, supported_access(abi_version()))?;
+    if handled == 0 {
+        log::warn!("Landlock is not supported by the kernel, filesystem access is not restricted");
+        return Ok(());
+    }
+
+    let attr = RulesetAttr {
+        handled_access_fs: handled,
+    };
+    let ruleset = unsafe {
+        libc::syscall(
+            SYS_LANDLOCK_CREATE_RULESET,
+            &attr as *const RulesetAttr,
+            mem::size_of::<RulesetAttr>(),
+            0,
+        )
+    };
+    let ruleset = Errno::result(ruleset).context("failed to create the Landlock ruleset")? as RawFd;
+
+    let result = add_rules(ruleset, landlock, handled).and_then(|_| {
+        let res = unsafe { libc::syscall(SYS_LANDLOCK_RESTRICT_SELF, ruleset, 0) };
+        Errno::result(res).context(
+            "failed to enforce the Landlock ruleset, which needs noNewPrivileges or CAP_SYS_ADMIN",
+        )
+    });
+    close(ruleset)?;
+    result.map(|_| ())
+}
+
+fn add_rules(ruleset: RawFd, landlock: &Landlock, handled: u64) -> Result<()> {
+    for rule in &landlock.rules.path_beneath {
+        let allowed = LandlockFsAccess::to_mask(&rule.allowed_access) & handled;
+        for path in &rule.paths {
+            add_path_beneath(ruleset, path, allowed)
+                .with_context(|| format!("failed to add the Landlock rule for {:?}", path))?;
+        }
+    }
+    Ok(())
+}
+
+fn add_path_beneath(ruleset: RawFd, path: &Path, allowed: u64) -> Result<()> {
+    let fd = open(path, OFlag::O_PATH | OFlag::O_CLOEXEC, Mode::empty())?;
+    let attr = PathBeneathAttr {
+        allowed_access: allowed,
+        parent_fd: fd,
+    };
+    let res = unsafe {
+        libc::syscall(
+            SYS_LANDLOCK_ADD_RULE,
+            ruleset,
+            LANDLOCK_RULE_PATH_BENEATH,
+            &attr as *const PathBeneathAttr,
+            0,
+        )
+    };
+    close(fd)?;
+    Errno::result(res)?;
+    Ok(())
+}
+
+/// Version of the Landlock ABI of the kernel, which is 0 if Landlock is not available
+fn abi_version() -> i64 {
+    let version = unsafe {
+        libc::syscall(
+            SYS_LANDLOCK_CREATE_RULESET,
+            ptr::null::<RulesetAttr>(),
+            0,
+            LANDLOCK_CREATE_RULESET_VERSION,
+        )
+    };
+    version.max(0)
+}
+
+/// Access rights which a version of the ABI knows. Version 2 added refer, 3 truncate.
+fn supported_access(abi: i64) -> u64 {
+    match abi {
+        i64::MIN..=0 => 0,
+        1 => (LandlockFsAccess::Refer as u64) - 1,
+        2 => (LandlockFsAccess::Truncate as u64) - 1,
+        _ => ((LandlockFsAccess::Truncate as u64) << 1) - 1,
+    }
+}
+
+/// Returns the access rights of the ruleset which the kernel supports
+fn handled_access(landlock: &Landlock, supported: u64) -> Result<u64> {
+    let requested = LandlockFsAccess::to_mask(&landlock.ruleset.handled_access_fs);
+    let unsupported = requested & !supported;
+    if unsupported != 0 {
+        if landlock.disable_best_effort {
+            bail!(
+                "the kernel does not support the Landlock access rights {:#x}, and best effort is disabled",
+                unsupported
+            )
+        }
+        log::warn!(
+            "the kernel does not support the Landlock access rights {:#x}, they are not restricted",
+            unsupported
+        );
+    }
+    Ok(requested & supported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn landlock(disable_best_effort: bool) -> Landlock {
+        serde_json::from_value(serde_json::json!({
+            "ruleset": { "handledAccessFS": ["execute", "read_file", "refer", "truncate"] },
+            "rules": {
+                "pathBeneath": [{ "allowedAccess": ["execute", "read_file"], "paths": ["/usr"] }]
+            },
+            "disableBestEffort": disable_best_effort
+        }))
+        .expect("parse landlock")
+    }
+
+    #[test]
+    fn test_handled_access() {
+        let execute_read = LandlockFsAccess::Execute as u64 | LandlockFsAccess::ReadFile as u64;
+        assert_eq!(
+            handled_access(&landlock(false), supported_access(1)).unwrap(),
+            execute_read
+        );
+        assert_eq!(
+            handled_access(&landlock(false), supported_access(2)).unwrap(),
+            execute_read | LandlockFsAccess::Refer as u64
+        );
+        assert_eq!(
+            handled_access(&landlock(true), supported_access(3)).unwrap(),
+            execute_read | LandlockFsAccess::Refer as u64 | LandlockFsAccess::Truncate as u64
+        );
+        assert!(handled_access(&landlock(true), supported_access(1)).is_err());
+        assert_eq!(
+            handled_access(&landlock(false), supported_access(0)).unwrap(),
+            0
+        );
+    }
+}
@@ -2,19 +2,48 @@
 #[macro_use]
 extern crate quickcheck;
 
+pub mod batch;
 pub mod capabilities;
 pub mod cgroups;
+pub mod checkpoint;
 pub mod command;
-pub mod cond;
+pub mod config;
 pub mod container;
+pub mod cpu_affinity;
 pub mod create;
+pub mod criu;
+pub mod devices;
+pub mod events;
+pub mod exec;
+pub mod exec_fifo;
+pub mod hooks;
+pub mod init;
+pub mod ioprio;
+pub mod landlock;
+pub mod list;
 pub mod logger;
+pub mod metrics;
 pub mod namespaces;
 pub mod notify_socket;
+pub mod output;
+pub mod pause;
+pub mod personality;
 pub mod process;
+pub mod ps;
+pub mod reaper;
+pub mod restore;
+pub mod rlimits;
 pub mod rootfs;
+pub mod rootless;
+pub mod run;
+pub mod scheduler;
+pub mod seccomp;
+pub mod selinux;
 pub mod signal;
+pub mod spec;
 pub mod start;
 pub mod stdio;
+pub mod sysctl;
 pub mod tty;
+pub mod update;
 pub mod utils;
@@ -0,0 +1,157 @@
+//! Lists the containers in the state roots
+
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
+use clap::Clap;
+
+use crate::cgroups::{self, common::CgroupManager};
+use crate::container::{Container, ContainerStatus, StateEntry};
+use crate::output::Format;
+
+#[derive(Clap, Debug)]
+pub struct List {
+    /// format of the output, table or json
+    #[clap(short, long, default_value = "table")]
+    pub format: Format,
+    /// only list containers with one of these statuses, e.g. running,created
+    #[clap(short, long, use_delimiter = true)]
+    pub status: Vec<StatusFilter>,
+    /// only print the ids of the containers
+    #[clap(short, long)]
+    pub quiet: bool,
+}
+
+/// Status given to --status
+#[derive(Debug, Copy, Clone)]
+pub struct StatusFilter(ContainerStatus);
+
+impl FromStr for StatusFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(status: &str) -> Result<Self> {
+        use ContainerStatus::*;
+        Ok(StatusFilter(match status {
+            "creating" => Creating,
+            "created" => Created,
+            "running" => Running,
+            "paused" => Paused,
+            "stopped" => Stopped,
+            _ => bail!("{} is not a valid status", status),
+        }))
+    }
+}
+
+impl List {
+    pub fn exec(&self, roots: &[PathBuf]) -> Result<()> {
+        let mut entries: Vec<StateEntry> = Container::load_all(roots)?
+            .into_iter()
+            .map(|container| {
+                refresh_stale(container, |container| {
+                    cgroups::common::create_cgroup_manager(container.cgroups_path()?)
+                })
+            })
+            .filter(|container| self.matches(container.status()))
+            .map(StateEntry::from)
+            .collect();
+        entries.sort_by(|a, b| a.state.id.cmp(&b.state.id));
+
+        if self.quiet {
+            for entry in &entries {
+                println!("{}", entry.state.id);
+            }
+        } else {
+            println!("{}", self.format.render_all(&entries)?);
+        }
+        Ok(())
+    }
+
+    fn matches(&self, status: ContainerStatus) -> bool {
+        self.status.is_empty() || self.status.iter().any(|filter| filter.0 == status)
+    }
+}
+
+/// Shows a container which looks alive as stopped if it is stale, e.g. after a crash of the
+/// host, when its pid may have been reused by an unrelated process. The state is not saved.
+fn refresh_stale<F>(container: Container, cmanager: F) -> Container
+where
+    F: FnOnce(&Container) -> Result<Box<dyn CgroupManager>>,
+{
+    // processes of others may be in shared cgroups, so they tell nothing
+    if !container.can_kill() || container.state.shared_cgroups_path.is_some() {
+        return container;
+    }
+    let refreshed =
+        cmanager(&container).and_then(|cmanager| container.refresh_stale(cmanager.as_ref()));
+    match refreshed {
+        Ok(refreshed) => refreshed,
+        Err(e) => {
+            log::debug!("failed to check whether {} is stale: {}", container.id(), e);
+            container
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_filter() {
+        let list = List::try_parse_from(["list", "--status", "running,created"]).unwrap();
+        assert!(list.matches(ContainerStatus::Running));
+        assert!(list.matches(ContainerStatus::Created));
+        assert!(!list.matches(ContainerStatus::Stopped));
+
+        let list = List::try_parse_from(["list"]).unwrap();
+        assert!(list.matches(ContainerStatus::Stopped));
+
+        assert!(List::try_parse_from(["list", "--status", "running,unknown"]).is_err());
+    }
+
+    #[test]
+    fn test_refresh_stale() {
+        use crate::cgroups::v2::manager::Manager;
+        use std::fs;
+
+        let tmp = std::env::temp_dir().join("test_list_refresh_stale");
+        let _ = fs::remove_dir_all(&tmp);
+        let cgroup = tmp.join("cgroup").join("stale");
+        fs::create_dir_all(&cgroup).unwrap();
+        fs::write(cgroup.join("cgroup.procs"), "").unwrap();
+        let cmanager = |_: &Container| -> Result<Box<dyn CgroupManager>> {
+            Ok(Box::new(Manager::new(
+                tmp.join("cgroup"),
+                PathBuf::from("/stale"),
+            )?))
+        };
+
+        // the pid is alive, as it has been reused by this process
+        let pid = std::process::id() as i32;
+        let container =
+            Container::new("test", ContainerStatus::Running, Some(pid), "/bundle", &tmp)
+                .unwrap()
+                .refresh_status()
+                .unwrap();
+        assert_eq!(container.status(), ContainerStatus::Running);
+        let container = refresh_stale(container, cmanager);
+        assert_eq!(container.status(), ContainerStatus::Stopped);
+
+        fs::write(cgroup.join("cgroup.procs"), format!("{}\n", pid)).unwrap();
+        let container =
+            Container::new("test", ContainerStatus::Running, Some(pid), "/bundle", &tmp).unwrap();
+        let container = refresh_stale(container, cmanager);
+        assert_eq!(container.status(), ContainerStatus::Running);
+
+        // the processes in shared cgroups may be those of others
+        fs::write(cgroup.join("cgroup.procs"), "").unwrap();
+        let container =
+            Container::new("test", ContainerStatus::Running, Some(pid), "/bundle", &tmp)
+                .unwrap()
+                .set_shared_cgroups_path(PathBuf::from("/stale"));
+        let container = refresh_stale(container, cmanager);
+        assert_eq!(container.status(), ContainerStatus::Running);
+        fs::remove_dir_all(&tmp).unwrap();
+    }
+}
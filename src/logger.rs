@@ -14,10 +14,17 @@ use once_cell::sync::OnceCell;
 
 pub static YOUKI_LOGGER: OnceCell<YoukiLogger> = OnceCell::new();
 pub static LOG_FILE: OnceCell<Option<File>> = OnceCell::new();
+/// Path of the log file, which is passed on when youki executes itself
+pub static LOG_FILE_PATH: OnceCell<Option<PathBuf>> = OnceCell::new();
 
-pub fn init(log_file: Option<PathBuf>) -> Result<()> {
+/// Sets up logging at the level of YOUKI_LOG_LEVEL, or else at the given one
+pub fn init(log_file: Option<PathBuf>, log_level: Option<&str>) -> Result<()> {
+    LOG_FILE_PATH.get_or_init(|| log_file.clone());
     let _log_file = LOG_FILE.get_or_init(|| -> Option<File> {
-        let level_filter = if let Ok(log_level_str) = env::var("YOUKI_LOG_LEVEL") {
+        let log_level_str = env::var("YOUKI_LOG_LEVEL")
+            .ok()
+            .or_else(|| log_level.map(str::to_owned));
+        let level_filter = if let Some(log_level_str) = log_level_str {
             LevelFilter::from_str(&log_level_str).unwrap_or(LevelFilter::Warn)
         } else {
             LevelFilter::Warn
@@ -2,35 +2,73 @@
 //! Container Runtime written in Rust, inspired by [railcar](https://github.com/oracle/railcar)
 //! This crate provides a container runtime which can be used by a high-level container runtime to run containers.
 
+use std::env;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use anyhow::{bail, Result};
 use clap::Clap;
-use nix::sys::signal as nix_signal;
+use nix::sys::signal::{self as nix_signal, Signal};
 
+use youki::batch;
+use youki::checkpoint;
 use youki::command::linux::LinuxCommand;
-use youki::container::{Container, ContainerStatus};
+use youki::config::{RuntimeConfig, Timeouts};
+use youki::container::{Container, ContainerStatus, OciState, StateEntry};
 use youki::create;
+use youki::events;
+use youki::exec;
+use youki::hooks;
+use youki::init;
+use youki::list;
+use youki::metrics;
+use youki::output::Format;
+use youki::pause;
+use youki::ps;
+use youki::restore;
+use youki::run;
 use youki::signal;
+use youki::spec;
 use youki::start;
+use youki::update;
 
-use youki::cgroups;
+use youki::cgroups::{
+    self,
+    common::{CgroupManager, FreezerState},
+};
 use youki::utils;
 
+/// Time for the processes of a container to die after SIGKILL, e.g. to flush dirty pages
+const KILL_TIMEOUT: Duration = Duration::from_secs(10);
+
 /// High-level commandline option definition
 /// This takes global options as well as individual commands as specified in [OCI runtime-spec](https://github.com/opencontainers/runtime-spec/blob/master/runtime.md)
 /// Also check [runc commandline documentation](https://github.com/opencontainers/runc/blob/master/man/runc.8.md) for more explanation
 #[derive(Clap, Debug)]
 #[clap(version = "1.0", author = "utam0k <k0ma@utam0k.jp>")]
 struct Opts {
-    /// root directory to store container state
-    #[clap(short, long, default_value = "/run/youki")]
-    root: PathBuf,
+    /// root directory to store container state, /run/youki unless configured otherwise
+    #[clap(short, long)]
+    root: Option<PathBuf>,
+    /// directory to store container state in when the root directory is not usable
+    #[clap(long)]
+    fallback_root: Option<PathBuf>,
     #[clap(short, long)]
     log: Option<PathBuf>,
+    /// log format, accepted for compatibility with high-level runtimes
+    #[allow(dead_code)]
     #[clap(long)]
     log_format: Option<String>,
+    /// override a timeout of the configuration as name=seconds, where the name is
+    /// child_sync, start, hooks or criu
+    #[clap(long = "timeout", number_of_values = 1)]
+    timeouts: Vec<String>,
+    /// let systemd create the cgroups of new containers as transient scope units, as
+    /// cgroup_manager = "systemd" in the configuration does
+    #[clap(long)]
+    systemd_cgroup: bool,
     /// command to actually manage container
     #[clap(subcommand)]
     subcmd: SubCommand,
@@ -38,18 +76,44 @@ struct Opts {
 
 #[derive(Clap, Debug)]
 pub struct Kill {
-    container_id: String,
-    signal: String,
+    /// send the signal to all processes of the container instead of its init process only
+    #[clap(short, long)]
+    all: bool,
+    /// send the signal to every container in the state root
+    #[clap(long)]
+    all_containers: bool,
+    /// ids of the containers followed by the signal, by name or number, e.g. a b SIGTERM,
+    /// a b TERM or a b 15
+    #[clap(required = true)]
+    targets: Vec<String>,
+}
+
+impl Kill {
+    /// Splits the targets into the ids of the containers and the signal, which is last
+    fn ids_and_signal(&self) -> (&[String], &str) {
+        let (signal, ids) = self.targets.split_last().unwrap();
+        (ids, signal)
+    }
 }
 
 #[derive(Clap, Debug)]
 pub struct Delete {
-    container_id: String,
+    /// delete every container in the state root
+    #[clap(long)]
+    all_containers: bool,
+    container_ids: Vec<String>,
+    /// also delete a container which is still alive, killing its processes, or whose
+    /// processes are gone while its state is left behind, e.g. after a crash of the host
+    #[clap(short, long)]
+    force: bool,
 }
 
 #[derive(Clap, Debug)]
 pub struct StateArgs {
     pub container_id: String,
+    /// format of the output, json or table
+    #[clap(short, long, default_value = "json")]
+    pub format: Format,
 }
 
 /// Subcommands accepted by Youki, confirming with [OCI runtime-spec](https://github.com/opencontainers/runtime-spec/blob/master/runtime.md)
@@ -60,107 +124,350 @@ enum SubCommand {
     Create(create::Create),
     #[clap(version = "0.0.1", author = "utam0k <k0ma@utam0k.jp>")]
     Start(start::Start),
+    /// create and start a container, and wait for it to exit
+    #[clap(version = "0.0.1", author = "utam0k <k0ma@utam0k.jp>")]
+    Run(run::Run),
+    #[clap(version = "0.0.1", author = "utam0k <k0ma@utam0k.jp>")]
+    Exec(exec::Exec),
     #[clap(version = "0.0.1", author = "utam0k <k0ma@utam0k.jp>")]
     Kill(Kill),
     #[clap(version = "0.0.1", author = "utam0k <k0ma@utam0k.jp>")]
     Delete(Delete),
     #[clap(version = "0.0.1", author = "utam0k <k0ma@utam0k.jp>")]
     State(StateArgs),
+    #[clap(version = "0.0.1", author = "utam0k <k0ma@utam0k.jp>")]
+    Events(events::Events),
+    #[clap(version = "0.0.1", author = "utam0k <k0ma@utam0k.jp>")]
+    List(list::List),
+    #[clap(version = "0.0.1", author = "utam0k <k0ma@utam0k.jp>")]
+    Metrics(metrics::Metrics),
+    #[clap(version = "0.0.1", author = "utam0k <k0ma@utam0k.jp>")]
+    Ps(ps::Ps),
+    #[clap(version = "0.0.1", author = "utam0k <k0ma@utam0k.jp>")]
+    Pause(pause::Pause),
+    #[clap(version = "0.0.1", author = "utam0k <k0ma@utam0k.jp>")]
+    Resume(pause::Resume),
+    #[clap(version = "0.0.1", author = "utam0k <k0ma@utam0k.jp>")]
+    Update(update::Update),
+    #[clap(version = "0.0.1", author = "utam0k <k0ma@utam0k.jp>")]
+    Spec(spec::Spec),
+    #[clap(version = "0.0.1", author = "utam0k <k0ma@utam0k.jp>")]
+    Checkpoint(checkpoint::Checkpoint),
+    #[clap(version = "0.0.1", author = "utam0k <k0ma@utam0k.jp>")]
+    Restore(restore::Restore),
+    /// used by create only, to prepare the container from inside of its namespaces
+    #[clap(setting = clap::AppSettings::Hidden)]
+    Init(init::Init),
+}
+
+/// Roots to store container state in, in the order of preference. An unprivileged user
+/// can use its runtime directory when the root directory is not writable.
+fn state_root_candidates(opts: &Opts, config: &RuntimeConfig) -> Vec<PathBuf> {
+    let root = opts
+        .root
+        .clone()
+        .or_else(|| config.root.clone())
+        .unwrap_or_else(|| PathBuf::from(utils::DEFAULT_STATE_ROOT));
+    let mut candidates = vec![root];
+    candidates.extend(opts.fallback_root.clone());
+    if !nix::unistd::geteuid().is_root() {
+        if let Some(runtime_dir) = env::var_os("XDG_RUNTIME_DIR") {
+            candidates.push(PathBuf::from(runtime_dir).join("youki"));
+        }
+    }
+    candidates
 }
 
 /// This is the entry point in the container runtime. The binary is run by a high-level container runtime,
 /// with various flags passed. This parses the flags, creates and manages appropriate resources.
 fn main() -> Result<()> {
     let opts = Opts::parse();
+    let mut config = RuntimeConfig::load()?;
+    for timeout in &opts.timeouts {
+        config.timeouts.set(timeout)?;
+    }
+    if opts.systemd_cgroup {
+        config.cgroup_manager = Some("systemd".to_owned());
+    }
 
-    if let Err(e) = youki::logger::init(opts.log) {
+    let log = opts.log.clone().or_else(|| config.log.clone());
+    if let Err(e) = youki::logger::init(log, config.log_level.as_deref()) {
         eprintln!("log init failed: {:?}", e);
     }
 
-    let root_path = PathBuf::from(&opts.root);
-    fs::create_dir_all(&root_path)?;
+    // init is executed with the limits of the container already set
+    if !matches!(opts.subcmd, SubCommand::Init(_)) {
+        if let Err(e) = utils::raise_nofile_limit() {
+            log::warn!("failed to raise the limit of open files: {}", e);
+        }
+    }
+
+    let root_path = utils::select_state_root(&state_root_candidates(&opts, &config))?;
 
     match opts.subcmd {
-        SubCommand::Create(create) => create.exec(root_path, LinuxCommand),
-        SubCommand::Start(start) => start.exec(root_path),
-        SubCommand::Kill(kill) => {
-            // resolves relative paths, symbolic links etc. and get complete path
+        SubCommand::Create(create) => create.exec(root_path, &config, LinuxCommand),
+        SubCommand::Start(start) => start.exec(root_path, &config.timeouts),
+        SubCommand::Run(run) => {
+            // create changes the working directory before start looks into the root
             let root_path = fs::canonicalize(root_path)?;
-            // state of container is stored in a directory named as container id inside
-            // root directory given in commandline options
-            let container_root = root_path.join(&kill.container_id);
-            if !container_root.exists() {
-                bail!("{} doesn't exist.", kill.container_id)
+            let container_id = &run.create.container_id;
+            match run.exec(root_path.clone(), &config, LinuxCommand)? {
+                run::Outcome::Running => Ok(()),
+                // the container is gone along with its process, like with runc, and run exits
+                // with its code even if it could not be deleted
+                run::Outcome::Exited(code) => {
+                    if let Err(e) =
+                        delete_container(&root_path, container_id, false, &config.timeouts)
+                    {
+                        log::warn!("{:?}", e);
+                        eprintln!("WARNING: failed to delete {}: {:#}", container_id, e);
+                    }
+                    std::process::exit(code);
+                }
+                run::Outcome::NotStarted(err) => {
+                    delete_container(&root_path, container_id, true, &config.timeouts)?;
+                    Err(err)
+                }
             }
-
-            // load container state from json file, and check status of the container
-            // it might be possible that kill is invoked on a already stopped container etc.
-            let container = Container::load(container_root)?.refresh_status()?;
-            if container.can_kill() {
-                let sig = signal::from_str(kill.signal.as_str())?;
-                log::debug!("kill signal {} to {}", sig, container.pid().unwrap());
-                nix_signal::kill(container.pid().unwrap(), sig)?;
-                container.update_status(ContainerStatus::Stopped)?.save()?;
-                std::process::exit(0)
-            } else {
-                bail!(
-                    "{} could not be killed because it was {:?}",
-                    container.id(),
-                    container.status()
-                )
+        }
+        SubCommand::Exec(exec) => exec.exec(root_path, LinuxCommand),
+        SubCommand::Events(events) => events.exec(&utils::search_state_roots(&root_path)),
+        SubCommand::List(list) => list.exec(&utils::search_state_roots(&root_path)),
+        SubCommand::Metrics(metrics) => metrics.exec(&utils::search_state_roots(&root_path)),
+        SubCommand::Ps(ps) => ps.exec(&utils::search_state_roots(&root_path)),
+        SubCommand::Pause(pause) => pause.exec(root_path),
+        SubCommand::Resume(resume) => resume.exec(root_path),
+        SubCommand::Update(update) => update.exec(root_path),
+        SubCommand::Spec(spec) => spec.exec(),
+        SubCommand::Checkpoint(checkpoint) => {
+            checkpoint.exec(root_path.clone(), &config.timeouts)?;
+            // CRIU has killed the processes, the monitor may not have recorded it yet
+            if checkpoint.stops_container() {
+                delete_container(&root_path, &checkpoint.container_id, true, &config.timeouts)?;
             }
+            Ok(())
+        }
+        SubCommand::Restore(restore) => restore.exec(root_path, &config.timeouts),
+        SubCommand::Init(init) => init.exec(root_path, &config.timeouts, LinuxCommand),
+        SubCommand::Kill(kill) => {
+            // resolves relative paths, symbolic links etc. and get complete path
+            let root_path = fs::canonicalize(root_path)?;
+            let (ids, signal) = kill.ids_and_signal();
+            let signal = signal::from_str(signal)?;
+            let ids = batch::container_ids(&root_path, ids, kill.all_containers)?;
+            batch::run("kill", &ids, |id| {
+                kill_container(&root_path, id, signal, kill.all)
+            })
         }
         SubCommand::Delete(delete) => {
-            log::debug!("start deleting {}", delete.container_id);
-            // state of container is stored in a directory named as container id inside
-            // root directory given in commandline options
-            let container_root = root_path.join(&delete.container_id);
-            if !container_root.exists() {
-                bail!("{} doesn't exist.", delete.container_id)
+            let ids =
+                batch::container_ids(&root_path, &delete.container_ids, delete.all_containers)?;
+            batch::run("delete", &ids, |id| {
+                delete_container(&root_path, id, delete.force, &config.timeouts)
+            })
+        }
+        SubCommand::State(state_args) => {
+            let roots = utils::search_state_roots(&root_path);
+            let container = Container::find(&roots, &state_args.container_id)?.refresh_status()?;
+            // refresh_status has checked whether the init process is still alive
+            let output = match state_args.format {
+                Format::Json => serde_json::to_string_pretty(&OciState::from(&container.state))?,
+                Format::Table => state_args.format.render(&StateEntry::from(container))?,
+            };
+            println!("{}", output);
+            std::process::exit(0);
+        }
+    }
+}
+
+fn kill_container(root_path: &Path, container_id: &str, signal: Signal, all: bool) -> Result<()> {
+    // state of container is stored in a directory named as container id inside
+    // root directory given in commandline options
+    let container_root = root_path.join(container_id);
+    if !container_root.exists() {
+        bail!("{} doesn't exist.", container_id)
+    }
+
+    // load container state from json file, and check status of the container
+    // it might be possible that kill is invoked on a already stopped container etc.
+    let container = Container::load(container_root)?.refresh_status()?;
+    if container.can_kill() {
+        if all {
+            signal_all(&container, signal)?;
+        } else {
+            log::debug!("kill signal {} to {}", signal, container.pid().unwrap());
+            nix_signal::kill(container.pid().unwrap(), signal)?;
+        }
+        // other signals may be handled or ignored, the status is refreshed from the process
+        if signal == Signal::SIGKILL {
+            container.update_status(ContainerStatus::Stopped)?.save()?;
+        }
+        Ok(())
+    } else {
+        bail!(
+            "{} could not be killed because it was {:?}",
+            container.id(),
+            container.status()
+        )
+    }
+}
+
+fn delete_container(
+    root_path: &Path,
+    container_id: &str,
+    force: bool,
+    timeouts: &Timeouts,
+) -> Result<()> {
+    log::debug!("start deleting {}", container_id);
+    // state of container is stored in a directory named as container id inside
+    // root directory given in commandline options
+    let container_root = root_path.join(container_id);
+    if !container_root.exists() {
+        bail!("{} doesn't exist.", container_id)
+    }
+    // load container state from json file, and check status of the container
+    // it might be possible that delete is invoked on a running container.
+    log::debug!("load the container from {:?}", container_root);
+    let container = match Container::load(container_root.clone()) {
+        Ok(container) => container.refresh_status()?,
+        // a crash of the host may have cut the state short while it was written
+        Err(e) if force => {
+            log::warn!("failed to load the state of {}: {}", container_id, e);
+            Container::new(
+                container_id,
+                ContainerStatus::Creating,
+                None,
+                "",
+                &container_root,
+            )?
+        }
+        Err(e) => return Err(e),
+    };
+
+    // remove the cgroup created for the container
+    // check https://man7.org/linux/man-pages/man7/cgroups.7.html
+    // creating and removing cgroups section for more information on cgroups
+    let cgroups_path = container.cgroups_path()?;
+    let cmanager = cgroups::common::create_cgroup_manager(cgroups_path)?;
+
+    // a container left behind by a crash of the host may still look alive,
+    // as its pid can be reused by an unrelated process
+    let container = if force && !container.can_delete() {
+        let container = container.refresh_stale(cmanager.as_ref())?;
+        if container.can_delete() {
+            container.save()?;
+        }
+        container
+    } else {
+        container
+    };
+
+    let container = if force && !container.can_delete() {
+        kill_all(&container, cmanager.as_ref())?;
+        let container = container.update_status(ContainerStatus::Stopped)?;
+        container.save()?;
+        container
+    } else {
+        container
+    };
+
+    if container.can_delete() {
+        // the directory storing container state reserves the id, so it is removed
+        // last. A create of the same id fails until then, instead of getting its
+        // cgroups removed by this delete.
+        // shared cgroups belong to whoever created them
+        if container.state.shared_cgroups_path.is_none() {
+            cmanager.remove()?;
+        }
+        // the copy of config.json goes along with the state
+        let config_path = container.root.join("config.json");
+        let hooks = match oci_spec::Spec::load(&config_path.to_string_lossy()) {
+            Ok(spec) => spec.hooks,
+            Err(e) => {
+                log::warn!("failed to load the hooks of {}: {}", container.id(), e);
+                None
             }
-            // load container state from json file, and check status of the container
-            // it might be possible that delete is invoked on a running container.
-            log::debug!("load the container from {:?}", container_root);
-            let container = Container::load(container_root)?.refresh_status()?;
-            if container.can_delete() {
-                if container.root.exists() {
-                    nix::unistd::chdir(&PathBuf::from(&container.state.bundle))?;
-                    let config_absolute_path = &PathBuf::from(&container.state.bundle)
-                        .join(Path::new("config.json"))
-                        .to_string_lossy()
-                        .to_string();
-                    log::debug!("load spec from {:?}", config_absolute_path);
-                    let spec = oci_spec::Spec::load(config_absolute_path)?;
-                    log::debug!("spec: {:?}", spec);
-
-                    // remove the directory storing container state
-                    log::debug!("remove dir {:?}", container.root);
-                    fs::remove_dir_all(&container.root)?;
-
-                    let cgroups_path =
-                        utils::get_cgroup_path(&spec.linux.unwrap().cgroups_path, container.id());
-
-                    // remove the cgroup created for the container
-                    // check https://man7.org/linux/man-pages/man7/cgroups.7.html
-                    // creating and removing cgroups section for more information on cgroups
-                    let cmanager = cgroups::common::create_cgroup_manager(cgroups_path)?;
-                    cmanager.remove()?;
-                }
-                std::process::exit(0)
-            } else {
-                bail!(
-                    "{} could not be deleted because it was {:?}",
+        };
+        log::debug!("remove dir {:?}", container.root);
+        fs::remove_dir_all(&container.root)?;
+
+        // the container is gone whether or not they succeed
+        if let Some(hooks) = hooks {
+            let state = OciState::from(&container.update_status(ContainerStatus::Stopped)?.state);
+            if let Err(e) = hooks::run(&hooks.poststop, &state, timeouts.hooks()) {
+                log::warn!(
+                    "failed to run the poststop hooks of {}: {:?}",
                     container.id(),
-                    container.status()
-                )
+                    e
+                );
+                eprintln!("WARNING: failed to run the poststop hooks: {:#}", e);
             }
         }
-        SubCommand::State(state_args) => {
-            let root_path = fs::canonicalize(root_path)?;
-            let container_root = root_path.join(state_args.container_id);
-            let container = Container::load(container_root)?.refresh_status()?;
-            println!("{}", serde_json::to_string_pretty(&container.state)?);
-            std::process::exit(0);
+        Ok(())
+    } else {
+        bail!(
+            "{} could not be deleted because it was {:?}",
+            container.id(),
+            container.status()
+        )
+    }
+}
+
+/// Sends the signal to all processes of a container, which are those in its cgroups
+fn signal_all(container: &Container, signal: Signal) -> Result<()> {
+    if container.state.shared_cgroups_path.is_some() {
+        bail!(
+            "the cgroups of {} are shared, its processes cannot be told apart from those of others",
+            container.id()
+        );
+    }
+    let cmanager = cgroups::common::create_cgroup_manager(container.cgroups_path()?)?;
+    for pid in cmanager.get_all_pids()? {
+        log::debug!("kill signal {} to {} of {}", signal, pid, container.id());
+        // the process may have exited in the meantime
+        let _ = nix_signal::kill(pid, signal);
+    }
+    // frozen processes would not die before they are thawed
+    if signal == Signal::SIGKILL && container.status() == ContainerStatus::Paused {
+        cmanager.freeze(FreezerState::Thawed)?;
+    }
+    Ok(())
+}
+
+/// Kills the processes of a container which is alive and waits until they are gone
+fn kill_all(container: &Container, cmanager: &dyn CgroupManager) -> Result<()> {
+    // frozen processes would not die before they are thawed
+    if container.status() == ContainerStatus::Paused {
+        cmanager.freeze(FreezerState::Thawed)?;
+    }
+    // shared cgroups also contain the processes of others, so only the init process is
+    // killed, which takes the rest of its pid namespace with it
+    let shared = container.state.shared_cgroups_path.is_some();
+    let pids = match container.pid() {
+        Some(pid) if shared => vec![pid],
+        _ => cmanager.get_all_pids()?,
+    };
+    for pid in &pids {
+        log::debug!("kill {} of {}", pid, container.id());
+        let _ = nix_signal::kill(*pid, Signal::SIGKILL);
+    }
+
+    let start = Instant::now();
+    loop {
+        let alive = if shared {
+            pids.iter().any(|pid| nix_signal::kill(*pid, None).is_ok())
+        } else {
+            !cmanager.get_all_pids()?.is_empty()
+        };
+        if !alive {
+            return Ok(());
+        }
+        if start.elapsed() > KILL_TIMEOUT {
+            bail!(
+                "processes of {} are still alive after SIGKILL",
+                container.id()
+            );
         }
+        thread::sleep(Duration::from_millis(10));
     }
 }
@@ -0,0 +1,295 @@
+//! Serves the resource usage of every container in the state roots in the text format of
+//! Prometheus, so that hosts without a container engine can be monitored as well.
+
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use clap::Clap;
+
+use crate::cgroups;
+use crate::cgroups::stats::{self, BlkioStatEntry, Psi, Stats};
+use crate::container::Container;
+
+#[derive(Clap, Debug)]
+pub struct Metrics {
+    /// address to serve the metrics on, at /metrics
+    #[clap(long, default_value = "127.0.0.1:9090")]
+    pub listen: SocketAddr,
+}
+
+impl Metrics {
+    pub fn exec(&self, roots: &[PathBuf]) -> Result<()> {
+        let listener = TcpListener::bind(self.listen)
+            .with_context(|| format!("failed to listen on {}", self.listen))?;
+        log::info!("serving metrics on http://{}/metrics", self.listen);
+
+        // scrapes are rare, so they are answered one at a time
+        for stream in listener.incoming() {
+            let result = stream
+                .map_err(anyhow::Error::from)
+                .and_then(|stream| respond(stream, roots));
+            if let Err(e) = result {
+                log::warn!("failed to serve a request for metrics: {:#}", e);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn respond(mut stream: TcpStream, roots: &[PathBuf]) -> Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+
+    let mut parts = request_line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some(path)) if path.split('?').next() == Some("/metrics") => {
+            ("200 OK", render(&collect(roots)?))
+        }
+        _ => ("404 Not Found", "not found\n".to_owned()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    Ok(())
+}
+
+/// Statistics of the containers which have processes, by id
+fn collect(roots: &[PathBuf]) -> Result<Vec<(String, Stats)>> {
+    let mut collected = Vec::new();
+    for container in Container::load_all(roots)? {
+        let pid = match container.pid() {
+            Some(pid) if container.can_kill() => pid,
+            _ => continue,
+        };
+        // a container may exit while it is scraped, which must not fail the others
+        let stats = container.cgroups_path().and_then(|path| {
+            let mut stats = cgroups::common::create_cgroup_manager(path)?.stats()?;
+            stats.network_interfaces = stats::network_interfaces(pid)?;
+            Ok(stats)
+        });
+        match stats {
+            Ok(stats) => collected.push((container.id().to_owned(), stats)),
+            Err(e) => log::warn!(
+                "failed to get the statistics of {}: {:#}",
+                container.id(),
+                e
+            ),
+        }
+    }
+    collected.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(collected)
+}
+
+/// Labels and values of the samples of a metric
+type Samples = Vec<(String, String)>;
+
+/// Formats the statistics in the text exposition format of Prometheus
+fn render(containers: &[(String, Stats)]) -> String {
+    let mut out = String::new();
+    let mut family = |name: &str, typ: &str, help: &str, samples: Samples| {
+        if samples.is_empty() {
+            return;
+        }
+        let _ = writeln!(out, "# HELP youki_container_{} {}", name, help);
+        let _ = writeln!(out, "# TYPE youki_container_{} {}", name, typ);
+        for (labels, value) in samples {
+            let _ = writeln!(out, "youki_container_{}{{{}}} {}", name, labels, value);
+        }
+    };
+    let each = |f: &dyn Fn(&str, &Stats) -> Samples| {
+        containers
+            .iter()
+            .flat_map(|(id, stats)| f(&format!("id=\"{}\"", escape(id)), stats))
+            .collect::<Vec<_>>()
+    };
+
+    family(
+        "cpu_usage_seconds_total",
+        "counter",
+        "Total CPU time consumed.",
+        each(&|id, stats| vec![(id.to_owned(), seconds(stats.cpu.usage.total, 1e9))]),
+    );
+    family(
+        "memory_usage_bytes",
+        "gauge",
+        "Current memory usage.",
+        each(&|id, stats| vec![(id.to_owned(), stats.memory.usage.usage.to_string())]),
+    );
+    family(
+        "memory_limit_bytes",
+        "gauge",
+        "Memory limit, left out when there is none.",
+        each(&|id, stats| match stats.memory.usage.limit {
+            u64::MAX => vec![],
+            limit => vec![(id.to_owned(), limit.to_string())],
+        }),
+    );
+    family(
+        "pids_current",
+        "gauge",
+        "Number of processes.",
+        each(&|id, stats| vec![(id.to_owned(), stats.pids.current.to_string())]),
+    );
+    family(
+        "blkio_bytes_total",
+        "counter",
+        "Bytes transferred to and from block devices.",
+        each(&|id, stats| blkio_samples(id, &stats.blkio.service_bytes)),
+    );
+    family(
+        "blkio_ios_total",
+        "counter",
+        "IO operations on block devices.",
+        each(&|id, stats| blkio_samples(id, &stats.blkio.serviced)),
+    );
+    family(
+        "network_receive_bytes_total",
+        "counter",
+        "Bytes received by network interfaces.",
+        each(&|id, stats| {
+            stats
+                .network_interfaces
+                .iter()
+                .map(|interface| {
+                    let labels = format!("{},interface=\"{}\"", id, escape(&interface.name));
+                    (labels, interface.rx_bytes.to_string())
+                })
+                .collect()
+        }),
+    );
+    family(
+        "network_transmit_bytes_total",
+        "counter",
+        "Bytes transmitted by network interfaces.",
+        each(&|id, stats| {
+            stats
+                .network_interfaces
+                .iter()
+                .map(|interface| {
+                    let labels = format!("{},interface=\"{}\"", id, escape(&interface.name));
+                    (labels, interface.tx_bytes.to_string())
+                })
+                .collect()
+        }),
+    );
+    family(
+        "pressure_seconds_total",
+        "counter",
+        "Time in which tasks stalled on a resource, from pressure stall information.",
+        each(&|id, stats| {
+            let resources = [
+                ("cpu", &stats.cpu.psi),
+                ("memory", &stats.memory.psi),
+                ("io", &stats.blkio.psi),
+            ];
+            resources
+                .iter()
+                .filter_map(|(resource, psi)| psi.as_ref().map(|psi| (resource, psi)))
+                .flat_map(|(resource, psi): (&&str, &Psi)| {
+                    vec![("some", &psi.some), ("full", &psi.full)]
+                        .into_iter()
+                        .map(move |(kind, data)| {
+                            let labels =
+                                format!("{},resource=\"{}\",kind=\"{}\"", id, resource, kind);
+                            (labels, seconds(data.total, 1e6))
+                        })
+                })
+                .collect()
+        }),
+    );
+    out
+}
+
+fn blkio_samples(id: &str, entries: &[BlkioStatEntry]) -> Samples {
+    entries
+        .iter()
+        .map(|entry| {
+            let labels = format!(
+                "{},device=\"{}:{}\",op=\"{}\"",
+                id,
+                entry.major,
+                entry.minor,
+                escape(&entry.op)
+            );
+            (labels, entry.value.to_string())
+        })
+        .collect()
+}
+
+fn seconds(value: u64, per_second: f64) -> String {
+    (value as f64 / per_second).to_string()
+}
+
+/// Escapes a label value, in which backslashes, quotes and line feeds are special
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cgroups::stats::{NetworkInterface, PsiData};
+
+    #[test]
+    fn test_render() {
+        let mut stats = Stats::default();
+        stats.cpu.usage.total = 1_500_000_000;
+        stats.memory.usage.usage = 4096;
+        stats.memory.usage.limit = u64::MAX;
+        stats.pids.current = 3;
+        stats.blkio.service_bytes.push(BlkioStatEntry {
+            major: 8,
+            minor: 0,
+            op: "Read".to_owned(),
+            value: 512,
+        });
+        stats.network_interfaces.push(NetworkInterface {
+            name: "eth0".to_owned(),
+            rx_bytes: 100,
+            tx_bytes: 200,
+            ..Default::default()
+        });
+        stats.memory.psi = Some(Psi {
+            some: PsiData {
+                total: 2_000_000,
+                ..Default::default()
+            },
+            full: PsiData::default(),
+        });
+
+        let text = render(&[("web".to_owned(), stats)]);
+        assert!(text.contains("# TYPE youki_container_cpu_usage_seconds_total counter\n"));
+        assert!(text.contains("youki_container_cpu_usage_seconds_total{id=\"web\"} 1.5\n"));
+        assert!(text.contains("youki_container_memory_usage_bytes{id=\"web\"} 4096\n"));
+        assert!(!text.contains("memory_limit_bytes"));
+        assert!(text.contains("youki_container_pids_current{id=\"web\"} 3\n"));
+        assert!(text.contains(
+            "youki_container_blkio_bytes_total{id=\"web\",device=\"8:0\",op=\"Read\"} 512\n"
+        ));
+        assert!(!text.contains("blkio_ios_total"));
+        assert!(text.contains(
+            "youki_container_network_transmit_bytes_total{id=\"web\",interface=\"eth0\"} 200\n"
+        ));
+        assert!(text.contains(
+            "youki_container_pressure_seconds_total{id=\"web\",resource=\"memory\",kind=\"some\"} 2\n"
+        ));
+        assert!(!text.contains("resource=\"cpu\""));
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+}
@@ -3,12 +3,17 @@
 //! Process (processes in a namespace have two PIDs, one for the global PID,
 //! which is used by the main system and the second one is for the child within the process tree),
 //! Interprocess Communication (Control or communication between processes),
-//! Network (which network devices can be seen by the processes in the namespace), User (User configs), 
+//! Network (which network devices can be seen by the processes in the namespace), User (User configs),
 //! UTS (hostname and domain information, processes will think they're running on servers with different names),
-//! Cgroup (Resource limits, execution priority etc.)
+//! Cgroup (Resource limits, execution priority etc.),
+//! Time (offsets of the monotonic and boot time clocks)
 
-use anyhow::Result;
+use std::collections::HashMap;
+use std::fs;
+
+use anyhow::{bail, Context, Result};
 use nix::{
+    errno::Errno,
     fcntl,
     sched::{self, CloneFlags},
     sys::stat,
@@ -16,7 +21,11 @@ use nix::{
 };
 
 use crate::command::{linux::LinuxCommand, test::TestHelperCommand, Command};
-use oci_spec::{LinuxNamespace, LinuxNamespaceType};
+use crate::process::message::Phase;
+use oci_spec::{LinuxNamespace, LinuxNamespaceType, LinuxTimeOffset};
+
+/// CLONE_NEWTIME, which neither nix nor libc know yet
+const CLONE_NEWTIME: libc::c_int = 0x80;
 
 pub struct Namespaces {
     spaces: Vec<LinuxNamespace>,
@@ -48,24 +57,31 @@ impl From<Vec<LinuxNamespace>> for Namespaces {
 }
 
 impl Namespaces {
+    /// Whether the process gets a namespace of the type, be it a new or an existing one
+    pub fn contains(&self, typ: LinuxNamespaceType) -> bool {
+        self.spaces.iter().any(|ns| ns.typ == typ)
+    }
+
+    /// Joins the existing namespaces given by path. The user namespace is joined first,
+    /// as the others are owned by it and joining them needs capabilities in it. All of
+    /// them are opened before, as the paths may be gone once the mount namespace is joined.
     pub fn apply_setns(&self) -> Result<()> {
-        let to_enter: Vec<(CloneFlags, i32)> = self
-            .spaces
-            .iter()
-            .filter(|ns| ns.path.is_some())
-            .map(|ns| {
+        let mut spaces: Vec<&LinuxNamespace> = self.spaces.iter().collect();
+        spaces.sort_by_key(|ns| ns.typ != LinuxNamespaceType::User);
+        let to_enter: Vec<(CloneFlags, i32, &str)> = spaces
+            .into_iter()
+            .filter_map(|ns| ns.path.as_ref().map(|path| (ns, path)))
+            .map(|(ns, path)| {
                 let space = CloneFlags::from_bits_truncate(ns.typ as i32);
-                let fd = fcntl::open(
-                    &*ns.path.as_ref().unwrap().clone(),
-                    fcntl::OFlag::empty(),
-                    stat::Mode::empty(),
-                )
-                .unwrap();
-                (space, fd)
+                let fd = fcntl::open(path.as_str(), fcntl::OFlag::empty(), stat::Mode::empty())
+                    .with_context(|| Phase::with_path("open namespace", path))?;
+                Ok((space, fd, path.as_str()))
             })
-            .collect();
-        for &(space, fd) in &to_enter {
-            self.command.set_ns(fd, space)?;
+            .collect::<Result<_>>()?;
+        for &(space, fd, path) in &to_enter {
+            self.command
+                .set_ns(fd, space)
+                .with_context(|| Phase::with_path("setns", path))?;
             unistd::close(fd)?;
             if space == sched::CloneFlags::CLONE_NEWUSER {
                 self.command.set_id(Uid::from_raw(0), Gid::from_raw(0))?;
@@ -74,10 +90,54 @@ impl Namespaces {
         Ok(())
     }
 
+    /// Creates the namespaces which are not given by path, except those of without
     pub fn apply_unshare(&self, without: CloneFlags) -> Result<()> {
         self.command.unshare(self.clone_flags & !without)?;
         Ok(())
     }
+
+    /// Creates a time namespace for the children of the process, unless it is given by
+    /// path, and sets the offsets of its clocks. They can only be set before a process is
+    /// in the namespace, i.e. before the process forks.
+    pub fn apply_time_offsets(&self, offsets: &HashMap<String, LinuxTimeOffset>) -> Result<()> {
+        let unshared = self
+            .spaces
+            .iter()
+            .any(|ns| ns.typ == LinuxNamespaceType::Time && ns.path.is_none());
+        if !unshared {
+            return Ok(());
+        }
+        let offsets = format_time_offsets(offsets)?;
+        Errno::result(unsafe { libc::unshare(CLONE_NEWTIME) })
+            .context(Phase::new("unshare time namespace"))?;
+        if !offsets.is_empty() {
+            log::debug!("set offsets of the time namespace to {:?}", offsets);
+            fs::write("/proc/self/timens_offsets", offsets)
+                .context(Phase::new("set time offsets"))?;
+        }
+        Ok(())
+    }
+}
+
+/// Formats the offsets like /proc/<pid>/timens_offsets takes them, a clock per line
+fn format_time_offsets(offsets: &HashMap<String, LinuxTimeOffset>) -> Result<String> {
+    let mut clocks: Vec<&String> = offsets.keys().collect();
+    clocks.sort();
+    let mut lines = String::new();
+    for clock in clocks {
+        if clock != "monotonic" && clock != "boottime" {
+            bail!("linux.timeOffsets has the unknown clock {}", clock);
+        }
+        let offset = &offsets[clock];
+        if offset.nanosecs >= 1_000_000_000 {
+            bail!(
+                "nanosecs of {} in linux.timeOffsets are not below a second",
+                clock
+            );
+        }
+        lines.push_str(&format!("{} {} {}\n", clock, offset.secs, offset.nanosecs));
+    }
+    Ok(lines)
 }
 
 mod tests {
@@ -127,6 +187,61 @@ mod tests {
         assert_eq!(setns_args, expect);
     }
 
+    #[test]
+    fn test_namespaces_set_ns_user_first() {
+        let mut sample_linux_namespaces = gen_sample_linux_namespaces();
+        sample_linux_namespaces[3].path = Some("/dev/null".to_string());
+        let namespaces: Namespaces = sample_linux_namespaces.into();
+        assert!(namespaces.contains(LinuxNamespaceType::User));
+        assert!(!namespaces.contains(LinuxNamespaceType::Uts));
+        assert!(!namespaces.clone_flags.contains(CloneFlags::CLONE_NEWUSER));
+        assert!(namespaces.apply_setns().is_ok());
+
+        let test_command: &TestHelperCommand = namespaces.command.as_any().downcast_ref().unwrap();
+        let setns_args: Vec<_> = test_command
+            .get_setns_args()
+            .into_iter()
+            .map(|(_fd, cf)| cf)
+            .collect();
+        assert_eq!(
+            setns_args,
+            vec![
+                CloneFlags::CLONE_NEWUSER,
+                CloneFlags::CLONE_NEWNS,
+                CloneFlags::CLONE_NEWNET
+            ]
+        );
+    }
+
+    #[test]
+    fn test_format_time_offsets() {
+        let mut offsets = HashMap::new();
+        offsets.insert(
+            "monotonic".to_string(),
+            LinuxTimeOffset {
+                secs: -10,
+                nanosecs: 500,
+            },
+        );
+        offsets.insert(
+            "boottime".to_string(),
+            LinuxTimeOffset {
+                secs: 86400,
+                nanosecs: 0,
+            },
+        );
+        assert_eq!(
+            format_time_offsets(&offsets).unwrap(),
+            "boottime 86400 0\nmonotonic -10 500\n"
+        );
+
+        offsets.insert("realtime".to_string(), LinuxTimeOffset::default());
+        assert!(format_time_offsets(&offsets).is_err());
+        offsets.remove("realtime");
+        offsets.get_mut("boottime").unwrap().nanosecs = 1_000_000_000;
+        assert!(format_time_offsets(&offsets).is_err());
+    }
+
     #[test]
     fn test_namespaces_unshare() {
         let sample_linux_namespaces = gen_sample_linux_namespaces();
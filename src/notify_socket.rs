@@ -1,59 +1,169 @@
-use std::io::prelude::*;
-use std::os::unix::io::AsRawFd;
-use std::os::unix::net::{UnixListener, UnixStream};
-use std::path::Path;
+use std::fs;
+use std::io;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::path::{Path, PathBuf};
 
-use anyhow::Result;
-use nix::unistd::close;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use nix::sys::signal;
+use nix::unistd::Pid;
+use oci_spec::{Mount, Spec};
 
 pub const NOTIFY_FILE: &str = "notify.sock";
+/// Environment variable with the socket of the service manager, e.g. of systemd
+pub const NOTIFY_SOCKET: &str = "NOTIFY_SOCKET";
+/// Directory of the container with the socket which the container notifies
+const PROXY_DIR: &str = "notify";
+/// Where the directory of the proxy socket is mounted in the container
+const CONTAINER_PROXY_DIR: &str = "/run/notify";
+
+/// Mounts the directory of the proxy socket into the container and points the process of
+/// the container to the socket. The socket itself is bound by start, which relays from it.
+pub fn add_proxy(container_dir: &Path, spec: &mut Spec) -> Result<()> {
+    let dir = container_dir.join(PROXY_DIR);
+    fs::create_dir(&dir).with_context(|| format!("failed to create {:?}", dir))?;
+    spec.mounts.push(Mount {
+        destination: PathBuf::from(CONTAINER_PROXY_DIR),
+        typ: "bind".to_owned(),
+        source: fs::canonicalize(&dir)?,
+        options: ["bind", "nosuid", "noexec", "nodev"]
+            .iter()
+            .map(|option| option.to_string())
+            .collect(),
+    });
+    let prefix = format!("{}=", NOTIFY_SOCKET);
+    spec.process.env.retain(|env| !env.starts_with(&prefix));
+    spec.process
+        .env
+        .push(format!("{}{}/{}", prefix, CONTAINER_PROXY_DIR, NOTIFY_FILE));
+    Ok(())
+}
 
-pub struct NotifyListener {
-    socket: UnixListener,
+/// Relays the notifications of the container to the service manager which runs the
+/// runtime, e.g. to systemd for a service of Type=notify
+pub struct NotifyProxy {
+    socket: UnixDatagram,
+    host_socket: String,
 }
 
-impl NotifyListener {
-    pub fn new(root: &Path) -> Result<Self> {
-        let _notify_file_path = root.join(NOTIFY_FILE);
-        let stream = UnixListener::bind("notify.sock")?;
-        Ok(Self { socket: stream })
+impl NotifyProxy {
+    /// Binds the socket which the container notifies, replacing one left by an earlier start
+    pub fn bind(container_root: &Path, host_socket: &str) -> Result<Self> {
+        let path = container_root.join(PROXY_DIR).join(NOTIFY_FILE);
+        let _ = fs::remove_file(&path);
+        let socket =
+            UnixDatagram::bind(&path).with_context(|| format!("failed to bind {:?}", path))?;
+        // the process of the container may run as any user
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o777))?;
+        Ok(Self {
+            socket,
+            host_socket: host_socket.to_owned(),
+        })
     }
 
-    pub fn wait_for_container_start(&mut self) -> Result<()> {
-        match self.socket.accept() {
-            Ok((mut socket, _addr)) => {
-                let mut response = String::new();
-                socket.read_to_string(&mut response)?;
-                log::debug!("receive :{}", response);
+    /// Relays the messages of the container until it reports READY=1, which is passed on
+    /// along with MAINPID, so that the service manager watches the container instead of the
+    /// runtime. Messages sent after that are not relayed. Returns early if the container
+    /// exits before it is ready.
+    pub fn relay(&self, main_pid: Pid) -> Result<()> {
+        let host = UnixDatagram::unbound()?;
+        let host_addr = socket_addr(&self.host_socket)?;
+        self.socket
+            .set_read_timeout(Some(Duration::from_millis(100)))?;
+        let mut buf = [0; 4096];
+        loop {
+            let len = match self.socket.recv(&mut buf) {
+                Ok(len) => len,
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    if signal::kill(main_pid, None).is_err() {
+                        log::debug!("container exited before it was ready");
+                        return Ok(());
+                    }
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            let (message, ready) = relayed_message(&buf[..len], main_pid);
+            host.send_to_addr(&message, &host_addr)
+                .with_context(|| format!("failed to notify {}", self.host_socket))?;
+            if ready {
+                return Ok(());
             }
-            Err(e) => println!("accept function failed: {:?}", e),
         }
-        Ok(())
     }
+}
 
-    pub fn close(&mut self) -> Result<()> {
-        close(self.socket.as_raw_fd())?;
-        Ok(())
+/// Address of the socket in NOTIFY_SOCKET, which is abstract if it starts with @
+fn socket_addr(socket: &str) -> Result<SocketAddr> {
+    let addr = match socket.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None => SocketAddr::from_pathname(socket)?,
+    };
+    Ok(addr)
+}
+
+/// The message to relay, with MAINPID added once the container is ready, and whether it is
+fn relayed_message(message: &[u8], main_pid: Pid) -> (Vec<u8>, bool) {
+    let ready = message
+        .split(|b| *b == b'\n')
+        .any(|line| line == b"READY=1");
+    let mut message = message.to_vec();
+    if ready {
+        if message.last() != Some(&b'\n') {
+            message.push(b'\n');
+        }
+        message.extend(format!("MAINPID={}\n", main_pid).as_bytes());
     }
+    (message, ready)
 }
 
-pub struct NotifySocket {}
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-impl NotifySocket {
-    pub fn new(_root: &Path) -> Result<Self> {
-        Ok(Self {})
+    #[test]
+    fn test_relayed_message() {
+        let pid = Pid::from_raw(42);
+        assert_eq!(
+            relayed_message(b"STATUS=starting", pid),
+            (b"STATUS=starting".to_vec(), false)
+        );
+        assert_eq!(
+            relayed_message(b"STATUS=up\nREADY=1", pid),
+            (b"STATUS=up\nREADY=1\nMAINPID=42\n".to_vec(), true)
+        );
+        assert!(!relayed_message(b"READY=10", pid).1);
     }
 
-    pub fn notify_container_start(&mut self) -> Result<()> {
-        log::debug!("connection start");
-        let mut stream = UnixStream::connect("notify.sock")?;
-        stream.write_all(b"start container")?;
-        log::debug!("write finish");
-        Ok(())
-    }
+    #[test]
+    fn test_relay() {
+        let tmp = std::env::temp_dir().join("test_notify_relay");
+        let _ = fs::remove_dir_all(&tmp);
+        fs::create_dir_all(tmp.join(PROXY_DIR)).unwrap();
+        let host_path = tmp.join("host.sock");
+        let host = UnixDatagram::bind(&host_path).unwrap();
+
+        let proxy = NotifyProxy::bind(&tmp, &host_path.to_string_lossy()).unwrap();
+        let container = UnixDatagram::unbound().unwrap();
+        let proxy_path = tmp.join(PROXY_DIR).join(NOTIFY_FILE);
+        container.send_to(b"STATUS=starting", &proxy_path).unwrap();
+        container.send_to(b"READY=1\n", &proxy_path).unwrap();
+        proxy.relay(nix::unistd::getpid()).unwrap();
 
-    pub fn notify_container_finish(&mut self) -> Result<()> {
-        // self.socket.write_all(b"finish container")?;
-        Ok(())
+        let mut buf = [0; 64];
+        let len = host.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"STATUS=starting");
+        let len = host.recv(&mut buf).unwrap();
+        let ready = format!("READY=1\nMAINPID={}\n", nix::unistd::getpid());
+        assert_eq!(&buf[..len], ready.as_bytes());
+        fs::remove_dir_all(&tmp).unwrap();
     }
 }
@@ -0,0 +1,139 @@
+//! Prints the output of subcommands, as json for machines or as a table for humans
+
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
+use serde::Serialize;
+
+/// Format of the output, selected with --format
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Format {
+    Json,
+    Table,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(format: &str) -> Result<Self> {
+        Ok(match format {
+            "json" => Format::Json,
+            "table" => Format::Table,
+            _ => bail!("{} is not a valid format, use json or table", format),
+        })
+    }
+}
+
+/// Item which a subcommand prints. Its json form is its serialization, so that the field
+/// names stay the same wherever it is printed.
+pub trait Output: Serialize {
+    /// Headers of the columns in the table
+    fn headers() -> Vec<&'static str>;
+    /// Values of the columns in the table, in the order of the headers
+    fn row(&self) -> Vec<String>;
+}
+
+impl Format {
+    /// Renders a single item, such as the state of a container
+    pub fn render<T: Output>(&self, item: &T) -> Result<String> {
+        match self {
+            Format::Json => Ok(serde_json::to_string_pretty(item)?),
+            Format::Table => Ok(table(&T::headers(), &[item.row()])),
+        }
+    }
+
+    /// Renders several items, such as every container. Json is an array even when
+    /// there are none.
+    pub fn render_all<T: Output>(&self, items: &[T]) -> Result<String> {
+        match self {
+            Format::Json => Ok(serde_json::to_string_pretty(items)?),
+            Format::Table => {
+                let rows: Vec<Vec<String>> = items.iter().map(|item| item.row()).collect();
+                Ok(table(&T::headers(), &rows))
+            }
+        }
+    }
+}
+
+/// Aligns the columns to the widest value in them, separated by three spaces
+fn table(headers: &[&str], rows: &[Vec<String>]) -> String {
+    let mut widths: Vec<usize> = headers.iter().map(|header| header.len()).collect();
+    for row in rows {
+        for (width, value) in widths.iter_mut().zip(row) {
+            *width = (*width).max(value.len());
+        }
+    }
+
+    let headers: Vec<String> = headers.iter().map(|header| header.to_string()).collect();
+    std::iter::once(&headers)
+        .chain(rows)
+        .map(|row| {
+            row.iter()
+                .zip(&widths)
+                .map(|(value, width)| format!("{:width$}", value, width = width))
+                .collect::<Vec<String>>()
+                .join("   ")
+                .trim_end()
+                .to_owned()
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Item {
+        item_id: String,
+        pid: i32,
+    }
+
+    impl Output for Item {
+        fn headers() -> Vec<&'static str> {
+            vec!["ID", "PID"]
+        }
+
+        fn row(&self) -> Vec<String> {
+            vec![self.item_id.clone(), self.pid.to_string()]
+        }
+    }
+
+    #[test]
+    fn test_format_from_str() {
+        assert_eq!(Format::from_str("json").unwrap(), Format::Json);
+        assert_eq!(Format::from_str("table").unwrap(), Format::Table);
+        assert!(Format::from_str("yaml").is_err());
+    }
+
+    #[test]
+    fn test_render() {
+        let items = vec![
+            Item {
+                item_id: "a-long-id".to_owned(),
+                pid: 1,
+            },
+            Item {
+                item_id: "b".to_owned(),
+                pid: 12345,
+            },
+        ];
+
+        assert_eq!(
+            Format::Table.render_all(&items).unwrap(),
+            "ID          PID\na-long-id   1\nb           12345"
+        );
+        assert_eq!(
+            Format::Table.render(&items[1]).unwrap(),
+            "ID   PID\nb    12345"
+        );
+
+        let json: serde_json::Value =
+            serde_json::from_str(&Format::Json.render_all(&items).unwrap()).unwrap();
+        assert_eq!(json[0]["itemId"], "a-long-id");
+        assert_eq!(json[1]["pid"], 12345);
+        assert_eq!(Format::Json.render_all::<Item>(&[]).unwrap(), "[]");
+    }
+}
@@ -0,0 +1,75 @@
+//! Freezes the processes of containers and lets them run again, without them noticing
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+use clap::Clap;
+
+use crate::batch;
+use crate::cgroups::{self, common::FreezerState};
+use crate::container::{Container, ContainerStatus};
+
+#[derive(Clap, Debug)]
+pub struct Pause {
+    /// pause every container in the state root
+    #[clap(long)]
+    all_containers: bool,
+    container_ids: Vec<String>,
+}
+
+#[derive(Clap, Debug)]
+pub struct Resume {
+    /// resume every container in the state root
+    #[clap(long)]
+    all_containers: bool,
+    container_ids: Vec<String>,
+}
+
+impl Pause {
+    pub fn exec(&self, root_path: PathBuf) -> Result<()> {
+        let ids = batch::container_ids(&root_path, &self.container_ids, self.all_containers)?;
+        batch::run("pause", &ids, |id| {
+            let container = load(&root_path, id)?;
+            if !container.can_pause() {
+                bail!(
+                    "{} could not be paused because it was {:?}",
+                    container.id(),
+                    container.status()
+                )
+            }
+            freeze(&container, FreezerState::Frozen)?;
+            container.update_status(ContainerStatus::Paused)?.save()
+        })
+    }
+}
+
+impl Resume {
+    pub fn exec(&self, root_path: PathBuf) -> Result<()> {
+        let ids = batch::container_ids(&root_path, &self.container_ids, self.all_containers)?;
+        batch::run("resume", &ids, |id| {
+            let container = load(&root_path, id)?;
+            if !container.can_resume() {
+                bail!(
+                    "{} could not be resumed because it was {:?}",
+                    container.id(),
+                    container.status()
+                )
+            }
+            freeze(&container, FreezerState::Thawed)?;
+            container.update_status(ContainerStatus::Running)?.save()
+        })
+    }
+}
+
+fn load(root_path: &Path, container_id: &str) -> Result<Container> {
+    let container_root = root_path.join(container_id);
+    if !container_root.exists() {
+        bail!("{} doesn't exist.", container_id)
+    }
+    Container::load(container_root)?.refresh_status()
+}
+
+fn freeze(container: &Container, state: FreezerState) -> Result<()> {
+    let cmanager = cgroups::common::create_cgroup_manager(container.cgroups_path()?)?;
+    cmanager.freeze(state)
+}
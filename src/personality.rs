@@ -0,0 +1,60 @@
+//! Sets the execution domain of linux.personality for the container process, e.g. LINUX32
+//! so that a 32-bit userland sees a 32-bit machine in uname(2) on a 64-bit kernel. The
+//! personality is kept across exec.
+
+use anyhow::{Context, Result};
+use nix::errno::Errno;
+use oci_spec::{LinuxPersonality, LinuxPersonalityDomain};
+
+use crate::container::Warning;
+
+/// PER_LINUX and PER_LINUX32 of linux/personality.h
+const PER_LINUX: libc::c_ulong = 0x0000;
+const PER_LINUX32: libc::c_ulong = 0x0008;
+
+fn persona(domain: LinuxPersonalityDomain) -> libc::c_ulong {
+    match domain {
+        LinuxPersonalityDomain::Linux => PER_LINUX,
+        LinuxPersonalityDomain::Linux32 => PER_LINUX32,
+    }
+}
+
+/// Sets the domain of the personality for the current process
+pub fn apply(personality: &LinuxPersonality) -> Result<()> {
+    let res = unsafe { libc::personality(persona(personality.domain)) };
+    Errno::result(res)
+        .map(drop)
+        .with_context(|| format!("failed to set personality {:?}", personality.domain))
+}
+
+/// The spec defines no flags yet, so whatever is given is left out
+pub fn unsupported(personality: &LinuxPersonality) -> Option<Warning> {
+    if personality.flags.is_empty() {
+        return None;
+    }
+    Some(Warning::new(
+        "linux.personality.flags",
+        &format!("{:?} are not supported", personality.flags),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_personality() {
+        let personality: LinuxPersonality =
+            serde_json::from_str(r#"{ "domain": "LINUX32" }"#).expect("parse personality");
+        assert_eq!(persona(personality.domain), PER_LINUX32);
+        assert_eq!(unsupported(&personality), None);
+
+        let personality: LinuxPersonality =
+            serde_json::from_str(r#"{ "domain": "LINUX", "flags": ["SHORT_INODE"] }"#)
+                .expect("parse personality");
+        assert_eq!(persona(personality.domain), PER_LINUX);
+        assert!(unsupported(&personality).is_some());
+
+        assert!(serde_json::from_str::<LinuxPersonality>(r#"{ "domain": "BSD" }"#).is_err());
+    }
+}
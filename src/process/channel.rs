@@ -0,0 +1,192 @@
+//! Channel over a socketpair, on which typed messages are exchanged between
+//! the processes taking part in creating a container
+
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::socket::{self, ControlMessage, ControlMessageOwned, MsgFlags};
+use nix::sys::uio::IoVec;
+
+use crate::process::message::Message;
+
+/// One end of a channel. Each message is sent as its length in 4 big endian
+/// bytes followed by the message serialized as json.
+pub struct Channel {
+    stream: UnixStream,
+}
+
+/// Creates a connected pair of channels. Both ends are close-on-exec, so that
+/// they are not leaked to the command of the container.
+pub fn channel() -> Result<(Channel, Channel)> {
+    let (first, second) = UnixStream::pair()?;
+    Ok((Channel { stream: first }, Channel { stream: second }))
+}
+
+impl Channel {
+    pub fn send(&mut self, msg: &Message) -> Result<()> {
+        log::debug!("send message {:?}", msg);
+        let data = serde_json::to_vec(msg)?;
+        self.stream.write_all(&(data.len() as u32).to_be_bytes())?;
+        self.stream.write_all(&data)?;
+        Ok(())
+    }
+
+    /// Waits for the next message. Fails if the other end is closed, which
+    /// happens when the process holding it exits.
+    pub fn recv(&mut self) -> Result<Message> {
+        let mut len = [0; 4];
+        self.read_exact(&mut len)?;
+        let mut data = vec![0; u32::from_be_bytes(len) as usize];
+        self.read_exact(&mut data)?;
+
+        let msg = serde_json::from_slice(&data)?;
+        log::debug!("receive message {:?}", msg);
+        Ok(msg)
+    }
+
+    /// Waits for the next message for at most the given duration, and returns None
+    /// if none arrived. A message is never partially consumed on timeout.
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<Option<Message>> {
+        let mut fds = [PollFd::new(self.stream.as_raw_fd(), PollFlags::POLLIN)];
+        match poll(&mut fds, timeout.as_millis() as i32) {
+            Ok(0) | Err(nix::Error::Sys(Errno::EINTR)) => Ok(None),
+            // readable, or the other end is closed which recv reports
+            Ok(_) => self.recv().map(Some),
+            Err(e) => bail!("failed to poll the channel: {}", e),
+        }
+    }
+
+    /// Passes a file descriptor to the other process, which receives it with recv_fd. It
+    /// follows the message which announces it.
+    pub fn send_fd(&mut self, fd: RawFd) -> Result<()> {
+        let fds = [fd];
+        socket::sendmsg(
+            self.stream.as_raw_fd(),
+            &[IoVec::from_slice(&[0])],
+            &[ControlMessage::ScmRights(&fds)],
+            MsgFlags::empty(),
+            None,
+        )?;
+        Ok(())
+    }
+
+    /// Receives the file descriptor which the other process passes with send_fd
+    pub fn recv_fd(&mut self) -> Result<RawFd> {
+        let mut buf = [0];
+        let mut cmsg_buffer = nix::cmsg_space!(RawFd);
+        let msg = socket::recvmsg(
+            self.stream.as_raw_fd(),
+            &[IoVec::from_mut_slice(&mut buf)],
+            Some(&mut cmsg_buffer),
+            MsgFlags::MSG_CMSG_CLOEXEC,
+        )?;
+        for cmsg in msg.cmsgs() {
+            if let ControlMessageOwned::ScmRights(fds) = cmsg {
+                if let Some(fd) = fds.first() {
+                    return Ok(*fd);
+                }
+            }
+        }
+        bail!("no file descriptor is passed on the channel")
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
+        match self.stream.read_exact(buf) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                bail!("channel is closed by the other process")
+            }
+            Err(e) => bail!("failed to receive a message on the channel: {}", e),
+        }
+    }
+}
+
+impl AsRawFd for Channel {
+    fn as_raw_fd(&self) -> RawFd {
+        self.stream.as_raw_fd()
+    }
+}
+
+impl FromRawFd for Channel {
+    unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        Self {
+            stream: UnixStream::from_raw_fd(fd),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::message::SetupError;
+
+    #[test]
+    fn test_send_and_recv() -> Result<()> {
+        let (mut sender, mut receiver) = channel()?;
+        sender.send(&Message::InitPid(42))?;
+        let err = SetupError {
+            phase: Some("mount".to_owned()),
+            path: Some("/proc".into()),
+            errno: Some(1),
+            detail: "mount of /proc failed: EPERM: Operation not permitted".to_owned(),
+        };
+        sender.send(&Message::Error(err))?;
+        sender.send(&Message::InitReady)?;
+
+        assert_eq!(receiver.recv()?, Message::InitPid(42));
+        match receiver.recv()? {
+            Message::Error(err) => assert_eq!(err.to_string(), "mount /proc failed: EPERM"),
+            msg => panic!("unexpected message {:?}", msg),
+        }
+        assert_eq!(receiver.recv()?, Message::InitReady);
+        Ok(())
+    }
+
+    #[test]
+    fn test_send_and_recv_fd() -> Result<()> {
+        let (mut sender, mut receiver) = channel()?;
+        let (read, write) = nix::unistd::pipe()?;
+        sender.send(&Message::SeccompListener)?;
+        sender.send_fd(write)?;
+        nix::unistd::close(write)?;
+
+        assert_eq!(receiver.recv()?, Message::SeccompListener);
+        let received = receiver.recv_fd()?;
+        nix::unistd::write(received, b"x")?;
+        let mut buf = [0];
+        nix::unistd::read(read, &mut buf)?;
+        assert_eq!(&buf, b"x");
+        nix::unistd::close(received)?;
+        nix::unistd::close(read)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_recv_fails_when_closed() -> Result<()> {
+        let (sender, mut receiver) = channel()?;
+        drop(sender);
+        assert!(receiver.recv().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_recv_timeout() -> Result<()> {
+        let (mut sender, mut receiver) = channel()?;
+        assert_eq!(receiver.recv_timeout(Duration::from_millis(10))?, None);
+
+        sender.send(&Message::Proceed)?;
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_millis(10))?,
+            Some(Message::Proceed)
+        );
+
+        drop(sender);
+        assert!(receiver.recv_timeout(Duration::from_millis(10)).is_err());
+        Ok(())
+    }
+}
@@ -1,26 +1,19 @@
-use std::io::ErrorKind;
-use std::io::Read;
-use std::io::Write;
+use std::time::Duration;
 
 use anyhow::{bail, Result};
-use mio::unix::pipe;
-use mio::unix::pipe::Receiver;
-use mio::unix::pipe::Sender;
-use mio::{Events, Interest, Poll, Token};
 use nix::unistd::Pid;
 
-use super::{MAX_EVENTS, WAIT_FOR_INIT};
+use crate::process::channel::Channel;
+use crate::process::init::InitProcess;
 use crate::process::message::Message;
 
-// Token is used to identify which socket generated an event
-const CHILD: Token = Token(1);
-
-/// Contains sending end of pipe for parent process, receiving end of pipe
-/// for the init process and poller for that
+/// Contains the channel to the parent process, and the end of the channel
+/// between the parent and the init process which is handed to the init process
 pub struct ChildProcess {
-    sender_for_parent: Sender,
-    receiver: Option<Receiver>,
-    poll: Option<Poll>,
+    channel: Channel,
+    init_channel: Option<Channel>,
+    /// time to wait for a message from the parent process
+    timeout: Duration,
 }
 
 // Note : The original youki process first forks into 'parent' (P) and 'child' (C1) process
@@ -29,97 +22,61 @@ pub struct ChildProcess {
 // a process point of view, init process is child of child process, which is child of original youki process.
 impl ChildProcess {
     /// create a new Child process structure
-    pub fn new(sender_for_parent: Sender) -> Result<Self> {
-        Ok(Self {
-            sender_for_parent,
-            receiver: None,
-            poll: None,
-        })
+    pub fn new(channel: Channel, init_channel: Channel, timeout: Duration) -> Self {
+        Self {
+            channel,
+            init_channel: Some(init_channel),
+            timeout,
+        }
     }
 
-    /// sets up sockets for init process
-    pub fn setup_pipe(&mut self) -> Result<Sender> {
-        // create a new pipe
-        let (sender, mut receiver) = pipe::new()?;
-        // create a new poll, and register the receiving end of pipe to it
-        // This will poll for the read events, so when data is written to sending end of the pipe,
-        // the receiving end will be readable and poll wil notify
-        let poll = Poll::new()?;
-        poll.registry()
-            .register(&mut receiver, CHILD, Interest::READABLE)?;
-
-        self.receiver = Some(receiver);
-        self.poll = Some(poll);
-        Ok(sender)
+    /// Asks the parent process to write the uid/gid mappings of the newly created
+    /// user namespace, and waits until they are written
+    pub fn request_mapping(&mut self) -> Result<()> {
+        self.channel.send(&Message::MappingRequest)?;
+        match receive(&mut self.channel, self.timeout)? {
+            Message::MappingWritten => Ok(()),
+            msg => bail!("receive unexpected message {:?} in child process", msg),
+        }
     }
 
-    /// Indicate that child process has forked the init process to parent process
+    /// Indicate to the parent process that the init process is forked
     pub fn notify_parent(&mut self, init_pid: Pid) -> Result<()> {
-        log::debug!(
-            "child send to parent {:?}",
-            (Message::ChildReady as u8).to_be_bytes()
-        );
-        // write ChildReady message to the pipe to parent
-        self.write_message_for_parent(Message::ChildReady)?;
-        // write pid of init process which is forked by child process to the pipe,
-        // Pid in nix::unistd is type alias of SessionId which itself is alias of i32
-        self.sender_for_parent
-            .write_all(&(init_pid.as_raw()).to_be_bytes())?;
-        Ok(())
+        // the end of the init process is not needed here any more
+        self.init_channel.take();
+        self.channel.send(&Message::InitPid(init_pid.as_raw()))
     }
 
-    /// writes given message to pipe for the parent
-    #[inline]
-    fn write_message_for_parent(&mut self, msg: Message) -> Result<()> {
-        self.sender_for_parent
-            .write_all(&(msg as u8).to_be_bytes())?;
-        Ok(())
+    /// Waits for the parent process to finish creating the container. This fails
+    /// as soon as the parent process exits, however long it takes.
+    pub fn wait_for_created(&mut self) -> Result<()> {
+        match self.channel.recv()? {
+            Message::Created => Ok(()),
+            msg => bail!("receive unexpected message {:?} in child process", msg),
+        }
     }
 
-    /// Wait for the init process to be ready
-    pub fn wait_for_init_ready(&mut self) -> Result<()> {
-        // make sure pipe for init process is set up
-        let receiver = self
-            .receiver
-            .as_mut()
-            .expect("Complete the setup of uds in advance.");
-        let poll = self
-            .poll
-            .as_mut()
-            .expect("Complete the setup of uds in advance.");
-
-        // Create collection with capacity to store up to MAX_EVENTS events
-        let mut events = Events::with_capacity(MAX_EVENTS);
-        // poll the receiving end of pipe created for WAIT_FOR_INIT duration an event
-        poll.poll(&mut events, Some(WAIT_FOR_INIT))?;
-        for event in events.iter() {
-            // check if the event token in PARENT
-            // note that this does not assign anything to PARENT, but instead compares PARENT and event.token()
-            // check http://patshaughnessy.net/2018/1/18/learning-rust-if-let-vs--match for a bit more detailed explanation
-            if let CHILD = event.token() {
-                // read message from the init process
-                let mut buf = [0; 1];
-                match receiver.read_exact(&mut buf) {
-                    // This error simply means that there are no more incoming connections waiting to be accepted at this point.
-                    Err(ref e) if e.kind() == ErrorKind::WouldBlock => (),
-                    Err(e) => bail!(
-                        "Failed to receive a message from the child process. {:?}",
-                        e
-                    ),
-                    _ => (),
-                }
-                match Message::from(u8::from_be_bytes(buf)) {
-                    Message::InitReady => return Ok(()),
-                    msg => bail!("receive unexpected message {:?} in child process", msg),
-                }
-            } else {
-                unreachable!()
-            }
+    /// Reports to the parent process that setting up the container failed
+    pub fn report_error(&mut self, err: &anyhow::Error) {
+        if let Err(e) = self.channel.send(&Message::Error(err.into())) {
+            log::warn!("failed to report error to parent process: {}", e);
         }
-        // should not reach here, as there should be a ready event from init within WAIT_FOR_INIT duration
-        unreachable!(
-            "No message received from init process within {} seconds",
-            WAIT_FOR_INIT.as_secs()
-        );
+    }
+
+    /// Turns into the init process in the second fork, which only talks to the parent process
+    pub fn into_init(mut self) -> InitProcess {
+        InitProcess::new(self.init_channel.take().unwrap(), self.timeout)
+    }
+}
+
+/// Receives the next message from the parent process, which fails if the parent
+/// process exits or does not answer in time
+pub(super) fn receive(channel: &mut Channel, timeout: Duration) -> Result<Message> {
+    match channel.recv_timeout(timeout)? {
+        Some(msg) => Ok(msg),
+        None => bail!(
+            "no message from parent process within {} seconds",
+            timeout.as_secs()
+        ),
     }
 }
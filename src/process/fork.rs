@@ -1,120 +1,325 @@
 use std::fs;
-use std::io::prelude::*;
+use std::os::unix::io::RawFd;
 use std::path::Path;
 use std::process::exit;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 
 use anyhow::bail;
 use child::ChildProcess;
-use init::InitProcess;
+use nix::errno::Errno;
 use nix::sched;
+use nix::sys::signal::{self, Signal};
 use nix::sys::wait::{waitpid, WaitStatus};
 use nix::unistd;
 use nix::unistd::Pid;
 
+use crate::capabilities;
+use crate::cgroups;
 use crate::cgroups::common::CgroupManager;
-use crate::container::ContainerStatus;
-use crate::process::{child, init, parent, Process};
-use crate::{cond::Cond, container::Container};
+use crate::config::Timeouts;
+use crate::container::Container;
+use crate::container::{ContainerStatus, ExitStatus, OciState};
+use crate::cpu_affinity;
+use crate::hooks;
+use crate::process::channel::channel;
+use crate::process::{child, parent, Process};
+use crate::reaper;
+use crate::rootless;
+use crate::seccomp;
+use crate::utils;
 
 /// Function to perform the first fork for in order to run the container process
 pub fn fork_first<P: AsRef<Path>>(
     pid_file: Option<P>,
     is_userns: bool,
-    linux: &oci_spec::Linux,
+    spec: &oci_spec::Spec,
     container: &Container,
     cmanager: Box<dyn CgroupManager>,
+    timeouts: &Timeouts,
 ) -> Result<Process> {
-    // create a new pipe
-    let ccond = Cond::new()?;
-
-    // create new parent process structure
-    let (mut parent, sender_for_parent) = parent::ParentProcess::new()?;
-    // create a new child process structure with sending end of parent process
-    let child = child::ChildProcess::new(sender_for_parent)?;
+    let linux = spec.linux.as_ref().unwrap();
+    let child_sync = timeouts.child_sync();
+    // create the channels from the parent process to the child process and to the
+    // init process, the end for the init process is passed down by the child process
+    let (parent_channel, child_channel) = channel()?;
+    let (parent_init_channel, init_channel) = channel()?;
+    let parent_pid = unistd::getpid();
 
     unsafe {
         // fork the process
         match unistd::fork()? {
             // in the child process
             unistd::ForkResult::Child => {
-                // if Out-of-memory score adjustment is set in specification.
-                // set the score value for the current process
+                // die along with the parent process while setting up, as nobody would
+                // finish creating the container. It may have died before this is set.
+                set_death_signal(Some(Signal::SIGKILL))?;
+                if unistd::getppid() != parent_pid {
+                    exit(1);
+                }
+
+                drop(parent_channel);
+                drop(parent_init_channel);
+                let mut child = child::ChildProcess::new(child_channel, init_channel, child_sync);
+                // the init process and the command of the container inherit the score. The
+                // one of linux.resources is from before it moved to process in the spec.
                 // check https://dev.to/rrampage/surviving-the-linux-oom-killer-2ki9 for some more information
-                if let Some(ref r) = linux.resources {
-                    if let Some(adj) = r.oom_score_adj {
-                        let mut f = fs::File::create("/proc/self/oom_score_adj")?;
-                        f.write_all(adj.to_string().as_bytes())?;
-                    }
+                let oom_score_adj = spec.process.oom_score_adj.or_else(|| {
+                    linux
+                        .resources
+                        .as_ref()
+                        .and_then(|resources| resources.oom_score_adj)
+                });
+                if let Some(adj) = oom_score_adj {
+                    utils::set_oom_score_adj(adj)?;
+                }
+                // the init process inherits them, until it is in its cgroups
+                if let Some(affinity) = &spec.process.exec_cpu_affinity {
+                    cpu_affinity::apply_initial(affinity)?;
                 }
 
                 // if new user is specified in specification, this will be true
                 // and new namespace will be created, check https://man7.org/linux/man-pages/man7/user_namespaces.7.html
                 // for more information
                 if is_userns {
+                    // /proc/<pid> of a process which is not dumpable belongs to root, so an
+                    // unprivileged parent process could not write the mappings
+                    prctl::set_dumpable(true).map_err(Errno::from_i32)?;
                     sched::unshare(sched::CloneFlags::CLONE_NEWUSER)?;
+                    child.request_mapping()?;
+                    prctl::set_dumpable(false).map_err(Errno::from_i32)?;
                 }
 
-                ccond.notify()?;
                 Ok(Process::Child(child))
             }
             // in the parent process
-            unistd::ForkResult::Parent { child } => {
-                ccond.wait()?;
-
-                // wait for child to fork init process and report back its pid
-                let init_pid = parent.wait_for_child_ready()?;
-                log::debug!("init pid is {:?}", init_pid);
-                cmanager.apply(&linux.resources.as_ref().unwrap(), Pid::from_raw(init_pid))?;
-
-                // update status and pid of the container process
-                container
-                    .update_status(ContainerStatus::Created)?
-                    .set_pid(init_pid)
-                    .save()?;
-                // if file to write the pid to is specified, write pid of the child
-                if let Some(pid_file) = pid_file {
-                    fs::write(&pid_file, format!("{}", child))?;
+            unistd::ForkResult::Parent { child: child_pid } => {
+                // close the ends of the children, so that receiving fails
+                // instead of blocking once they exit
+                drop(child_channel);
+                drop(init_channel);
+                let mut parent = parent::ParentProcess::new(
+                    child_pid,
+                    parent_channel,
+                    parent_init_channel,
+                    child_sync,
+                );
+
+                let created = create_container(
+                    &mut parent,
+                    pid_file,
+                    is_userns,
+                    spec,
+                    container,
+                    cmanager.as_ref(),
+                    timeouts,
+                );
+                if let Err(err) = created {
+                    // the init process dies along with the child process
+                    let _ = signal::kill(child_pid, Signal::SIGKILL);
+                    let _ = waitpid(child_pid, None);
+                    cleanup(container, cmanager.as_ref());
+                    return Err(err);
                 }
+
+                // the child process stops watching for the exit of this process
+                parent.notify_created()?;
                 Ok(Process::Parent(parent))
             }
         }
     }
 }
 
+/// Drives the children through setting up the container, and records it as created
+fn create_container<P: AsRef<Path>>(
+    parent: &mut parent::ParentProcess,
+    pid_file: Option<P>,
+    is_userns: bool,
+    spec: &oci_spec::Spec,
+    container: &Container,
+    cmanager: &dyn CgroupManager,
+    timeouts: &Timeouts,
+) -> Result<()> {
+    let linux = spec.linux.as_ref().unwrap();
+    if is_userns {
+        parent.wait_for_mapping_request()?;
+        rootless::write_id_mappings(parent.child_pid(), linux)?;
+        parent.notify_mapping_written()?;
+    }
+
+    // wait for child to fork init process and report back its pid
+    let init_pid = parent.wait_for_init_pid()?;
+    log::debug!("init pid is {:?}", init_pid);
+    if let Some(shared) = &container.state.shared_cgroups_path {
+        // a rootless container may stay in the cgroup of the user, which the init process
+        // has been in since it was forked, and which the user cannot write to
+        if rootless::cgroup_of(Pid::from_raw(init_pid))? != *shared {
+            cmanager.add_task(Pid::from_raw(init_pid))?;
+        }
+    } else {
+        cmanager.apply(linux.resources.as_ref().unwrap(), Pid::from_raw(init_pid))?;
+    }
+
+    // the init process is in its cgroups, let it prepare the container
+    parent.notify_init_proceed()?;
+    // the init process asks for the hooks of the runtime once it has prepared the mounts,
+    // the createContainer hooks are run by itself afterwards
+    let runtime_hooks = hooks::runtime_hooks(&spec.hooks);
+    if !runtime_hooks.is_empty() {
+        parent.wait_for_hooks_request()?;
+        // the container is still being created, its init process is not recorded yet
+        let mut state = OciState::from(&container.state);
+        state.pid = Some(init_pid);
+        hooks::run(runtime_hooks, &state, timeouts.hooks())
+            .context("failed to run the createRuntime hooks")?;
+        parent.notify_hooks_done()?;
+    }
+    let create_container = spec.hooks.iter().flat_map(|hooks| &hooks.create_container);
+    let listener =
+        parent.wait_for_init_ready(hooks::timeout_of(create_container, timeouts.hooks()))?;
+    if let Some(fd) = listener {
+        let sent = send_seccomp_listener(linux, fd, init_pid, container);
+        let _ = unistd::close(fd);
+        sent?;
+    }
+
+    // update status and pid of the container process
+    let mut container = container
+        .update_status(ContainerStatus::Created)?
+        .set_pid(init_pid);
+    // record the capabilities the init process ended up with, to verify that the
+    // requested ones took effect
+    match capabilities::read_sets(Pid::from_raw(init_pid)) {
+        Ok(sets) => {
+            log::debug!("capabilities of the init process: {:?}", sets);
+            container = container.set_capabilities(sets);
+        }
+        Err(e) => log::warn!("failed to read the capabilities of {}: {}", init_pid, e),
+    }
+    container.save()?;
+    // supervisors such as containerd-shim watch the init process through it
+    if let Some(pid_file) = pid_file {
+        utils::write_pid_file(pid_file, Pid::from_raw(init_pid))?;
+    }
+    Ok(())
+}
+
+/// Passes the listener of the seccomp filter on to the agent of linux.seccomp.listenerPath.
+/// Without an agent, the syscalls to notify about fail with ENOSYS once it is closed.
+fn send_seccomp_listener(
+    linux: &oci_spec::Linux,
+    fd: RawFd,
+    init_pid: i32,
+    container: &Container,
+) -> Result<()> {
+    let seccomp = match linux.seccomp.as_ref() {
+        Some(seccomp) => seccomp,
+        None => return Ok(()),
+    };
+    let listener_path = match &seccomp.listener_path {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+    // the init process has yet to be recorded, and the container is still being created
+    let mut state = OciState::from(&container.state);
+    state.pid = Some(init_pid);
+    seccomp::send_listener(
+        listener_path,
+        seccomp.listener_metadata.as_deref(),
+        fd,
+        init_pid,
+        &state,
+    )
+}
+
 /// Function to perform the second fork, which will spawn the actual container process
-pub fn fork_init(mut child_process: ChildProcess) -> Result<Process> {
-    // setup sockets for init process
-    let sender_for_child = child_process.setup_pipe()?;
+pub fn fork_init(
+    mut child_process: ChildProcess,
+    container: &Container,
+    cgroups_path: &Path,
+) -> Result<Process> {
     unsafe {
         // for the process into current process (C1) (which is child of first_fork) and init process
         match unistd::fork()? {
             // if it is child process, create new InitProcess structure and return
-            unistd::ForkResult::Child => Ok(Process::Init(InitProcess::new(sender_for_child))),
+            unistd::ForkResult::Child => {
+                // the child process watches the parent process from here on, and
+                // kills this process only through its own death
+                set_death_signal(Some(Signal::SIGKILL))?;
+                Ok(Process::Init(child_process.into_init()))
+            }
             // in the forking process C1
             unistd::ForkResult::Parent { child } => {
-                // wait for init process to be ready
-                child_process.wait_for_init_ready()?;
-                // notify the parent process (original youki process) that init process is forked and ready
-                child_process.notify_parent(child)?;
+                // outlive the parent process, to clean up if it dies before the container is created
+                set_death_signal(None)?;
+
+                // notify the parent process (original youki process) that init process is forked,
+                // the init process reports to the parent process directly from here on
+                let created = child_process
+                    .notify_parent(child)
+                    .and_then(|_| child_process.wait_for_created());
+                if let Err(err) = created {
+                    log::warn!(
+                        "parent process is gone before creating the container: {}",
+                        err
+                    );
+                    let _ = signal::kill(child, Signal::SIGKILL);
+                    let _ = waitpid(child, None);
+                    match cgroups::common::create_cgroup_manager(cgroups_path) {
+                        Ok(cmanager) => cleanup(container, cmanager.as_ref()),
+                        Err(e) => log::warn!("failed to remove cgroups of the container: {}", e),
+                    }
+                    exit(1);
+                }
 
                 // wait for the init process, which is container process, to change state
                 // check https://man7.org/linux/man-pages/man3/wait.3p.html for more information
-                match waitpid(child, None)? {
-                    // if normally exited
-                    WaitStatus::Exited(pid, status) => {
-                        log::debug!("exited pid: {:?}, status: {:?}", pid, status);
-                        exit(status);
-                    }
-                    // if terminated by a signal
-                    WaitStatus::Signaled(pid, status, _) => {
-                        log::debug!("signaled pid: {:?}, status: {:?}", pid, status);
-                        exit(0);
-                    }
-                    _ => bail!("abnormal exited!"),
+                let status = waitpid(child, None)?;
+                if let Err(e) = record_exit(container, status, cgroups_path) {
+                    log::warn!("failed to record the exit of the container: {}", e);
                 }
+                // the exit code of the container, 128 + the signal if it was killed by one,
+                // which run exits with if the state could not be recorded
+                log::debug!("init process {} ended with {:?}", child, status);
+                exit(reaper::exit_code(status));
             }
         }
     }
 }
+
+/// Records how the init process exited in the state of the container
+pub fn record_exit(container: &Container, status: WaitStatus, cgroups_path: &Path) -> Result<()> {
+    let cmanager = cgroups::common::create_cgroup_manager(cgroups_path)?;
+    let exit_status = ExitStatus::new(status, cmanager.oom_kill_count()? > 0);
+    log::debug!("container exited with {:?}", exit_status);
+    // the state has changed since the fork, e.g. it has been started
+    Container::load(container.root.clone())?
+        .update_status(ContainerStatus::Stopped)?
+        .set_exit_status(exit_status)
+        .save()
+}
+
+/// Removes what a create that failed or was abandoned after the fork left behind, before
+/// create removes the state directory itself. Mounts need no cleanup, as they are in the
+/// mount namespace of the container, which is gone along with its processes.
+fn cleanup(container: &Container, cmanager: &dyn CgroupManager) {
+    if container.state.shared_cgroups_path.is_some() {
+        log::debug!("cgroups of the container are shared, they are left in place");
+    } else if let Err(e) = cmanager.remove() {
+        log::warn!("failed to remove cgroups of the container: {}", e);
+    }
+    if let Err(e) = fs::remove_dir_all(&container.root) {
+        log::warn!("failed to remove {:?}: {}", container.root, e);
+    }
+}
+
+/// Sets the signal which the process receives when its parent dies, None clears it
+pub fn set_death_signal(sig: Option<Signal>) -> Result<()> {
+    if let Err(errno) = prctl::set_death_signal(sig.map_or(0, |sig| sig as isize)) {
+        bail!(
+            "failed to set parent death signal: {}",
+            Errno::from_i32(errno)
+        );
+    }
+    Ok(())
+}
@@ -1,38 +1,94 @@
-use std::io::Write;
+use std::env;
+use std::ffi::CString;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::time::Duration;
 
-use anyhow::Result;
-use mio::unix::pipe::Sender;
+use anyhow::{bail, Context, Result};
+use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+use nix::unistd;
 
+use super::child::receive;
+use crate::process::channel::Channel;
 use crate::process::message::Message;
 
-/// Contains sending end for pipe for the child process
+/// Environment variable which passes the file descriptor of the channel
+/// to youki executed as the init subcommand
+const SYNC_FD_ENV: &str = "_YOUKI_SYNC_FD";
+
+/// Contains the channel to the parent process, which is the original youki process
 pub struct InitProcess {
-    sender_for_child: Sender,
+    channel: Channel,
+    /// time to wait for a message from the parent process
+    timeout: Duration,
 }
 
 impl InitProcess {
     /// create a new Init process structure
-    pub fn new(sender_for_child: Sender) -> Self {
-        Self { sender_for_child }
+    pub fn new(channel: Channel, timeout: Duration) -> Self {
+        Self { channel, timeout }
+    }
+
+    /// Takes over the channel handed down by exec_init
+    pub fn from_env(timeout: Duration) -> Result<Self> {
+        let fd: RawFd = env::var(SYNC_FD_ENV)
+            .with_context(|| format!("{} is not set, init is run by create only", SYNC_FD_ENV))?
+            .parse()?;
+        env::remove_var(SYNC_FD_ENV);
+        // do not leak the channel to the command of the container
+        fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+        Ok(Self::new(unsafe { Channel::from_raw_fd(fd) }, timeout))
+    }
+
+    /// Replaces this process by youki run with the given arguments, which are expected
+    /// to run the init subcommand. The channel stays open across exec for it.
+    pub fn exec_init(&self, args: &[String]) -> Result<()> {
+        let fd = self.channel.as_raw_fd();
+        fcntl(fd, FcntlArg::F_SETFD(FdFlag::empty()))?;
+        env::set_var(SYNC_FD_ENV, fd.to_string());
+
+        let path = CString::new("/proc/self/exe")?;
+        let args = std::iter::once("youki")
+            .chain(args.iter().map(String::as_str))
+            .map(CString::new)
+            .collect::<Result<Vec<_>, _>>()?;
+        unistd::execv(&path, &args)?;
+        Ok(())
+    }
+
+    /// Waits for the parent process to place this process in its cgroups
+    pub fn wait_to_proceed(&mut self) -> Result<()> {
+        match receive(&mut self.channel, self.timeout)? {
+            Message::Proceed => Ok(()),
+            msg => bail!("receive unexpected message {:?} in init process", msg),
+        }
+    }
+
+    /// Asks the parent process to run the prestart and createRuntime hooks, and waits for
+    /// them as long as they may take
+    pub fn wait_for_runtime_hooks(&mut self, hooks_timeout: Duration) -> Result<()> {
+        self.channel.send(&Message::HooksRequest)?;
+        match receive(&mut self.channel, self.timeout + hooks_timeout)? {
+            Message::HooksDone => Ok(()),
+            msg => bail!("receive unexpected message {:?} in init process", msg),
+        }
+    }
+
+    /// Hands the listener of the seccomp filter over to the parent process, which passes it
+    /// on to the agent which handles the notifications
+    pub fn send_seccomp_listener(&mut self, fd: RawFd) -> Result<()> {
+        self.channel.send(&Message::SeccompListener)?;
+        self.channel.send_fd(fd)
     }
 
     /// Notify that this process is ready
-    // The child here is in perspective of overall hierarchy
-    // main youki process -> child process -> init process
-    // the child here does not mean child of the init process
     pub fn ready(&mut self) -> Result<()> {
-        log::debug!(
-            "init send to child {:?}",
-            (Message::InitReady as u8).to_be_bytes()
-        );
-        self.write_message_for_child(Message::InitReady)?;
-        Ok(())
+        self.channel.send(&Message::InitReady)
     }
 
-    #[inline]
-    fn write_message_for_child(&mut self, msg: Message) -> Result<()> {
-        self.sender_for_child
-            .write_all(&(msg as u8).to_be_bytes())?;
-        Ok(())
+    /// Reports to the parent process that setting up the container failed
+    pub fn report_error(&mut self, err: &anyhow::Error) {
+        if let Err(e) = self.channel.send(&Message::Error(err.into())) {
+            log::warn!("failed to report error to parent process: {}", e);
+        }
     }
 }
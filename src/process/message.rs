@@ -1,16 +1,155 @@
-/// Used as a wrapper for messages to be sent between child and parent processes
-#[derive(Debug)]
+use std::fmt::{self, Display};
+use std::path::{Path, PathBuf};
+
+use nix::errno::Errno;
+use serde::{Deserialize, Serialize};
+
+/// Used as a wrapper for messages to be sent between the youki process,
+/// the intermediate (child) process and the init process
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub enum Message {
-    ChildReady = 0x00,
-    InitReady = 0x01,
+    /// child -> parent: the user namespace is created, its uid/gid mappings can be written
+    MappingRequest,
+    /// parent -> child: the uid/gid mappings of the user namespace are written
+    MappingWritten,
+    /// child -> parent: pid of the forked init process, as seen from the parent
+    InitPid(i32),
+    /// parent -> init: the init process is placed in its cgroups and can prepare the container
+    Proceed,
+    /// init -> parent: the mounts are prepared, the prestart and createRuntime hooks can run
+    HooksRequest,
+    /// parent -> init: the prestart and createRuntime hooks have run
+    HooksDone,
+    /// init -> parent: the listener of the seccomp filter, which is passed right after
+    SeccompListener,
+    /// init -> parent: the container is prepared and waits to be started
+    InitReady,
+    /// parent -> child: the container is created, and the parent process exits
+    Created,
+    /// child or init -> parent: setting up the container failed
+    Error(SetupError),
+}
+
+/// Names the step of setting up the container, e.g. mounting /proc. It is attached
+/// to errors as context, so that the step can be reported to the parent process.
+#[derive(Debug)]
+pub struct Phase {
+    name: String,
+    path: Option<PathBuf>,
+}
+
+impl Phase {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            path: None,
+        }
+    }
+
+    pub fn with_path<P: AsRef<Path>>(name: &str, path: P) -> Self {
+        Self {
+            name: name.to_owned(),
+            path: Some(path.as_ref().to_path_buf()),
+        }
+    }
 }
 
-impl From<u8> for Message {
-    fn from(from: u8) -> Self {
-        match from {
-            0x00 => Message::ChildReady,
-            0x01 => Message::InitReady,
-            _ => panic!("unknown message."),
+impl Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.path {
+            Some(path) => write!(f, "{} {}", self.name, path.display()),
+            None => write!(f, "{}", self.name),
         }
     }
 }
+
+/// Error which happened while the child or init process set up the container
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct SetupError {
+    pub phase: Option<String>,
+    pub path: Option<PathBuf>,
+    pub errno: Option<i32>,
+    /// the chain of the error, without the phase
+    pub detail: String,
+}
+
+impl From<&anyhow::Error> for SetupError {
+    fn from(err: &anyhow::Error) -> Self {
+        let phase = err.downcast_ref::<Phase>();
+        let errno = err.chain().find_map(|cause| {
+            if let Some(nix::Error::Sys(errno)) = cause.downcast_ref::<nix::Error>() {
+                Some(*errno as i32)
+            } else {
+                cause
+                    .downcast_ref::<std::io::Error>()
+                    .and_then(|e| e.raw_os_error())
+            }
+        });
+
+        let phase_context = phase.map(|p| p.to_string());
+        let detail = err
+            .chain()
+            .map(|cause| cause.to_string())
+            .filter(|cause| Some(cause) != phase_context.as_ref())
+            .collect::<Vec<_>>()
+            .join(": ");
+
+        Self {
+            phase: phase.map(|p| p.name.clone()),
+            path: phase.and_then(|p| p.path.clone()),
+            errno,
+            detail,
+        }
+    }
+}
+
+impl Display for SetupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let phase = match (&self.phase, &self.path) {
+            (Some(phase), Some(path)) => format!("{} {}", phase, path.display()),
+            (Some(phase), None) => phase.clone(),
+            (None, _) => return write!(f, "{}", self.detail),
+        };
+        match self.errno {
+            Some(errno) => write!(f, "{} failed: {:?}", phase, Errno::from_i32(errno)),
+            None => write!(f, "{} failed: {}", phase, self.detail),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{Context, Result};
+
+    fn mount_proc() -> Result<()> {
+        Err(nix::Error::Sys(Errno::EPERM)).context("mount of /proc failed")
+    }
+
+    #[test]
+    fn test_setup_error_with_phase() {
+        let err = mount_proc()
+            .context(Phase::with_path("mount", "/proc"))
+            .unwrap_err();
+        let setup_error = SetupError::from(&err);
+
+        assert_eq!(setup_error.phase, Some("mount".to_owned()));
+        assert_eq!(setup_error.path, Some(PathBuf::from("/proc")));
+        assert_eq!(setup_error.errno, Some(Errno::EPERM as i32));
+        assert_eq!(setup_error.to_string(), "mount /proc failed: EPERM");
+        assert_eq!(
+            setup_error.detail,
+            "mount of /proc failed: EPERM: Operation not permitted"
+        );
+    }
+
+    #[test]
+    fn test_setup_error_without_phase() {
+        let err = mount_proc().unwrap_err();
+        let setup_error = SetupError::from(&err);
+
+        assert_eq!(setup_error.phase, None);
+        assert_eq!(setup_error.errno, Some(Errno::EPERM as i32));
+        assert_eq!(setup_error.to_string(), setup_error.detail);
+    }
+}
@@ -3,6 +3,7 @@
 
 use std::time::Duration;
 
+pub mod channel;
 pub mod fork;
 pub mod message;
 
@@ -20,9 +21,5 @@ pub enum Process {
     Child(child::ChildProcess),
     Init(init::InitProcess),
 }
-/// Maximum event capacity of polling
-const MAX_EVENTS: usize = 128;
-/// Time to wait when polling for message from child process
-const WAIT_FOR_CHILD: Duration = Duration::from_secs(5);
-/// Time to wait when polling for message from init process
-const WAIT_FOR_INIT: Duration = Duration::from_millis(1000);
+/// Interval in which the parent process checks whether the child process is still alive
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
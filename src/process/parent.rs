@@ -1,89 +1,184 @@
-use std::io::ErrorKind;
-use std::io::Read;
+use std::os::unix::io::RawFd;
+use std::time::{Duration, Instant};
 
-use super::{MAX_EVENTS, WAIT_FOR_CHILD};
-use crate::process::message::Message;
-use anyhow::{bail, Result};
-use mio::unix::pipe;
-use mio::unix::pipe::{Receiver, Sender};
-use mio::{Events, Interest, Poll, Token};
+use anyhow::{anyhow, bail, Context, Result};
+use nix::sys::signal::{self, Signal};
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::{self, Pid};
 
-// Token is used to identify which socket generated an event
-const PARENT: Token = Token(0);
+use super::POLL_INTERVAL;
+use crate::process::channel::Channel;
+use crate::process::message::Message;
 
-/// Contains receiving end of pipe to child process and a poller for that.
+/// Contains the channels to the child process and to the init process
 pub struct ParentProcess {
-    receiver: Receiver,
-    poll: Poll,
+    child: Pid,
+    channel: Channel,
+    init_channel: Channel,
+    /// time to wait for a message from the child or init process
+    timeout: Duration,
 }
 
-// Poll is used to register and listen for various events
-// by registering it with an event source such as receiving end of a pipe
 impl ParentProcess {
     /// Create new Parent process structure
-    pub fn new() -> Result<(Self, Sender)> {
-        // create a new pipe
-        let (sender, mut receiver) = pipe::new()?;
-        // create a new poll, and register the receiving end of pipe to it
-        // This will poll for the read events, so when data is written to sending end of the pipe,
-        // the receiving end will be readable and poll wil notify
-        let poll = Poll::new()?;
-        poll.registry()
-            .register(&mut receiver, PARENT, Interest::READABLE)?;
-        Ok((Self { receiver, poll }, sender))
+    pub fn new(child: Pid, channel: Channel, init_channel: Channel, timeout: Duration) -> Self {
+        Self {
+            child,
+            channel,
+            init_channel,
+            timeout,
+        }
     }
 
-    /// Waits for associated child process to send ready message
-    /// and return the pid of init process which is forked by child process
-    pub fn wait_for_child_ready(&mut self) -> Result<i32> {
-        // Create collection with capacity to store up to MAX_EVENTS events
-        let mut events = Events::with_capacity(MAX_EVENTS);
-
-        // poll the receiving end of pipe created for WAIT_FOR_CHILD duration for an event
-        self.poll.poll(&mut events, Some(WAIT_FOR_CHILD))?;
-        for event in events.iter() {
-            // check if the event token in PARENT
-            // note that this does not assign anything to PARENT, but instead compares PARENT and event.token()
-            // check http://patshaughnessy.net/2018/1/18/learning-rust-if-let-vs--match for a bit more detailed explanation
-            if let PARENT = event.token() {
-                // read data from pipe
-                let mut buf = [0; 1];
-                match self.receiver.read_exact(&mut buf) {
-                    // This error simply means that there are no more incoming connections waiting to be accepted at this point.
-                    Err(ref e) if e.kind() == ErrorKind::WouldBlock => (),
-                    Err(e) => bail!(
-                        "Failed to receive a message from the child process. {:?}",
-                        e
-                    ),
-                    _ => (),
-                };
-                // convert to Message wrapper
-                match Message::from(u8::from_be_bytes(buf)) {
-                    Message::ChildReady => {
-                        // read pid of init process forked by child, 4 bytes as the type is i32
-                        let mut buf = [0; 4];
-                        match self.receiver.read_exact(&mut buf) {
-                            // This error simply means that there are no more incoming connections waiting to be accepted at this point.
-                            Err(ref e) if e.kind() == ErrorKind::WouldBlock => (),
-                            Err(e) => bail!(
-                                "Failed to receive a message from the child process. {:?}",
-                                e
-                            ),
-                            _ => (),
-                        }
-                        return Ok(i32::from_be_bytes(buf));
-                    }
-                    msg => bail!("receive unexpected message {:?} in parent process", msg),
+    pub fn child_pid(&self) -> Pid {
+        self.child
+    }
+
+    /// Waits for the child process to create the user namespace
+    pub fn wait_for_mapping_request(&mut self) -> Result<()> {
+        match receive(&mut self.channel, self.child, "child", self.timeout)? {
+            Message::MappingRequest => Ok(()),
+            msg => bail!("receive unexpected message {:?} in parent process", msg),
+        }
+    }
+
+    /// Lets the child process know that the mappings of its user namespace are written
+    pub fn notify_mapping_written(&mut self) -> Result<()> {
+        self.channel.send(&Message::MappingWritten)
+    }
+
+    /// Waits for the child process to fork the init process,
+    /// and returns the pid of the init process
+    pub fn wait_for_init_pid(&mut self) -> Result<i32> {
+        match receive(&mut self.channel, self.child, "child", self.timeout)? {
+            Message::InitPid(pid) => Ok(pid),
+            msg => bail!("receive unexpected message {:?} in parent process", msg),
+        }
+    }
+
+    /// Lets the init process go on preparing the container
+    pub fn notify_init_proceed(&mut self) -> Result<()> {
+        self.init_channel.send(&Message::Proceed)
+    }
+
+    /// Waits for the init process to prepare the mounts of the container
+    pub fn wait_for_hooks_request(&mut self) -> Result<()> {
+        match receive(&mut self.init_channel, self.child, "init", self.timeout)? {
+            Message::HooksRequest => Ok(()),
+            msg => bail!("receive unexpected message {:?} in parent process", msg),
+        }
+    }
+
+    /// Lets the init process go on once the hooks of the runtime have run
+    pub fn notify_hooks_done(&mut self) -> Result<()> {
+        self.init_channel.send(&Message::HooksDone)
+    }
+
+    /// Waits for the init process to prepare the container, which may take longer by the
+    /// time its hooks have, and returns the listener of its seccomp filter if it has handed
+    /// one over
+    pub fn wait_for_init_ready(&mut self, hooks_timeout: Duration) -> Result<Option<RawFd>> {
+        let mut listener = None;
+        let timeout = self.timeout + hooks_timeout;
+        let ready = loop {
+            // the child process exits as soon as the init process does, so watching
+            // the child process is enough to notice the death of either
+            match receive(&mut self.init_channel, self.child, "init", timeout) {
+                Ok(Message::SeccompListener) => match self.init_channel.recv_fd() {
+                    Ok(fd) => listener = Some(fd),
+                    Err(e) => break Err(e),
+                },
+                Ok(Message::InitReady) => break Ok(()),
+                Ok(msg) => {
+                    break Err(anyhow!(
+                        "receive unexpected message {:?} in parent process",
+                        msg
+                    ))
                 }
-            } else {
-                // as the poll is registered with only parent token
-                unreachable!()
+                Err(e) => break Err(e),
+            }
+        };
+        match ready {
+            Ok(()) => Ok(listener),
+            Err(e) => {
+                if let Some(fd) = listener {
+                    let _ = unistd::close(fd);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Lets the child process know that the container is created
+    pub fn notify_created(&mut self) -> Result<()> {
+        self.channel.send(&Message::Created)
+    }
+}
+
+/// Receives the next message, turning an error reported by the other process into an error.
+/// Fails as soon as the child process exits, as the channel may stay open when another
+/// process still holds its end, and kills the child process if it does not answer in time.
+fn receive(channel: &mut Channel, child: Pid, from: &str, timeout: Duration) -> Result<Message> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let received = channel
+            .recv_timeout(POLL_INTERVAL)
+            .with_context(|| format!("failed to wait for {} process", from))?;
+        match received {
+            Some(Message::Error(err)) => {
+                log::debug!("{} process failed: {}", from, err.detail);
+                bail!("{}", err)
+            }
+            Some(msg) => return Ok(msg),
+            None => (),
+        }
+
+        match waitpid(child, Some(WaitPidFlag::WNOHANG))? {
+            WaitStatus::Exited(_, status) => {
+                bail!(
+                    "{} process exited with status {} before it was ready",
+                    from,
+                    status
+                )
+            }
+            WaitStatus::Signaled(_, sig, _) => {
+                bail!("{} process was killed by {} before it was ready", from, sig)
+            }
+            _ => (),
+        }
+
+        if Instant::now() >= deadline {
+            let _ = signal::kill(child, Signal::SIGKILL);
+            bail!(
+                "{} process was not ready within {} seconds",
+                from,
+                timeout.as_secs()
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::channel::channel;
+    use nix::unistd::{fork, ForkResult};
+
+    #[test]
+    fn test_receive_fails_when_child_exits() -> Result<()> {
+        // the other end is kept open here, so only the exit of the child can end the wait
+        let (_sender, mut receiver) = channel()?;
+        match unsafe { fork()? } {
+            ForkResult::Child => unsafe { libc::_exit(3) },
+            ForkResult::Parent { child } => {
+                let err =
+                    receive(&mut receiver, child, "child", Duration::from_secs(5)).unwrap_err();
+                assert_eq!(
+                    err.to_string(),
+                    "child process exited with status 3 before it was ready"
+                );
             }
         }
-        // should not reach here, as there should be a ready event from child within WAIT_FOR_CHILD duration
-        unreachable!(
-            "No message received from child process within {} seconds",
-            WAIT_FOR_CHILD.as_secs()
-        );
+        Ok(())
     }
 }
@@ -0,0 +1,117 @@
+//! Lists the processes of a container, like runc ps
+
+use std::path::PathBuf;
+use std::process::Command;
+
+use anyhow::{bail, Context, Result};
+use clap::Clap;
+use nix::unistd::Pid;
+
+use crate::cgroups;
+use crate::container::Container;
+use crate::output::Format;
+
+#[derive(Clap, Debug)]
+pub struct Ps {
+    /// format of the output, table or json, which is the list of the pids
+    #[clap(short, long, default_value = "table")]
+    pub format: Format,
+    pub container_id: String,
+    /// options of ps for the table after --, -ef if none are given
+    ps_options: Vec<String>,
+}
+
+impl Ps {
+    pub fn exec(&self, roots: &[PathBuf]) -> Result<()> {
+        let container = Container::find(roots, &self.container_id)?.refresh_status()?;
+        if !container.can_kill() {
+            bail!(
+                "{} has no processes because it was {:?}",
+                container.id(),
+                container.status()
+            )
+        }
+        let cmanager = cgroups::common::create_cgroup_manager(container.cgroups_path()?)?;
+        let pids = cmanager.get_all_pids()?;
+
+        match self.format {
+            Format::Json => {
+                let pids: Vec<i32> = pids.iter().map(|pid| pid.as_raw()).collect();
+                println!("{}", serde_json::to_string(&pids)?);
+            }
+            Format::Table => {
+                let options = if self.ps_options.is_empty() {
+                    vec!["-ef".to_owned()]
+                } else {
+                    self.ps_options.clone()
+                };
+                let output = Command::new("ps")
+                    .args(&options)
+                    .output()
+                    .context("failed to run ps")?;
+                if !output.status.success() {
+                    bail!(
+                        "ps {} failed: {}",
+                        options.join(" "),
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    );
+                }
+                print!(
+                    "{}",
+                    filter_processes(&String::from_utf8_lossy(&output.stdout), &pids)?
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Keeps the header and the lines of the output of ps which are of the given processes
+fn filter_processes(output: &str, pids: &[Pid]) -> Result<String> {
+    let mut lines = output.lines();
+    let header = match lines.next() {
+        Some(header) => header,
+        None => return Ok(String::new()),
+    };
+    let column = match header.split_whitespace().position(|name| name == "PID") {
+        Some(column) => column,
+        None => bail!("the output of ps has no PID column"),
+    };
+
+    let mut filtered = format!("{}\n", header);
+    for line in lines {
+        let pid = line
+            .split_whitespace()
+            .nth(column)
+            .and_then(|pid| pid.parse().ok())
+            .map(Pid::from_raw);
+        if pid.is_some_and(|pid| pids.contains(&pid)) {
+            filtered.push_str(line);
+            filtered.push('\n');
+        }
+    }
+    Ok(filtered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_processes() {
+        let output = "\
+UID          PID    PPID  C STIME TTY          TIME CMD
+root           1       0  0 10:00 ?        00:00:01 /sbin/init
+root        4242    4200  0 10:01 ?        00:00:00 sleep 100
+";
+        let filtered = filter_processes(output, &[Pid::from_raw(4242)]).unwrap();
+        assert_eq!(
+            filtered,
+            "\
+UID          PID    PPID  C STIME TTY          TIME CMD
+root        4242    4200  0 10:01 ?        00:00:00 sleep 100
+"
+        );
+        assert!(filter_processes("USER COMMAND\n", &[]).is_err());
+    }
+}
@@ -0,0 +1,113 @@
+//! Minimal init which can be run as PID 1 of the container instead of the user's process.
+//! Many programs are not written to be PID 1: they neither reap the orphaned processes
+//! which get re-parented to them, nor handle the signals which the kernel doesn't deliver
+//! to PID 1 with a default action. This runs the user's process as its child, forwards
+//! signals to it, reaps every zombie and exits with the status of the user's process.
+
+use std::path::Path;
+use std::process;
+
+use anyhow::{bail, Result};
+use nix::sys::signal::{self, SigSet, SigmaskHow, Signal};
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::{self, Pid};
+
+use crate::utils;
+
+/// Runs the given program as a child of the current process and acts as init for it.
+/// It returns only if the program could not be started.
+pub fn run(path: impl AsRef<Path>, args: &[String], envs: &[String]) -> Result<()> {
+    // signals are handled synchronously by sigwait, so block all of them before forking
+    // to not miss any that arrive before the loop is entered
+    let mut original_mask = SigSet::empty();
+    signal::sigprocmask(
+        SigmaskHow::SIG_BLOCK,
+        Some(&SigSet::all()),
+        Some(&mut original_mask),
+    )?;
+
+    match unsafe { unistd::fork()? } {
+        unistd::ForkResult::Child => {
+            // the child must not return into the runtime, which the parent process goes on
+            // running as init
+            let exec = signal::sigprocmask(SigmaskHow::SIG_SETMASK, Some(&original_mask), None)
+                .map_err(anyhow::Error::from)
+                .and_then(|_| utils::do_exec(path, args, envs));
+            if let Err(e) = exec {
+                log::error!("failed to run the process of the container: {}", e);
+            }
+            unsafe { libc::_exit(127) }
+        }
+        unistd::ForkResult::Parent { child } => {
+            let status = supervise(child)?;
+            log::debug!("process of the container exited with {:?}", status);
+            process::exit(exit_code(status));
+        }
+    }
+}
+
+/// Forwards signals to the child and reaps zombies until the child exits
+fn supervise(child: Pid) -> Result<WaitStatus> {
+    loop {
+        match SigSet::all().wait()? {
+            Signal::SIGCHLD => {
+                if let Some(status) = reap(child)? {
+                    return Ok(status);
+                }
+            }
+            sig => {
+                log::debug!("forward {} to {}", sig, child);
+                if let Err(e) = signal::kill(child, sig) {
+                    log::warn!("failed to forward {} to {}: {}", sig, child, e);
+                }
+            }
+        }
+    }
+}
+
+/// Reaps all exited processes, returns the status of the child if it is one of them
+fn reap(child: Pid) -> Result<Option<WaitStatus>> {
+    let mut child_status = None;
+    loop {
+        match waitpid(None, Some(WaitPidFlag::WNOHANG)) {
+            Ok(WaitStatus::StillAlive) | Err(nix::Error::Sys(nix::errno::Errno::ECHILD)) => break,
+            Ok(status) => {
+                if status.pid() == Some(child) {
+                    child_status = Some(status);
+                }
+            }
+            Err(e) => bail!("failed to reap processes: {}", e),
+        }
+    }
+    Ok(child_status)
+}
+
+/// Converts the wait status of a process to an exit code the way shells do,
+/// i.e. 128 + the number of the signal if it was terminated by a signal
+pub fn exit_code(status: WaitStatus) -> i32 {
+    match status {
+        WaitStatus::Exited(_, code) => code,
+        WaitStatus::Signaled(_, sig, _) => 128 + sig as i32,
+        _ => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_code() {
+        let pid = Pid::from_raw(1);
+        assert_eq!(exit_code(WaitStatus::Exited(pid, 0)), 0);
+        assert_eq!(exit_code(WaitStatus::Exited(pid, 3)), 3);
+        assert_eq!(
+            exit_code(WaitStatus::Signaled(pid, Signal::SIGKILL, false)),
+            137
+        );
+        assert_eq!(
+            exit_code(WaitStatus::Signaled(pid, Signal::SIGTERM, true)),
+            143
+        );
+    }
+}
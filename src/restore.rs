@@ -0,0 +1,224 @@
+//! Restores a container from the images which checkpoint wrote, and registers it again as
+//! a running container
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+use anyhow::{bail, Result};
+use clap::Clap;
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::{self, Pid};
+
+use crate::cgroups;
+use crate::config::Timeouts;
+use crate::container::{Container, ContainerStatus};
+use crate::criu;
+use crate::process::channel::{channel, Channel};
+use crate::process::fork;
+use crate::process::message::Message;
+use crate::rootfs;
+use crate::utils;
+
+#[derive(Clap, Debug)]
+pub struct Restore {
+    /// directory to read the images of the container from
+    #[clap(long, default_value = "checkpoint")]
+    image_path: PathBuf,
+    /// directory for the log of CRIU, the image path by default
+    #[clap(long)]
+    work_path: Option<PathBuf>,
+    /// path to the bundle directory, containing config.json and root filesystem
+    #[clap(short, long, default_value = ".")]
+    bundle: PathBuf,
+    /// File to write pid of the container restored
+    #[clap(short, long)]
+    pid_file: Option<PathBuf>,
+    /// restore established TCP connections
+    #[clap(long)]
+    tcp_established: bool,
+    /// restore unix sockets connected to the outside of the container
+    #[clap(long)]
+    ext_unix_sk: bool,
+    /// restore a container attached to a terminal
+    #[clap(long)]
+    shell_job: bool,
+    /// restore file locks
+    #[clap(long)]
+    file_locks: bool,
+    /// how CRIU handles the cgroups of the container: soft, full, strict or ignore
+    #[clap(long)]
+    manage_cgroups_mode: Option<String>,
+    /// name of the container to restore
+    pub container_id: String,
+}
+
+impl Restore {
+    pub fn exec(&self, root_path: PathBuf, timeouts: &Timeouts) -> Result<()> {
+        let container_dir = root_path.join(&self.container_id);
+        // creating the directory reserves the id, like create does
+        match fs::create_dir(&container_dir) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                bail!("container {} already exists", self.container_id)
+            }
+            Err(e) => return Err(e.into()),
+        }
+        let restored = self.restore(&container_dir, timeouts);
+        if restored.is_err() {
+            let _ = fs::remove_dir_all(&container_dir);
+        }
+        restored
+    }
+
+    fn restore(&self, container_dir: &Path, timeouts: &Timeouts) -> Result<()> {
+        let bundle = fs::canonicalize(&self.bundle)?;
+        let config_path = bundle.join("config.json");
+        let spec = oci_spec::Spec::load(&config_path.to_string_lossy())?;
+        let linux = match &spec.linux {
+            Some(linux) => linux,
+            None => bail!("config.json of {:?} has no linux section", bundle),
+        };
+        let cgroups_path = utils::get_cgroup_path(&linux.cgroups_path, &self.container_id);
+        if cgroups::systemd::is_systemd_path(&cgroups_path) {
+            bail!("restore does not support cgroups managed by systemd yet");
+        }
+        fs::copy(&config_path, container_dir.join("config.json"))?;
+        let rootfs = rootfs::resolve_root_path(&bundle, &spec.root.path)?;
+
+        let container = Container::new(
+            &self.container_id,
+            ContainerStatus::Creating,
+            None,
+            bundle.to_str().unwrap(),
+            container_dir,
+        )?
+        .set_annotations(spec.annotations.clone())
+        .set_config_digest(utils::config_digest(&config_path)?);
+        container.save()?;
+
+        let options = criu::Options {
+            image_path: fs::canonicalize(&self.image_path)?,
+            work_path: self.work_path.clone(),
+            tcp_established: self.tcp_established,
+            ext_unix_sk: self.ext_unix_sk,
+            shell_job: self.shell_job,
+            file_locks: self.file_locks,
+            manage_cgroups_mode: self.manage_cgroups_mode.clone(),
+            ..Default::default()
+        };
+        log::debug!("restore {} from {:?}", container.id(), options.image_path);
+
+        // the restored init process becomes a child of the monitor, which waits for it like
+        // the one of create does
+        let (mut parent_channel, monitor_channel) = channel()?;
+        match unsafe { unistd::fork()? } {
+            unistd::ForkResult::Child => {
+                drop(parent_channel);
+                monitor(
+                    monitor_channel,
+                    &container,
+                    &spec,
+                    &rootfs,
+                    &cgroups_path,
+                    &options,
+                    timeouts,
+                )
+            }
+            unistd::ForkResult::Parent { child } => {
+                drop(monitor_channel);
+                let init_pid = match parent_channel.recv() {
+                    Ok(Message::InitPid(pid)) => {
+                        log::debug!("restored init pid is {}", pid);
+                        Pid::from_raw(pid)
+                    }
+                    Ok(Message::Error(err)) => {
+                        let _ = waitpid(child, None);
+                        bail!("failed to restore {}: {}", container.id(), err)
+                    }
+                    Ok(msg) => bail!("unexpected message {:?} from the monitor", msg),
+                    Err(e) => {
+                        let _ = waitpid(child, None);
+                        return Err(e);
+                    }
+                };
+                // the pid of the init process, as create writes it
+                if let Some(pid_file) = &self.pid_file {
+                    utils::write_pid_file(pid_file, init_pid)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Restores the container, reports the pid of its init process, and waits for it to exit
+fn monitor(
+    mut channel: Channel,
+    container: &Container,
+    spec: &oci_spec::Spec,
+    rootfs: &Path,
+    cgroups_path: &Path,
+    options: &criu::Options,
+    timeouts: &Timeouts,
+) -> ! {
+    let restored = restore_init(container, spec, rootfs, cgroups_path, options, timeouts);
+    let init_pid = match restored {
+        Ok(pid) => pid,
+        Err(err) => {
+            log::debug!("failed to restore {}: {:?}", container.id(), err);
+            if let Err(e) = channel.send(&Message::Error((&err).into())) {
+                log::warn!("failed to report error to parent process: {}", e);
+            }
+            exit(1);
+        }
+    };
+    if let Err(e) = channel.send(&Message::InitPid(init_pid.as_raw())) {
+        log::warn!("failed to report the init pid to parent process: {}", e);
+    }
+    drop(channel);
+
+    let status = match waitpid(init_pid, None) {
+        Ok(status) => status,
+        Err(e) => {
+            log::warn!("failed to wait for {}: {}", init_pid, e);
+            exit(1);
+        }
+    };
+    if let Err(e) = fork::record_exit(container, status, cgroups_path) {
+        log::warn!("failed to record the exit of the container: {}", e);
+    }
+    match status {
+        WaitStatus::Exited(_, status) => exit(status),
+        _ => exit(0),
+    }
+}
+
+fn restore_init(
+    container: &Container,
+    spec: &oci_spec::Spec,
+    rootfs: &Path,
+    cgroups_path: &Path,
+    options: &criu::Options,
+    timeouts: &Timeouts,
+) -> Result<Pid> {
+    let init_pid = criu::restore(
+        spec,
+        rootfs,
+        cgroups_path,
+        &container.root,
+        options,
+        timeouts.criu(),
+    )?;
+    // CRIU has put the processes into the cgroups, the limits come from the spec
+    let cmanager = cgroups::common::create_cgroup_manager(cgroups_path)?;
+    if let Some(resources) = spec.linux.as_ref().and_then(|l| l.resources.as_ref()) {
+        cmanager.apply(resources, init_pid)?;
+    }
+    container
+        .update_status(ContainerStatus::Running)?
+        .set_pid(init_pid.as_raw())
+        .save()?;
+    Ok(init_pid)
+}
@@ -0,0 +1,55 @@
+//! Checks the resource limits of process.rlimits, which are set with setrlimit(2) before
+//! the process of the container is executed. The kernel would reject the same mistakes, but
+//! only once the container is half set up, without telling which entry is wrong.
+
+use std::collections::HashSet;
+
+use anyhow::{bail, Result};
+use oci_spec::LinuxRlimit;
+
+/// Checks that no limit is given twice and that no soft limit is above its hard limit
+pub fn validate(rlimits: &[LinuxRlimit]) -> Result<()> {
+    let mut seen = HashSet::new();
+    for rlimit in rlimits {
+        if !seen.insert(rlimit.typ) {
+            bail!("rlimit {:?} is given more than once", rlimit.typ);
+        }
+        if rlimit.soft > rlimit.hard {
+            bail!(
+                "soft limit {} of rlimit {:?} is above its hard limit {}",
+                rlimit.soft,
+                rlimit.typ,
+                rlimit.hard
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oci_spec::LinuxRlimitType;
+
+    fn rlimit(typ: LinuxRlimitType, soft: u64, hard: u64) -> LinuxRlimit {
+        LinuxRlimit { typ, hard, soft }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(&[]).is_ok());
+        assert!(validate(&[
+            rlimit(LinuxRlimitType::RlimitNofile, 1024, 4096),
+            rlimit(LinuxRlimitType::RlimitCore, 0, 0),
+        ])
+        .is_ok());
+
+        let err = validate(&[rlimit(LinuxRlimitType::RlimitNproc, 20, 10)]).unwrap_err();
+        assert!(err.to_string().contains("RlimitNproc"), "{}", err);
+        assert!(validate(&[
+            rlimit(LinuxRlimitType::RlimitNofile, 1024, 1024),
+            rlimit(LinuxRlimitType::RlimitNofile, 512, 512),
+        ])
+        .is_err());
+    }
+}
@@ -1,23 +1,194 @@
 //! During kernel initialization, a minimal replica of the ramfs filesystem is loaded, called rootfs.
 //! Most systems mount another filesystem over it
 
-use std::fs::OpenOptions;
+use std::fs::{self, OpenOptions};
 use std::fs::{canonicalize, create_dir_all, remove_file};
-use std::os::unix::fs::symlink;
+use std::os::unix::fs::{symlink, MetadataExt, PermissionsExt};
 use std::path::{Path, PathBuf};
 
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use nix::errno::Errno;
-use nix::fcntl::{open, OFlag};
 use nix::mount::mount as nix_mount;
-use nix::mount::MsFlags;
-use nix::sys::stat::Mode;
-use nix::sys::stat::{mknod, umask};
-use nix::unistd::{chdir, chown, close, getcwd};
+use nix::mount::{umount2, MntFlags, MsFlags};
+use nix::sys::statvfs::{statvfs, FsFlags};
+use nix::unistd::{chdir, fchownat, getcwd, geteuid, getpid, FchownatFlags};
 use nix::unistd::{Gid, Uid};
 
-use crate::utils::PathBufExt;
-use oci_spec::{LinuxDevice, LinuxDeviceType, Mount, Spec};
+use crate::cgroups;
+use crate::container::Warning;
+use crate::devices;
+use crate::process::message::Phase;
+use crate::seccomp;
+use crate::selinux;
+use oci_spec::{LinuxIdMapping, LinuxNamespaceType, Mount, Spec};
+
+/// Resolves root.path of the spec to the absolute path of the container's root filesystem.
+/// A relative path is evaluated against the bundle directory and must stay inside of it after
+/// symlinks are resolved, while an absolute path is an explicit choice of the caller and is taken as is.
+pub fn resolve_root_path(bundle: &Path, root_path: &Path) -> Result<PathBuf> {
+    let bundle = canonicalize(bundle)?;
+    let path = if root_path.is_absolute() {
+        root_path.to_path_buf()
+    } else {
+        bundle.join(root_path)
+    };
+
+    // a symlink could be pointed elsewhere between now and the time the rootfs is mounted
+    if fs::symlink_metadata(&path).is_ok_and(|metadata| metadata.file_type().is_symlink()) {
+        bail!("root path {:?} is a symlink, which is not allowed", path)
+    }
+    let rootfs = match canonicalize(&path) {
+        Ok(rootfs) => rootfs,
+        Err(e) => bail!("failed to resolve root path {:?}: {}", path, e),
+    };
+    if root_path.is_relative() && !rootfs.starts_with(&bundle) {
+        bail!(
+            "root path {:?} resolves to {:?}, which is outside of the bundle {:?}",
+            root_path,
+            rootfs,
+            bundle
+        )
+    }
+    if !rootfs.is_dir() {
+        bail!("root path {:?} is not a directory", rootfs)
+    }
+
+    Ok(rootfs)
+}
+
+/// Checks that the rootfs cannot be tampered with by other users and that the command of
+/// the container can be run from it, before anything is mounted
+pub fn check_root(rootfs: &Path, spec: &Spec) -> Result<()> {
+    let metadata = fs::metadata(rootfs)?;
+    let owner = metadata.uid();
+    let mappings = spec
+        .linux
+        .as_ref()
+        .map(|linux| linux.uid_mappings.as_slice())
+        .unwrap_or_default();
+    if !is_trusted_owner(owner, geteuid().as_raw(), mappings) {
+        bail!(
+            "rootfs {:?} is owned by uid {}, which is neither root, the caller nor mapped into the container",
+            rootfs,
+            owner
+        )
+    }
+    if metadata.permissions().mode() & 0o002 != 0 {
+        bail!(
+            "rootfs {:?} is writable by every user, who could swap its contents",
+            rootfs
+        )
+    }
+
+    let flags = statvfs(rootfs)?.flags();
+    if flags.contains(FsFlags::ST_NOEXEC) {
+        bail!(
+            "rootfs {:?} is on a filesystem mounted noexec, so the command of the container cannot be run",
+            rootfs
+        )
+    }
+    if flags.contains(FsFlags::ST_RDONLY) && !spec.root.readonly {
+        log::warn!(
+            "rootfs {:?} is on a read-only filesystem, but root.readonly is not set",
+            rootfs
+        );
+    }
+    Ok(())
+}
+
+fn is_trusted_owner(owner: u32, euid: u32, mappings: &[LinuxIdMapping]) -> bool {
+    owner == 0
+        || owner == euid
+        || mappings
+            .iter()
+            .any(|m| m.host_id <= owner && (owner as u64) < m.host_id as u64 + m.size as u64)
+}
+
+/// Returns warnings for the mounts of the spec which are skipped or changed while preparing
+/// the rootfs
+pub fn unsupported(spec: &Spec) -> Vec<Warning> {
+    let mut warnings = Vec::new();
+    for m in &spec.mounts {
+        if let Some(namespace) = shared_host_namespace(spec, m) {
+            warnings.push(Warning::new(
+                &format!("mounts[{}]", m.destination.display()),
+                &format!(
+                    "mounted read-only, as the container shares the {} namespace of the host",
+                    namespace
+                ),
+            ));
+        }
+    }
+    let labeled = !spec.process.selinux_label.is_empty()
+        || spec
+            .linux
+            .as_ref()
+            .is_some_and(|linux| !linux.mount_label.is_empty());
+    if labeled && !selinux::is_enabled() {
+        warnings.push(Warning::new(
+            "process.selinuxLabel",
+            "SELinux is disabled on the host, the labels are ignored",
+        ));
+    }
+    if let Some(linux) = &spec.linux {
+        let new_timens = linux
+            .namespaces
+            .iter()
+            .any(|ns| ns.typ == LinuxNamespaceType::Time && ns.path.is_none());
+        if !linux.time_offsets.is_empty() && !new_timens {
+            warnings.push(Warning::new(
+                "linux.timeOffsets",
+                "the offsets are only set for a new time namespace, they are ignored",
+            ));
+        }
+    }
+    if let Some(seccomp) = spec.linux.as_ref().and_then(|linux| linux.seccomp.as_ref()) {
+        let unfiltered: Vec<_> = seccomp
+            .architectures
+            .iter()
+            .filter(|arch| !seccomp::is_filtered(**arch))
+            .collect();
+        if !unfiltered.is_empty() {
+            warnings.push(Warning::new(
+                "linux.seccomp.architectures",
+                &format!(
+                    "syscalls of {:?} are not filtered, they get the default action",
+                    unfiltered
+                ),
+            ));
+        }
+        let notifies = seccomp.filter_flags()
+            & oci_spec::LinuxSeccompFlag::SeccompFilterFlagNewListener as u32
+            != 0;
+        if notifies && seccomp.listener_path.is_none() {
+            warnings.push(Warning::new(
+                "linux.seccomp.listenerPath",
+                "no agent handles SCMP_ACT_NOTIFY without it, the syscalls fail with ENOSYS",
+            ));
+        }
+    }
+    warnings
+}
+
+/// Returns the namespace of the host which the container shares and which a proc or
+/// sysfs mount would give access to, e.g. /proc/sys or the network devices in /sys.
+/// Such mounts are made read-only, so that the container cannot reconfigure the host.
+fn shared_host_namespace(spec: &Spec, m: &Mount) -> Option<&'static str> {
+    let (typ, name) = match m.typ.as_str() {
+        "proc" => (LinuxNamespaceType::Pid, "pid"),
+        "sysfs" => (LinuxNamespaceType::Network, "network"),
+        _ => return None,
+    };
+    let unshared = spec
+        .linux
+        .as_ref()
+        .is_some_and(|linux| linux.namespaces.iter().any(|ns| ns.typ == typ));
+    if unshared {
+        None
+    } else {
+        Some(name)
+    }
+}
 
 pub fn prepare_rootfs(spec: &Spec, rootfs: &Path, bind_devices: bool) -> Result<()> {
     let mut flags = MsFlags::MS_REC;
@@ -30,190 +201,180 @@ pub fn prepare_rootfs(spec: &Spec, rootfs: &Path, bind_devices: bool) -> Result<
         },
         None => flags |= MsFlags::MS_SLAVE,
     };
-    nix_mount(None::<&str>, "/", None::<&str>, flags, None::<&str>)?;
+    nix_mount(None::<&str>, "/", None::<&str>, flags, None::<&str>)
+        .context(Phase::new("set rootfs propagation"))?;
 
     log::debug!("mount root fs {:?}", rootfs);
     nix_mount::<Path, Path, str, str>(
-        Some(&rootfs),
-        &rootfs,
+        Some(rootfs),
+        rootfs,
         None::<&str>,
         MsFlags::MS_BIND | MsFlags::MS_REC,
         None::<&str>,
-    )?;
+    )
+    .with_context(|| Phase::with_path("bind mount rootfs", rootfs))?;
 
     for m in spec.mounts.iter() {
-        let (flags, data) = parse_mount(&m);
+        let (mut flags, propagation, data) = parse_mount(m);
+        if shared_host_namespace(spec, m).is_some() {
+            flags |= MsFlags::MS_RDONLY;
+        }
         let ml = &spec.linux.as_ref().unwrap().mount_label;
         if m.typ == "cgroup" {
-            // skip
-            log::warn!("A feature of cgroup is unimplemented.");
-        } else if m.destination == PathBuf::from("/dev") {
-            mount_to_container(&m, rootfs, flags & !MsFlags::MS_RDONLY, &data, &ml)?;
+            let cgroupns = spec.linux.as_ref().is_some_and(|linux| {
+                linux
+                    .namespaces
+                    .iter()
+                    .any(|ns| ns.typ == LinuxNamespaceType::Cgroup)
+            });
+            mount_cgroups(m, rootfs, flags, cgroupns, ml)
+                .with_context(|| Phase::with_path("mount", &m.destination))?;
+        } else if m.destination == Path::new("/dev") {
+            let flags = flags & !MsFlags::MS_RDONLY;
+            mount_to_container(m, rootfs, flags, propagation, &data, ml)
+                .with_context(|| Phase::with_path("mount", &m.destination))?;
         } else {
-            mount_to_container(&m, rootfs, flags, &data, &ml)?;
+            mount_to_container(m, rootfs, flags, propagation, &data, ml)
+                .with_context(|| Phase::with_path("mount", &m.destination))?;
         }
     }
 
     let olddir = getcwd()?;
     chdir(rootfs)?;
 
-    setup_default_symlinks(rootfs)?;
-    create_devices(&spec.linux.as_ref().unwrap().devices, bind_devices)?;
-    setup_ptmx(rootfs)?;
+    setup_default_symlinks(rootfs).context(Phase::new("create default symlinks"))?;
+    devices::create_devices(&spec.linux.as_ref().unwrap().devices, bind_devices)
+        .context(Phase::new("create devices"))?;
+    setup_ptmx(rootfs).context(Phase::with_path("create", "/dev/ptmx"))?;
 
     chdir(&olddir)?;
 
     Ok(())
 }
 
-fn setup_ptmx(rootfs: &Path) -> Result<()> {
-    if let Err(e) = remove_file(rootfs.join("dev/ptmx")) {
-        if e.kind() != ::std::io::ErrorKind::NotFound {
-            bail!("could not delete /dev/ptmx")
+/// Makes linux.readonlyPaths read-only for the container, once its root is changed, by
+/// binding each over itself and remounting the bind read-only. Paths which do not exist in
+/// the container are skipped.
+pub fn readonly_paths(paths: &[String]) -> Result<()> {
+    for path in paths {
+        match nix_mount(
+            Some(path.as_str()),
+            path.as_str(),
+            None::<&str>,
+            MsFlags::MS_BIND | MsFlags::MS_REC,
+            None::<&str>,
+        ) {
+            Ok(()) => {}
+            Err(nix::Error::Sys(Errno::ENOENT)) => continue,
+            Err(e) => return Err(e).with_context(|| Phase::with_path("bind mount", path)),
         }
+        // in a user namespace, the remount fails unless it keeps the flags which the mount
+        // was locked with
+        let locked = locked_flags(statvfs(path.as_str())?.flags());
+        nix_mount(
+            Some(path.as_str()),
+            path.as_str(),
+            None::<&str>,
+            MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY | MsFlags::MS_REC | locked,
+            None::<&str>,
+        )
+        .with_context(|| Phase::with_path("remount read-only", path))?;
     }
-    symlink("pts/ptmx", rootfs.join("dev/ptmx"))?;
     Ok(())
 }
 
-fn setup_default_symlinks(rootfs: &Path) -> Result<()> {
-    if Path::new("/proc/kcore").exists() {
-        symlink("/proc/kcore", "dev/kcore")?;
-    }
-
-    let defaults = [
-        ("/proc/self/fd", "dev/fd"),
-        ("/proc/self/fd/0", "dev/stdin"),
-        ("/proc/self/fd/1", "dev/stdout"),
-        ("/proc/self/fd/2", "dev/stderr"),
-    ];
-    for &(src, dst) in defaults.iter() {
-        symlink(src, rootfs.join(dst))?;
-    }
+/// Remounts the root of the container read-only for root.readonly, once the root is changed
+/// and nothing is left to be created in it
+pub fn readonly_root() -> Result<()> {
+    let locked = locked_flags(statvfs("/")?.flags());
+    nix_mount(
+        Some("/"),
+        "/",
+        None::<&str>,
+        MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY | locked,
+        None::<&str>,
+    )
+    .context(Phase::new("remount root read-only"))?;
     Ok(())
 }
 
-pub fn default_devices() -> Vec<LinuxDevice> {
-    vec![
-        LinuxDevice {
-            path: PathBuf::from("/dev/null"),
-            typ: LinuxDeviceType::C,
-            major: 1,
-            minor: 3,
-            file_mode: Some(0o066),
-            uid: None,
-            gid: None,
-        },
-        LinuxDevice {
-            path: PathBuf::from("/dev/zero"),
-            typ: LinuxDeviceType::C,
-            major: 1,
-            minor: 5,
-            file_mode: Some(0o066),
-            uid: None,
-            gid: None,
-        },
-        LinuxDevice {
-            path: PathBuf::from("/dev/full"),
-            typ: LinuxDeviceType::C,
-            major: 1,
-            minor: 7,
-            file_mode: Some(0o066),
-            uid: None,
-            gid: None,
-        },
-        LinuxDevice {
-            path: PathBuf::from("/dev/tty"),
-            typ: LinuxDeviceType::C,
-            major: 5,
-            minor: 0,
-            file_mode: Some(0o066),
-            uid: None,
-            gid: None,
-        },
-        LinuxDevice {
-            path: PathBuf::from("/dev/urandom"),
-            typ: LinuxDeviceType::C,
-            major: 1,
-            minor: 9,
-            file_mode: Some(0o066),
-            uid: None,
-            gid: None,
-        },
-        LinuxDevice {
-            path: PathBuf::from("/dev/random"),
-            typ: LinuxDeviceType::C,
-            major: 1,
-            minor: 8,
-            file_mode: Some(0o066),
-            uid: None,
-            gid: None,
-        },
+/// Flags of a mount which a remount in a user namespace has to keep
+fn locked_flags(flags: FsFlags) -> MsFlags {
+    [
+        (FsFlags::ST_NOSUID, MsFlags::MS_NOSUID),
+        (FsFlags::ST_NODEV, MsFlags::MS_NODEV),
+        (FsFlags::ST_NOEXEC, MsFlags::MS_NOEXEC),
+        (FsFlags::ST_NOATIME, MsFlags::MS_NOATIME),
+        (FsFlags::ST_NODIRATIME, MsFlags::MS_NODIRATIME),
+        (FsFlags::ST_RELATIME, MsFlags::MS_RELATIME),
     ]
+    .iter()
+    .filter(|(fs_flag, _)| flags.contains(*fs_flag))
+    .fold(MsFlags::empty(), |locked, (_, ms_flag)| locked | *ms_flag)
 }
 
-fn create_devices(devices: &[LinuxDevice], bind: bool) -> Result<()> {
-    let old_mode = umask(Mode::from_bits_truncate(0o000));
-    if bind {
-        let _ = default_devices()
-            .iter()
-            .chain(devices)
-            .map(|dev| {
-                if !dev.path.starts_with("/dev") {
-                    panic!("{} is not a valid device path", dev.path.display());
-                }
-                bind_dev(dev)
-            })
-            .collect::<Result<Vec<_>>>()?;
-    } else {
-        default_devices()
-            .iter()
-            .chain(devices)
-            .map(|dev| {
-                if !dev.path.starts_with("/dev") {
-                    panic!("{} is not a valid device path", dev.path.display());
-                }
-                mknod_dev(dev)
-            })
-            .collect::<Result<Vec<_>>>()?;
+/// Hides linux.maskedPaths from the container, once its root is changed. Files are covered
+/// by /dev/null and directories by an empty read-only tmpfs. Paths which do not exist in the
+/// container are skipped.
+pub fn mask_paths(paths: &[String], mount_label: &str) -> Result<()> {
+    for path in paths {
+        match nix_mount(
+            Some("/dev/null"),
+            path.as_str(),
+            None::<&str>,
+            MsFlags::MS_BIND,
+            None::<&str>,
+        ) {
+            Ok(()) | Err(nix::Error::Sys(Errno::ENOENT)) => {}
+            Err(nix::Error::Sys(Errno::ENOTDIR)) => {
+                let data = selinux::mount_data("", mount_label);
+                nix_mount(
+                    Some("tmpfs"),
+                    path.as_str(),
+                    Some("tmpfs"),
+                    MsFlags::MS_RDONLY,
+                    Some(data.as_str()),
+                )
+                .with_context(|| Phase::with_path("mask", path))?;
+            }
+            Err(e) => return Err(e).with_context(|| Phase::with_path("mask", path)),
+        }
     }
-    umask(old_mode);
     Ok(())
 }
 
-fn bind_dev(dev: &LinuxDevice) -> Result<()> {
-    let fd = open(
-        &dev.path.as_in_container()?,
-        OFlag::O_RDWR | OFlag::O_CREAT,
-        Mode::from_bits_truncate(0o644),
-    )?;
-    close(fd)?;
-    nix_mount(
-        Some(&*dev.path.as_in_container()?),
-        &dev.path,
-        None::<&str>,
-        MsFlags::MS_BIND,
-        None::<&str>,
-    )?;
+/// Points /dev/ptmx to the multiplexer of the devpts instance of the container, replacing
+/// the one of the image, which would allocate terminals of the host
+fn setup_ptmx(rootfs: &Path) -> Result<()> {
+    if let Err(e) = remove_file(rootfs.join("dev/ptmx")) {
+        if e.kind() != ::std::io::ErrorKind::NotFound {
+            bail!("could not delete /dev/ptmx: {}", e)
+        }
+    }
+    symlink("pts/ptmx", rootfs.join("dev/ptmx"))?;
     Ok(())
 }
 
-fn mknod_dev(dev: &LinuxDevice) -> Result<()> {
-    fn makedev(major: u64, minor: u64) -> u64 {
-        (minor & 0xff) | ((major & 0xfff) << 8) | ((minor & !0xff) << 12) | ((major & !0xfff) << 32)
+/// Creates the symlinks which are expected in /dev. Those which the image has already, as
+/// its /dev may not be a mount of its own, are kept.
+fn setup_default_symlinks(rootfs: &Path) -> Result<()> {
+    let mut defaults = vec![
+        ("/proc/self/fd", "dev/fd"),
+        ("/proc/self/fd/0", "dev/stdin"),
+        ("/proc/self/fd/1", "dev/stdout"),
+        ("/proc/self/fd/2", "dev/stderr"),
+    ];
+    if Path::new("/proc/kcore").exists() {
+        defaults.push(("/proc/kcore", "dev/core"));
+    }
+    for &(src, dst) in defaults.iter() {
+        match symlink(src, rootfs.join(dst)) {
+            Err(e) if e.kind() == ::std::io::ErrorKind::AlreadyExists => {
+                log::debug!("/{} exists already", dst)
+            }
+            result => result.with_context(|| format!("failed to create /{}", dst))?,
+        }
     }
-
-    mknod(
-        &dev.path.as_in_container()?,
-        dev.typ.to_sflag()?,
-        Mode::from_bits_truncate(dev.file_mode.unwrap_or(0)),
-        makedev(dev.major, dev.minor),
-    )?;
-    chown(
-        &dev.path.as_in_container()?,
-        dev.uid.map(Uid::from_raw),
-        dev.gid.map(Gid::from_raw),
-    )?;
     Ok(())
 }
 
@@ -221,15 +382,18 @@ fn mount_to_container(
     m: &Mount,
     rootfs: &Path,
     flags: MsFlags,
+    propagation: MsFlags,
     data: &str,
     label: &str,
 ) -> Result<()> {
-    let d = if !label.is_empty() && m.typ != "proc" && m.typ != "sysfs" {
-        if data.is_empty() {
-            format!("context=\"{}\"", label)
-        } else {
-            format!("{},context=\"{}\"", data, label)
-        }
+    // proc, sysfs and cgroup have labels of their own, and a bind mount keeps the labels
+    // of its source. mqueue does not support a context, its root is labeled instead.
+    let labeled = !matches!(
+        m.typ.as_str(),
+        "proc" | "sysfs" | "cgroup" | "cgroup2" | "mqueue"
+    ) && !flags.contains(MsFlags::MS_BIND);
+    let d = if labeled {
+        selinux::mount_data(data, label)
     } else {
         data.to_string()
     };
@@ -241,48 +405,147 @@ fn mount_to_container(
     );
     let dest = Path::new(&dest_for_host);
 
-    let src = if m.typ == "bind" {
-        let src = canonicalize(&m.source)?;
-        let dir = if src.is_file() {
-            Path::new(&dest).parent().unwrap()
-        } else {
-            Path::new(&dest)
-        };
-        create_dir_all(&dir).unwrap();
-        if src.is_file() {
-            OpenOptions::new()
-                .create(true)
-                .write(true)
-                .open(&dest)
-                .unwrap();
-        }
+    // a bind may be given by its options only, with any type
+    let src = if m.typ == "bind" || flags.contains(MsFlags::MS_BIND) {
+        let src = canonicalize(&m.source)
+            .with_context(|| format!("failed to resolve bind source {:?}", m.source))?;
+        create_mount_point(dest, src.is_dir())?;
         src
     } else {
-        create_dir_all(&dest).unwrap();
+        create_mount_point(dest, true)?;
         PathBuf::from(&m.source)
     };
 
-    if let Err(::nix::Error::Sys(errno)) =
-        nix_mount(Some(&*src), dest, Some(&*m.typ), flags, Some(&*d))
-    {
-        if errno != Errno::EINVAL {
-            bail!("mount of {} failed", m.destination.display());
+    // the tmpfs is filled with what the image has at the destination before it covers it
+    if m.typ == "tmpfs" && m.options.iter().any(|option| option == "tmpcopyup") {
+        mount_copied_up(dest, flags, &d, data)?;
+    } else {
+        match nix_mount(Some(&*src), dest, Some(&*m.typ), flags, Some(&*d)) {
+            // the kernel may not support the label, retry without it
+            Err(::nix::Error::Sys(Errno::EINVAL)) => {
+                nix_mount(Some(&*src), dest, Some(&*m.typ), flags, Some(data))?
+            }
+            // a user namespace may only mount sysfs along with a network namespace of its
+            // own, the one of the host is bound instead
+            Err(::nix::Error::Sys(Errno::EPERM)) if m.typ == "sysfs" => {
+                log::debug!("bind mount /sys to {:?} instead of mounting sysfs", dest);
+                let flags = flags | MsFlags::MS_BIND | MsFlags::MS_REC;
+                nix_mount(Some("/sys"), dest, None::<&str>, flags, None::<&str>)?;
+                nix_mount(
+                    Some(dest),
+                    dest,
+                    None::<&str>,
+                    flags | MsFlags::MS_REMOUNT,
+                    None::<&str>,
+                )?;
+            }
+            Err(err) => return Err(err.into()),
+            Ok(()) => (),
         }
-        nix_mount(Some(&*src), dest, Some(&*m.typ), flags, Some(data))?;
+    }
+    if m.typ == "mqueue" && !label.is_empty() {
+        selinux::set_file_label(dest, label)?;
     }
     if flags.contains(MsFlags::MS_BIND)
-        && flags.intersects(
-            !(MsFlags::MS_REC
-                | MsFlags::MS_REMOUNT
-                | MsFlags::MS_BIND
-                | MsFlags::MS_PRIVATE
-                | MsFlags::MS_SHARED
-                | MsFlags::MS_SLAVE),
-        )
+        && flags.intersects(!(MsFlags::MS_REC | MsFlags::MS_REMOUNT | MsFlags::MS_BIND))
     {
         nix_mount(
-            Some(&*dest),
-            &*dest,
+            Some(dest),
+            dest,
+            None::<&str>,
+            flags | MsFlags::MS_REMOUNT,
+            None::<&str>,
+        )?;
+    }
+    // the propagation is changed by a call of its own, mount ignores it along with others
+    if !propagation.is_empty() {
+        nix_mount(None::<&str>, dest, None::<&str>, propagation, None::<&str>)?;
+    }
+    Ok(())
+}
+
+/// Mounts the cgroups of the container for a mount of type cgroup. In a cgroup namespace,
+/// the hierarchies are mounted afresh and rooted at the cgroups of the container, otherwise
+/// the cgroups of the container are bound. The v1 hierarchies go below a tmpfs, as on the host.
+fn mount_cgroups(
+    m: &Mount,
+    rootfs: &Path,
+    flags: MsFlags,
+    cgroupns: bool,
+    label: &str,
+) -> Result<()> {
+    let dest = PathBuf::from(format!("{}{}", rootfs.display(), m.destination.display()));
+    create_dir_all(&dest)?;
+    let own_cgroups = procfs::process::Process::myself()?.cgroups()?;
+
+    if let Some(root) = cgroups::common::cgroup2_root()? {
+        if cgroupns {
+            nix_mount(Some("cgroup2"), &dest, Some("cgroup2"), flags, None::<&str>)?;
+        } else {
+            let own = own_cgroups
+                .iter()
+                .find(|cgroup| cgroup.hierarchy == 0)
+                .ok_or_else(|| anyhow!("the process is in no cgroup v2"))?;
+            bind_mount(
+                &root.join(own.pathname.trim_start_matches('/')),
+                &dest,
+                flags,
+            )?;
+        }
+        return Ok(());
+    }
+
+    let data = selinux::mount_data("mode=755", label);
+    nix_mount(
+        Some("tmpfs"),
+        &dest,
+        Some("tmpfs"),
+        flags & !MsFlags::MS_RDONLY,
+        Some(data.as_str()),
+    )?;
+    let hierarchies = procfs::process::Process::myself()?.mountinfo()?;
+    for cgroup in own_cgroups.iter().filter(|cgroup| cgroup.hierarchy != 0) {
+        if cgroup.controllers.is_empty() {
+            continue;
+        }
+        let dir = hierarchy_dir(&cgroup.controllers);
+        let target = dest.join(&dir);
+        create_dir_all(&target)?;
+        if cgroupns {
+            let options = cgroup.controllers.join(",");
+            nix_mount(
+                Some("cgroup"),
+                &target,
+                Some("cgroup"),
+                flags,
+                Some(options.as_str()),
+            )?;
+        } else {
+            let hierarchy = hierarchies
+                .iter()
+                .find(|mount| {
+                    mount.fs_type == "cgroup" && mount.mount_point.file_name() == Some(dir.as_ref())
+                })
+                .ok_or_else(|| anyhow!("could not find the mount point of cgroup {}", dir))?;
+            let own = hierarchy
+                .mount_point
+                .join(cgroup.pathname.trim_start_matches('/'));
+            bind_mount(&own, &target, flags)?;
+        }
+        // co-mounted controllers are found under each of their names, as on the host
+        if cgroup.controllers.len() > 1 {
+            for controller in &cgroup.controllers {
+                let link = dest.join(controller.trim_start_matches("name="));
+                if !link.exists() {
+                    symlink(&dir, &link)?;
+                }
+            }
+        }
+    }
+    if flags.contains(MsFlags::MS_RDONLY) {
+        nix_mount(
+            None::<&str>,
+            &dest,
             None::<&str>,
             flags | MsFlags::MS_REMOUNT,
             None::<&str>,
@@ -291,11 +554,155 @@ fn mount_to_container(
     Ok(())
 }
 
-fn parse_mount(m: &Mount) -> (MsFlags, String) {
+/// Name of the directory of a cgroup v1 hierarchy, e.g. cpu,cpuacct or systemd for the
+/// named hierarchy name=systemd
+fn hierarchy_dir(controllers: &[String]) -> String {
+    controllers
+        .iter()
+        .map(|controller| controller.trim_start_matches("name="))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Binds the source recursively and remounts the bind with the flags, e.g. read-only
+fn bind_mount(src: &Path, dest: &Path, flags: MsFlags) -> Result<()> {
+    let bind = MsFlags::MS_BIND | MsFlags::MS_REC;
+    nix_mount(Some(src), dest, None::<&str>, bind, None::<&str>)?;
+    if !(flags & !bind).is_empty() {
+        nix_mount(
+            Some(dest),
+            dest,
+            None::<&str>,
+            flags | bind | MsFlags::MS_REMOUNT,
+            None::<&str>,
+        )?;
+    }
+    Ok(())
+}
+
+/// Creates the destination of a mount if it is missing, as an empty file for a bind of
+/// anything but a directory, e.g. of /etc/resolv.conf or of a socket
+fn create_mount_point(dest: &Path, is_dir: bool) -> Result<()> {
+    let dir = if is_dir { dest } else { dest.parent().unwrap() };
+    create_dir_all(dir).with_context(|| format!("failed to create {:?}", dir))?;
+    if !is_dir && fs::symlink_metadata(dest).is_err() {
+        OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(dest)
+            .with_context(|| format!("failed to create {:?}", dest))?;
+    }
+    Ok(())
+}
+
+/// Mounts a tmpfs aside, copies the contents of the destination into it and moves it over
+/// the destination, for the tmpcopyup option
+fn mount_copied_up(dest: &Path, flags: MsFlags, data: &str, unlabeled_data: &str) -> Result<()> {
+    let staging = std::env::temp_dir().join(format!("youki-tmpcopyup-{}", getpid()));
+    fs::create_dir(&staging)?;
+    let mounted = (|| {
+        // writable until the contents are copied
+        let copy_flags = flags & !MsFlags::MS_RDONLY;
+        match nix_mount(
+            Some("tmpfs"),
+            &staging,
+            Some("tmpfs"),
+            copy_flags,
+            Some(data),
+        ) {
+            // the kernel may not support the label, retry without it
+            Err(::nix::Error::Sys(Errno::EINVAL)) => nix_mount(
+                Some("tmpfs"),
+                &staging,
+                Some("tmpfs"),
+                copy_flags,
+                Some(unlabeled_data),
+            )?,
+            Err(err) => return Err(err.into()),
+            Ok(()) => (),
+        }
+        copy_dir_contents(dest, &staging)
+            .with_context(|| format!("failed to copy up the contents of {:?}", dest))?;
+        nix_mount(
+            Some(&staging),
+            dest,
+            None::<&str>,
+            MsFlags::MS_MOVE,
+            None::<&str>,
+        )?;
+        if flags.contains(MsFlags::MS_RDONLY) {
+            nix_mount(
+                None::<&str>,
+                dest,
+                None::<&str>,
+                flags | MsFlags::MS_REMOUNT,
+                None::<&str>,
+            )?;
+        }
+        Ok(())
+    })();
+    if mounted.is_err() {
+        let _ = umount2(&staging, MntFlags::MNT_DETACH);
+    }
+    let _ = fs::remove_dir(&staging);
+    mounted
+}
+
+/// Copies the contents of a directory recursively, keeping the modes, owners and symlinks.
+/// Anything but files, directories and symlinks is skipped.
+fn copy_dir_contents(from: &Path, to: &Path) -> Result<()> {
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let (src, dst) = (entry.path(), to.join(entry.file_name()));
+        let metadata = fs::symlink_metadata(&src)?;
+        let file_type = metadata.file_type();
+        if file_type.is_symlink() {
+            symlink(fs::read_link(&src)?, &dst)?;
+        } else if file_type.is_dir() {
+            fs::create_dir(&dst)?;
+            copy_dir_contents(&src, &dst)?;
+            fs::set_permissions(&dst, metadata.permissions())?;
+        } else if file_type.is_file() {
+            fs::copy(&src, &dst)?;
+        } else {
+            log::debug!("skip copying up {:?}, which is not a regular file", src);
+            continue;
+        }
+        fchownat(
+            None,
+            &dst,
+            Some(Uid::from_raw(metadata.uid())),
+            Some(Gid::from_raw(metadata.gid())),
+            FchownatFlags::NoFollowSymlink,
+        )?;
+    }
+    Ok(())
+}
+
+/// Translates the options of a mount into the flags of mount, the propagation, which is
+/// changed separately, and the data for the filesystem, which gets the options left
+fn parse_mount(m: &Mount) -> (MsFlags, MsFlags, String) {
+    // unknown to nix
+    let lazytime = unsafe { MsFlags::from_bits_unchecked(libc::MS_LAZYTIME) };
     let mut flags = MsFlags::empty();
+    let mut propagation = MsFlags::empty();
     let mut data = Vec::new();
     for s in &m.options {
-        if let Some((is_clear, flag)) = match s.as_str() {
+        if let Some(flag) = match s.as_str() {
+            "private" => Some(MsFlags::MS_PRIVATE),
+            "rprivate" => Some(MsFlags::MS_PRIVATE | MsFlags::MS_REC),
+            "shared" => Some(MsFlags::MS_SHARED),
+            "rshared" => Some(MsFlags::MS_SHARED | MsFlags::MS_REC),
+            "slave" => Some(MsFlags::MS_SLAVE),
+            "rslave" => Some(MsFlags::MS_SLAVE | MsFlags::MS_REC),
+            "unbindable" => Some(MsFlags::MS_UNBINDABLE),
+            "runbindable" => Some(MsFlags::MS_UNBINDABLE | MsFlags::MS_REC),
+            _ => None,
+        } {
+            // the last one counts
+            propagation = flag;
+        } else if let Some((is_clear, flag)) = match s.as_str() {
             "defaults" => Some((false, MsFlags::empty())),
             "ro" => Some((false, MsFlags::MS_RDONLY)),
             "rw" => Some((true, MsFlags::MS_RDONLY)),
@@ -317,18 +724,18 @@ fn parse_mount(m: &Mount) -> (MsFlags, String) {
             "nodiratime" => Some((false, MsFlags::MS_NODIRATIME)),
             "bind" => Some((false, MsFlags::MS_BIND)),
             "rbind" => Some((false, MsFlags::MS_BIND | MsFlags::MS_REC)),
-            "unbindable" => Some((false, MsFlags::MS_UNBINDABLE)),
-            "runbindable" => Some((false, MsFlags::MS_UNBINDABLE | MsFlags::MS_REC)),
-            "private" => Some((false, MsFlags::MS_PRIVATE)),
-            "rprivate" => Some((false, MsFlags::MS_PRIVATE | MsFlags::MS_REC)),
-            "shared" => Some((false, MsFlags::MS_SHARED)),
-            "rshared" => Some((false, MsFlags::MS_SHARED | MsFlags::MS_REC)),
-            "slave" => Some((false, MsFlags::MS_SLAVE)),
-            "rslave" => Some((false, MsFlags::MS_SLAVE | MsFlags::MS_REC)),
             "relatime" => Some((false, MsFlags::MS_RELATIME)),
             "norelatime" => Some((true, MsFlags::MS_RELATIME)),
             "strictatime" => Some((false, MsFlags::MS_STRICTATIME)),
             "nostrictatime" => Some((true, MsFlags::MS_STRICTATIME)),
+            "iversion" => Some((false, MsFlags::MS_I_VERSION)),
+            "noiversion" => Some((true, MsFlags::MS_I_VERSION)),
+            "lazytime" => Some((false, lazytime)),
+            "nolazytime" => Some((true, lazytime)),
+            "silent" => Some((false, MsFlags::MS_SILENT)),
+            // handled by the runtime, not by the filesystem
+            "tmpcopyup" => Some((false, MsFlags::empty())),
+            "loud" => Some((true, MsFlags::MS_SILENT)),
             _ => None,
         } {
             if is_clear {
@@ -340,5 +747,244 @@ fn parse_mount(m: &Mount) -> (MsFlags, String) {
             data.push(s.as_str());
         };
     }
-    (flags, data.join(","))
+    (flags, propagation, data.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup_bundle(name: &str) -> PathBuf {
+        let bundle = std::env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&bundle);
+        fs::create_dir_all(bundle.join("rootfs")).expect("create rootfs directory");
+        bundle
+    }
+
+    #[test]
+    fn test_parse_mount() {
+        let mount = Mount {
+            destination: PathBuf::from("/tmp"),
+            typ: "tmpfs".to_owned(),
+            source: PathBuf::from("tmpfs"),
+            options: [
+                "nosuid",
+                "ro",
+                "rw",
+                "rprivate",
+                "rslave",
+                "lazytime",
+                "tmpcopyup",
+                "mode=755",
+                "size=64k",
+            ]
+            .iter()
+            .map(|option| option.to_string())
+            .collect(),
+        };
+        let (flags, propagation, data) = parse_mount(&mount);
+        assert_eq!(flags.bits(), MsFlags::MS_NOSUID.bits() | libc::MS_LAZYTIME);
+        assert_eq!(propagation, MsFlags::MS_SLAVE | MsFlags::MS_REC);
+        assert_eq!(data, "mode=755,size=64k");
+
+        let mount = Mount {
+            options: vec!["rbind".to_owned(), "private".to_owned()],
+            ..mount
+        };
+        let (flags, propagation, data) = parse_mount(&mount);
+        assert_eq!(flags, MsFlags::MS_BIND | MsFlags::MS_REC);
+        assert_eq!(propagation, MsFlags::MS_PRIVATE);
+        assert!(data.is_empty());
+    }
+
+    #[test]
+    fn test_setup_dev_symlinks() {
+        let rootfs = std::env::temp_dir().join("test_setup_dev_symlinks");
+        let _ = fs::remove_dir_all(&rootfs);
+        fs::create_dir_all(rootfs.join("dev")).unwrap();
+        fs::write(rootfs.join("dev/ptmx"), "").unwrap();
+        symlink("/proc/self/fd/0", rootfs.join("dev/stdin")).unwrap();
+
+        setup_default_symlinks(&rootfs).unwrap();
+        setup_ptmx(&rootfs).unwrap();
+        let link = |name: &str| fs::read_link(rootfs.join("dev").join(name)).unwrap();
+        assert_eq!(link("fd"), Path::new("/proc/self/fd"));
+        assert_eq!(link("stdin"), Path::new("/proc/self/fd/0"));
+        assert_eq!(link("stderr"), Path::new("/proc/self/fd/2"));
+        assert_eq!(link("ptmx"), Path::new("pts/ptmx"));
+        fs::remove_dir_all(&rootfs).unwrap();
+    }
+
+    #[test]
+    fn test_hierarchy_dir() {
+        let controllers = |names: &[&str]| names.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(hierarchy_dir(&controllers(&["memory"])), "memory");
+        assert_eq!(
+            hierarchy_dir(&controllers(&["cpu", "cpuacct"])),
+            "cpu,cpuacct"
+        );
+        assert_eq!(hierarchy_dir(&controllers(&["name=systemd"])), "systemd");
+    }
+
+    #[test]
+    fn test_create_mount_point() {
+        let tmp = std::env::temp_dir().join("test_create_mount_point");
+        let _ = fs::remove_dir_all(&tmp);
+        fs::create_dir_all(&tmp).unwrap();
+
+        create_mount_point(&tmp.join("etc/resolv.conf"), false).unwrap();
+        assert!(tmp.join("etc/resolv.conf").is_file());
+        create_mount_point(&tmp.join("data/dir"), true).unwrap();
+        assert!(tmp.join("data/dir").is_dir());
+        // an existing file is kept as it is
+        fs::write(tmp.join("etc/hosts"), "127.0.0.1 localhost").unwrap();
+        create_mount_point(&tmp.join("etc/hosts"), false).unwrap();
+        assert_eq!(
+            fs::read_to_string(tmp.join("etc/hosts")).unwrap(),
+            "127.0.0.1 localhost"
+        );
+        fs::remove_dir_all(&tmp).unwrap();
+    }
+
+    #[test]
+    fn test_copy_dir_contents() {
+        let tmp = std::env::temp_dir().join("test_copy_dir_contents");
+        let _ = fs::remove_dir_all(&tmp);
+        let (from, to) = (tmp.join("from"), tmp.join("to"));
+        fs::create_dir_all(from.join("dir")).unwrap();
+        fs::create_dir_all(&to).unwrap();
+        fs::write(from.join("dir/file"), "content").unwrap();
+        fs::set_permissions(from.join("dir"), fs::Permissions::from_mode(0o750)).unwrap();
+        symlink("dir/file", from.join("link")).unwrap();
+
+        copy_dir_contents(&from, &to).unwrap();
+        assert_eq!(fs::read_to_string(to.join("dir/file")).unwrap(), "content");
+        let mode = fs::metadata(to.join("dir")).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o750);
+        assert_eq!(
+            fs::read_link(to.join("link")).unwrap(),
+            Path::new("dir/file")
+        );
+        fs::remove_dir_all(&tmp).unwrap();
+    }
+
+    #[test]
+    fn test_locked_flags() {
+        assert_eq!(
+            locked_flags(FsFlags::ST_RDONLY | FsFlags::ST_NOSUID | FsFlags::ST_NODEV),
+            MsFlags::MS_NOSUID | MsFlags::MS_NODEV
+        );
+        assert_eq!(
+            locked_flags(FsFlags::ST_NOEXEC | FsFlags::ST_RELATIME),
+            MsFlags::MS_NOEXEC | MsFlags::MS_RELATIME
+        );
+        assert_eq!(locked_flags(FsFlags::empty()), MsFlags::empty());
+    }
+
+    #[test]
+    fn test_shared_host_namespace() {
+        let mut spec: Spec = serde_json::from_str(
+            r#"{
+                "ociVersion": "1.0.2",
+                "root": { "path": "rootfs" },
+                "process": { "user": { "uid": 0, "gid": 0 }, "args": ["sh"], "cwd": "/" },
+                "mounts": [
+                    { "destination": "/proc", "type": "proc", "source": "proc" },
+                    { "destination": "/sys", "type": "sysfs", "source": "sysfs" },
+                    { "destination": "/tmp", "type": "tmpfs", "source": "tmpfs" }
+                ],
+                "linux": { "namespaces": [{ "type": "pid" }, { "type": "mount" }] }
+            }"#,
+        )
+        .expect("parse spec");
+        let shared: Vec<_> = spec
+            .mounts
+            .iter()
+            .map(|m| shared_host_namespace(&spec, m))
+            .collect();
+        assert_eq!(shared, vec![None, Some("network"), None]);
+        assert_eq!(unsupported(&spec).len(), 1);
+
+        spec.linux = None;
+        assert_eq!(shared_host_namespace(&spec, &spec.mounts[0]), Some("pid"));
+    }
+
+    #[test]
+    fn test_resolve_relative_root_path() {
+        let bundle = setup_bundle("test_resolve_relative_root_path");
+        let rootfs = resolve_root_path(&bundle, Path::new("rootfs")).expect("resolve root path");
+        assert_eq!(rootfs, canonicalize(bundle.join("rootfs")).unwrap());
+        fs::remove_dir_all(&bundle).unwrap();
+    }
+
+    #[test]
+    fn test_resolve_absolute_root_path() {
+        let bundle = setup_bundle("test_resolve_absolute_root_path");
+        let outside = std::env::temp_dir();
+        let rootfs = resolve_root_path(&bundle, &outside).expect("resolve root path");
+        assert_eq!(rootfs, canonicalize(outside).unwrap());
+        fs::remove_dir_all(&bundle).unwrap();
+    }
+
+    #[test]
+    fn test_resolve_root_path_escaping_bundle() {
+        let bundle = setup_bundle("test_resolve_root_path_escaping_bundle");
+        symlink(std::env::temp_dir(), bundle.join("escape")).unwrap();
+        assert!(resolve_root_path(&bundle, Path::new("escape")).is_err());
+        assert!(resolve_root_path(&bundle, Path::new("../")).is_err());
+        fs::remove_dir_all(&bundle).unwrap();
+    }
+
+    #[test]
+    fn test_resolve_root_path_symlink() {
+        let bundle = setup_bundle("test_resolve_root_path_symlink");
+        symlink("rootfs", bundle.join("link")).unwrap();
+        assert!(resolve_root_path(&bundle, Path::new("link")).is_err());
+        fs::remove_dir_all(&bundle).unwrap();
+    }
+
+    #[test]
+    fn test_is_trusted_owner() {
+        let mappings = [LinuxIdMapping {
+            host_id: 100000,
+            container_id: 0,
+            size: 65536,
+        }];
+        assert!(is_trusted_owner(0, 1000, &[]));
+        assert!(is_trusted_owner(1000, 1000, &[]));
+        assert!(is_trusted_owner(100000, 1000, &mappings));
+        assert!(is_trusted_owner(165535, 1000, &mappings));
+        assert!(!is_trusted_owner(165536, 1000, &mappings));
+        assert!(!is_trusted_owner(1001, 1000, &mappings));
+    }
+
+    #[test]
+    fn test_check_world_writable_root() {
+        let bundle = setup_bundle("test_check_world_writable_root");
+        let rootfs = bundle.join("rootfs");
+        let mut spec: Spec = serde_json::from_str(
+            r#"{
+                "ociVersion": "1.0.2",
+                "root": { "path": "rootfs" },
+                "process": { "user": { "uid": 0, "gid": 0 }, "args": ["sh"], "cwd": "/" }
+            }"#,
+        )
+        .expect("parse spec");
+        spec.root.readonly = true;
+
+        fs::set_permissions(&rootfs, fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(check_root(&rootfs, &spec).is_ok());
+        fs::set_permissions(&rootfs, fs::Permissions::from_mode(0o777)).unwrap();
+        assert!(check_root(&rootfs, &spec).is_err());
+        fs::remove_dir_all(&bundle).unwrap();
+    }
+
+    #[test]
+    fn test_resolve_root_path_not_directory() {
+        let bundle = setup_bundle("test_resolve_root_path_not_directory");
+        fs::write(bundle.join("file"), "").unwrap();
+        assert!(resolve_root_path(&bundle, Path::new("file")).is_err());
+        assert!(resolve_root_path(&bundle, Path::new("missing")).is_err());
+        fs::remove_dir_all(&bundle).unwrap();
+    }
 }
@@ -0,0 +1,659 @@
+//! Containers of an unprivileged user. The user namespace is created first, and the
+//! mappings of its ids are written by the parent process, as only the owner of the user
+//! namespace outside of it may write them.
+//!
+//! The preconditions are checked up front, otherwise create fails deep inside with a bare
+//! EPERM. Every one which is missing is reported along with how to fix it.
+
+use std::fs;
+use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{bail, Context, Result};
+use nix::unistd::{self, AccessFlags, Pid, User};
+use oci_spec::{Linux, LinuxIdMapping, LinuxNamespaceType, Spec};
+use procfs::process::Process;
+
+use crate::cgroups;
+use crate::utils::PathBufExt;
+
+const MAX_USER_NAMESPACES: &str = "/proc/sys/user/max_user_namespaces";
+const UNPRIVILEGED_USERNS_CLONE: &str = "/proc/sys/kernel/unprivileged_userns_clone";
+
+/// Precondition which is not met, along with how to fix it
+#[derive(Debug, PartialEq)]
+struct Missing {
+    precondition: String,
+    fix: String,
+}
+
+impl Missing {
+    fn new(precondition: &str, fix: &str) -> Self {
+        Self {
+            precondition: precondition.to_owned(),
+            fix: fix.to_owned(),
+        }
+    }
+}
+
+/// Whether youki runs as an unprivileged user
+pub fn is_rootless() -> bool {
+    !unistd::geteuid().is_root()
+}
+
+/// Writes the uid and gid mappings of the user namespace which the process has created
+pub fn write_id_mappings(pid: Pid, linux: &Linux) -> Result<()> {
+    let proc_dir = PathBuf::from(format!("/proc/{}", pid));
+    if !is_rootless() {
+        write_mappings(&proc_dir.join("uid_map"), &linux.uid_mappings)?;
+        return write_mappings(&proc_dir.join("gid_map"), &linux.gid_mappings);
+    }
+
+    if needs_helper(&linux.uid_mappings, unistd::geteuid().as_raw()) {
+        run_helper("newuidmap", pid, &linux.uid_mappings)?;
+    } else {
+        write_mappings(&proc_dir.join("uid_map"), &linux.uid_mappings)?;
+    }
+    if needs_helper(&linux.gid_mappings, unistd::getegid().as_raw()) {
+        run_helper("newgidmap", pid, &linux.gid_mappings)
+    } else {
+        // an unprivileged user may only map gids once setgroups is denied, otherwise the
+        // container could drop supplementary groups which deny it access
+        if !linux.gid_mappings.is_empty() {
+            fs::write(proc_dir.join("setgroups"), "deny")
+                .with_context(|| format!("failed to deny setgroups of {}", pid))?;
+        }
+        write_mappings(&proc_dir.join("gid_map"), &linux.gid_mappings)
+    }
+}
+
+/// The kernel lets an unprivileged process map nothing but its own id. The setuid
+/// helpers map the ids granted to the user in /etc/subuid and /etc/subgid.
+fn needs_helper(mappings: &[LinuxIdMapping], own_id: u32) -> bool {
+    match mappings {
+        [] => false,
+        [mapping] => !(mapping.host_id == own_id && mapping.size == 1),
+        _ => true,
+    }
+}
+
+fn run_helper(helper: &str, pid: Pid, mappings: &[LinuxIdMapping]) -> Result<()> {
+    let args = helper_args(pid, mappings);
+    log::debug!("run {} {:?}", helper, args);
+    let output = Command::new(helper)
+        .args(&args)
+        .output()
+        .with_context(|| format!("failed to run {}", helper))?;
+    if !output.status.success() {
+        bail!(
+            "{} failed with {}: {}",
+            helper,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// Arguments of newuidmap and newgidmap: the pid, then the mappings as triples
+fn helper_args(pid: Pid, mappings: &[LinuxIdMapping]) -> Vec<String> {
+    let mut args = vec![pid.to_string()];
+    for m in mappings {
+        args.push(m.container_id.to_string());
+        args.push(m.host_id.to_string());
+        args.push(m.size.to_string());
+    }
+    args
+}
+
+fn write_mappings(path: &Path, mappings: &[LinuxIdMapping]) -> Result<()> {
+    if mappings.is_empty() {
+        return Ok(());
+    }
+    log::debug!("write {:?} to {:?}", mappings, path);
+    // the kernel takes the mappings in a single write
+    fs::write(path, format_mappings(mappings))
+        .with_context(|| format!("failed to write the mappings to {:?}", path))
+}
+
+fn format_mappings(mappings: &[LinuxIdMapping]) -> String {
+    mappings
+        .iter()
+        .map(|m| format!("{} {} {}\n", m.container_id, m.host_id, m.size))
+        .collect()
+}
+
+/// Whether the cgroups of the container can be created by the user
+pub fn is_delegated(cgroups_path: &Path) -> bool {
+    check_cgroup_delegation(cgroups_path).is_empty()
+}
+
+/// Returns the cgroup v2 of a process, relative to the root of the hierarchy
+pub fn cgroup_of(pid: Pid) -> Result<PathBuf> {
+    Process::new(pid.as_raw())?
+        .cgroups()?
+        .into_iter()
+        .find(|cgroup| cgroup.hierarchy == 0)
+        .map(|cgroup| PathBuf::from(cgroup.pathname))
+        .with_context(|| format!("{} is not in the cgroup v2 hierarchy", pid))
+}
+
+/// Fails listing every precondition of a rootless container which is not met. Without a
+/// cgroups path, the container stays in the cgroup of the user.
+pub fn preflight(spec: &Spec, cgroups_path: Option<&Path>) -> Result<()> {
+    let uid = unistd::geteuid();
+    let gid = unistd::getegid();
+    let user = User::from_uid(uid)?.map(|user| user.name);
+
+    let mut missing = Vec::new();
+    let linux = spec.linux.as_ref();
+    let has_userns = linux.is_some_and(|linux| {
+        linux
+            .namespaces
+            .iter()
+            .any(|ns| matches!(ns.typ, LinuxNamespaceType::User))
+    });
+    if !has_userns {
+        missing.push(Missing::new(
+            "the container has no user namespace",
+            "add a user namespace to linux.namespaces of config.json, with uidMappings and gidMappings",
+        ));
+    }
+    missing.extend(check_user_namespaces());
+
+    if let Some(linux) = linux {
+        missing.extend(check_mappings(
+            &linux.uid_mappings,
+            uid.as_raw(),
+            user.as_deref(),
+            "/etc/subuid",
+            "newuidmap",
+        ));
+        missing.extend(check_mappings(
+            &linux.gid_mappings,
+            gid.as_raw(),
+            user.as_deref(),
+            "/etc/subgid",
+            "newgidmap",
+        ));
+        missing.extend(check_process_user(spec, linux));
+    }
+    let delegation = match cgroups_path {
+        Some(cgroups_path) => check_cgroup_delegation(cgroups_path),
+        None => Vec::new(),
+    };
+    missing.extend(check_mounts(
+        spec,
+        cgroups_path.is_some() && delegation.is_empty(),
+    ));
+    missing.extend(delegation);
+
+    if missing.is_empty() {
+        return Ok(());
+    }
+    let report: Vec<String> = missing
+        .iter()
+        .map(|m| format!("  - {}: {}", m.precondition, m.fix))
+        .collect();
+    bail!(
+        "cannot create a rootless container as {}, as preconditions are not met:\n{}",
+        user.unwrap_or_else(|| uid.to_string()),
+        report.join("\n")
+    )
+}
+
+/// The process of the container can only become a user and group which are mapped, e.g.
+/// root when nothing but the own id of the user is mapped
+fn check_process_user(spec: &Spec, linux: &Linux) -> Vec<Missing> {
+    let user = &spec.process.user;
+    let ids = [
+        ("uid", user.uid, &linux.uid_mappings, "uidMappings"),
+        ("gid", user.gid, &linux.gid_mappings, "gidMappings"),
+    ];
+    let mut missing: Vec<Missing> = ids
+        .iter()
+        .filter(|(_, id, mappings, _)| !is_mapped(*id, mappings))
+        .map(|(name, id, _, field)| {
+            Missing::new(
+                &format!(
+                    "{} {} of the process is not mapped in the container",
+                    name, id
+                ),
+                &format!(
+                    "set process.user.{} to a mapped one, or add it to linux.{} of config.json",
+                    name, field
+                ),
+            )
+        })
+        .collect();
+    for gid in &user.additional_gids {
+        if !is_mapped(*gid, &linux.gid_mappings) {
+            missing.push(Missing::new(
+                &format!(
+                    "additional gid {} of the process is not mapped in the container",
+                    gid
+                ),
+                "remove it from process.user.additionalGids, or add it to linux.gidMappings",
+            ));
+        }
+    }
+    // setgroups is denied when the gids are mapped without newgidmap
+    let denied = !linux.gid_mappings.is_empty()
+        && !needs_helper(&linux.gid_mappings, unistd::getegid().as_raw());
+    if denied && !user.additional_gids.is_empty() {
+        missing.push(Missing::new(
+            "process.user.additionalGids cannot be set, as only the own gid is mapped",
+            "map a range of /etc/subgid in linux.gidMappings, or remove the additional gids",
+        ));
+    }
+    missing
+}
+
+fn is_mapped(id: u32, mappings: &[LinuxIdMapping]) -> bool {
+    mappings
+        .iter()
+        .any(|m| m.container_id <= id && (id as u64) < m.container_id as u64 + m.size as u64)
+}
+
+fn check_user_namespaces() -> Vec<Missing> {
+    let mut missing = Vec::new();
+    if read_sysctl(MAX_USER_NAMESPACES) == Some(0) {
+        missing.push(Missing::new(
+            "user namespaces are disabled by user.max_user_namespaces",
+            "run sysctl -w user.max_user_namespaces=15000",
+        ));
+    }
+    // only some distributions have this switch, e.g. Debian
+    if read_sysctl(UNPRIVILEGED_USERNS_CLONE) == Some(0) {
+        missing.push(Missing::new(
+            "unprivileged user namespaces are disabled by kernel.unprivileged_userns_clone",
+            "run sysctl -w kernel.unprivileged_userns_clone=1",
+        ));
+    }
+    missing
+}
+
+fn read_sysctl(path: &str) -> Option<u64> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/// Mapping more than the own id of the user needs subordinate ids granted to the user,
+/// and the setuid helper to write them into the map
+fn check_mappings(
+    mappings: &[LinuxIdMapping],
+    own_id: u32,
+    user: Option<&str>,
+    subid_file: &str,
+    helper: &str,
+) -> Vec<Missing> {
+    let others: Vec<&LinuxIdMapping> = mappings
+        .iter()
+        .filter(|m| !(m.host_id == own_id && m.size == 1))
+        .collect();
+    if others.is_empty() {
+        return Vec::new();
+    }
+
+    let mut missing = Vec::new();
+    let ranges = fs::read_to_string(subid_file)
+        .map(|content| subid_ranges(&content, user, own_id))
+        .unwrap_or_default();
+    for mapping in others {
+        if !is_covered(mapping, &ranges, own_id) {
+            missing.push(Missing::new(
+                &format!(
+                    "ids {}-{} of the host are not granted to the user in {}",
+                    mapping.host_id,
+                    mapping.host_id as u64 + mapping.size as u64 - 1,
+                    subid_file
+                ),
+                &format!(
+                    "add a line like {}:{}:{} to {}, or change the mappings of config.json",
+                    user.unwrap_or("<user>"),
+                    mapping.host_id,
+                    mapping.size,
+                    subid_file
+                ),
+            ));
+        }
+    }
+
+    match find_in_path(helper) {
+        None => missing.push(Missing::new(
+            &format!("{} is not installed", helper),
+            "install the uidmap package (shadow-utils on some distributions)",
+        )),
+        Some(path) if !is_privileged_helper(&path) => missing.push(Missing::new(
+            &format!(
+                "{:?} is neither setuid root nor has file capabilities",
+                path
+            ),
+            &format!("run chmod u+s {:?}", path),
+        )),
+        Some(_) => {}
+    }
+    missing
+}
+
+/// Parses the ranges granted to the user in /etc/subuid or /etc/subgid, whose lines
+/// are like user:100000:65536, with the user given by name or id
+fn subid_ranges(content: &str, user: Option<&str>, own_id: u32) -> Vec<(u64, u64)> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.trim().split(':').collect();
+            if fields.len() != 3 {
+                return None;
+            }
+            let is_user = Some(fields[0]) == user || fields[0] == own_id.to_string();
+            if !is_user {
+                return None;
+            }
+            Some((fields[1].parse().ok()?, fields[2].parse().ok()?))
+        })
+        .collect()
+}
+
+fn is_covered(mapping: &LinuxIdMapping, ranges: &[(u64, u64)], own_id: u32) -> bool {
+    let start = mapping.host_id as u64;
+    let end = start + mapping.size as u64;
+    // the own id of the user may be mapped along with subordinate ones
+    if mapping.size == 1 && mapping.host_id == own_id {
+        return true;
+    }
+    ranges
+        .iter()
+        .any(|(first, count)| *first <= start && end <= first + count)
+}
+
+fn find_in_path(binary: &str) -> Option<PathBuf> {
+    let paths = std::env::var_os("PATH")?;
+    std::env::split_paths(&paths)
+        .map(|dir| dir.join(binary))
+        .find(|path| path.is_file())
+}
+
+fn is_privileged_helper(path: &Path) -> bool {
+    let setuid_root = fs::metadata(path)
+        .map(|metadata| metadata.permissions().mode() & 0o4000 != 0 && metadata.uid() == 0)
+        .unwrap_or(false);
+    setuid_root || has_file_capabilities(path)
+}
+
+fn has_file_capabilities(path: &Path) -> bool {
+    let path = match std::ffi::CString::new(path.to_string_lossy().as_bytes()) {
+        Ok(path) => path,
+        Err(_) => return false,
+    };
+    let name = std::ffi::CString::new("security.capability").unwrap();
+    let size = unsafe { libc::getxattr(path.as_ptr(), name.as_ptr(), std::ptr::null_mut(), 0) };
+    size > 0
+}
+
+/// Mounts which an unprivileged user cannot do even in its own user namespace
+fn check_mounts(spec: &Spec, cgroups_delegated: bool) -> Vec<Missing> {
+    let has_pidns = spec.linux.as_ref().is_some_and(|linux| {
+        linux
+            .namespaces
+            .iter()
+            .any(|ns| ns.typ == LinuxNamespaceType::Pid)
+    });
+
+    let mut missing = Vec::new();
+    for m in &spec.mounts {
+        let destination = m.destination.display();
+        let is_bind = m.typ == "bind" || m.options.iter().any(|o| o == "bind" || o == "rbind");
+        if m.typ == "proc" && !has_pidns {
+            missing.push(Missing::new(
+                &format!("{} mounts a new proc without a pid namespace", destination),
+                "add a pid namespace to linux.namespaces of config.json, or bind mount /proc instead",
+            ));
+        } else if !is_bind && is_block_device(&m.source) {
+            missing.push(Missing::new(
+                &format!(
+                    "{} mounts the block device {}, which only root can do",
+                    destination,
+                    m.source.display()
+                ),
+                "mount the device on the host and bind mount it into the container",
+            ));
+        } else if (m.typ == "cgroup" || m.typ == "cgroup2")
+            && !m.options.iter().any(|o| o == "ro")
+            && !cgroups_delegated
+        {
+            missing.push(Missing::new(
+                &format!(
+                    "{} mounts cgroups writable, but they are not delegated to the user",
+                    destination
+                ),
+                "add the ro option to the mount, or delegate the cgroup of the container",
+            ));
+        }
+    }
+    missing
+}
+
+fn is_block_device(path: &Path) -> bool {
+    fs::metadata(path)
+        .map(|metadata| metadata.file_type().is_block_device())
+        .unwrap_or(false)
+}
+
+/// The cgroups of the container can only be created in a cgroup v2 hierarchy which is
+/// delegated to the user, i.e. writable by it
+fn check_cgroup_delegation(cgroups_path: &Path) -> Vec<Missing> {
+    let mount = Process::myself()
+        .and_then(|process| process.mountinfo())
+        .ok()
+        .and_then(|mounts| mounts.into_iter().find(|m| m.fs_type == "cgroup2"));
+    let mount = match mount {
+        Some(mount) => mount,
+        None => {
+            return vec![Missing::new(
+                "cgroups cannot be delegated without the cgroup v2 hierarchy",
+                "boot the host with systemd.unified_cgroup_hierarchy=1",
+            )]
+        }
+    };
+    // the systemd instance of the user creates the scope and delegates it
+    if cgroups::systemd::is_systemd_path(cgroups_path) {
+        return Vec::new();
+    }
+
+    let full_path = if cgroups_path.is_absolute() {
+        mount.mount_point.join_absolute_path(cgroups_path)
+    } else {
+        Ok(mount.mount_point.join(cgroups_path))
+    };
+    let mut existing = match full_path {
+        Ok(path) => path,
+        Err(_) => return Vec::new(),
+    };
+    while !existing.exists() {
+        if !existing.pop() {
+            return Vec::new();
+        }
+    }
+
+    if unistd::access(&existing, AccessFlags::W_OK).is_err() {
+        return vec![Missing::new(
+            &format!("cgroup {:?} is not delegated to the user", existing),
+            "set linux.cgroupsPath to a cgroup the user owns, e.g. run youki in a scope of systemd-run --user --scope -p Delegate=yes",
+        )];
+    }
+    Vec::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapping(host_id: u32, size: u32) -> LinuxIdMapping {
+        LinuxIdMapping {
+            host_id,
+            container_id: 0,
+            size,
+        }
+    }
+
+    #[test]
+    fn test_format_mappings() {
+        let mappings = vec![
+            LinuxIdMapping {
+                host_id: 1000,
+                container_id: 0,
+                size: 1,
+            },
+            LinuxIdMapping {
+                host_id: 100000,
+                container_id: 1,
+                size: 65536,
+            },
+        ];
+        assert_eq!(format_mappings(&mappings), "0 1000 1\n1 100000 65536\n");
+        assert_eq!(format_mappings(&[]), "");
+    }
+
+    #[test]
+    fn test_needs_helper() {
+        assert!(!needs_helper(&[], 1000));
+        assert!(!needs_helper(&[mapping(1000, 1)], 1000));
+        assert!(needs_helper(&[mapping(1001, 1)], 1000));
+        assert!(needs_helper(&[mapping(1000, 2)], 1000));
+        assert!(needs_helper(
+            &[mapping(1000, 1), mapping(100000, 65536)],
+            1000
+        ));
+    }
+
+    #[test]
+    fn test_helper_args() {
+        let mappings = [
+            mapping(1000, 1),
+            LinuxIdMapping {
+                host_id: 100000,
+                container_id: 1,
+                size: 65536,
+            },
+        ];
+        assert_eq!(
+            helper_args(Pid::from_raw(42), &mappings),
+            vec!["42", "0", "1000", "1", "1", "100000", "65536"]
+        );
+    }
+
+    #[test]
+    fn test_is_mapped() {
+        let mappings = [
+            mapping(1000, 1),
+            LinuxIdMapping {
+                host_id: 100000,
+                container_id: 1,
+                size: 65536,
+            },
+        ];
+        assert!(is_mapped(0, &mappings));
+        assert!(is_mapped(65536, &mappings));
+        assert!(!is_mapped(65537, &mappings));
+        assert!(!is_mapped(1000, &mappings[..1]));
+        assert!(!is_mapped(0, &[]));
+    }
+
+    #[test]
+    fn test_subid_ranges() {
+        let content = "alice:100000:65536\n1000:300000:1000\nbob:200000:65536\ninvalid\n";
+        assert_eq!(
+            subid_ranges(content, Some("alice"), 1000),
+            vec![(100000, 65536), (300000, 1000)]
+        );
+        assert_eq!(subid_ranges(content, None, 1001), vec![]);
+    }
+
+    #[test]
+    fn test_is_covered() {
+        let ranges = vec![(100000, 65536)];
+        assert!(is_covered(&mapping(1000, 1), &ranges, 1000));
+        assert!(is_covered(&mapping(100000, 65536), &ranges, 1000));
+        assert!(!is_covered(&mapping(100000, 65537), &ranges, 1000));
+        assert!(!is_covered(&mapping(1001, 1), &ranges, 1000));
+    }
+
+    #[test]
+    fn test_check_mounts() {
+        let spec: Spec = serde_json::from_str(
+            r#"{
+                "ociVersion": "1.0.2",
+                "root": { "path": "rootfs" },
+                "process": { "user": { "uid": 0, "gid": 0 }, "args": ["sh"], "cwd": "/" },
+                "mounts": [
+                    { "destination": "/proc", "type": "proc", "source": "proc" },
+                    { "destination": "/sys/fs/cgroup", "type": "cgroup2", "source": "cgroup" },
+                    { "destination": "/cgroup-ro", "type": "cgroup2", "source": "cgroup",
+                      "options": ["ro"] }
+                ],
+                "linux": { "namespaces": [{ "type": "mount" }, { "type": "user" }] }
+            }"#,
+        )
+        .expect("parse spec");
+
+        let missing = check_mounts(&spec, false);
+        assert_eq!(missing.len(), 2);
+        assert!(missing[0].precondition.starts_with("/proc"));
+        assert!(missing[1].precondition.starts_with("/sys/fs/cgroup"));
+        assert_eq!(check_mounts(&spec, true).len(), 1);
+    }
+
+    #[test]
+    fn test_check_additional_gids() {
+        let mut spec: Spec = serde_json::from_str(
+            r#"{
+                "ociVersion": "1.0.2",
+                "root": { "path": "rootfs" },
+                "process": {
+                    "user": { "uid": 0, "gid": 0, "additionalGids": [10, 70000] },
+                    "args": ["sh"]
+                },
+                "linux": { "namespaces": [{ "type": "user" }] }
+            }"#,
+        )
+        .expect("parse spec");
+        let linux = spec.linux.as_mut().unwrap();
+        linux.uid_mappings = vec![mapping(100000, 65536)];
+        linux.gid_mappings = vec![mapping(100000, 65536)];
+        let linux = spec.linux.clone().unwrap();
+        let missing = check_process_user(&spec, &linux);
+        assert_eq!(missing.len(), 1);
+        assert!(missing[0].precondition.contains("70000"));
+
+        // only the own gid is mapped, so setgroups is denied
+        let mut linux = linux;
+        linux.gid_mappings = vec![mapping(unistd::getegid().as_raw(), 1)];
+        spec.process.user.additional_gids = vec![0];
+        let missing = check_process_user(&spec, &linux);
+        assert_eq!(missing.len(), 1);
+        assert!(missing[0].precondition.contains("additionalGids"));
+    }
+
+    #[test]
+    fn test_check_mappings_of_own_id() {
+        let missing = check_mappings(
+            &[mapping(1000, 1)],
+            1000,
+            Some("alice"),
+            "/nonexistent/subuid",
+            "newuidmap",
+        );
+        assert!(missing.is_empty());
+
+        let missing = check_mappings(
+            &[mapping(100000, 10)],
+            1000,
+            Some("alice"),
+            "/nonexistent/subuid",
+            "nonexistent-newuidmap",
+        );
+        assert_eq!(missing.len(), 2);
+        assert!(missing[0].fix.contains("alice:100000:10"));
+    }
+}
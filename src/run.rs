@@ -0,0 +1,211 @@
+//! Creates and starts a container in one go, and stays in the foreground until it exits,
+//! which is what running a container by hand needs
+
+use std::os::unix::io::RawFd;
+use std::path::{Path, PathBuf};
+use std::thread::{self, JoinHandle};
+
+use anyhow::{bail, Result};
+use clap::Clap;
+use nix::errno::Errno;
+use nix::sys::signal::{self, SigSet, SigmaskHow, Signal};
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::Pid;
+use oci_spec::LinuxNamespaceType;
+
+use crate::cgroups;
+use crate::cgroups::common::CgroupManager;
+use crate::command::Command;
+use crate::config::RuntimeConfig;
+use crate::container::Container;
+use crate::create::Create;
+use crate::process::Process;
+use crate::reaper;
+use crate::start::Start;
+use crate::stdio;
+use crate::tty::{self, RawTerminal};
+
+const STDIN: RawFd = 0;
+const STDOUT: RawFd = 1;
+
+#[derive(Clap, Debug)]
+pub struct Run {
+    /// return once the container is started instead of waiting for it to exit
+    #[clap(short, long)]
+    detach: bool,
+    #[clap(flatten)]
+    pub create: Create,
+}
+
+/// How run ended, which decides whether the container is deleted
+pub enum Outcome {
+    /// The container is left running, or this is one of the processes of the container
+    Running,
+    /// The container has exited with the code
+    Exited(i32),
+    /// The container was created but could not be started, and has been stopped
+    NotStarted(anyhow::Error),
+}
+
+impl Run {
+    pub fn exec(
+        &self,
+        root_path: PathBuf,
+        config: &RuntimeConfig,
+        command: impl Command,
+    ) -> Result<Outcome> {
+        let spec = oci_spec::Spec::load(&self.create.bundle.join("config.json").to_string_lossy())?;
+        // without a console socket, the terminal of the container is attached to run
+        let console = if spec.process.terminal && self.create.console_socket.is_none() {
+            if self.detach {
+                bail!("run --detach needs --console-socket for a terminal, nothing would attach to it");
+            }
+            Some(tty::ConsoleListener::new(&self.create.container_id)?)
+        } else {
+            None
+        };
+        let mut create = self.create.clone();
+        if let Some(console) = &console {
+            create.console_socket = Some(console.path().display().to_string());
+        }
+
+        // the process of the container shares the stdio of run, as it is forked from it
+        let monitor = match create.create(root_path.clone(), config, command)? {
+            Process::Parent(parent) => parent.child_pid(),
+            _ => return Ok(Outcome::Running),
+        };
+        // the signals are handled synchronously by sigwait, the container has its own mask
+        // already. Those which arrive while the container is started are forwarded once it
+        // is, instead of leaving it behind as run dies. They are blocked before the threads
+        // copying the terminal inherit the mask.
+        if !self.detach {
+            signal::sigprocmask(SigmaskHow::SIG_BLOCK, Some(&SigSet::all()), None)?;
+        }
+        let container_root = root_path.join(&self.create.container_id);
+        let terminal = match console.map(|console| console.receive()).transpose() {
+            Ok(terminal) => terminal,
+            Err(err) => {
+                stop(&container_root, monitor)?;
+                return Ok(Outcome::NotStarted(err));
+            }
+        };
+
+        let start = Start {
+            container_id: self.create.container_id.clone(),
+        };
+        if let Err(err) = start.exec(root_path, &config.timeouts) {
+            stop(&container_root, monitor)?;
+            return Ok(Outcome::NotStarted(err));
+        }
+        if self.detach {
+            return Ok(Outcome::Running);
+        }
+        // restores the terminal of run when it is dropped
+        let (_raw, output) = match terminal {
+            Some(master) => {
+                let (raw, output) = attach(master)?;
+                (raw, Some(output))
+            }
+            None => (None, None),
+        };
+
+        let container = Container::load(container_root.clone())?;
+        let init_pid = match container.pid() {
+            Some(pid) => pid,
+            None => bail!("{} has no init process", container.id()),
+        };
+        // without a pid namespace of its own, the other processes of the container do not
+        // go along with its init process, they get the signals as well
+        let own_pid_ns = spec.linux.as_ref().is_some_and(|linux| {
+            linux
+                .namespaces
+                .iter()
+                .any(|ns| ns.typ == LinuxNamespaceType::Pid && ns.path.is_none())
+        });
+        let cmanager = if own_pid_ns || container.state.shared_cgroups_path.is_some() {
+            None
+        } else {
+            Some(cgroups::common::create_cgroup_manager(
+                container.cgroups_path()?,
+            )?)
+        };
+        let status = forward_signals(init_pid, monitor, terminal, cmanager.as_deref())?;
+        // the output is drained once every process of the container has closed the terminal
+        if let Some(output) = output {
+            let _ = output.join();
+        }
+        // the monitor exits with the code of the init process as well, the state tells it
+        // even if the monitor was killed itself
+        let code = Container::load(container_root)
+            .ok()
+            .and_then(|container| container.state.exit_status)
+            .map_or_else(|| reaper::exit_code(status), |exit| exit.code);
+        Ok(Outcome::Exited(code))
+    }
+}
+
+/// Kills the container which could not be started, and waits for its monitor
+fn stop(container_root: &Path, monitor: Pid) -> Result<()> {
+    if let Some(pid) = Container::load(container_root.to_path_buf())?.pid() {
+        let _ = signal::kill(pid, Signal::SIGKILL);
+    }
+    let _ = waitpid(monitor, None);
+    Ok(())
+}
+
+/// Attaches the terminal of the container to the stdio of run, which is put into raw mode
+/// if it is a terminal. Returns it along with the thread which copies the output.
+fn attach(master: RawFd) -> Result<(Option<RawTerminal>, JoinHandle<Result<u64>>)> {
+    let raw = RawTerminal::new(STDIN)?;
+    if raw.is_some() {
+        tty::copy_size(STDIN, master)?;
+    }
+    thread::spawn(move || stdio::forward_stdin(STDIN, master, true));
+    let output = thread::spawn(move || stdio::drain(master, STDOUT));
+    Ok((raw, output))
+}
+
+/// Forwards the signals which run receives to the init process of the container, or to every
+/// process in the cgroups given, until the monitor, i.e. the process which waits for the init
+/// process, exits. The signals have to be blocked already. A resize of the terminal of run is
+/// passed on to the one of the container instead.
+fn forward_signals(
+    init_pid: Pid,
+    monitor: Pid,
+    terminal: Option<RawFd>,
+    cmanager: Option<&dyn CgroupManager>,
+) -> Result<WaitStatus> {
+    loop {
+        // the container may have exited before the signals were blocked
+        match waitpid(monitor, Some(WaitPidFlag::WNOHANG))? {
+            WaitStatus::StillAlive => {}
+            status => return Ok(status),
+        }
+        match (SigSet::all().wait()?, terminal) {
+            (Signal::SIGCHLD, _) => {}
+            (Signal::SIGWINCH, Some(master)) => {
+                if let Err(e) = tty::copy_size(STDIN, master) {
+                    log::warn!("failed to resize the terminal of the container: {}", e);
+                }
+            }
+            (sig, _) => {
+                let pids = match cmanager.map(|cmanager| cmanager.get_all_pids()) {
+                    Some(Ok(pids)) => pids,
+                    Some(Err(e)) => {
+                        log::warn!("failed to list the processes of the container: {}", e);
+                        vec![init_pid]
+                    }
+                    None => vec![init_pid],
+                };
+                for pid in pids {
+                    log::debug!("forward {} to {}", sig, pid);
+                    // the process may have exited in between
+                    match signal::kill(pid, sig) {
+                        Ok(()) | Err(nix::Error::Sys(Errno::ESRCH)) => {}
+                        Err(e) => log::warn!("failed to forward {} to {}: {}", sig, pid, e),
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,223 @@
+//! Sets the scheduling policy of the container process with sched_setattr(2), e.g. to run
+//! real-time or deadline-scheduled workloads. The attributes are kept across exec.
+//!
+//! The container can also get a core scheduling cookie of its own, so that its threads never
+//! run on the SMT siblings of a core at the same time as threads of other containers.
+
+use std::mem;
+
+use anyhow::{bail, Result};
+use nix::errno::Errno;
+use oci_spec::{LinuxSchedulerFlag, LinuxSchedulerPolicy, Scheduler};
+
+/// Annotation which gives the container a core scheduling cookie of its own if "true".
+/// The OCI spec has no field for it.
+pub const SCHED_CORE_ANNOTATION: &str = "org.youki.sched-core";
+
+const PR_SCHED_CORE: libc::c_int = 62;
+const PR_SCHED_CORE_CREATE: libc::c_ulong = 1;
+/// Scope of the cookie, which is the whole thread group
+const PIDTYPE_TGID: libc::c_ulong = 1;
+
+/// struct sched_attr of the kernel, in its first version without utilization clamping
+#[repr(C)]
+#[derive(Debug, PartialEq)]
+struct SchedAttr {
+    size: u32,
+    sched_policy: u32,
+    sched_flags: u64,
+    sched_nice: i32,
+    sched_priority: u32,
+    sched_runtime: u64,
+    sched_deadline: u64,
+    sched_period: u64,
+}
+
+/// Checks the attributes as far as the kernel does, so that a mistake is reported at create
+/// instead of by the init process
+pub fn validate(scheduler: &Scheduler) -> Result<()> {
+    if !(-20..=19).contains(&scheduler.nice) {
+        bail!(
+            "scheduler nice {} is not between -20 and 19",
+            scheduler.nice
+        )
+    }
+
+    // struct sched_attr is passed without the utilization values, which the spec has no
+    // fields for, and the kernel rejects the flags without them
+    let clamps = [
+        LinuxSchedulerFlag::SchedFlagUtilClampMin,
+        LinuxSchedulerFlag::SchedFlagUtilClampMax,
+    ];
+    if let Some(flag) = scheduler.flags.iter().find(|flag| clamps.contains(flag)) {
+        bail!(
+            "scheduler flag {:?} needs a utilization value, which the spec has no field for",
+            flag
+        )
+    }
+
+    let real_time = matches!(
+        scheduler.policy,
+        LinuxSchedulerPolicy::SchedFifo | LinuxSchedulerPolicy::SchedRr
+    );
+    if real_time && !(1..=99).contains(&scheduler.priority) {
+        bail!(
+            "scheduler priority {} of {:?} is not between 1 and 99",
+            scheduler.priority,
+            scheduler.policy
+        )
+    }
+    if !real_time && scheduler.priority != 0 {
+        bail!(
+            "scheduler priority is only for SCHED_FIFO and SCHED_RR, not {:?}",
+            scheduler.policy
+        )
+    }
+
+    let (runtime, deadline, period) = (scheduler.runtime, scheduler.deadline, scheduler.period);
+    if scheduler.policy != LinuxSchedulerPolicy::SchedDeadline {
+        if runtime != 0 || deadline != 0 || period != 0 {
+            bail!(
+                "scheduler runtime, deadline and period are only for SCHED_DEADLINE, not {:?}",
+                scheduler.policy
+            )
+        }
+        return Ok(());
+    }
+    // a period of 0 is taken to be the deadline
+    if runtime == 0 || runtime > deadline || (period != 0 && deadline > period) {
+        bail!(
+            "SCHED_DEADLINE needs 0 < runtime <= deadline <= period, got {}, {} and {}",
+            runtime,
+            deadline,
+            period
+        )
+    }
+    Ok(())
+}
+
+/// Sets the policy and attributes of the current process
+pub fn apply(scheduler: &Scheduler) -> Result<()> {
+    let attr = sched_attr(scheduler);
+    let res = unsafe { libc::syscall(libc::SYS_sched_setattr, 0, &attr as *const SchedAttr, 0) };
+    Errno::result(res)?;
+    Ok(())
+}
+
+/// Parses the value of the core scheduling annotation
+pub fn parse_sched_core(value: &str) -> Result<bool> {
+    match value {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => bail!(
+            "{} must be true or false, not {}",
+            SCHED_CORE_ANNOTATION,
+            value
+        ),
+    }
+}
+
+/// Creates a new core scheduling cookie for the current process, which the processes it
+/// forks and executes inherit. This needs Linux 5.14 built with CONFIG_SCHED_CORE.
+pub fn create_core_cookie() -> Result<()> {
+    let res = unsafe {
+        libc::prctl(
+            PR_SCHED_CORE,
+            PR_SCHED_CORE_CREATE,
+            0 as libc::c_ulong,
+            PIDTYPE_TGID,
+            0 as libc::c_ulong,
+        )
+    };
+    match Errno::result(res) {
+        Ok(_) => Ok(()),
+        // without SMT no other thread can share the core in the first place
+        Err(nix::Error::Sys(Errno::ENODEV)) => {
+            log::debug!("SMT is not enabled, core scheduling is not needed");
+            Ok(())
+        }
+        Err(nix::Error::Sys(Errno::EINVAL)) => {
+            bail!("core scheduling is not supported by the kernel, which needs CONFIG_SCHED_CORE")
+        }
+        Err(e) => bail!("failed to create a core scheduling cookie: {}", e),
+    }
+}
+
+fn sched_attr(scheduler: &Scheduler) -> SchedAttr {
+    SchedAttr {
+        size: mem::size_of::<SchedAttr>() as u32,
+        sched_policy: scheduler.policy as u32,
+        sched_flags: LinuxSchedulerFlag::to_mask(&scheduler.flags),
+        sched_nice: scheduler.nice,
+        sched_priority: scheduler.priority as u32,
+        sched_runtime: scheduler.runtime,
+        sched_deadline: scheduler.deadline,
+        sched_period: scheduler.period,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scheduler(value: serde_json::Value) -> Scheduler {
+        serde_json::from_value(value).expect("parse scheduler")
+    }
+
+    #[test]
+    fn test_validate() {
+        let valid = [
+            serde_json::json!({ "policy": "SCHED_OTHER", "nice": -5 }),
+            serde_json::json!({ "policy": "SCHED_FIFO", "priority": 50 }),
+            serde_json::json!({ "policy": "SCHED_DEADLINE", "runtime": 10000, "deadline": 20000 }),
+            serde_json::json!({ "policy": "SCHED_DEADLINE", "runtime": 10000, "deadline": 20000, "period": 30000 }),
+        ];
+        for value in valid.iter() {
+            assert!(validate(&scheduler(value.clone())).is_ok(), "{}", value);
+        }
+
+        let invalid = [
+            serde_json::json!({ "policy": "SCHED_OTHER", "nice": 20 }),
+            serde_json::json!({ "policy": "SCHED_RR" }),
+            serde_json::json!({ "policy": "SCHED_OTHER", "flags": ["SCHED_FLAG_UTIL_CLAMP_MIN"] }),
+            serde_json::json!({ "policy": "SCHED_BATCH", "priority": 10 }),
+            serde_json::json!({ "policy": "SCHED_IDLE", "runtime": 10000 }),
+            serde_json::json!({ "policy": "SCHED_DEADLINE", "runtime": 30000, "deadline": 20000 }),
+            serde_json::json!({ "policy": "SCHED_DEADLINE", "runtime": 10000, "deadline": 20000, "period": 15000 }),
+        ];
+        for value in invalid.iter() {
+            assert!(validate(&scheduler(value.clone())).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn test_parse_sched_core() {
+        assert!(parse_sched_core("true").unwrap());
+        assert!(!parse_sched_core("false").unwrap());
+        assert!(parse_sched_core("yes").is_err());
+    }
+
+    #[test]
+    fn test_sched_attr() {
+        let attr = sched_attr(&scheduler(serde_json::json!({
+            "policy": "SCHED_DEADLINE",
+            "flags": ["SCHED_FLAG_RESET_ON_FORK", "SCHED_FLAG_DL_OVERRUN"],
+            "runtime": 10000,
+            "deadline": 20000,
+            "period": 30000
+        })));
+        assert_eq!(
+            attr,
+            SchedAttr {
+                size: 48,
+                sched_policy: 6,
+                sched_flags: 0x05,
+                sched_nice: 0,
+                sched_priority: 0,
+                sched_runtime: 10000,
+                sched_deadline: 20000,
+                sched_period: 30000,
+            }
+        );
+    }
+}
@@ -0,0 +1,713 @@
+//! Filters the syscalls of the container process with seccomp. The rules of linux.seccomp
+//! are compiled into a BPF program, which the kernel runs on every syscall of the process
+//! and of the programs it executes. Syscalls with SCMP_ACT_NOTIFY are handed to an agent
+//! outside of the container, which gets the listener of the filter on linux.seccomp.listenerPath.
+
+mod syscalls;
+
+use std::io::Write;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use nix::errno::Errno;
+use nix::sys::socket::{self, ControlMessage, MsgFlags};
+use nix::sys::uio::IoVec;
+use oci_spec::{Arch, LinuxSeccomp, LinuxSeccompArg, LinuxSeccompFlag, LinuxSeccompOperator};
+use serde::Serialize;
+
+use crate::container::OciState;
+
+const SECCOMP_SET_MODE_FILTER: libc::c_uint = 1;
+/// Name of the listener in the fds of the state sent to the agent
+const SECCOMP_FD_NAME: &str = "seccompFd";
+const SECCOMP_RET_KILL_PROCESS: u32 = 0x80000000;
+/// Syscalls of x32 are numbered from this on, with the architecture of x86_64
+const X32_SYSCALL_BIT: u32 = 0x40000000;
+/// Maximum length of a BPF program
+const BPF_MAXINSNS: usize = 4096;
+
+const BPF_LD: u16 = 0x00;
+const BPF_W: u16 = 0x00;
+const BPF_ABS: u16 = 0x20;
+const BPF_ALU: u16 = 0x04;
+const BPF_AND: u16 = 0x50;
+const BPF_JMP: u16 = 0x05;
+const BPF_JEQ: u16 = 0x10;
+const BPF_JGT: u16 = 0x20;
+const BPF_JGE: u16 = 0x30;
+const BPF_K: u16 = 0x00;
+const BPF_RET: u16 = 0x06;
+
+/// Offsets in struct seccomp_data, which the program loads from
+const OFFSET_NR: u32 = 0;
+const OFFSET_ARCH: u32 = 4;
+const OFFSET_ARGS: u32 = 16;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct SockFilter {
+    code: u16,
+    jt: u8,
+    jf: u8,
+    k: u32,
+}
+
+#[repr(C)]
+struct SockFprog {
+    len: libc::c_ushort,
+    filter: *const SockFilter,
+}
+
+/// Loads the filter of the spec into the current process. Unless no_new_privileges is
+/// set, this needs CAP_SYS_ADMIN. Returns the listener of the filter if it has rules with
+/// SCMP_ACT_NOTIFY.
+pub fn load(seccomp: &LinuxSeccomp) -> Result<Option<RawFd>> {
+    let filter = compile(seccomp, Arch::native() as u32)?;
+    let prog = SockFprog {
+        len: filter.len() as libc::c_ushort,
+        filter: filter.as_ptr(),
+    };
+    let flags = seccomp.filter_flags();
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_seccomp,
+            SECCOMP_SET_MODE_FILTER,
+            flags,
+            &prog as *const SockFprog,
+        )
+    };
+    let res = Errno::result(res).context("failed to load the seccomp filter")?;
+    if flags & LinuxSeccompFlag::SeccompFilterFlagNewListener as u32 != 0 {
+        Ok(Some(res as RawFd))
+    } else {
+        Ok(None)
+    }
+}
+
+/// What the agent on linux.seccomp.listenerPath receives along with the listener
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ContainerProcessState<'a> {
+    oci_version: &'a str,
+    /// names of the file descriptors passed with the state, in their order
+    fds: Vec<&'a str>,
+    pid: i32,
+    metadata: &'a str,
+    state: &'a OciState,
+}
+
+/// Passes the listener of the filter of the init process to the agent listening on the
+/// socket, along with the state of the container as the runtime spec describes
+pub fn send_listener(
+    listener_path: &Path,
+    metadata: Option<&str>,
+    fd: RawFd,
+    pid: i32,
+    state: &OciState,
+) -> Result<()> {
+    let process_state = ContainerProcessState {
+        oci_version: &state.oci_version,
+        fds: vec![SECCOMP_FD_NAME],
+        pid,
+        metadata: metadata.unwrap_or_default(),
+        state,
+    };
+    let data = serde_json::to_vec(&process_state)?;
+    let mut stream = UnixStream::connect(listener_path).with_context(|| {
+        format!(
+            "failed to connect to the seccomp agent on {:?}",
+            listener_path
+        )
+    })?;
+    let fds = [fd];
+    let sent = socket::sendmsg(
+        stream.as_raw_fd(),
+        &[IoVec::from_slice(&data)],
+        &[ControlMessage::ScmRights(&fds)],
+        MsgFlags::empty(),
+        None,
+    )
+    .context("failed to send the seccomp listener")?;
+    stream.write_all(&data[sent..])?;
+    Ok(())
+}
+
+/// Whether the syscalls of the architecture are filtered. Only those of the architecture
+/// youki is built for are, the other ones of the spec get the default action.
+pub fn is_filtered(arch: Arch) -> bool {
+    arch as u32 == Arch::ScmpArchNative as u32 || arch as u32 == Arch::native() as u32
+}
+
+/// Looks up the number of a syscall of the architecture youki is built for
+fn syscall_number(name: &str) -> Option<u32> {
+    syscalls::SYSCALLS
+        .binary_search_by(|(syscall, _)| syscall.cmp(&name))
+        .ok()
+        .map(|i| syscalls::SYSCALLS[i].1)
+}
+
+/// A syscall with the comparisons of its arguments which all have to hold for the action
+struct Rule<'a> {
+    nr: u32,
+    args: Vec<&'a LinuxSeccompArg>,
+    ret: u32,
+}
+
+/// Target of a jump in a rule: the next instructions, or the rule after it, which is where
+/// a comparison which does not hold continues
+#[derive(Clone, Copy)]
+enum Target {
+    Skip(u8),
+    NextRule,
+}
+
+struct Insn {
+    code: u16,
+    jt: Target,
+    jf: Target,
+    k: u32,
+}
+
+impl Insn {
+    fn stmt(code: u16, k: u32) -> Self {
+        Self {
+            code,
+            jt: Target::Skip(0),
+            jf: Target::Skip(0),
+            k,
+        }
+    }
+
+    fn jump(code: u16, k: u32, jt: Target, jf: Target) -> Self {
+        Self { code, jt, jf, k }
+    }
+}
+
+fn stmt(code: u16, k: u32) -> SockFilter {
+    SockFilter {
+        code,
+        jt: 0,
+        jf: 0,
+        k,
+    }
+}
+
+fn jump(code: u16, k: u32, jt: u8, jf: u8) -> SockFilter {
+    SockFilter { code, jt, jf, k }
+}
+
+/// Compiles the filter for the architecture. Each rule checks the syscall number and
+/// its arguments and returns its action, the first rule which matches wins. Syscalls of
+/// the other architectures of the spec get the default action, as there are no numbers
+/// of their syscalls to filter them by, and those of any architecture not in the spec
+/// kill the process.
+fn compile(seccomp: &LinuxSeccomp, arch: u32) -> Result<Vec<SockFilter>> {
+    if syscalls::SYSCALLS.is_empty() {
+        bail!("seccomp is not supported on this architecture yet");
+    }
+    let default_ret = seccomp.default_action.to_ret(seccomp.default_errno_ret);
+    let listed = |other: Arch| {
+        seccomp
+            .architectures
+            .iter()
+            .any(|listed| *listed as u32 == other as u32)
+    };
+    // x32 has the architecture of x86_64 and is told apart by the syscall number
+    let mut others: Vec<u32> = seccomp
+        .architectures
+        .iter()
+        .map(|other| *other as u32)
+        .filter(|other| {
+            *other != arch
+                && *other != Arch::ScmpArchNative as u32
+                && *other != Arch::ScmpArchX32 as u32
+        })
+        .collect();
+    others.sort_unstable();
+    others.dedup();
+    if others.len() > u8::MAX as usize - 2 {
+        bail!("too many seccomp architectures: {}", others.len());
+    }
+
+    // the native architecture jumps over the others to its rules, the others jump to the
+    // default action after the one which kills the process
+    let n = others.len() as u8;
+    let mut filter = vec![
+        stmt(BPF_LD | BPF_W | BPF_ABS, OFFSET_ARCH),
+        jump(BPF_JMP | BPF_JEQ | BPF_K, arch, n + 1 + (n > 0) as u8, 0),
+    ];
+    for (i, other) in others.iter().enumerate() {
+        filter.push(jump(BPF_JMP | BPF_JEQ | BPF_K, *other, n - i as u8, 0));
+    }
+    filter.push(stmt(BPF_RET | BPF_K, SECCOMP_RET_KILL_PROCESS));
+    if n > 0 {
+        filter.push(stmt(BPF_RET | BPF_K, default_ret));
+    }
+    filter.push(stmt(BPF_LD | BPF_W | BPF_ABS, OFFSET_NR));
+    if arch == Arch::ScmpArchX86_64 as u32 {
+        let x32_ret = if listed(Arch::ScmpArchX32) {
+            default_ret
+        } else {
+            SECCOMP_RET_KILL_PROCESS
+        };
+        filter.push(jump(BPF_JMP | BPF_JGE | BPF_K, X32_SYSCALL_BIT, 0, 1));
+        filter.push(stmt(BPF_RET | BPF_K, x32_ret));
+    }
+    let mut nr_loaded = true;
+    for rule in rules(seccomp, default_ret)? {
+        let block = compile_rule(&rule, nr_loaded)?;
+        filter.extend(block);
+        nr_loaded = rule.args.is_empty();
+    }
+    filter.push(stmt(BPF_RET | BPF_K, default_ret));
+
+    if filter.len() > BPF_MAXINSNS {
+        bail!(
+            "the seccomp filter has {} instructions, more than the kernel allows",
+            filter.len()
+        );
+    }
+    Ok(filter)
+}
+
+/// Rules of the spec by syscall number. Rules with the action of the default are left out,
+/// and conditions on the same argument are alternatives, as they are in runc.
+fn rules(seccomp: &LinuxSeccomp, default_ret: u32) -> Result<Vec<Rule<'_>>> {
+    let mut rules = Vec::new();
+    for syscall in &seccomp.syscalls {
+        let ret = syscall.action.to_ret(syscall.errno_ret);
+        if ret == default_ret {
+            continue;
+        }
+        if let Some(arg) = syscall.args.iter().find(|arg| arg.index > 5) {
+            bail!(
+                "argument {} of {:?} does not exist, a syscall has six",
+                arg.index,
+                syscall.names
+            );
+        }
+        let repeated = syscall.args.iter().enumerate().any(|(i, arg)| {
+            syscall.args[..i]
+                .iter()
+                .any(|other| other.index == arg.index)
+        });
+        let conditions: Vec<Vec<&LinuxSeccompArg>> = if repeated {
+            syscall.args.iter().map(|arg| vec![arg]).collect()
+        } else {
+            vec![syscall.args.iter().collect()]
+        };
+
+        for name in &syscall.names {
+            let nr = match syscall_number(name) {
+                Some(nr) => nr,
+                None => {
+                    log::warn!("syscall {} is unknown, its seccomp rule is ignored", name);
+                    continue;
+                }
+            };
+            for args in &conditions {
+                rules.push(Rule {
+                    nr,
+                    args: args.clone(),
+                    ret,
+                });
+            }
+        }
+    }
+    Ok(rules)
+}
+
+/// Compiles a rule into instructions which return its action if it matches, and otherwise
+/// continue after them
+fn compile_rule(rule: &Rule, nr_loaded: bool) -> Result<Vec<SockFilter>> {
+    let mut insns = Vec::new();
+    if !nr_loaded {
+        insns.push(Insn::stmt(BPF_LD | BPF_W | BPF_ABS, OFFSET_NR));
+    }
+    insns.push(Insn::jump(
+        BPF_JMP | BPF_JEQ | BPF_K,
+        rule.nr,
+        Target::Skip(0),
+        Target::NextRule,
+    ));
+    for arg in &rule.args {
+        insns.extend(compare(arg));
+    }
+    insns.push(Insn::stmt(BPF_RET | BPF_K, rule.ret));
+
+    let len = insns.len();
+    let resolve = |target: Target, i: usize| -> Result<u8> {
+        match target {
+            Target::Skip(n) => Ok(n),
+            Target::NextRule if len - i - 1 <= u8::MAX as usize => Ok((len - i - 1) as u8),
+            Target::NextRule => bail!("the seccomp rule for syscall {} is too long", rule.nr),
+        }
+    };
+    insns
+        .iter()
+        .enumerate()
+        .map(|(i, insn)| {
+            Ok(SockFilter {
+                code: insn.code,
+                jt: resolve(insn.jt, i)?,
+                jf: resolve(insn.jf, i)?,
+                k: insn.k,
+            })
+        })
+        .collect()
+}
+
+/// Instructions which continue if the condition on the argument holds. The arguments are
+/// 64 bits wide, and BPF compares 32 bits at a time, so the upper half is compared first.
+fn compare(arg: &LinuxSeccompArg) -> Vec<Insn> {
+    let offset = OFFSET_ARGS + 8 * arg.index as u32;
+    let (hi_offset, lo_offset) = if cfg!(target_endian = "little") {
+        (offset + 4, offset)
+    } else {
+        (offset, offset + 4)
+    };
+    let load_hi = Insn::stmt(BPF_LD | BPF_W | BPF_ABS, hi_offset);
+    let load_lo = Insn::stmt(BPF_LD | BPF_W | BPF_ABS, lo_offset);
+    let (hi, lo) = ((arg.value >> 32) as u32, arg.value as u32);
+    let jeq = BPF_JMP | BPF_JEQ | BPF_K;
+    let jgt = BPF_JMP | BPF_JGT | BPF_K;
+    let jge = BPF_JMP | BPF_JGE | BPF_K;
+    let (next, fail) = (Target::Skip(0), Target::NextRule);
+
+    match arg.op {
+        LinuxSeccompOperator::ScmpCmpEq => vec![
+            load_hi,
+            Insn::jump(jeq, hi, next, fail),
+            load_lo,
+            Insn::jump(jeq, lo, next, fail),
+        ],
+        LinuxSeccompOperator::ScmpCmpNe => vec![
+            load_hi,
+            Insn::jump(jeq, hi, next, Target::Skip(2)),
+            load_lo,
+            Insn::jump(jeq, lo, fail, next),
+        ],
+        // value is the mask, value_two what the masked argument is compared with
+        LinuxSeccompOperator::ScmpCmpMaskedEq => vec![
+            load_hi,
+            Insn::stmt(BPF_ALU | BPF_AND | BPF_K, hi),
+            Insn::jump(jeq, (arg.value_two >> 32) as u32, next, fail),
+            load_lo,
+            Insn::stmt(BPF_ALU | BPF_AND | BPF_K, lo),
+            Insn::jump(jeq, arg.value_two as u32, next, fail),
+        ],
+        LinuxSeccompOperator::ScmpCmpGt | LinuxSeccompOperator::ScmpCmpGe => {
+            let code = match arg.op {
+                LinuxSeccompOperator::ScmpCmpGt => jgt,
+                _ => jge,
+            };
+            vec![
+                load_hi,
+                Insn::jump(jgt, hi, Target::Skip(3), next),
+                Insn::jump(jeq, hi, next, fail),
+                load_lo,
+                Insn::jump(code, lo, next, fail),
+            ]
+        }
+        // less than is the negation of greater or equal, and less or equal of greater than
+        LinuxSeccompOperator::ScmpCmpLt | LinuxSeccompOperator::ScmpCmpLe => {
+            let code = match arg.op {
+                LinuxSeccompOperator::ScmpCmpLt => jge,
+                _ => jgt,
+            };
+            vec![
+                load_hi,
+                Insn::jump(jgt, hi, fail, next),
+                Insn::jump(jeq, hi, next, Target::Skip(2)),
+                load_lo,
+                Insn::jump(code, lo, fail, next),
+            ]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs the filter on a syscall like the kernel does
+    fn run(filter: &[SockFilter], arch: u32, nr: u32, args: [u64; 6]) -> u32 {
+        let mut data = [0u8; 64];
+        data[0..4].copy_from_slice(&nr.to_ne_bytes());
+        data[4..8].copy_from_slice(&arch.to_ne_bytes());
+        for (i, arg) in args.iter().enumerate() {
+            let offset = OFFSET_ARGS as usize + 8 * i;
+            data[offset..offset + 8].copy_from_slice(&arg.to_ne_bytes());
+        }
+
+        let (mut pc, mut acc) = (0, 0u32);
+        loop {
+            let insn = filter[pc];
+            pc += 1;
+            match insn.code {
+                code if code == BPF_LD | BPF_W | BPF_ABS => {
+                    let k = insn.k as usize;
+                    acc = u32::from_ne_bytes([data[k], data[k + 1], data[k + 2], data[k + 3]]);
+                }
+                code if code == BPF_ALU | BPF_AND | BPF_K => acc &= insn.k,
+                code if code == BPF_RET | BPF_K => return insn.k,
+                code => {
+                    let holds = match code & 0xf0 {
+                        BPF_JEQ => acc == insn.k,
+                        BPF_JGT => acc > insn.k,
+                        BPF_JGE => acc >= insn.k,
+                        _ => panic!("unexpected instruction {:?}", insn),
+                    };
+                    pc += if holds { insn.jt } else { insn.jf } as usize;
+                }
+            }
+        }
+    }
+
+    fn seccomp(value: serde_json::Value) -> LinuxSeccomp {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_syscall_number() {
+        assert!(syscall_number("read").is_some());
+        assert!(syscall_number("unknown").is_none());
+        for pair in syscalls::SYSCALLS.windows(2) {
+            assert!(pair[0].0 < pair[1].0);
+        }
+    }
+
+    #[test]
+    fn test_compile_actions() {
+        let seccomp = seccomp(serde_json::json!({
+            "defaultAction": "SCMP_ACT_ERRNO",
+            "defaultErrnoRet": 38,
+            "syscalls": [
+                { "names": ["read", "write", "no_such_syscall"], "action": "SCMP_ACT_ALLOW" },
+                { "names": ["getpid"], "action": "SCMP_ACT_ERRNO" },
+                { "names": ["kill"], "action": "SCMP_ACT_ERRNO", "errnoRet": 13 }
+            ]
+        }));
+        let arch = Arch::native() as u32;
+        let filter = compile(&seccomp, arch).unwrap();
+        let nr = |name| syscall_number(name).unwrap();
+        let allow = 0x7fff0000;
+        assert_eq!(run(&filter, arch, nr("read"), [0; 6]), allow);
+        assert_eq!(run(&filter, arch, nr("write"), [0; 6]), allow);
+        // errno actions return EPERM unless the rule gives another errno
+        assert_eq!(
+            run(&filter, arch, nr("getpid"), [0; 6]),
+            0x00050000 | libc::EPERM as u32
+        );
+        assert_eq!(run(&filter, arch, nr("kill"), [0; 6]), 0x00050000 | 13);
+        assert_eq!(run(&filter, arch, nr("close"), [0; 6]), 0x00050000 | 38);
+        assert_eq!(
+            run(&filter, !arch, nr("read"), [0; 6]),
+            SECCOMP_RET_KILL_PROCESS
+        );
+    }
+
+    #[test]
+    fn test_compile_args() {
+        let seccomp = seccomp(serde_json::json!({
+            "defaultAction": "SCMP_ACT_ALLOW",
+            "syscalls": [
+                {
+                    "names": ["personality"],
+                    "action": "SCMP_ACT_ERRNO",
+                    "args": [
+                        { "index": 0, "value": 8, "op": "SCMP_CMP_NE" },
+                        { "index": 0, "value": 0x100000008u64, "op": "SCMP_CMP_NE" }
+                    ]
+                },
+                {
+                    "names": ["socket"],
+                    "action": "SCMP_ACT_ERRNO",
+                    "args": [
+                        { "index": 0, "value": 0x100000000u64, "op": "SCMP_CMP_GE" },
+                        { "index": 1, "value": 0x0f, "valueTwo": 3, "op": "SCMP_CMP_MASKED_EQ" }
+                    ]
+                },
+                {
+                    "names": ["kill"],
+                    "action": "SCMP_ACT_ERRNO",
+                    "args": [{ "index": 1, "value": 9, "op": "SCMP_CMP_LE" }]
+                },
+                {
+                    "names": ["tkill"],
+                    "action": "SCMP_ACT_ERRNO",
+                    "args": [{ "index": 1, "value": 0x200000000u64, "op": "SCMP_CMP_LT" }]
+                },
+                {
+                    "names": ["setpgid"],
+                    "action": "SCMP_ACT_ERRNO",
+                    "args": [{ "index": 5, "value": 5, "op": "SCMP_CMP_GT" }]
+                }
+            ]
+        }));
+        let arch = Arch::native() as u32;
+        let filter = compile(&seccomp, arch).unwrap();
+        let nr = |name| syscall_number(name).unwrap();
+        let (allow, eperm) = (0x7fff0000, 0x00050000 | libc::EPERM as u32);
+
+        // conditions on the same argument are alternatives
+        assert_eq!(
+            run(&filter, arch, nr("personality"), [8, 0, 0, 0, 0, 0]),
+            eperm
+        );
+        assert_eq!(
+            run(&filter, arch, nr("personality"), [0, 0, 0, 0, 0, 0]),
+            eperm
+        );
+        // conditions on different arguments all have to hold
+        let socket = nr("socket");
+        assert_eq!(
+            run(&filter, arch, socket, [0x100000000, 0x13, 0, 0, 0, 0]),
+            eperm
+        );
+        assert_eq!(
+            run(&filter, arch, socket, [0x200000000, 0x03, 0, 0, 0, 0]),
+            eperm
+        );
+        assert_eq!(
+            run(&filter, arch, socket, [0xffffffff, 0x03, 0, 0, 0, 0]),
+            allow
+        );
+        assert_eq!(
+            run(&filter, arch, socket, [0x100000000, 0x04, 0, 0, 0, 0]),
+            allow
+        );
+
+        assert_eq!(run(&filter, arch, nr("kill"), [0, 9, 0, 0, 0, 0]), eperm);
+        assert_eq!(run(&filter, arch, nr("kill"), [0, 10, 0, 0, 0, 0]), allow);
+        assert_eq!(
+            run(&filter, arch, nr("kill"), [0, 0x100000000, 0, 0, 0, 0]),
+            allow
+        );
+        assert_eq!(
+            run(&filter, arch, nr("tkill"), [0, 0x1ffffffff, 0, 0, 0, 0]),
+            eperm
+        );
+        assert_eq!(
+            run(&filter, arch, nr("tkill"), [0, 0x200000000, 0, 0, 0, 0]),
+            allow
+        );
+        assert_eq!(run(&filter, arch, nr("setpgid"), [0, 0, 0, 0, 0, 6]), eperm);
+        assert_eq!(run(&filter, arch, nr("setpgid"), [0, 0, 0, 0, 0, 5]), allow);
+        assert_eq!(run(&filter, arch, nr("read"), [0; 6]), allow);
+    }
+
+    #[test]
+    fn test_compile_invalid_index() {
+        let seccomp = seccomp(serde_json::json!({
+            "defaultAction": "SCMP_ACT_ALLOW",
+            "syscalls": [{
+                "names": ["read"],
+                "action": "SCMP_ACT_ERRNO",
+                "args": [{ "index": 6, "value": 0, "op": "SCMP_CMP_EQ" }]
+            }]
+        }));
+        assert!(compile(&seccomp, Arch::native() as u32).is_err());
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_compile_architectures() {
+        // the architectures of the default profile of Docker and containerd
+        let seccomp = seccomp(serde_json::json!({
+            "defaultAction": "SCMP_ACT_ERRNO",
+            "architectures": ["SCMP_ARCH_X86_64", "SCMP_ARCH_X86", "SCMP_ARCH_X32"],
+            "syscalls": [{ "names": ["read"], "action": "SCMP_ACT_ALLOW" }]
+        }));
+        let arch = Arch::ScmpArchX86_64 as u32;
+        let filter = compile(&seccomp, arch).unwrap();
+        let (allow, errno) = (0x7fff0000, 0x00050000 | libc::EPERM as u32);
+        let read = syscall_number("read").unwrap();
+        assert_eq!(run(&filter, arch, read, [0; 6]), allow);
+        assert_eq!(run(&filter, arch, read + 1, [0; 6]), errno);
+        // read(2) of x86 is 3, which gets the default action like every syscall of x86
+        let x86 = Arch::ScmpArchX86 as u32;
+        assert_eq!(run(&filter, x86, 3, [0; 6]), errno);
+        assert_eq!(run(&filter, x86, read, [0; 6]), errno);
+        assert_eq!(run(&filter, arch, X32_SYSCALL_BIT, [0; 6]), errno);
+        let aarch64 = Arch::ScmpArchAarch64 as u32;
+        assert_eq!(
+            run(&filter, aarch64, read, [0; 6]),
+            SECCOMP_RET_KILL_PROCESS
+        );
+
+        // without x32 in the spec, its syscalls kill the process
+        let seccomp = LinuxSeccomp {
+            architectures: vec![Arch::ScmpArchX86_64, Arch::ScmpArchX86],
+            ..seccomp
+        };
+        let filter = compile(&seccomp, arch).unwrap();
+        assert_eq!(
+            run(&filter, arch, X32_SYSCALL_BIT, [0; 6]),
+            SECCOMP_RET_KILL_PROCESS
+        );
+        assert_eq!(run(&filter, x86, 3, [0; 6]), errno);
+        assert_eq!(run(&filter, arch, read, [0; 6]), allow);
+    }
+
+    #[test]
+    fn test_send_listener() {
+        use crate::container::{ContainerStatus, State};
+        use std::io::Read;
+        use std::os::unix::net::UnixListener;
+
+        let tmp = std::env::temp_dir().join("test_send_listener");
+        let _ = std::fs::remove_dir_all(&tmp);
+        std::fs::create_dir_all(&tmp).unwrap();
+        let socket_path = tmp.join("agent.sock");
+        let agent = UnixListener::bind(&socket_path).unwrap();
+
+        let state = OciState::from(&State::new(
+            "test",
+            ContainerStatus::Created,
+            Some(42),
+            "/bundle",
+        ));
+        let (read, write) = nix::unistd::pipe().unwrap();
+        send_listener(&socket_path, Some("agent data"), write, 42, &state).unwrap();
+        nix::unistd::close(write).unwrap();
+
+        let (mut stream, _) = agent.accept().unwrap();
+        let mut data = vec![0; 4096];
+        let mut cmsg_buffer = nix::cmsg_space!(RawFd);
+        let msg = socket::recvmsg(
+            stream.as_raw_fd(),
+            &[IoVec::from_mut_slice(&mut data)],
+            Some(&mut cmsg_buffer),
+            MsgFlags::empty(),
+        )
+        .unwrap();
+        let len = msg.bytes;
+        let received = match msg.cmsgs().next() {
+            Some(socket::ControlMessageOwned::ScmRights(fds)) => fds[0],
+            cmsg => panic!("unexpected control message {:?}", cmsg),
+        };
+        let mut rest = Vec::new();
+        stream.read_to_end(&mut rest).unwrap();
+        data.truncate(len);
+        data.extend(rest);
+
+        let process_state: serde_json::Value = serde_json::from_slice(&data).unwrap();
+        assert_eq!(process_state["fds"], serde_json::json!(["seccompFd"]));
+        assert_eq!(process_state["pid"], 42);
+        assert_eq!(process_state["metadata"], "agent data");
+        assert_eq!(process_state["state"]["id"], "test");
+        assert_eq!(process_state["state"]["status"], "created");
+
+        // the received descriptor is the write end of the pipe
+        nix::unistd::write(received, b"x").unwrap();
+        let mut buf = [0];
+        nix::unistd::read(read, &mut buf).unwrap();
+        assert_eq!(&buf, b"x");
+        nix::unistd::close(received).unwrap();
+        nix::unistd::close(read).unwrap();
+        std::fs::remove_dir_all(&tmp).unwrap();
+    }
+}
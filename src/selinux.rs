@@ -0,0 +1,252 @@
+//! SELinux labels of containers. When SELinux is enforcing and the engine supplies no
+//! labels, every container gets a unique pair of MCS categories, like container-selinux
+//! does, so that containers are isolated from each other by default.
+
+use std::collections::HashSet;
+use std::ffi::CString;
+use std::fs;
+use std::io::Read;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use nix::errno::Errno;
+use serde::{Deserialize, Serialize};
+
+use crate::container::Container;
+
+const ENFORCE_PATH: &str = "/sys/fs/selinux/enforce";
+/// Extended attribute with the label of a file
+const XATTR_NAME: &[u8] = b"security.selinux\0";
+const SELINUX_CONFIG: &str = "/etc/selinux/config";
+const DEFAULT_PROCESS_LABEL: &str = "system_u:system_r:container_t:s0";
+const DEFAULT_FILE_LABEL: &str = "system_u:object_r:container_file_t:s0";
+/// Categories to choose from, as container-selinux does
+const CATEGORIES: u32 = 1024;
+
+/// Labels generated for a container, which are kept in its state until it is deleted
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Labels {
+    pub process_label: String,
+    pub mount_label: String,
+}
+
+impl Labels {
+    /// MCS level of the labels, e.g. s0:c1,c2, which tells containers apart
+    fn level(&self) -> Option<&str> {
+        self.process_label.splitn(4, ':').nth(3)
+    }
+}
+
+/// Whether SELinux is enabled on the host, enforcing or permissive. Labels are ignored
+/// without it, like in runc.
+pub fn is_enabled() -> bool {
+    Path::new(ENFORCE_PATH).exists()
+}
+
+pub fn is_enforcing() -> bool {
+    matches!(fs::read_to_string(ENFORCE_PATH), Ok(enforce) if enforce.trim() == "1")
+}
+
+/// Generates labels with a pair of categories which no other container in the state
+/// root uses. Deleting a container removes its state, which releases its pair.
+pub fn generate_labels(root_path: &Path, container_id: &str) -> Result<Labels> {
+    let used = used_levels(root_path, container_id);
+    let (process_base, file_base) = base_labels();
+
+    for _ in 0..1000 {
+        let (a, b) = random_categories()?;
+        let level = format!("s0:c{},c{}", a, b);
+        if used.contains(&level) {
+            continue;
+        }
+        return Ok(Labels {
+            process_label: with_level(&process_base, &level),
+            mount_label: with_level(&file_base, &level),
+        });
+    }
+    bail!("failed to find an unused pair of MCS categories")
+}
+
+/// Sets the label which the process gets when it executes the next program
+pub fn set_exec_label(label: &str) -> Result<()> {
+    if !is_enabled() {
+        return Ok(());
+    }
+    let path = "/proc/thread-self/attr/exec";
+    fs::write(path, label).with_context(|| format!("failed to set the SELinux label {}", label))
+}
+
+/// Labels a file, e.g. the root of a filesystem which cannot be mounted with a context
+pub fn set_file_label(path: &Path, label: &str) -> Result<()> {
+    if !is_enabled() {
+        return Ok(());
+    }
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    let res = unsafe {
+        libc::lsetxattr(
+            c_path.as_ptr(),
+            XATTR_NAME.as_ptr() as *const libc::c_char,
+            label.as_ptr() as *const libc::c_void,
+            label.len(),
+            0,
+        )
+    };
+    Errno::result(res)
+        .with_context(|| format!("failed to set the SELinux label {} of {:?}", label, path))?;
+    Ok(())
+}
+
+/// Adds the label to the data of a mount, so that all files of the filesystem get it. The
+/// label is quoted, as the commas of its categories would separate options otherwise.
+pub fn mount_data(data: &str, label: &str) -> String {
+    if !is_enabled() {
+        return data.to_owned();
+    }
+    with_context(data, label)
+}
+
+fn with_context(data: &str, label: &str) -> String {
+    if label.is_empty() {
+        data.to_owned()
+    } else if data.is_empty() {
+        format!("context=\"{}\"", label)
+    } else {
+        format!("{},context=\"{}\"", data, label)
+    }
+}
+
+fn used_levels(root_path: &Path, container_id: &str) -> HashSet<String> {
+    let mut used = HashSet::new();
+    let entries = match fs::read_dir(root_path) {
+        Ok(entries) => entries,
+        Err(_) => return used,
+    };
+    for entry in entries.flatten() {
+        if entry.file_name() == container_id {
+            continue;
+        }
+        if let Ok(container) = Container::load(entry.path()) {
+            if let Some(level) = container
+                .state
+                .selinux_labels
+                .as_ref()
+                .and_then(Labels::level)
+            {
+                used.insert(level.to_owned());
+            }
+        }
+    }
+    used
+}
+
+/// Reads the labels of containers from the lxc_contexts of the policy in use
+fn base_labels() -> (String, String) {
+    let policy = fs::read_to_string(SELINUX_CONFIG)
+        .ok()
+        .and_then(|config| {
+            config
+                .lines()
+                .find_map(|line| line.trim().strip_prefix("SELINUXTYPE=").map(str::to_owned))
+        })
+        .unwrap_or_else(|| "targeted".to_owned());
+    let contexts = Path::new("/etc/selinux")
+        .join(policy)
+        .join("contexts/lxc_contexts");
+    let contexts = fs::read_to_string(contexts).unwrap_or_default();
+    (
+        context_of(&contexts, "process").unwrap_or_else(|| DEFAULT_PROCESS_LABEL.to_owned()),
+        context_of(&contexts, "file").unwrap_or_else(|| DEFAULT_FILE_LABEL.to_owned()),
+    )
+}
+
+/// Parses a line like process = "system_u:system_r:container_t:s0" of lxc_contexts
+fn context_of(contexts: &str, key: &str) -> Option<String> {
+    contexts.lines().find_map(|line| {
+        let (name, value) = line.split_once('=')?;
+        if name.trim() != key {
+            return None;
+        }
+        Some(value.trim().trim_matches('"').to_owned())
+    })
+}
+
+/// Replaces the MCS level of a label, which is everything after its type
+fn with_level(label: &str, level: &str) -> String {
+    let parts: Vec<&str> = label.splitn(4, ':').collect();
+    format!("{}:{}", parts[..parts.len().min(3)].join(":"), level)
+}
+
+/// Chooses two different categories, the lower one first
+fn random_categories() -> Result<(u32, u32)> {
+    let mut bytes = [0u8; 8];
+    fs::File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    let a = u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) % CATEGORIES;
+    let b = u32::from_ne_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) % (CATEGORIES - 1);
+    // skip a itself, so that the pair is always two different categories
+    let b = if b >= a { b + 1 } else { b };
+    Ok((a.min(b), a.max(b)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_context_of() {
+        let contexts = r#"process = "system_u:system_r:container_t:s0"
+content = "system_u:object_r:virt_var_lib_t:s0"
+file = "system_u:object_r:container_file_t:s0"
+"#;
+        assert_eq!(
+            context_of(contexts, "process"),
+            Some("system_u:system_r:container_t:s0".to_owned())
+        );
+        assert_eq!(
+            context_of(contexts, "file"),
+            Some("system_u:object_r:container_file_t:s0".to_owned())
+        );
+        assert_eq!(context_of(contexts, "sandbox_kvm_process"), None);
+    }
+
+    #[test]
+    fn test_with_level() {
+        assert_eq!(
+            with_level("system_u:system_r:container_t:s0", "s0:c1,c2"),
+            "system_u:system_r:container_t:s0:c1,c2"
+        );
+        let labels = Labels {
+            process_label: with_level("system_u:system_r:container_t", "s0:c3,c4"),
+            mount_label: with_level("system_u:object_r:container_file_t:s0", "s0:c3,c4"),
+        };
+        assert_eq!(
+            labels.process_label,
+            "system_u:system_r:container_t:s0:c3,c4"
+        );
+        assert_eq!(labels.level(), Some("s0:c3,c4"));
+    }
+
+    #[test]
+    fn test_with_context() {
+        let label = "system_u:object_r:container_file_t:s0:c1,c2";
+        assert_eq!(
+            with_context("", label),
+            "context=\"system_u:object_r:container_file_t:s0:c1,c2\""
+        );
+        assert_eq!(
+            with_context("mode=755", label),
+            "mode=755,context=\"system_u:object_r:container_file_t:s0:c1,c2\""
+        );
+        assert_eq!(with_context("mode=755", ""), "mode=755");
+    }
+
+    #[test]
+    fn test_random_categories() {
+        for _ in 0..100 {
+            let (a, b) = random_categories().unwrap();
+            assert!(a < b);
+            assert!(b < CATEGORIES);
+        }
+    }
+}
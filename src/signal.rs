@@ -10,9 +10,9 @@ pub fn from_str(signal: &str) -> Result<Signal> {
         "2" | "INT" | "SIGINT" => Signal::SIGINT,
         "3" | "QUIT" | "SIGQUIT" => Signal::SIGQUIT,
         "4" | "ILL" | "SIGILL" => Signal::SIGILL,
-        "5" | "BUS" | "SIGBUS" => Signal::SIGBUS,
+        "5" | "TRAP" | "SIGTRAP" => Signal::SIGTRAP,
         "6" | "ABRT" | "IOT" | "SIGABRT" | "SIGIOT" => Signal::SIGABRT,
-        "7" | "TRAP" | "SIGTRAP" => Signal::SIGTRAP,
+        "7" | "BUS" | "SIGBUS" => Signal::SIGBUS,
         "8" | "FPE" | "SIGFPE" => Signal::SIGFPE,
         "9" | "KILL" | "SIGKILL" => Signal::SIGKILL,
         "10" | "USR1" | "SIGUSR1" => Signal::SIGUSR1,
@@ -40,3 +40,26 @@ pub fn from_str(signal: &str) -> Result<Signal> {
         _ => bail! {"{} is not a valid signal", signal},
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!(from_str("SIGTERM").unwrap(), Signal::SIGTERM);
+        assert_eq!(from_str("term").unwrap(), Signal::SIGTERM);
+        assert_eq!(from_str("15").unwrap(), Signal::SIGTERM);
+        assert!(from_str("SIGFOO").is_err());
+        assert!(from_str("0").is_err());
+    }
+
+    #[test]
+    fn test_numbers_match_the_kernel() {
+        for number in 1..=31 {
+            if let Ok(signal) = from_str(&number.to_string()) {
+                assert_eq!(signal as i32, number);
+            }
+        }
+    }
+}
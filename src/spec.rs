@@ -0,0 +1,228 @@
+//! Generates a default config.json to start a bundle from, like runc spec
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use anyhow::{bail, Result};
+use clap::Clap;
+use nix::unistd;
+use serde_json::{json, Value};
+
+#[derive(Clap, Debug)]
+pub struct Spec {
+    /// path to the bundle directory to write config.json into
+    #[clap(short, long, default_value = ".")]
+    pub bundle: PathBuf,
+    /// generate a config for a container of an unprivileged user, which runs in a user
+    /// namespace with the current user as root
+    #[clap(long)]
+    pub rootless: bool,
+}
+
+impl Spec {
+    pub fn exec(&self) -> Result<()> {
+        let config_path = self.bundle.join("config.json");
+        let mut spec = default_spec();
+        if self.rootless {
+            to_rootless(
+                &mut spec,
+                unistd::geteuid().as_raw(),
+                unistd::getegid().as_raw(),
+            );
+        }
+
+        // an existing config is most likely edited by hand, so it is never replaced
+        let file = match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&config_path)
+        {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                bail!("{:?} already exists", config_path)
+            }
+            Err(e) => return Err(e.into()),
+        };
+        serde_json::to_writer_pretty(file, &spec)?;
+        Ok(())
+    }
+}
+
+/// Config of a container which runs sh on a terminal with the rootfs directory of the
+/// bundle as its root, with the same defaults as runc
+fn default_spec() -> Value {
+    let capabilities = json!(["CAP_AUDIT_WRITE", "CAP_KILL", "CAP_NET_BIND_SERVICE"]);
+    json!({
+        "ociVersion": "1.0.2",
+        "process": {
+            "terminal": true,
+            "user": { "uid": 0, "gid": 0 },
+            "args": ["sh"],
+            "env": [
+                "PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin",
+                "TERM=xterm"
+            ],
+            "cwd": "/",
+            "capabilities": {
+                "bounding": capabilities,
+                "effective": capabilities,
+                "inheritable": capabilities,
+                "permitted": capabilities,
+                "ambient": capabilities
+            },
+            "rlimits": [{ "type": "RLIMIT_NOFILE", "hard": 1024, "soft": 1024 }],
+            "noNewPrivileges": true
+        },
+        "root": { "path": "rootfs", "readonly": true },
+        "hostname": "youki",
+        "mounts": [
+            { "destination": "/proc", "type": "proc", "source": "proc" },
+            {
+                "destination": "/dev",
+                "type": "tmpfs",
+                "source": "tmpfs",
+                "options": ["nosuid", "strictatime", "mode=755", "size=65536k"]
+            },
+            {
+                "destination": "/dev/pts",
+                "type": "devpts",
+                "source": "devpts",
+                "options": ["nosuid", "noexec", "newinstance", "ptmxmode=0666", "mode=0620", "gid=5"]
+            },
+            {
+                "destination": "/dev/shm",
+                "type": "tmpfs",
+                "source": "shm",
+                "options": ["nosuid", "noexec", "nodev", "mode=1777", "size=65536k"]
+            },
+            {
+                "destination": "/dev/mqueue",
+                "type": "mqueue",
+                "source": "mqueue",
+                "options": ["nosuid", "noexec", "nodev"]
+            },
+            {
+                "destination": "/sys",
+                "type": "sysfs",
+                "source": "sysfs",
+                "options": ["nosuid", "noexec", "nodev", "ro"]
+            },
+            {
+                "destination": "/sys/fs/cgroup",
+                "type": "cgroup",
+                "source": "cgroup",
+                "options": ["nosuid", "noexec", "nodev", "relatime", "ro"]
+            }
+        ],
+        "linux": {
+            "resources": {
+                "devices": [{ "allow": false, "access": "rwm" }]
+            },
+            "namespaces": [
+                { "type": "pid" },
+                { "type": "network" },
+                { "type": "ipc" },
+                { "type": "uts" },
+                { "type": "mount" }
+            ],
+            "maskedPaths": [
+                "/proc/acpi",
+                "/proc/asound",
+                "/proc/kcore",
+                "/proc/keys",
+                "/proc/latency_stats",
+                "/proc/timer_list",
+                "/proc/timer_stats",
+                "/proc/sched_debug",
+                "/sys/firmware",
+                "/proc/scsi"
+            ],
+            "readonlyPaths": [
+                "/proc/bus",
+                "/proc/fs",
+                "/proc/irq",
+                "/proc/sys",
+                "/proc/sysrq-trigger"
+            ]
+        }
+    })
+}
+
+/// Adjusts the config for an unprivileged user: the container gets a user namespace in
+/// which the user is root, and keeps the network of the host, as an unprivileged user
+/// cannot set up one of its own. What needs privileges on the host is left out.
+fn to_rootless(spec: &mut Value, uid: u32, gid: u32) {
+    let linux = &mut spec["linux"];
+    if let Some(namespaces) = linux["namespaces"].as_array_mut() {
+        namespaces.retain(|ns| ns["type"] != "network" && ns["type"] != "user");
+        namespaces.push(json!({ "type": "user" }));
+    }
+    linux["uidMappings"] = json!([{ "hostID": uid, "containerID": 0, "size": 1 }]);
+    linux["gidMappings"] = json!([{ "hostID": gid, "containerID": 0, "size": 1 }]);
+    // the cgroups of an unprivileged user are set up by whoever delegates them, and the
+    // cgroup hierarchy cannot be mounted
+    if let Some(linux) = linux.as_object_mut() {
+        linux.remove("resources");
+    }
+
+    if let Some(mounts) = spec["mounts"].as_array_mut() {
+        mounts.retain(|mount| mount["type"] != "cgroup");
+        for mount in mounts.iter_mut() {
+            // sysfs cannot be mounted without owning the network namespace, the one of
+            // the host is bound instead
+            if mount["destination"] == "/sys" {
+                *mount = json!({
+                    "destination": "/sys",
+                    "type": "none",
+                    "source": "/sys",
+                    "options": ["rbind", "nosuid", "noexec", "nodev", "ro"]
+                });
+            }
+            // the tty group of the host is not mapped into the user namespace
+            if let Some(options) = mount.get_mut("options").and_then(Value::as_array_mut) {
+                options.retain(|option| !option.as_str().unwrap_or_default().starts_with("gid="));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_spec() {
+        let spec: oci_spec::Spec = serde_json::from_value(default_spec()).unwrap();
+        assert_eq!(spec.root.path, PathBuf::from("rootfs"));
+        assert_eq!(spec.process.args, vec!["sh"]);
+        assert_eq!(spec.linux.unwrap().namespaces.len(), 5);
+    }
+
+    #[test]
+    fn test_to_rootless() {
+        let mut spec = default_spec();
+        to_rootless(&mut spec, 1000, 100);
+        let spec: oci_spec::Spec = serde_json::from_value(spec).unwrap();
+
+        let linux = spec.linux.unwrap();
+        let namespaces: Vec<_> = linux.namespaces.iter().map(|ns| ns.typ).collect();
+        assert!(namespaces.contains(&oci_spec::LinuxNamespaceType::User));
+        assert!(!namespaces.contains(&oci_spec::LinuxNamespaceType::Network));
+        assert_eq!(linux.uid_mappings[0].host_id, 1000);
+        assert_eq!(linux.gid_mappings[0].host_id, 100);
+        assert!(linux.resources.is_none());
+
+        assert!(!spec.mounts.iter().any(|m| m.typ == "cgroup"));
+        let sys = spec
+            .mounts
+            .iter()
+            .find(|m| m.destination == std::path::Path::new("/sys"))
+            .unwrap();
+        assert_eq!(sys.typ, "none");
+        assert!(spec
+            .mounts
+            .iter()
+            .all(|m| !m.options.iter().any(|o| o.starts_with("gid="))));
+    }
+}
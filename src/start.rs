@@ -1,13 +1,17 @@
 //! Starts execution of the container
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
 use clap::Clap;
 use nix::unistd;
 
-use crate::container::{Container, ContainerStatus};
-use crate::notify_socket::NotifySocket;
+use crate::config::Timeouts;
+use crate::container::{Container, ContainerStatus, OciState};
+use crate::exec_fifo;
+use crate::hooks;
+use crate::notify_socket::NotifyProxy;
+use crate::utils;
 
 #[derive(Clap, Debug)]
 pub struct Start {
@@ -15,7 +19,7 @@ pub struct Start {
 }
 
 impl Start {
-    pub fn exec(&self, root_path: PathBuf) -> Result<()> {
+    pub fn exec(&self, root_path: PathBuf, timeouts: &Timeouts) -> Result<()> {
         let container_root = root_path.join(&self.container_id);
         if !container_root.exists() {
             bail!("{} doesn't exist.", self.container_id)
@@ -31,12 +35,62 @@ impl Start {
             bail!(err_msg);
         }
 
+        // the container has been set up from the config at the time of create, edits
+        // made to it since then do not take effect
+        if let Some(created_digest) = &container.state.config_digest {
+            let config_path = Path::new(&container.state.bundle).join("config.json");
+            match utils::config_digest(&config_path) {
+                Ok(digest) if &digest == created_digest => {}
+                Ok(_) => {
+                    log::warn!("{:?} has changed since create", config_path);
+                    eprintln!(
+                        "WARNING: {:?} has changed since {} was created, the changes are ignored",
+                        config_path,
+                        container.id()
+                    );
+                }
+                Err(e) => log::warn!("failed to check {:?} for changes: {}", config_path, e),
+            }
+        }
+
         unistd::chdir(container.root.as_os_str())?;
 
-        let mut notify_socket = NotifySocket::new(&container.root)?;
-        notify_socket.notify_container_start()?;
+        // bound before the container starts, so that none of its messages are lost
+        let proxy = match &container.state.notify_socket {
+            Some(host_socket) => Some(NotifyProxy::bind(&container.root, host_socket)?),
+            None => None,
+        };
+        exec_fifo::start(&container.root, timeouts.start())?;
 
-        container.update_status(ContainerStatus::Running)?.save()?;
+        let container = container.update_status(ContainerStatus::Running)?;
+        container.save()?;
+
+        // the container runs whether or not they succeed
+        if let Err(e) = run_poststart_hooks(&container, timeouts) {
+            log::warn!("{:?}", e);
+            eprintln!("WARNING: {:#}", e);
+        }
+        // start returns once the container is ready, as the service manager expects
+        if let (Some(proxy), Some(pid)) = (proxy, container.pid()) {
+            if let Err(e) = proxy.relay(pid) {
+                log::warn!("{:?}", e);
+                eprintln!("WARNING: {:#}", e);
+            }
+        }
         Ok(())
     }
 }
+
+fn run_poststart_hooks(container: &Container, timeouts: &Timeouts) -> Result<()> {
+    let config_path = container.root.join("config.json");
+    let spec = oci_spec::Spec::load(&config_path.to_string_lossy())?;
+    if let Some(hooks) = &spec.hooks {
+        hooks::run(
+            &hooks.poststart,
+            &OciState::from(&container.state),
+            timeouts.hooks(),
+        )
+        .context("failed to run the poststart hooks")?;
+    }
+    Ok(())
+}
@@ -1,7 +1,8 @@
 use std::os::unix::io::{AsRawFd, RawFd};
 
-use anyhow::Result;
-use nix::unistd::dup2;
+use anyhow::{bail, Result};
+use nix::errno::Errno;
+use nix::unistd::{close, dup2, read, write};
 
 #[derive(Debug)]
 pub struct FileDescriptor(RawFd);
@@ -46,3 +47,95 @@ pub fn connect_stdio(
     dup2(stderr.as_raw_fd(), STDERR)?;
     Ok(())
 }
+
+/// Character which a terminal in canonical mode interprets as end of file (^D)
+const EOT: u8 = 0x04;
+
+/// Copies data from one file descriptor to another until the source reaches EOF.
+/// The master side of a pty reports EIO instead of EOF once every slave is closed,
+/// which is treated as EOF as well.
+pub fn drain(from: RawFd, to: RawFd) -> Result<u64> {
+    let mut buf = [0u8; 4096];
+    let mut total = 0;
+    loop {
+        let n = match read(from, &mut buf) {
+            Ok(0) | Err(nix::Error::Sys(Errno::EIO)) => break,
+            Ok(n) => n,
+            Err(nix::Error::Sys(Errno::EINTR)) => continue,
+            Err(e) => bail!("failed to read from {}: {}", from, e),
+        };
+        write_all(to, &buf[..n])?;
+        total += n as u64;
+    }
+    Ok(total)
+}
+
+/// Forwards stdin of youki to the container until it reaches EOF, and then passes the EOF on.
+/// A pipe is closed so that the container reads EOF, while for a terminal the EOF character
+/// is sent, because the master side of a pty cannot be half closed without hanging up.
+pub fn forward_stdin(stdin: RawFd, container_stdin: RawFd, is_tty: bool) -> Result<()> {
+    drain(stdin, container_stdin)?;
+    if is_tty {
+        write_all(container_stdin, &[EOT])?;
+    } else {
+        close(container_stdin)?;
+    }
+    Ok(())
+}
+
+fn write_all(fd: RawFd, mut buf: &[u8]) -> Result<()> {
+    while !buf.is_empty() {
+        match write(fd, buf) {
+            Ok(n) => buf = &buf[n..],
+            Err(nix::Error::Sys(Errno::EINTR)) => continue,
+            Err(e) => bail!("failed to write to {}: {}", fd, e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nix::unistd::pipe;
+
+    fn read_to_end(fd: RawFd) -> Vec<u8> {
+        let mut content = Vec::new();
+        let mut buf = [0u8; 64];
+        loop {
+            match read(fd, &mut buf).unwrap() {
+                0 => break,
+                n => content.extend_from_slice(&buf[..n]),
+            }
+        }
+        content
+    }
+
+    #[test]
+    fn test_forward_stdin_closes_on_eof() {
+        let (stdin_r, stdin_w) = pipe().unwrap();
+        let (container_r, container_w) = pipe().unwrap();
+        write_all(stdin_w, b"hi\n").unwrap();
+        close(stdin_w).unwrap();
+
+        forward_stdin(stdin_r, container_w, false).unwrap();
+        // reaching EOF here means the write side was closed by forward_stdin
+        assert_eq!(read_to_end(container_r), b"hi\n");
+        close(stdin_r).unwrap();
+        close(container_r).unwrap();
+    }
+
+    #[test]
+    fn test_drain_until_pty_closed() {
+        let pty = nix::pty::openpty(None, None).unwrap();
+        let (out_r, out_w) = pipe().unwrap();
+        write_all(pty.slave, b"output").unwrap();
+        close(pty.slave).unwrap();
+
+        drain(pty.master, out_w).unwrap();
+        close(out_w).unwrap();
+        assert!(!read_to_end(out_r).is_empty());
+        close(pty.master).unwrap();
+        close(out_r).unwrap();
+    }
+}
@@ -0,0 +1,125 @@
+//! Sets the kernel parameters of linux.sysctl for the container. Only those which are
+//! namespaced are allowed, and only along with the namespace, as others would change the
+//! parameters of the host.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use oci_spec::{Linux, LinuxNamespaceType};
+
+/// Parameters of the ipc namespace which are not below fs.mqueue
+const IPC_PARAMETERS: &[&str] = &[
+    "kernel.msgmax",
+    "kernel.msgmnb",
+    "kernel.msgmni",
+    "kernel.sem",
+    "kernel.shmall",
+    "kernel.shmmax",
+    "kernel.shmmni",
+    "kernel.shm_rmid_forced",
+];
+
+/// Checks that every parameter belongs to a namespace of the container
+pub fn validate(linux: &Linux) -> Result<()> {
+    for key in linux.sysctl.keys() {
+        let namespace = match namespace_of(key) {
+            Some(namespace) => namespace,
+            None => bail!(
+                "sysctl {} is not namespaced, setting it would change the host",
+                key
+            ),
+        };
+        if !linux.namespaces.iter().any(|ns| ns.typ == namespace) {
+            bail!(
+                "sysctl {} needs a {:?} namespace, setting it would change the host",
+                key,
+                namespace
+            );
+        }
+    }
+    Ok(())
+}
+
+fn namespace_of(key: &str) -> Option<LinuxNamespaceType> {
+    if IPC_PARAMETERS.contains(&key) || key.starts_with("fs.mqueue.") {
+        Some(LinuxNamespaceType::Ipc)
+    } else if key.starts_with("net.") {
+        Some(LinuxNamespaceType::Network)
+    } else if key == "kernel.hostname" || key == "kernel.domainname" {
+        Some(LinuxNamespaceType::Uts)
+    } else {
+        None
+    }
+}
+
+/// Writes the parameters to /proc/sys of the container
+pub fn apply(sysctl: &HashMap<String, String>) -> Result<()> {
+    for (key, value) in sysctl {
+        fs::write(path(key), value)
+            .with_context(|| format!("failed to set sysctl {} to {}", key, value))?;
+    }
+    Ok(())
+}
+
+/// Path of the parameter below /proc/sys. Dots separate the directories, and slashes stand
+/// for the dots in a name, e.g. of the interface eth0.100.
+fn path(key: &str) -> PathBuf {
+    let relative: String = key
+        .chars()
+        .map(|c| match c {
+            '.' => '/',
+            '/' => '.',
+            c => c,
+        })
+        .collect();
+    PathBuf::from("/proc/sys").join(relative)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oci_spec::LinuxNamespace;
+
+    fn linux(sysctl: &[&str], namespaces: &[LinuxNamespaceType]) -> Linux {
+        let mut linux: Linux = serde_json::from_str("{}").unwrap();
+        linux.sysctl = sysctl
+            .iter()
+            .map(|key| (key.to_string(), "1".to_owned()))
+            .collect();
+        linux.namespaces = namespaces
+            .iter()
+            .map(|typ| LinuxNamespace {
+                typ: *typ,
+                path: None,
+            })
+            .collect();
+        linux
+    }
+
+    #[test]
+    fn test_validate() {
+        let namespaces = [LinuxNamespaceType::Ipc, LinuxNamespaceType::Network];
+        let namespaced = ["kernel.shmmax", "fs.mqueue.msg_max", "net.ipv4.ip_forward"];
+        assert!(validate(&linux(&namespaced, &namespaces)).is_ok());
+
+        let err = validate(&linux(&["net.ipv4.ip_forward"], &[])).unwrap_err();
+        assert!(err.to_string().contains("net.ipv4.ip_forward"));
+        let err = validate(&linux(&["vm.swappiness"], &namespaces)).unwrap_err();
+        assert!(err.to_string().contains("not namespaced"));
+        assert!(validate(&linux(&["kernel.hostname"], &namespaces)).is_err());
+    }
+
+    #[test]
+    fn test_path() {
+        assert_eq!(
+            path("net.ipv4.ip_forward"),
+            PathBuf::from("/proc/sys/net/ipv4/ip_forward")
+        );
+        assert_eq!(
+            path("net.ipv4.conf.eth0/100.forwarding"),
+            PathBuf::from("/proc/sys/net/ipv4/conf/eth0.100/forwarding")
+        );
+    }
+}
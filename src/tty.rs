@@ -1,81 +1,387 @@
 //! tty (teletype) for user-system interaction
 
+use std::fs::{self, OpenOptions};
 use std::os::unix::fs::symlink;
-use std::os::unix::io::AsRawFd;
-use std::path::Path;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
 
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
 use nix::errno::Errno;
-use nix::fcntl;
+use nix::fcntl::{self, OFlag};
+use nix::mount::{mount, MsFlags};
+use nix::pty::{self, Winsize};
 use nix::sys::socket;
-use nix::sys::stat;
-use nix::unistd::{close, setsid};
+use nix::sys::stat::{self, Mode};
+use nix::sys::termios::{self, SetArg, Termios};
+use nix::sys::uio::IoVec;
+use nix::unistd::{close, fchown, getpid, isatty, setsid, Gid, Uid};
 
 use crate::stdio;
 use crate::stdio::FileDescriptor;
 
-pub fn ready(console_fd: FileDescriptor) -> Result<()> {
-    let openpty_result = nix::pty::openpty(None, None)?;
-    let data: &[u8] = b"/dev/ptmx";
-    let iov = [nix::sys::uio::IoVec::from_slice(data)];
-    let fds = [openpty_result.master];
-    let cmsg = socket::ControlMessage::ScmRights(&fds);
+/// Name of the link to the console socket in the container directory. Paths of unix
+/// sockets are limited to 108 bytes, so the socket is connected through a relative link.
+const CONSOLE_SOCKET: &str = "console-socket";
+
+/// Links the console socket into the container directory, for init to connect to it
+pub fn link_console_socket(container_dir: &Path, console_socket: &str) -> Result<()> {
+    let console_socket = fs::canonicalize(console_socket)
+        .with_context(|| format!("console socket {} does not exist", console_socket))?;
+    symlink(console_socket, container_dir.join(CONSOLE_SOCKET))?;
+    Ok(())
+}
+
+/// Connects to the console socket linked into the container directory, which has to be
+/// the working directory
+pub fn connect_console_socket() -> Result<RawFd> {
+    let fd = socket::socket(
+        socket::AddressFamily::Unix,
+        socket::SockType::Stream,
+        socket::SockFlag::SOCK_CLOEXEC,
+        None,
+    )?;
+    let addr = socket::SockAddr::Unix(socket::UnixAddr::new(CONSOLE_SOCKET)?);
+    if let Err(e) = socket::connect(fd, &addr) {
+        let _ = close(fd);
+        bail!("failed to connect to the console socket: {}", e);
+    }
+    Ok(fd)
+}
+
+/// Allocates the terminal of the container from its own devpts, so it has to be called
+/// once the root is changed. The terminal becomes the controlling terminal and the stdio
+/// of this process and /dev/console, and its master side is sent over the console socket.
+pub fn setup_console(console_socket: RawFd, size: &oci_spec::Box) -> Result<()> {
+    let master = pty::posix_openpt(OFlag::O_RDWR | OFlag::O_NOCTTY | OFlag::O_CLOEXEC)?;
+    pty::grantpt(&master)?;
+    pty::unlockpt(&master)?;
+    let slave_path = pty::ptsname_r(&master)?;
+    if size.height > 0 && size.width > 0 {
+        let winsize = Winsize {
+            ws_row: size.height as u16,
+            ws_col: size.width as u16,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        if unsafe { libc::ioctl(master.as_raw_fd(), libc::TIOCSWINSZ, &winsize) } < 0 {
+            return Err(Errno::last()).context("failed to set the terminal size");
+        }
+    }
+    let slave = fcntl::open(
+        slave_path.as_str(),
+        OFlag::O_RDWR | OFlag::O_NOCTTY,
+        Mode::empty(),
+    )?;
+
+    // the engine on the other end only needs the master side, the name is informational
+    let iov = [IoVec::from_slice(b"/dev/ptmx")];
+    let fds = [master.as_raw_fd()];
     socket::sendmsg(
-        console_fd.as_raw_fd(),
+        console_socket,
         &iov,
-        &[cmsg],
+        &[socket::ControlMessage::ScmRights(&fds)],
         socket::MsgFlags::empty(),
         None,
-    )?;
+    )
+    .context("failed to send the terminal over the console socket")?;
+    close(console_socket)?;
 
     setsid()?;
-    if unsafe { libc::ioctl(openpty_result.slave, libc::TIOCSCTTY) } < 0 {
-        log::warn!("could not TIOCSCTTY");
-    };
-    let slave = FileDescriptor::from(openpty_result.slave);
-    stdio::connect_stdio(&slave, &slave, &slave).expect("could not dup tty to stderr");
-    close(console_fd.as_raw_fd())?;
+    if unsafe { libc::ioctl(slave, libc::TIOCSCTTY, 0) } < 0 {
+        return Err(Errno::last()).context("failed to set the controlling terminal");
+    }
+    let slave_fd = FileDescriptor::from(slave);
+    stdio::connect_stdio(&slave_fd, &slave_fd, &slave_fd)?;
+    bind_console(&slave_path);
+    close(slave)?;
     Ok(())
 }
 
-pub fn load_console_sockets(
-    container_dir: &Path,
-    console_socket: &str,
-) -> Result<(FileDescriptor, FileDescriptor)> {
-    let csocket = "console-stdout";
-    symlink(console_socket, container_dir.join(csocket))?;
+/// Makes /dev/console the terminal of the container, as programs like systemd write to it
+fn bind_console(slave_path: &str) {
+    let console = Path::new("/dev/console");
+    let bound = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(false)
+        .open(console)
+        .map_err(anyhow::Error::from)
+        .and_then(|_| {
+            mount(
+                Some(slave_path),
+                console,
+                None::<&str>,
+                MsFlags::MS_BIND,
+                None::<&str>,
+            )
+            .map_err(anyhow::Error::from)
+        });
+    // e.g. a read-only /dev, the terminal works without it
+    if let Err(e) = bound {
+        log::warn!("failed to bind the terminal to /dev/console: {}", e);
+    }
+}
 
-    let mut csocketfd = socket::socket(
-        socket::AddressFamily::Unix,
-        socket::SockType::Stream,
-        socket::SockFlag::empty(),
-        None,
-    )?;
-    csocketfd = match socket::connect(
-        csocketfd,
-        &socket::SockAddr::Unix(socket::UnixAddr::new(&*csocket)?),
-    ) {
-        Err(e) => {
-            if e != ::nix::Error::Sys(Errno::ENOENT) {
-                bail!("failed to open {}", csocket);
+/// Socket on which run receives the terminal of a container which it attaches to itself.
+/// The socket file is removed when it is dropped.
+pub struct ConsoleListener {
+    path: PathBuf,
+    listener: UnixListener,
+}
+
+impl ConsoleListener {
+    pub fn new(container_id: &str) -> Result<Self> {
+        let path =
+            std::env::temp_dir().join(format!("youki-{}-{}.console", container_id, getpid()));
+        let listener =
+            UnixListener::bind(&path).with_context(|| format!("failed to listen on {:?}", path))?;
+        Ok(Self { path, listener })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Receives the master side of the terminal, which init has sent by the time the
+    /// container is created
+    pub fn receive(&self) -> Result<RawFd> {
+        let (stream, _) = self.listener.accept()?;
+        let mut name = [0u8; 64];
+        let mut cmsg_buffer = nix::cmsg_space!(RawFd);
+        let msg = socket::recvmsg(
+            stream.as_raw_fd(),
+            &[IoVec::from_mut_slice(&mut name)],
+            Some(&mut cmsg_buffer),
+            socket::MsgFlags::MSG_CMSG_CLOEXEC,
+        )?;
+        for cmsg in msg.cmsgs() {
+            if let socket::ControlMessageOwned::ScmRights(fds) = cmsg {
+                if let Some(fd) = fds.first() {
+                    return Ok(*fd);
+                }
             }
-            -1
         }
-        Ok(()) => csocketfd,
-    };
-    let console = "console";
-    let consolefd = match fcntl::open(
-        &*console,
-        fcntl::OFlag::O_NOCTTY | fcntl::OFlag::O_RDWR,
-        stat::Mode::empty(),
-    ) {
-        Err(e) => {
-            if e != ::nix::Error::Sys(Errno::ENOENT) {
-                bail!("failed to open {}", console);
+        bail!("no terminal is passed on the console socket")
+    }
+}
+
+impl Drop for ConsoleListener {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Copies the size of a terminal to another one, e.g. from the terminal of run to the one
+/// of the container, whose foreground process the kernel sends SIGWINCH to
+pub fn copy_size(from: RawFd, to: RawFd) -> Result<()> {
+    let mut size: Winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(from, libc::TIOCGWINSZ, &mut size) } < 0 {
+        return Err(Errno::last()).context("failed to get the terminal size");
+    }
+    if unsafe { libc::ioctl(to, libc::TIOCSWINSZ, &size) } < 0 {
+        return Err(Errno::last()).context("failed to set the terminal size");
+    }
+    Ok(())
+}
+
+/// Group which owns terminals by convention, used if the container does not name one
+const DEFAULT_TTY_GID: u32 = 5;
+
+/// Hands the terminal of the container over to its user with mode 0620 and the tty group
+/// of the container, so that the process can write to its controlling terminal while
+/// programs like write(1) can reach it through the group
+pub fn set_ownership(fd: RawFd, uid: Uid, group_file: &Path) -> Result<()> {
+    let gid = tty_gid(group_file);
+    log::debug!("chown terminal to {}:{}", uid, gid);
+    fchown(fd, Some(uid), Some(gid))?;
+    stat::fchmod(fd, Mode::from_bits_truncate(0o620))?;
+    Ok(())
+}
+
+/// Looks up the gid of the tty group in a group file such as /etc/group
+fn tty_gid(group_file: &Path) -> Gid {
+    let gid = fs::read_to_string(group_file).ok().and_then(|groups| {
+        groups.lines().find_map(|line| {
+            let fields: Vec<&str> = line.split(':').collect();
+            match fields.as_slice() {
+                ["tty", _, gid, ..] => gid.parse().ok(),
+                _ => None,
             }
-            -1
+        })
+    });
+    Gid::from_raw(gid.unwrap_or(DEFAULT_TTY_GID))
+}
+
+/// Keeps the terminal of a file descriptor in raw mode while it is alive, so that
+/// key strokes are passed through to the container as they are typed instead of being
+/// line edited by the host. The original settings are restored when it is dropped,
+/// which also happens when unwinding from a panic.
+pub struct RawTerminal {
+    fd: RawFd,
+    original: Termios,
+}
+
+impl RawTerminal {
+    /// Puts the terminal into raw mode, returns None if the fd does not refer to a terminal
+    pub fn new(fd: RawFd) -> Result<Option<Self>> {
+        if !isatty(fd)? {
+            return Ok(None);
+        }
+
+        let original = termios::tcgetattr(fd)?;
+        let mut raw = original.clone();
+        termios::cfmakeraw(&mut raw);
+        termios::tcsetattr(fd, SetArg::TCSANOW, &raw)?;
+        Ok(Some(Self { fd, original }))
+    }
+
+    /// Restores the settings the terminal had before it was put into raw mode
+    pub fn restore(&self) -> Result<()> {
+        termios::tcsetattr(self.fd, SetArg::TCSANOW, &self.original)?;
+        Ok(())
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        if let Err(e) = self.restore() {
+            log::warn!("failed to restore terminal settings: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nix::sys::termios::LocalFlags;
+    use std::os::unix::fs::MetadataExt;
+
+    #[test]
+    fn test_raw_terminal() {
+        let pty = nix::pty::openpty(None, None).expect("open pty");
+        let is_canonical = |fd| {
+            termios::tcgetattr(fd)
+                .unwrap()
+                .local_flags
+                .contains(LocalFlags::ICANON | LocalFlags::ECHO)
+        };
+        assert!(is_canonical(pty.slave));
+
+        {
+            let _raw = RawTerminal::new(pty.slave)
+                .unwrap()
+                .expect("pty is a terminal");
+            assert!(!is_canonical(pty.slave));
         }
-        Ok(fd) => fd,
-    };
-    Ok((csocketfd.into(), consolefd.into()))
+        assert!(is_canonical(pty.slave));
+
+        close(pty.master).unwrap();
+        close(pty.slave).unwrap();
+    }
+
+    #[test]
+    fn test_tty_gid() {
+        let dir = std::env::temp_dir().join("youki_test_tty_gid");
+        fs::create_dir_all(&dir).unwrap();
+        let group_file = dir.join("group");
+
+        fs::write(&group_file, "root:x:0:\ntty:x:7:user\n").unwrap();
+        assert_eq!(tty_gid(&group_file), Gid::from_raw(7));
+        fs::write(&group_file, "root:x:0:\n").unwrap();
+        assert_eq!(tty_gid(&group_file), Gid::from_raw(DEFAULT_TTY_GID));
+        assert_eq!(
+            tty_gid(&dir.join("missing")),
+            Gid::from_raw(DEFAULT_TTY_GID)
+        );
+    }
+
+    #[test]
+    fn test_set_ownership() {
+        let dir = std::env::temp_dir().join("youki_test_set_ownership");
+        fs::create_dir_all(&dir).unwrap();
+        let group_file = dir.join("group");
+        // own user and group, which are allowed without privileges
+        let gid = nix::unistd::getgid();
+        fs::write(&group_file, format!("tty:x:{}:\n", gid)).unwrap();
+
+        let pty = nix::pty::openpty(None, None).expect("open pty");
+        let uid = nix::unistd::getuid();
+        set_ownership(pty.slave, uid, &group_file).unwrap();
+
+        let metadata = fs::metadata(format!("/proc/self/fd/{}", pty.slave)).unwrap();
+        assert_eq!(metadata.uid(), uid.as_raw());
+        assert_eq!(metadata.gid(), gid.as_raw());
+        assert_eq!(metadata.mode() & 0o777, 0o620);
+
+        close(pty.master).unwrap();
+        close(pty.slave).unwrap();
+    }
+
+    #[test]
+    fn test_link_console_socket() {
+        let dir = std::env::temp_dir().join("youki_test_link_console_socket");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("console.sock");
+        let _listener = std::os::unix::net::UnixListener::bind(&socket).unwrap();
+
+        assert!(link_console_socket(&dir, "/nonexistent/console.sock").is_err());
+        link_console_socket(&dir, socket.to_str().unwrap()).unwrap();
+        assert_eq!(fs::read_link(dir.join(CONSOLE_SOCKET)).unwrap(), socket);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_console_listener() {
+        let listener = ConsoleListener::new("test_console_listener").unwrap();
+        let path = listener.path().to_path_buf();
+        let stream = std::os::unix::net::UnixStream::connect(&path).unwrap();
+        let pty = nix::pty::openpty(None, None).unwrap();
+        socket::sendmsg(
+            stream.as_raw_fd(),
+            &[IoVec::from_slice(b"/dev/ptmx")],
+            &[socket::ControlMessage::ScmRights(&[pty.master])],
+            socket::MsgFlags::empty(),
+            None,
+        )
+        .unwrap();
+
+        let master = listener.receive().unwrap();
+        assert!(isatty(master).unwrap());
+        drop(listener);
+        assert!(!path.exists());
+        for fd in &[master, pty.master, pty.slave] {
+            close(*fd).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_copy_size() {
+        let from = nix::pty::openpty(None, None).unwrap();
+        let to = nix::pty::openpty(None, None).unwrap();
+        let size = Winsize {
+            ws_row: 40,
+            ws_col: 120,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        assert!(unsafe { libc::ioctl(from.master, libc::TIOCSWINSZ, &size) } >= 0);
+        copy_size(from.slave, to.master).unwrap();
+
+        let mut copied: Winsize = unsafe { std::mem::zeroed() };
+        assert!(unsafe { libc::ioctl(to.slave, libc::TIOCGWINSZ, &mut copied) } >= 0);
+        assert_eq!((copied.ws_row, copied.ws_col), (40, 120));
+        for fd in &[from.master, from.slave, to.master, to.slave] {
+            close(*fd).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_raw_terminal_not_a_tty() {
+        let (rfd, wfd) = nix::unistd::pipe().unwrap();
+        assert!(RawTerminal::new(rfd).unwrap().is_none());
+        close(rfd).unwrap();
+        close(wfd).unwrap();
+    }
 }
@@ -0,0 +1,190 @@
+//! Changes the resource limits of a container without restarting it, e.g. for docker update
+//! or the vertical scaling of a pod
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use clap::Clap;
+use oci_spec::{LinuxBlockIo, LinuxCpu, LinuxMemory, LinuxPids, LinuxResources};
+
+use crate::cgroups;
+use crate::container::Container;
+
+#[derive(Clap, Debug)]
+pub struct Update {
+    /// path to a json file with the resources, in the format of linux.resources of
+    /// config.json, or - to read them from stdin
+    #[clap(short, long)]
+    resources: Option<PathBuf>,
+    /// memory limit in bytes
+    #[clap(long)]
+    memory: Option<i64>,
+    /// soft memory limit in bytes
+    #[clap(long)]
+    memory_reservation: Option<i64>,
+    /// limit of memory plus swap in bytes, or -1 for unlimited swap
+    #[clap(long)]
+    memory_swap: Option<i64>,
+    /// relative cpu weight
+    #[clap(long)]
+    cpu_shares: Option<u64>,
+    /// cpu time in microseconds the container may use per period
+    #[clap(long)]
+    cpu_quota: Option<i64>,
+    /// period of the cpu quota in microseconds
+    #[clap(long)]
+    cpu_period: Option<u64>,
+    /// cpu time in microseconds the container may accumulate beyond its quota
+    #[clap(long)]
+    cpu_burst: Option<u64>,
+    /// cpus the container may run on, e.g. 0-3,6
+    #[clap(long)]
+    cpuset_cpus: Option<String>,
+    /// memory nodes the container may allocate from
+    #[clap(long)]
+    cpuset_mems: Option<String>,
+    /// maximum number of processes, or 0 for no limit
+    #[clap(long)]
+    pids_limit: Option<i64>,
+    /// relative block IO weight, from 10 to 1000
+    #[clap(long)]
+    blkio_weight: Option<u16>,
+    pub container_id: String,
+}
+
+impl Update {
+    pub fn exec(&self, root_path: PathBuf) -> Result<()> {
+        let container_root = root_path.join(&self.container_id);
+        if !container_root.exists() {
+            bail!("{} doesn't exist.", self.container_id)
+        }
+        let container = Container::load(container_root)?.refresh_status()?;
+        if !container.can_kill() {
+            bail!(
+                "cannot update {} because it was {:?}",
+                container.id(),
+                container.status()
+            );
+        }
+        if container.state.shared_cgroups_path.is_some() {
+            bail!(
+                "cannot update {} because its cgroups belong to another container",
+                container.id()
+            );
+        }
+
+        let changes = self.changes()?;
+        let resources = container.merged_resources(&changes)?;
+        let cmanager = cgroups::common::create_cgroup_manager(container.cgroups_path()?)?;
+        for warning in cmanager.unsupported(&changes) {
+            log::warn!("{}", warning);
+        }
+        cmanager.update(&resources)?;
+        container.persist_resources(&changes, false)
+    }
+
+    /// Resources to change, from the file given with the options on top
+    fn changes(&self) -> Result<LinuxResources> {
+        let mut changes: LinuxResources = match &self.resources {
+            Some(path) if path.as_os_str() == "-" => serde_json::from_reader(io::stdin())
+                .context("failed to parse resources from stdin")?,
+            Some(path) => {
+                let file = fs::File::open(path)
+                    .with_context(|| format!("failed to open resources {:?}", path))?;
+                serde_json::from_reader(file)
+                    .with_context(|| format!("failed to parse resources {:?}", path))?
+            }
+            None => LinuxResources::default(),
+        };
+
+        if self.memory.is_some() || self.memory_reservation.is_some() || self.memory_swap.is_some()
+        {
+            let memory = changes.memory.get_or_insert(LinuxMemory {
+                limit: None,
+                reservation: None,
+                swap: None,
+                kernel: None,
+                kernel_tcp: None,
+                swappiness: None,
+            });
+            memory.limit = self.memory.or(memory.limit);
+            memory.reservation = self.memory_reservation.or(memory.reservation);
+            memory.swap = self.memory_swap.or(memory.swap);
+        }
+        if self.cpu_shares.is_some()
+            || self.cpu_quota.is_some()
+            || self.cpu_period.is_some()
+            || self.cpu_burst.is_some()
+            || self.cpuset_cpus.is_some()
+            || self.cpuset_mems.is_some()
+        {
+            let cpu = changes.cpu.get_or_insert(LinuxCpu {
+                shares: None,
+                quota: None,
+                burst: None,
+                period: None,
+                realtime_runtime: None,
+                realtime_period: None,
+                cpus: None,
+                mems: None,
+            });
+            cpu.shares = self.cpu_shares.or(cpu.shares);
+            cpu.quota = self.cpu_quota.or(cpu.quota);
+            cpu.period = self.cpu_period.or(cpu.period);
+            cpu.burst = self.cpu_burst.or(cpu.burst);
+            cpu.cpus = self.cpuset_cpus.clone().or_else(|| cpu.cpus.take());
+            cpu.mems = self.cpuset_mems.clone().or_else(|| cpu.mems.take());
+        }
+        if let Some(limit) = self.pids_limit {
+            changes.pids = Some(LinuxPids { limit });
+        }
+        if let Some(weight) = self.blkio_weight {
+            if !(10..=1000).contains(&weight) {
+                bail!("blkio weight must be from 10 to 1000, not {}", weight);
+            }
+            let block_io = changes.block_io.get_or_insert(LinuxBlockIo {
+                blkio_weight: None,
+                blkio_leaf_weight: None,
+                blkio_weight_device: Vec::new(),
+                blkio_throttle_read_bps_device: Vec::new(),
+                blkio_throttle_write_bps_device: Vec::new(),
+                blkio_throttle_read_iops_device: Vec::new(),
+                blkio_throttle_write_iops_device: Vec::new(),
+            });
+            block_io.blkio_weight = Some(weight);
+        }
+        Ok(changes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changes() {
+        let update = Update::try_parse_from([
+            "update",
+            "--memory",
+            "4096",
+            "--cpu-quota",
+            "50000",
+            "--pids-limit",
+            "100",
+            "abc",
+        ])
+        .unwrap();
+        let changes = update.changes().unwrap();
+        let memory = changes.memory.unwrap();
+        assert_eq!((memory.limit, memory.swap), (Some(4096), None));
+        let cpu = changes.cpu.unwrap();
+        assert_eq!((cpu.quota, cpu.shares), (Some(50000), None));
+        assert_eq!(changes.pids.unwrap().limit, 100);
+        assert!(changes.block_io.is_none());
+
+        let update = Update::try_parse_from(["update", "--blkio-weight", "5", "abc"]).unwrap();
+        assert!(update.changes().is_err());
+    }
+}
@@ -2,11 +2,16 @@
 
 use std::ffi::CString;
 use std::fs;
+use std::os::unix::io::RawFd;
 use std::path::{Path, PathBuf};
 use std::time::Duration;
 
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
+use nix::fcntl;
+use nix::sys::statfs;
+use nix::unistd::AccessFlags;
 use nix::{env::clearenv, errno::Errno, unistd};
+use once_cell::sync::OnceCell;
 
 pub trait PathBufExt {
     fn as_in_container(&self) -> Result<PathBuf>;
@@ -56,6 +61,24 @@ pub fn do_exec(path: impl AsRef<Path>, args: &[String], envs: &[String]) -> Resu
     Ok(())
 }
 
+/// Marks every open file descriptor from min_fd on close-on-exec, so that the next exec
+/// closes those which are not meant to be passed on
+pub fn close_on_exec_from(min_fd: RawFd) -> Result<()> {
+    // collected first, as the directory being read has a file descriptor of its own
+    let fds: Vec<RawFd> = fs::read_dir("/proc/self/fd")?
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+        .filter(|fd| *fd >= min_fd)
+        .collect();
+    for fd in fds {
+        match fcntl::fcntl(fd, fcntl::FcntlArg::F_SETFD(fcntl::FdFlag::FD_CLOEXEC)) {
+            // the one of the directory, which is closed already
+            Ok(_) | Err(nix::Error::Sys(Errno::EBADF)) => {}
+            Err(e) => bail!("failed to set close-on-exec on {}: {}", fd, e),
+        }
+    }
+    Ok(())
+}
+
 #[inline]
 fn putenv(string: &CString) -> nix::Result<()> {
     let ptr = string.clone().into_raw();
@@ -76,6 +99,23 @@ pub fn get_cgroup_path(cgroups_path: &Option<PathBuf>, container_id: &str) -> Pa
     }
 }
 
+/// Writes the pid to the file through a temporary file which is renamed over it, so that
+/// whoever watches the file never reads it half written
+pub fn write_pid_file<P: AsRef<Path>>(path: P, pid: unistd::Pid) -> Result<()> {
+    let path = path.as_ref();
+    let file_name = match path.file_name() {
+        Some(file_name) => file_name.to_string_lossy(),
+        None => bail!("invalid pid file {:?}", path),
+    };
+    let tmp = path.with_file_name(format!(".{}.{}", file_name, unistd::getpid()));
+    fs::write(&tmp, pid.to_string()).with_context(|| format!("failed to write {:?}", tmp))?;
+    if let Err(e) = fs::rename(&tmp, path) {
+        let _ = fs::remove_file(&tmp);
+        return Err(e).with_context(|| format!("failed to write pid file {:?}", path));
+    }
+    Ok(())
+}
+
 pub fn delete_with_retry<P: AsRef<Path>>(path: P) -> Result<()> {
     let mut attempts = 0;
     let mut delay = Duration::from_millis(10);
@@ -94,9 +134,140 @@ pub fn delete_with_retry<P: AsRef<Path>>(path: P) -> Result<()> {
     bail!("could not delete {:?}", path)
 }
 
+/// Returns a digest of a config.json which ignores its formatting, as it is computed over
+/// the json with sorted keys and without whitespace. FNV-1a is enough to notice changes,
+/// its name is part of the digest so that another algorithm can be told apart.
+pub fn config_digest<P: AsRef<Path>>(config_path: P) -> Result<String> {
+    let config: serde_json::Value = serde_json::from_reader(fs::File::open(config_path)?)?;
+    let canonical = serde_json::to_string(&config)?;
+    let digest = canonical.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    Ok(format!("fnv1a64:{:016x}", digest))
+}
+
+/// Adjusts the badness of the process for the OOM killer, which is kept across fork and
+/// exec. Lowering it below the value it started with needs CAP_SYS_RESOURCE of the host,
+/// so it is set before a user namespace is entered.
+pub fn set_oom_score_adj(adj: i32) -> Result<()> {
+    fs::write("/proc/self/oom_score_adj", adj.to_string())
+        .with_context(|| format!("failed to set oom_score_adj to {}", adj))
+}
+
+/// Limit of open files which the runtime was started with, before it raised its own
+static ORIGINAL_NOFILE: OnceCell<libc::rlimit> = OnceCell::new();
+
+/// Raises the soft limit of open files of the runtime to the hard limit, as it may hold many
+/// file descriptors at once, e.g. to pass them on to containers
+pub fn raise_nofile_limit() -> Result<()> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    Errno::result(unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) })?;
+    let original = *ORIGINAL_NOFILE.get_or_init(|| limit);
+    limit.rlim_cur = original.rlim_max;
+    Errno::result(unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &limit) })?;
+    Ok(())
+}
+
+/// Puts back the limit of open files which the runtime was started with, so that a
+/// container does not inherit the raised one. The rlimits of its spec are set afterwards.
+pub fn restore_nofile_limit() -> Result<()> {
+    if let Some(original) = ORIGINAL_NOFILE.get() {
+        Errno::result(unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, original) })?;
+    }
+    Ok(())
+}
+
+/// Root for container state of the system, used unless --root is given
+pub const DEFAULT_STATE_ROOT: &str = "/run/youki";
+
+/// Picks the first of the candidate roots for container state which exists or can be
+/// created, and is writable. Fails naming every path tried, instead of failing later
+/// in the middle of writing the state.
+pub fn select_state_root(candidates: &[PathBuf]) -> Result<PathBuf> {
+    let mut tried = Vec::new();
+    for candidate in candidates {
+        match probe_state_root(candidate) {
+            Ok(()) => {
+                if tried.is_empty() {
+                    log::debug!("state root is {:?}", candidate);
+                } else {
+                    log::warn!("falling back to {:?} as state root", candidate);
+                }
+                // the state is meant to be gone with the containers when the host reboots
+                if !matches!(statfs::statfs(candidate), Ok(fs) if fs.filesystem_type() == statfs::TMPFS_MAGIC)
+                {
+                    log::warn!(
+                        "state root {:?} is not on tmpfs, its state outlives a reboot of the host",
+                        candidate
+                    );
+                }
+                return Ok(candidate.clone());
+            }
+            Err(e) => {
+                log::warn!("state root {:?} is not usable: {}", candidate, e);
+                tried.push(format!("{:?} ({})", candidate, e));
+            }
+        }
+    }
+
+    bail!("no usable state root, tried {}", tried.join(", "))
+}
+
+/// Returns the state roots which read-only commands look into, starting with the selected
+/// one. Containers of the system are in the default root, containers of unprivileged users
+/// in their runtime directories, of which root can see all.
+pub fn search_state_roots(root: &Path) -> Vec<PathBuf> {
+    let mut candidates = vec![PathBuf::from(DEFAULT_STATE_ROOT)];
+    if unistd::geteuid().is_root() {
+        if let Ok(entries) = fs::read_dir("/run/user") {
+            candidates.extend(entries.flatten().map(|entry| entry.path().join("youki")));
+        }
+    } else if let Some(runtime_dir) = std::env::var_os("XDG_RUNTIME_DIR") {
+        candidates.push(PathBuf::from(runtime_dir).join("youki"));
+    }
+
+    let mut roots = vec![root.to_path_buf()];
+    for candidate in candidates {
+        let known = roots
+            .iter()
+            .any(|root| fs::canonicalize(root).ok() == fs::canonicalize(&candidate).ok());
+        if candidate.is_dir() && !known {
+            roots.push(candidate);
+        }
+    }
+    roots
+}
+
+fn probe_state_root(root: &Path) -> Result<()> {
+    fs::create_dir_all(root)?;
+    unistd::access(root, AccessFlags::W_OK)?;
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::os::unix::io::AsRawFd;
+
+    #[test]
+    fn test_raise_and_restore_nofile_limit() {
+        let get = || {
+            let mut limit = libc::rlimit {
+                rlim_cur: 0,
+                rlim_max: 0,
+            };
+            unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) };
+            (limit.rlim_cur, limit.rlim_max)
+        };
+        let original = get();
+        raise_nofile_limit().unwrap();
+        assert_eq!(get(), (original.1, original.1));
+        restore_nofile_limit().unwrap();
+        assert_eq!(get(), original);
+    }
 
     #[test]
     fn test_join_absolute_path() {
@@ -110,12 +281,9 @@ mod tests {
 
     #[test]
     fn test_join_absolute_path_error() {
-        assert_eq!(
-            PathBuf::from("sample/a/")
-                .join_absolute_path(&PathBuf::from("b/c"))
-                .is_err(),
-            true
-        );
+        assert!(PathBuf::from("sample/a/")
+            .join_absolute_path(&PathBuf::from("b/c"))
+            .is_err());
     }
 
     #[test]
@@ -130,4 +298,94 @@ mod tests {
             PathBuf::from("/youki")
         );
     }
+
+    #[test]
+    fn test_select_state_root() {
+        let tmp = std::env::temp_dir().join("test_select_state_root");
+        let _ = fs::remove_dir_all(&tmp);
+        fs::create_dir_all(&tmp).unwrap();
+        // nothing can be created below a regular file
+        let file = tmp.join("file");
+        fs::write(&file, "").unwrap();
+        let unusable = file.join("youki");
+        let usable = tmp.join("youki");
+
+        assert_eq!(
+            select_state_root(&[unusable.clone(), usable.clone()]).unwrap(),
+            usable
+        );
+        assert!(usable.is_dir());
+
+        let err = select_state_root(std::slice::from_ref(&unusable)).unwrap_err();
+        assert!(err.to_string().contains(&format!("{:?}", unusable)));
+        fs::remove_dir_all(&tmp).unwrap();
+    }
+
+    #[test]
+    fn test_write_pid_file() {
+        let tmp = std::env::temp_dir().join("test_write_pid_file");
+        let _ = fs::remove_dir_all(&tmp);
+        fs::create_dir_all(&tmp).unwrap();
+        let pid_file = tmp.join("container.pid");
+
+        write_pid_file(&pid_file, unistd::Pid::from_raw(42)).unwrap();
+        assert_eq!(fs::read_to_string(&pid_file).unwrap(), "42");
+        write_pid_file(&pid_file, unistd::Pid::from_raw(7)).unwrap();
+        assert_eq!(fs::read_to_string(&pid_file).unwrap(), "7");
+        // only the pid file is left, no temporary one
+        assert_eq!(fs::read_dir(&tmp).unwrap().count(), 1);
+
+        assert!(write_pid_file(tmp.join("missing/container.pid"), unistd::getpid()).is_err());
+        fs::remove_dir_all(&tmp).unwrap();
+    }
+
+    #[test]
+    fn test_close_on_exec_from() {
+        let file = fs::File::open("/proc/self/status").unwrap();
+        let fd = nix::unistd::dup(file.as_raw_fd()).unwrap();
+        let flags = |fd| fcntl::fcntl(fd, fcntl::FcntlArg::F_GETFD).unwrap();
+        assert_eq!(flags(fd) & libc::FD_CLOEXEC, 0);
+
+        close_on_exec_from(fd + 1).unwrap();
+        assert_eq!(flags(fd) & libc::FD_CLOEXEC, 0);
+        close_on_exec_from(fd).unwrap();
+        assert_ne!(flags(fd) & libc::FD_CLOEXEC, 0);
+        nix::unistd::close(fd).unwrap();
+    }
+
+    #[test]
+    fn test_config_digest() {
+        let tmp = std::env::temp_dir().join("test_config_digest");
+        let _ = fs::remove_dir_all(&tmp);
+        fs::create_dir_all(&tmp).unwrap();
+        let config = tmp.join("config.json");
+
+        fs::write(&config, r#"{"ociVersion": "1.0.2", "hostname": "a"}"#).unwrap();
+        let digest = config_digest(&config).unwrap();
+        assert!(digest.starts_with("fnv1a64:"));
+
+        fs::write(
+            &config,
+            "{\n  \"hostname\": \"a\",\n  \"ociVersion\": \"1.0.2\"\n}",
+        )
+        .unwrap();
+        assert_eq!(config_digest(&config).unwrap(), digest);
+
+        fs::write(&config, r#"{"ociVersion": "1.0.2", "hostname": "b"}"#).unwrap();
+        assert_ne!(config_digest(&config).unwrap(), digest);
+        fs::remove_dir_all(&tmp).unwrap();
+    }
+
+    #[test]
+    fn test_search_state_roots() {
+        let tmp = std::env::temp_dir().join("test_search_state_roots");
+        let _ = fs::remove_dir_all(&tmp);
+        fs::create_dir_all(&tmp).unwrap();
+
+        let roots = search_state_roots(&tmp);
+        assert_eq!(roots[0], tmp);
+        assert_eq!(roots.iter().filter(|root| **root == tmp).count(), 1);
+        assert!(roots[1..].iter().all(|root| root.is_dir()));
+        fs::remove_dir_all(&tmp).unwrap();
+    }
 }